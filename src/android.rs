@@ -0,0 +1,75 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use target_tuples::Target;
+
+pub const DEFAULT_API_LEVEL: u32 = 21;
+
+pub struct Ndk {
+    pub root: PathBuf,
+    pub api: u32,
+    pub clang: PathBuf,
+    pub clangxx: PathBuf,
+    pub ar: PathBuf,
+    pub sysroot: PathBuf,
+}
+
+fn host_tag() -> &'static str {
+    if cfg!(target_os = "windows") {
+        "windows-x86_64"
+    } else if cfg!(target_os = "macos") {
+        "darwin-x86_64"
+    } else {
+        "linux-x86_64"
+    }
+}
+
+fn clang_triple(target: &Target) -> String {
+    let name = target.get_name();
+    match name.strip_prefix("armv7-") {
+        Some(rest) => format!("armv7a-{}", rest),
+        None => name.to_string(),
+    }
+}
+
+pub fn is_android(target: &Target) -> bool {
+    target.get_name().contains("-android")
+}
+
+pub fn detect(target: &Target, api: u32) -> std::io::Result<Ndk> {
+    let root = ["ANDROID_NDK_HOME", "ANDROID_NDK_ROOT", "ANDROID_NDK"]
+        .iter()
+        .find_map(std::env::var_os)
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("Building for {} requires ANDROID_NDK_HOME", target),
+            )
+        })?;
+
+    let toolchain = root.join("toolchains/llvm/prebuilt").join(host_tag());
+    let bin = toolchain.join("bin");
+    let wrapper_suffix = if cfg!(windows) { ".cmd" } else { "" };
+    let prefix = format!("{}{}", clang_triple(target), api);
+    let clang = bin.join(format!("{}-clang{}", prefix, wrapper_suffix));
+    if !clang.is_file() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "The NDK at {} has no compiler for {} at API level {}",
+                root.display(),
+                target,
+                api
+            ),
+        ));
+    }
+
+    Ok(Ndk {
+        clangxx: bin.join(format!("{}-clang++{}", prefix, wrapper_suffix)),
+        ar: bin.join(format!("llvm-ar{}", std::env::consts::EXE_SUFFIX)),
+        sysroot: toolchain.join("sysroot"),
+        root,
+        api,
+        clang,
+    })
+}
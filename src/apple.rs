@@ -0,0 +1,103 @@
+use std::{io::ErrorKind, path::PathBuf, process::Command};
+
+use target_tuples::Target;
+
+pub struct Sdk {
+    pub name: &'static str,
+    pub path: PathBuf,
+    pub clang: Option<PathBuf>,
+    pub deployment_var: &'static str,
+    pub min_version: Option<String>,
+    pub min_version_flag: &'static str,
+}
+
+fn xcrun(args: &[&str]) -> Option<PathBuf> {
+    let output = Command::new("xcrun").args(args).output().ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !path.is_empty() {
+        Some(PathBuf::from(path))
+    } else {
+        None
+    }
+}
+
+pub fn xcrun_find(tool: &str) -> Option<PathBuf> {
+    xcrun(&["--find", tool])
+}
+
+pub fn is_apple_mobile(target: &Target) -> bool {
+    sdk_info(target).is_some()
+}
+
+fn sdk_info(target: &Target) -> Option<(&'static str, &'static str, &'static str)> {
+    let name = target.get_name();
+    let simulator =
+        name.ends_with("-sim") || name.starts_with("x86_64") || name.starts_with("i386");
+    if name.contains("-apple-ios-macabi") {
+        None
+    } else if name.contains("-apple-ios") {
+        Some(if simulator {
+            (
+                "iphonesimulator",
+                "IPHONEOS_DEPLOYMENT_TARGET",
+                "-mios-simulator-version-min",
+            )
+        } else {
+            (
+                "iphoneos",
+                "IPHONEOS_DEPLOYMENT_TARGET",
+                "-miphoneos-version-min",
+            )
+        })
+    } else if name.contains("-apple-tvos") {
+        Some(if simulator {
+            (
+                "appletvsimulator",
+                "TVOS_DEPLOYMENT_TARGET",
+                "-mtvos-simulator-version-min",
+            )
+        } else {
+            ("appletvos", "TVOS_DEPLOYMENT_TARGET", "-mtvos-version-min")
+        })
+    } else if name.contains("-apple-watchos") {
+        Some(if simulator {
+            (
+                "watchsimulator",
+                "WATCHOS_DEPLOYMENT_TARGET",
+                "-mwatchos-simulator-version-min",
+            )
+        } else {
+            (
+                "watchos",
+                "WATCHOS_DEPLOYMENT_TARGET",
+                "-mwatchos-version-min",
+            )
+        })
+    } else {
+        None
+    }
+}
+
+pub fn detect(target: &Target, min_version: Option<String>) -> std::io::Result<Option<Sdk>> {
+    let (name, deployment_var, min_version_flag) = match sdk_info(target) {
+        Some(info) => info,
+        None => return Ok(None),
+    };
+    let path = xcrun(&["--sdk", name, "--show-sdk-path"]).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Cannot find the {} SDK for {}; is Xcode installed?",
+                name, target
+            ),
+        )
+    })?;
+    Ok(Some(Sdk {
+        name,
+        clang: xcrun(&["--sdk", name, "--find", "clang"]),
+        path,
+        deployment_var,
+        min_version: std::env::var(deployment_var).ok().or(min_version),
+        min_version_flag,
+    }))
+}
@@ -0,0 +1,113 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config::ArchiveStep, configure::Configuration};
+
+pub fn lib_file(name: &str, cfg: &Configuration) -> String {
+    if cfg.host.get_name().ends_with("msvc") {
+        format!("{}.lib", name)
+    } else {
+        format!("lib{}.a", name)
+    }
+}
+
+pub fn output(step: &ArchiveStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(lib_file(&step.name, cfg))
+}
+
+pub fn inputs(step: &ArchiveStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let mut inputs = crate::steps::listed_files(step.objects.iter(), cfg)?;
+    for name in &step.from_steps {
+        let artifacts = cfg.step_artifacts.get(name).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("No planned step is named {}", name),
+            )
+        })?;
+        inputs.extend(
+            artifacts
+                .iter()
+                .filter(|a| a.kind == "object" || a.kind == "staticlib")
+                .map(|a| a.path.clone()),
+        );
+    }
+    Ok(inputs)
+}
+
+fn is_lib_exe(tool: &Path) -> bool {
+    tool.file_stem()
+        .is_some_and(|n| n.eq_ignore_ascii_case("lib") || n.eq_ignore_ascii_case("llvm-lib"))
+}
+
+fn is_archive(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext == "a" || ext == "lib")
+}
+
+// lib.exe merges libraries by itself; ar would store them whole, so their members are pulled out
+// first and added one by one
+pub fn archive(step: &ArchiveStep, cfg: &Configuration) -> std::io::Result<()> {
+    let ar = cfg
+        .ar
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "No archiver was configured"))?;
+    let output = output(step, cfg);
+    if output.exists() {
+        std::fs::remove_file(&output)?;
+    }
+    let inputs = inputs(step, cfg)?;
+    crate::steps::note(&format!("archive {}", output.display()));
+
+    if is_lib_exe(ar) {
+        let mut cmd = Command::new(ar);
+        cmd.arg("/NOLOGO").arg(format!("/OUT:{}", output.display()));
+        if step.thin {
+            if ar
+                .file_stem()
+                .is_some_and(|n| n.eq_ignore_ascii_case("llvm-lib"))
+            {
+                cmd.arg("/llvmlibthin");
+            } else {
+                eprintln!(
+                    "cargo-autobuild: warning: {} cannot make thin archives; {} is a full one",
+                    ar.display(),
+                    output.display()
+                );
+            }
+        }
+        return crate::steps::run(cmd.args(&inputs));
+    }
+
+    let scratch = cfg.scratch_dir.join("archive").join(&step.name);
+    if scratch.exists() {
+        std::fs::remove_dir_all(&scratch)?;
+    }
+    let mut members = Vec::new();
+    for (i, input) in inputs.iter().enumerate() {
+        if !is_archive(input) {
+            members.push(input.clone());
+            continue;
+        }
+        let dir = scratch.join(i.to_string());
+        std::fs::create_dir_all(&dir)?;
+        crate::steps::run(Command::new(ar).arg("x").arg(input).current_dir(&dir))?;
+        let mut extracted = std::fs::read_dir(&dir)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        // Keeps the member order independent of the directory listing
+        extracted.sort();
+        members.extend(extracted);
+    }
+
+    let mut flags = "qcs".to_string();
+    if step.deterministic {
+        flags.push('D');
+    }
+    if step.thin {
+        flags.push('T');
+    }
+    crate::steps::run(Command::new(ar).arg(flags).arg(&output).args(&members))
+}
@@ -0,0 +1,78 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config::AssembleStep, configure::Configuration};
+
+pub fn sources(step: &AssembleStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    crate::steps::listed_files(step.sources.iter(), cfg)
+}
+
+// `.S` and `.sx` files go through the C preprocessor first, which the assembler alone lacks
+fn preprocessed(src: &Path) -> bool {
+    src.extension().is_some_and(|ext| ext == "S" || ext == "sx")
+}
+
+pub fn object(src: &Path, cfg: &Configuration) -> PathBuf {
+    let mut name = src.file_name().unwrap_or_default().to_os_string();
+    name.push(".o");
+    cfg.build_dir.join("asm").join(name)
+}
+
+pub fn archive(step: &AssembleStep, cfg: &Configuration) -> Option<PathBuf> {
+    let name = step.archive.as_ref()?;
+    Some(
+        cfg.build_dir
+            .join("asm")
+            .join(crate::archive::lib_file(name, cfg)),
+    )
+}
+
+fn tool<'a>(tool: &'a Option<PathBuf>, what: &str) -> std::io::Result<&'a Path> {
+    tool.as_deref().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, format!("No {} was configured", what))
+    })
+}
+
+// Also the command compile_commands.json records for `src`
+pub fn command(step: &AssembleStep, src: &Path, cfg: &Configuration) -> std::io::Result<Command> {
+    let mut cmd = if preprocessed(src) {
+        let mut cmd = Command::new(tool(&cfg.cc, "C compiler")?);
+        cmd.arg("-c");
+        cmd
+    } else {
+        Command::new(tool(&cfg.assembler, "assembler")?)
+    };
+    cmd.args(&step.flags)
+        .arg("-o")
+        .arg(object(src, cfg))
+        .arg(src);
+    Ok(cmd)
+}
+
+pub fn assemble(step: &AssembleStep, cfg: &Configuration) -> std::io::Result<()> {
+    std::fs::create_dir_all(cfg.build_dir.join("asm"))?;
+    let mut objects = Vec::new();
+    for src in sources(step, cfg)? {
+        let obj = object(&src, cfg);
+        crate::steps::note(&format!("assemble {} -> {}", src.display(), obj.display()));
+        crate::steps::run(&mut command(step, &src, cfg)?)?;
+        objects.push(obj);
+    }
+
+    if let Some(archive) = archive(step, cfg) {
+        if archive.exists() {
+            std::fs::remove_file(&archive)?;
+        }
+        crate::steps::note(&format!("archive {}", archive.display()));
+        crate::steps::run(
+            Command::new(tool(&cfg.ar, "archiver")?)
+                .arg("crs")
+                .arg(&archive)
+                .args(&objects),
+        )?;
+    }
+    Ok(())
+}
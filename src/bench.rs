@@ -0,0 +1,170 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct Results {
+    pub timestamp: u64,
+    pub suite: String,
+    // Nanoseconds per iteration, keyed by benchmark name
+    pub results: BTreeMap<String, f64>,
+}
+
+fn nanos(value: &str, unit: &str) -> Option<f64> {
+    let value: f64 = value.replace(',', "").parse().ok()?;
+    let scale = match unit {
+        "ps" => 0.001,
+        "ns" => 1.0,
+        "us" | "µs" => 1_000.0,
+        "ms" => 1_000_000.0,
+        "s" => 1_000_000_000.0,
+        _ => return None,
+    };
+    Some(value * scale)
+}
+
+// Reads libtest's `test name ... bench: 1,234 ns/iter (+/- 5)` and criterion's
+// `name  time: [low mid high]`, where criterion puts long names on a line of their own
+pub struct Parser {
+    pending: Option<String>,
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser { pending: None }
+    }
+
+    pub fn line(&mut self, line: &str, results: &mut BTreeMap<String, f64>) {
+        if let Some(rest) = line.strip_prefix("test ") {
+            if let Some((name, bench)) = rest.split_once(" ... bench:") {
+                let mut words = bench.split_whitespace();
+                let value = words.next().and_then(|value| {
+                    let unit = words.next()?.strip_suffix("/iter")?;
+                    nanos(value, unit)
+                });
+                if let Some(value) = value {
+                    results.insert(name.trim().to_string(), value);
+                }
+                return;
+            }
+        }
+        match line.split_once("time:") {
+            Some((name, times)) => {
+                let name = match name.trim() {
+                    "" => self.pending.take(),
+                    name => Some(name.to_string()),
+                };
+                let words: Vec<&str> = times
+                    .trim()
+                    .trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .split_whitespace()
+                    .collect();
+                if let (Some(name), [_, _, value, unit, ..]) = (name, &*words) {
+                    if let Some(value) = nanos(value, unit) {
+                        results.insert(name, value);
+                    }
+                }
+                self.pending = None;
+            }
+            None if !line.is_empty() && !line.starts_with(char::is_whitespace) => {
+                self.pending = Some(line.trim().to_string())
+            }
+            None => {}
+        }
+    }
+}
+
+fn dir(build_dir: &Path) -> PathBuf {
+    build_dir.join("bench")
+}
+
+fn baseline_path(build_dir: &Path, name: &str) -> PathBuf {
+    dir(build_dir)
+        .join("baselines")
+        .join(format!("{}.json", name))
+}
+
+fn save(path: &Path, results: &Results) -> std::io::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap())?;
+    let json = serde_json::to_vec_pretty(results).map_err(std::io::Error::other)?;
+    std::fs::write(path, json)
+}
+
+// Stores a run as bench/<seconds since the epoch>-<suite>.json and returns it
+pub fn archive(
+    build_dir: &Path,
+    suite: &str,
+    results: BTreeMap<String, f64>,
+) -> std::io::Result<Results> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let results = Results {
+        timestamp,
+        suite: suite.to_string(),
+        results,
+    };
+    let file = format!("{}-{}.json", timestamp, suite.replace(['/', '\\'], "_"));
+    let path = dir(build_dir).join(file);
+    save(&path, &results)?;
+    crate::steps::note(&format!("Benchmark results saved to {}", path.display()));
+    Ok(results)
+}
+
+pub fn save_baseline(build_dir: &Path, name: &str, results: &Results) -> std::io::Result<()> {
+    let path = baseline_path(build_dir, name);
+    save(&path, results)?;
+    crate::steps::note(&format!("Saved benchmark baseline {}", name));
+    Ok(())
+}
+
+// Reports every benchmark more than `threshold` percent slower than the baseline
+pub fn compare(
+    build_dir: &Path,
+    name: &str,
+    threshold: f64,
+    results: &Results,
+) -> std::io::Result<()> {
+    let path = baseline_path(build_dir, name);
+    let baseline: Results = match std::fs::read(&path) {
+        Ok(bytes) => serde_json::from_slice(&bytes)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            crate::steps::note(&format!(
+                "No benchmark baseline {} to compare against",
+                name
+            ));
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let mut regressions = Vec::new();
+    for (bench, &new) in &results.results {
+        let old = match baseline.results.get(bench) {
+            Some(&old) if old > 0.0 => old,
+            _ => continue,
+        };
+        let change = (new - old) / old * 100.0;
+        crate::steps::note(&format!("{}: {:+.2}% against {}", bench, change, name));
+        if change > threshold {
+            regressions.push(format!("{} ({:+.2}%)", bench, change));
+        }
+    }
+    if regressions.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "These benchmarks regressed more than {}% against {}: {}",
+            threshold,
+            name,
+            regressions.join(", ")
+        )))
+    }
+}
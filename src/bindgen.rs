@@ -0,0 +1,94 @@
+use std::{io::ErrorKind, path::PathBuf, process::Command};
+
+use crate::{config::BindgenStep, configure::Configuration, steps::substitute};
+
+pub fn output(step: &BindgenStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(&step.output)
+}
+
+pub fn inputs(step: &BindgenStep, cfg: &Configuration) -> Vec<PathBuf> {
+    step.headers
+        .iter()
+        .chain(&step.include_dirs)
+        .map(|p| cfg.src_dir.join(substitute(&p.to_string_lossy(), cfg)))
+        .collect()
+}
+
+fn pkg_config_includes(packages: &[String]) -> std::io::Result<Vec<String>> {
+    if packages.is_empty() {
+        return Ok(Vec::new());
+    }
+    let pkg_config = match std::env::var_os("PKG_CONFIG") {
+        Some(pkg_config) => PathBuf::from(pkg_config),
+        None => crate::tests::which_any(&["pkg-config", "pkgconf"])?,
+    };
+    let output = Command::new(pkg_config)
+        .arg("--cflags-only-I")
+        .args(packages)
+        .output()?;
+    if !output.status.success() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "pkg-config could not find {}: {}",
+                packages.join(", "),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(str::to_string)
+        .collect())
+}
+
+// bindgen takes a single header, so several are gathered into one that includes them all
+fn header(step: &BindgenStep, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    let headers: Vec<PathBuf> = inputs(step, cfg)
+        .into_iter()
+        .take(step.headers.len())
+        .collect();
+    if let [header] = &headers[..] {
+        return Ok(header.clone());
+    }
+    let mut wrapper = cfg.build_dir.join("bindgen").join(&step.output);
+    wrapper.set_extension("h");
+    if let Some(parent) = wrapper.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let text: String = headers
+        .iter()
+        .map(|h| format!("#include \"{}\"\n", h.display()))
+        .collect();
+    std::fs::write(&wrapper, text)?;
+    Ok(wrapper)
+}
+
+pub fn generate(step: &BindgenStep, cfg: &Configuration) -> std::io::Result<()> {
+    let bindgen = cfg
+        .bindgen
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "No bindgen was configured"))?;
+    let output = output(step, cfg);
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut cmd = Command::new(bindgen);
+    cmd.arg(header(step, cfg)?)
+        .arg("--output")
+        .arg(&output)
+        .args(&step.args)
+        .arg("--");
+    for dir in &step.include_dirs {
+        let dir = cfg.src_dir.join(substitute(&dir.to_string_lossy(), cfg));
+        cmd.arg(format!("-I{}", dir.display()));
+    }
+    cmd.args(pkg_config_includes(&step.pkg_config)?);
+    cmd.arg(format!(
+        "--target={}",
+        cfg.resolve_target(&step.target).get_name()
+    ));
+    cmd.args(&step.clang_args);
+    crate::steps::run(&mut cmd)
+}
@@ -0,0 +1,75 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, ProgramType},
+    configure::Configuration,
+};
+
+// Stage 0 is built by the compiler configure finds on its own. Later stages name the compiler of
+// the stage before in the configuration, which an environment variable still overrides
+pub fn stage_config(config: &Config, compiler: Option<&Path>) -> Config {
+    let mut config = config.clone();
+    let (key, compiler) = match (&config.bootstrap, compiler) {
+        (Some(bootstrap), Some(compiler)) => (bootstrap.program.clone(), compiler),
+        _ => return config,
+    };
+    match config.programs.get_mut(&key) {
+        Some(program) if !matches!(program.ty, ProgramType::Rustc) => {
+            program.names = Some(vec![compiler.display().to_string()]);
+        }
+        _ => {
+            config
+                .compilers
+                .insert("$host".to_string(), compiler.to_owned());
+        }
+    }
+    config
+}
+
+pub fn artifact(cfg: &Configuration, key: &str) -> std::io::Result<PathBuf> {
+    cfg.step_artifacts
+        .values()
+        .flatten()
+        .find(|a| a.key == key)
+        .map(|a| a.path.clone())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("The build has no artifact {}", key),
+            )
+        })
+}
+
+// A compiler built by itself from the same source must come out the same every time; when it
+// does not, it either miscompiles itself or does not build reproducibly
+pub fn compare(
+    keys: &[String],
+    earlier: &Configuration,
+    later: &Configuration,
+) -> std::io::Result<()> {
+    let mut differ = Vec::new();
+    for key in keys {
+        let (a, b) = (artifact(earlier, key)?, artifact(later, key)?);
+        if std::fs::read(&a)? != std::fs::read(&b)? {
+            eprintln!(
+                "cargo-autobuild: {} differs between {} and {}",
+                key,
+                a.display(),
+                b.display()
+            );
+            differ.push(key.as_str());
+        }
+    }
+    if differ.is_empty() {
+        println!("Stages agree on {}", keys.join(", "));
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("The last two stages differ in {}", differ.join(", ")),
+        ))
+    }
+}
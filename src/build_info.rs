@@ -0,0 +1,137 @@
+use std::{
+    process::{Command, Stdio},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::{configure::Configuration, sbom::version_of, timings::Timing};
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Git {
+    commit: String,
+    dirty: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Step {
+    name: String,
+    // ok, failed, skipped (up to date) or blocked (a step it depends on failed)
+    status: String,
+    duration: f64,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct BuildInfo {
+    version: Option<String>,
+    autobuild_version: &'static str,
+    git: Option<Git>,
+    command: String,
+    build: String,
+    host: String,
+    target: String,
+    profile: Value,
+    toolchain: Value,
+    started: String,
+    finished: String,
+    success: bool,
+    steps: Vec<Step>,
+}
+
+fn git(cfg: &Configuration, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(&cfg.src_dir)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if output.status.success() {
+        Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+    } else {
+        None
+    }
+}
+
+// The version of the package at the root of the source tree, when there is one
+fn version(cfg: &Configuration) -> Option<String> {
+    let manifest = std::fs::canonicalize(cfg.src_dir.join("Cargo.toml")).ok()?;
+    crate::cargo::metadata(&cfg.cargo, &manifest)
+        .ok()?
+        .packages
+        .into_iter()
+        .find(|p| p.manifest_path == manifest)
+        .map(|p| p.version)
+}
+
+fn secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+// Written whether or not the build succeeded, for dashboards and release tooling; badge.json is
+// a shields.io endpoint for the same result
+pub fn write(
+    cfg: &Configuration,
+    command: &str,
+    timings: &[Timing],
+    success: bool,
+) -> std::io::Result<()> {
+    let now = SystemTime::now();
+    let started = now - cfg.epoch.elapsed();
+    let rustc = &cfg.rustc.version;
+    let mut toolchain = json!({
+        "rustc": format!("{} {}.{}.{}", rustc.prgname, rustc.major, rustc.minor, rustc.patch),
+        "cargo": version_of(&cfg.cargo),
+    });
+    for (name, tool) in [("linker", &cfg.linker), ("cc", &cfg.cc)] {
+        if let Some(tool) = tool {
+            toolchain[name] = json!(version_of(tool));
+        }
+    }
+    let profile = &cfg.profile;
+    let info = BuildInfo {
+        version: version(cfg),
+        autobuild_version: env!("CARGO_PKG_VERSION"),
+        git: git(cfg, &["rev-parse", "HEAD"]).map(|commit| Git {
+            commit,
+            dirty: git(cfg, &["status", "--porcelain"]).is_some_and(|s| !s.is_empty()),
+        }),
+        command: command.to_string(),
+        build: cfg.build.get_name().to_string(),
+        host: cfg.host.get_name().to_string(),
+        target: cfg.target.get_name().to_string(),
+        profile: json!({
+            "name": profile.name,
+            "cargo-profile": profile.cargo_profile,
+            "opt-level": profile.opt_level,
+            "debug": profile.debug,
+            "lto": profile.lto,
+        }),
+        toolchain,
+        started: crate::sbom::format_time(secs(started)),
+        finished: crate::sbom::format_time(secs(now)),
+        success,
+        steps: timings
+            .iter()
+            .map(|t| Step {
+                name: t.name.clone(),
+                status: t.status.clone(),
+                duration: t.duration,
+            })
+            .collect(),
+    };
+    let json = serde_json::to_vec_pretty(&info).map_err(std::io::Error::other)?;
+    std::fs::write(cfg.build_dir.join("build-info.json"), json)?;
+
+    let badge = json!({
+        "schemaVersion": 1,
+        "label": "build",
+        "message": if success { "passing" } else { "failing" },
+        "color": if success { "brightgreen" } else { "red" },
+    });
+    std::fs::write(cfg.build_dir.join("badge.json"), badge.to_string())
+}
@@ -0,0 +1,193 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::Deserialize;
+
+#[derive(Deserialize)]
+pub struct Metadata {
+    pub packages: Vec<Package>,
+    pub workspace_root: PathBuf,
+    pub target_directory: PathBuf,
+    #[serde(default)]
+    pub workspace_members: Vec<String>,
+    #[serde(default)]
+    pub resolve: Option<Resolve>,
+}
+
+#[derive(Deserialize)]
+pub struct Resolve {
+    pub nodes: Vec<Node>,
+}
+
+#[derive(Deserialize)]
+pub struct Node {
+    pub id: String,
+    pub deps: Vec<NodeDep>,
+    #[serde(default)]
+    pub features: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct NodeDep {
+    // The name the dependency is imported as, after any rename
+    #[serde(default)]
+    pub name: String,
+    pub pkg: String,
+    pub dep_kinds: Vec<DepKind>,
+}
+
+// `kind` is null for normal dependencies, "dev" or "build" otherwise
+#[derive(Deserialize)]
+pub struct DepKind {
+    pub kind: Option<String>,
+}
+
+// `kind` is null for normal dependencies here too
+#[derive(Deserialize)]
+pub struct Dependency {
+    pub name: String,
+    #[serde(default)]
+    pub kind: Option<String>,
+}
+
+fn edition() -> String {
+    "2015".to_string()
+}
+
+#[derive(Deserialize)]
+pub struct Package {
+    pub name: String,
+    pub version: String,
+    #[serde(default = "edition")]
+    pub edition: String,
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub license: Option<String>,
+    #[serde(default)]
+    pub links: Option<String>,
+    pub manifest_path: PathBuf,
+    pub targets: Vec<CrateTarget>,
+    #[serde(default)]
+    pub license_file: Option<PathBuf>,
+    #[serde(default)]
+    pub readme: Option<PathBuf>,
+    #[serde(default)]
+    pub repository: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub dependencies: Vec<Dependency>,
+    #[serde(default)]
+    pub features: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Deserialize)]
+pub struct CrateTarget {
+    pub name: String,
+    pub kind: Vec<String>,
+    pub crate_types: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+// A line of `--message-format json` output: an artifact that was built, or a compiler message
+#[derive(Deserialize)]
+pub struct BuildMessage {
+    pub reason: String,
+    #[serde(default)]
+    pub manifest_path: Option<PathBuf>,
+    #[serde(default)]
+    pub executable: Option<PathBuf>,
+    #[serde(default)]
+    pub target: Option<CrateTarget>,
+    #[serde(default)]
+    pub profile: Option<ArtifactProfile>,
+    #[serde(default)]
+    pub message: Option<Diagnostic>,
+}
+
+#[derive(Deserialize)]
+pub struct ArtifactProfile {
+    pub test: bool,
+}
+
+#[derive(Deserialize)]
+pub struct Diagnostic {
+    #[serde(default)]
+    pub rendered: Option<String>,
+}
+
+pub fn metadata(cargo: &Path, manifest: &Path) -> std::io::Result<Metadata> {
+    let output = Command::new(cargo)
+        .arg("metadata")
+        .arg("--no-deps")
+        .arg("--format-version")
+        .arg("1")
+        .arg("--manifest-path")
+        .arg(manifest)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "Cannot read metadata for {}",
+            manifest.display()
+        )));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+// Like `metadata`, but with every dependency and the resolved graph for `target`
+pub fn dependencies(cargo: &Path, manifest: &Path, target: &str) -> std::io::Result<Metadata> {
+    let output = Command::new(cargo)
+        .arg("metadata")
+        .arg("--format-version")
+        .arg("1")
+        .arg("--filter-platform")
+        .arg(target)
+        .arg("--manifest-path")
+        .arg(manifest)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()?;
+
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "Cannot read dependencies for {}",
+            manifest.display()
+        )));
+    }
+
+    serde_json::from_slice(&output.stdout)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+impl Package {
+    // The directories holding each target's sources. A target at the top of the package, like a
+    // build.rs beside Cargo.toml, is listed as the one file, since the directory it is in also
+    // holds target/ and the build directory
+    pub fn sources(&self) -> Vec<PathBuf> {
+        let root = self.manifest_path.parent();
+        let mut dirs: Vec<PathBuf> = Vec::new();
+        let mut files = Vec::new();
+        for target in &self.targets {
+            match target.src_path.parent() {
+                Some(dir) if Some(dir) == root => files.push(target.src_path.clone()),
+                Some(dir) if !dirs.iter().any(|d| dir.starts_with(d)) => {
+                    dirs.retain(|d| !d.starts_with(dir));
+                    dirs.push(dir.to_owned());
+                }
+                _ => {}
+            }
+        }
+        dirs.extend(files);
+        dirs
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    borrow::Cow,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    config::{Mode, Step},
+    configure::Configuration,
+    engine::Runner,
+    plan::{Plan, PlannedStep},
+};
+
+#[cfg(unix)]
+fn file_mode(path: &Path) -> std::io::Result<Option<u32>> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(Some(path.metadata()?.permissions().mode() & 0o777))
+}
+
+#[cfg(not(unix))]
+fn file_mode(_: &Path) -> std::io::Result<Option<u32>> {
+    Ok(None)
+}
+
+// Collects the files under `dir` changed since `since`, without following links or descending
+// into `skip`; unreadable directories are passed over since the real prefix is often not ours
+fn changed_since(dir: &Path, since: SystemTime, skip: &[&Path], out: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let path = entry.path();
+        if skip.iter().any(|s| path.starts_with(s)) {
+            continue;
+        }
+        let meta = match path.symlink_metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            changed_since(&path, since, skip, out);
+        } else if meta.modified().is_ok_and(|m| m >= since) {
+            out.push(path);
+        }
+    }
+}
+
+// The install directories that are not inside another one
+fn install_roots(cfg: &Configuration) -> Vec<&Path> {
+    let mut roots: Vec<&Path> = cfg.dirs.values().map(|p| &**p).collect();
+    roots.sort();
+    roots.dedup();
+    let all = roots.clone();
+    roots.retain(|r| !all.iter().any(|o| o != r && r.starts_with(o)));
+    roots
+}
+
+fn stage(
+    plan: &Plan,
+    cfg: &Configuration,
+    force: bool,
+    staging: &Path,
+) -> std::io::Result<Vec<String>> {
+    let manifest = cfg.build_dir.join(crate::uninstall::MANIFEST);
+    let mut problems = Vec::new();
+    let mut runner = Runner::new(cfg, force);
+    for step in &plan.steps {
+        let before = crate::uninstall::installed(&cfg.build_dir)?.len();
+        runner.run(&Plan {
+            steps: vec![PlannedStep {
+                name: step.name.clone(),
+                step: Cow::Borrowed(&*step.step),
+                deps: Vec::new(),
+            }],
+        })?;
+        let mode = match &*step.step {
+            Step::Install(install) => match install.mode {
+                Some(Mode::Octal(mode)) => Some(mode as u32 & 0o777),
+                _ => None,
+            },
+            _ => None,
+        };
+        for path in crate::uninstall::installed(&cfg.build_dir)?
+            .into_iter()
+            .skip(before)
+        {
+            let rel = match path.strip_prefix(staging) {
+                Ok(rel) => Path::new("/").join(rel),
+                Err(_) => {
+                    problems.push(format!(
+                        "{}: {} was installed outside the staging root",
+                        step.name,
+                        path.display()
+                    ));
+                    continue;
+                }
+            };
+            let meta = match path.symlink_metadata() {
+                Ok(meta) => meta,
+                Err(_) => {
+                    problems.push(format!(
+                        "{}: {} is in {} but was not installed",
+                        step.name,
+                        rel.display(),
+                        manifest.display()
+                    ));
+                    continue;
+                }
+            };
+            if !cfg.dirs.values().any(|dir| rel.starts_with(dir)) {
+                problems.push(format!(
+                    "{}: {} is outside every install directory",
+                    step.name,
+                    rel.display()
+                ));
+            }
+            if let (Some(mode), false) = (mode, meta.file_type().is_symlink()) {
+                if let Some(actual) = file_mode(&path)? {
+                    if actual != mode {
+                        problems.push(format!(
+                            "{}: {} has mode {:o} instead of {:o}",
+                            step.name,
+                            rel.display(),
+                            actual,
+                            mode
+                        ));
+                    }
+                }
+            }
+        }
+    }
+    Ok(problems)
+}
+
+// Installs into a throwaway DESTDIR, leaving the install manifest as it was
+pub fn check(plan: &Plan, cfg: &Configuration, force: bool) -> std::io::Result<()> {
+    let staging = cfg.build_dir.join("check-install");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+    let manifest = cfg.build_dir.join(crate::uninstall::MANIFEST);
+    let saved = std::fs::read(&manifest).ok();
+    let destdir = std::env::var_os("DESTDIR");
+    std::env::set_var("DESTDIR", &staging);
+    let start = SystemTime::now();
+
+    let result = stage(plan, cfg, force, &staging);
+
+    match destdir {
+        Some(destdir) => std::env::set_var("DESTDIR", destdir),
+        None => std::env::remove_var("DESTDIR"),
+    }
+    match saved {
+        Some(saved) => std::fs::write(&manifest, saved)?,
+        None if manifest.exists() => std::fs::remove_file(&manifest)?,
+        None => {}
+    }
+    let mut problems = result?;
+
+    let mut escaped = Vec::new();
+    for root in install_roots(cfg) {
+        changed_since(root, start, &[&cfg.build_dir, &cfg.src_dir], &mut escaped);
+    }
+    problems.extend(
+        escaped
+            .into_iter()
+            .map(|path| format!("{} was written outside the staging root", path.display())),
+    );
+    std::fs::remove_dir_all(&staging)?;
+
+    if problems.is_empty() {
+        println!("check-install: ok");
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("check-install: {}", problem);
+    }
+    Err(std::io::Error::other(format!(
+        "{} problem(s) found",
+        problems.len()
+    )))
+}
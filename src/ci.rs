@@ -0,0 +1,203 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use crate::{cli::Options, config::Config};
+
+const GENERATED: &str = "# Generated by cargo-autobuild ci generate";
+
+// The hosts of the matrix; none builds for the runner itself
+fn hosts<'a>(opts: &'a Options, config: &'a Config) -> Vec<&'a str> {
+    if let Some(host) = &opts.host {
+        vec![host]
+    } else if !opts.hosts.is_empty() {
+        opts.hosts.iter().map(|h| &**h).collect()
+    } else {
+        config.options.hosts.iter().map(|h| &**h).collect()
+    }
+}
+
+// Cross compiling to Windows or macOS needs an SDK a Linux runner lacks, so those hosts get a
+// runner of their own
+fn runner(host: &str) -> &'static str {
+    if host.contains("windows") {
+        "windows-latest"
+    } else if host.contains("apple") {
+        "macos-latest"
+    } else {
+        "ubuntu-latest"
+    }
+}
+
+// JSON strings are valid YAML scalars
+fn quote(s: &str) -> String {
+    serde_json::Value::from(s).to_string()
+}
+
+// The options every job passes, with the host given by the provider's matrix variable
+fn options(opts: &Options, host: Option<&str>) -> String {
+    let mut args = Vec::new();
+    let config_name = opts
+        .config
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    if !crate::config::FILE_NAMES.contains(&&*config_name) {
+        args.push(format!("--config={}", config_name));
+    }
+    if let Some(profile) = &opts.profile {
+        args.push(format!("--profile={}", profile));
+    }
+    if let Some(host) = host {
+        args.push(format!("--host={}", host));
+    }
+    args.iter().map(|a| format!(" {}", a)).collect()
+}
+
+// `check` when the configuration has a test phase, as it builds first
+fn check_command(config: &Config) -> &'static str {
+    if config.test.step.is_empty() {
+        "build"
+    } else {
+        "check"
+    }
+}
+
+fn github(opts: &Options, config: &Config) -> String {
+    let hosts = hosts(opts, config);
+    let host = if hosts.is_empty() {
+        None
+    } else {
+        Some("${{ matrix.host }}")
+    };
+    let args = options(opts, host);
+    let mut out = format!("{}\nname: autobuild\n\n", GENERATED);
+    out.push_str("on:\n  push:\n  pull_request:\n\njobs:\n  build:\n");
+    if hosts.is_empty() {
+        out.push_str("    runs-on: ubuntu-latest\n");
+    } else {
+        out.push_str("    strategy:\n      fail-fast: false\n      matrix:\n        include:\n");
+        for host in &hosts {
+            out.push_str(&format!(
+                "          - host: {}\n            os: {}\n",
+                quote(host),
+                runner(host)
+            ));
+        }
+        out.push_str("    runs-on: ${{ matrix.os }}\n");
+    }
+    if let Some(image) = &config.container.image {
+        out.push_str(&format!("    container: {}\n", quote(image)));
+    }
+    out.push_str("    steps:\n      - uses: actions/checkout@v4\n");
+    out.push_str("      - uses: dtolnay/rust-toolchain@stable\n");
+    if host.is_some() {
+        out.push_str("        with:\n          targets: ${{ matrix.host }}\n");
+    }
+    out.push_str("      - run: cargo install cargo-autobuild --locked\n");
+    out.push_str(&format!(
+        "      - run: cargo autobuild {}{}\n",
+        check_command(config),
+        args
+    ));
+    if !config.install.step.is_empty() {
+        let name = if host.is_some() {
+            "dist-${{ matrix.host }}"
+        } else {
+            "dist"
+        };
+        out.push_str(&format!("      - run: cargo autobuild install{}\n", args));
+        out.push_str("        env:\n          DESTDIR: ${{ github.workspace }}/dist\n");
+        out.push_str("      - uses: actions/upload-artifact@v4\n");
+        out.push_str(&format!(
+            "        with:\n          name: {}\n          path: dist\n",
+            name
+        ));
+    }
+    out
+}
+
+// Shared runners are Linux machines, so every host is cross compiled there
+fn gitlab(opts: &Options, config: &Config) -> String {
+    let hosts = hosts(opts, config);
+    let host = if hosts.is_empty() {
+        None
+    } else {
+        Some("$HOST")
+    };
+    let args = options(opts, host);
+    let image = config.container.image.as_deref().unwrap_or("rust:latest");
+    let mut out = format!("{}\nstages:\n  - build\n\nbuild:\n", GENERATED);
+    out.push_str(&format!("  stage: build\n  image: {}\n", quote(image)));
+    if !hosts.is_empty() {
+        out.push_str("  parallel:\n    matrix:\n      - HOST:\n");
+        for host in &hosts {
+            out.push_str(&format!("          - {}\n", quote(host)));
+        }
+    }
+    out.push_str("  before_script:\n");
+    if host.is_some() {
+        out.push_str("    - rustup target add $HOST\n");
+    }
+    out.push_str("    - cargo install cargo-autobuild --locked\n  script:\n");
+    out.push_str(&format!(
+        "    - cargo autobuild {}{}\n",
+        check_command(config),
+        args
+    ));
+    if !config.install.step.is_empty() {
+        out.push_str(&format!(
+            "    - DESTDIR=$CI_PROJECT_DIR/dist cargo autobuild install{}\n",
+            args
+        ));
+        let name = if host.is_some() { "dist-$HOST" } else { "dist" };
+        out.push_str(&format!(
+            "  artifacts:\n    name: {}\n    paths:\n      - dist/\n",
+            quote(name)
+        ));
+    }
+    out
+}
+
+// The pipeline is derived from the configuration alone, so nothing is configured or built
+pub fn ci(opts: &Options, config: &Config) -> std::io::Result<()> {
+    match opts.args.first().map(|s| &**s) {
+        Some("generate") => {}
+        _ => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Usage: cargo autobuild ci generate [--provider=github|gitlab]",
+            ))
+        }
+    }
+    let (text, file) = match opts.provider.as_deref().unwrap_or("github") {
+        "github" => (
+            github(opts, config),
+            PathBuf::from(".github/workflows/autobuild.yml"),
+        ),
+        "gitlab" => (gitlab(opts, config), PathBuf::from(".gitlab-ci.yml")),
+        provider => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown CI provider {}; use github or gitlab", provider),
+            ))
+        }
+    };
+
+    let path = opts.src_dir().join(file);
+    if let Ok(existing) = std::fs::read_to_string(&path) {
+        if !existing.starts_with(GENERATED) && !opts.force {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} was not generated by cargo-autobuild; pass --force to replace it",
+                    path.display()
+                ),
+            ));
+        }
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&path, text)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
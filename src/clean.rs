@@ -0,0 +1,69 @@
+use std::path::{Path, PathBuf};
+
+use crate::{
+    cli::Options,
+    config::{Config, Step},
+    state::BuildState,
+};
+
+fn remove(path: &Path, dry_run: bool) -> std::io::Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    if dry_run {
+        println!("would remove {}", path.display());
+        return Ok(());
+    }
+    println!("removing {}", path.display());
+    if path.is_dir() {
+        std::fs::remove_dir_all(path)
+    } else {
+        std::fs::remove_file(path)
+    }
+}
+
+pub fn clean(opts: &Options, config: &Config, dist: bool) -> std::io::Result<()> {
+    let src_dir = opts.src_dir();
+    let build_dir = crate::configure::build_dir(opts, src_dir);
+    let hosts = if opts.hosts.is_empty() {
+        &config.options.hosts
+    } else {
+        &opts.hosts
+    };
+    let mut build_dirs = vec![build_dir.clone()];
+    build_dirs.extend(hosts.iter().map(|host| build_dir.join(host)));
+
+    let mut paths: Vec<PathBuf> = Vec::new();
+    for dir in &build_dirs {
+        paths.extend(BuildState::load(dir).outputs().cloned());
+        for name in &["tmp", "res", "pgo", "pgo.profdata", "pgo-target"] {
+            paths.push(dir.join(name));
+        }
+    }
+    if dist {
+        paths.extend(
+            config
+                .build
+                .step
+                .iter()
+                .chain(&config.install.step)
+                .filter_map(|s| match s {
+                    Step::ConfigureFile(s) => Some(src_dir.join(&s.base)),
+                    _ => None,
+                }),
+        );
+        paths.push(build_dir.clone());
+    }
+    paths.sort();
+    paths.dedup();
+
+    for path in &paths {
+        remove(path, opts.dry_run)?;
+    }
+    if !dist && !opts.dry_run {
+        for dir in build_dirs.iter().filter(|dir| dir.is_dir()) {
+            BuildState::default().save(dir)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::config::Isolation;
+
+#[derive(Clone)]
+pub struct Options {
+    pub command: String,
+    pub config: PathBuf,
+    pub build: Option<String>,
+    pub host: Option<String>,
+    pub hosts: Vec<String>,
+    pub target: Option<String>,
+    // dev unless given
+    pub profile: Option<String>,
+    pub sanitizers: Option<Vec<String>>,
+    pub target_cpu: Option<String>,
+    pub target_features: Option<Vec<String>>,
+    pub rustc_wrapper: Option<PathBuf>,
+    // Appended after the flags of the configuration file
+    pub rustflags: Vec<String>,
+    pub host_rustflags: Vec<String>,
+    pub build_script_rustflags: Vec<String>,
+    pub rustdocflags: Vec<String>,
+    pub dirs: HashMap<String, PathBuf>,
+    pub features: HashMap<String, bool>,
+    // --with-NAME[=VALUE] and --without-NAME, which only feature expressions read
+    pub packages: HashMap<String, String>,
+    pub force: bool,
+    pub keep_going: bool,
+    pub dry_run: bool,
+    pub hermetic: bool,
+    pub offline: bool,
+    pub static_link: bool,
+    pub hardened: bool,
+    pub interactive: bool,
+    pub no_color: bool,
+    pub from_cargo: bool,
+    pub timings: Option<String>,
+    pub format: String,
+    pub only: Vec<String>,
+    pub skip: Vec<String>,
+    pub until: Option<String>,
+    pub build_dir: Option<PathBuf>,
+    pub target_dir: Option<PathBuf>,
+    pub isolate: Option<Vec<Isolation>>,
+    pub scratch_dir: Option<PathBuf>,
+    // In place of the workers of the configuration; empty runs every step here
+    pub workers: Option<Vec<String>>,
+    // The image to run in; empty for the one in the configuration
+    pub in_container: Option<String>,
+    // For `ci generate`; github when not given
+    pub provider: Option<String>,
+    pub args: Vec<String>,
+}
+
+const DIR_OPTIONS: &[&str] = &[
+    "prefix",
+    "exec-prefix",
+    "bindir",
+    "sbindir",
+    "libexecdir",
+    "libdir",
+    "includedir",
+    "datarootdir",
+    "datadir",
+    "docdir",
+    "infodir",
+    "mandir",
+    "htmldir",
+    "pdfdir",
+    "dvidir",
+    "localedir",
+    "localstatedir",
+    "sharedstatedir",
+    "runstatedir",
+    "sysconfdir",
+];
+
+impl Options {
+    pub fn src_dir(&self) -> &Path {
+        self.config
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new("."))
+    }
+}
+
+fn usage_error(msg: String) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidInput, msg)
+}
+
+pub fn parse(args: impl IntoIterator<Item = String>) -> std::io::Result<Options> {
+    let mut args = args.into_iter().skip(1).peekable();
+
+    // Invoked as `cargo autobuild ...`
+    if args.peek().map(|s| &**s) == Some("autobuild") {
+        args.next();
+    }
+
+    let mut opts = Options {
+        command: String::new(),
+        config: PathBuf::from("autobuild.toml"),
+        build: None,
+        host: None,
+        hosts: Vec::new(),
+        target: None,
+        profile: None,
+        sanitizers: None,
+        target_cpu: None,
+        target_features: None,
+        rustc_wrapper: None,
+        rustflags: Vec::new(),
+        host_rustflags: Vec::new(),
+        build_script_rustflags: Vec::new(),
+        rustdocflags: Vec::new(),
+        dirs: HashMap::new(),
+        features: HashMap::new(),
+        packages: HashMap::new(),
+        force: false,
+        keep_going: false,
+        dry_run: false,
+        hermetic: false,
+        offline: false,
+        static_link: false,
+        hardened: false,
+        interactive: false,
+        no_color: false,
+        from_cargo: false,
+        timings: None,
+        format: "dot".to_string(),
+        only: Vec::new(),
+        skip: Vec::new(),
+        until: None,
+        build_dir: None,
+        target_dir: None,
+        isolate: None,
+        scratch_dir: None,
+        workers: None,
+        in_container: None,
+        provider: None,
+        args: Vec::new(),
+    };
+
+    let mut config_given = false;
+    while let Some(arg) = args.next() {
+        if let Some(flag) = arg.strip_prefix("--") {
+            let (name, mut inline) = match flag.find('=') {
+                Some(n) => (&flag[..n], Some(flag[n + 1..].to_string())),
+                None => (flag, None),
+            };
+            let mut value = |name: &str| {
+                inline
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| usage_error(format!("Option --{} requires a value", name)))
+            };
+            match name {
+                "config" => {
+                    opts.config = PathBuf::from(value(name)?);
+                    config_given = true;
+                }
+                "build" => opts.build = Some(value(name)?),
+                "host" => opts
+                    .hosts
+                    .extend(value(name)?.split(',').map(str::to_string)),
+                "target" => {
+                    let target = value(name)?;
+                    // As in autoconf, --target is what a compiler being built generates code
+                    // for; the platforms to build for, one or several, are given by --host
+                    if opts.target.is_some() || target.contains(',') {
+                        return Err(usage_error(
+                            "--target takes one triple; build for several platforms with --host a,b"
+                                .to_string(),
+                        ));
+                    }
+                    opts.target = Some(target);
+                }
+                "profile" => opts.profile = Some(value(name)?),
+                "sanitizer" => {
+                    opts.sanitizers = Some(
+                        value(name)?
+                            .split(',')
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                }
+                "force" => opts.force = true,
+                "keep-going" => opts.keep_going = true,
+                "dry-run" => opts.dry_run = true,
+                "hermetic" => opts.hermetic = true,
+                "offline" => opts.offline = true,
+                "static" => opts.static_link = true,
+                "hardened" => opts.hardened = true,
+                "with-cpu" => opts.target_cpu = Some(value(name)?),
+                "with-target-features" => {
+                    opts.target_features = Some(
+                        value(name)?
+                            .split(',')
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                }
+                "rustc-wrapper" => opts.rustc_wrapper = Some(PathBuf::from(value(name)?)),
+                "rustflags" => opts
+                    .rustflags
+                    .extend(value(name)?.split_whitespace().map(str::to_string)),
+                "host-rustflags" => opts
+                    .host_rustflags
+                    .extend(value(name)?.split_whitespace().map(str::to_string)),
+                "build-script-rustflags" => opts
+                    .build_script_rustflags
+                    .extend(value(name)?.split_whitespace().map(str::to_string)),
+                "rustdocflags" => opts
+                    .rustdocflags
+                    .extend(value(name)?.split_whitespace().map(str::to_string)),
+                "only" => opts
+                    .only
+                    .extend(value(name)?.split(',').map(str::to_string)),
+                "skip" => opts
+                    .skip
+                    .extend(value(name)?.split(',').map(str::to_string)),
+                "until" => opts.until = Some(value(name)?),
+                "format" => opts.format = value(name)?,
+                "build-dir" => opts.build_dir = Some(PathBuf::from(value(name)?)),
+                "target-dir" => opts.target_dir = Some(PathBuf::from(value(name)?)),
+                "scratch-dir" => opts.scratch_dir = Some(PathBuf::from(value(name)?)),
+                "isolate" => {
+                    let mut isolate = Vec::new();
+                    for kind in value(name)?.split(',').filter(|s| !s.is_empty()) {
+                        isolate.push(match kind {
+                            "triple" => Isolation::Triple,
+                            "profile" => Isolation::Profile,
+                            kind => {
+                                return Err(usage_error(format!(
+                                    "Unknown --isolate value {}",
+                                    kind
+                                )))
+                            }
+                        });
+                    }
+                    opts.isolate = Some(isolate);
+                }
+                "workers" => {
+                    opts.workers = Some(
+                        value(name)?
+                            .split(',')
+                            .filter(|s| !s.is_empty())
+                            .map(str::to_string)
+                            .collect(),
+                    )
+                }
+                "provider" => opts.provider = Some(value(name)?),
+                "in-container" => opts.in_container = Some(inline.take().unwrap_or_default()),
+                "interactive" => opts.interactive = true,
+                "no-color" => opts.no_color = true,
+                "from-cargo" => opts.from_cargo = true,
+                "timings" => {
+                    opts.timings = Some(inline.take().unwrap_or_else(|| "html,json".to_string()))
+                }
+                name if DIR_OPTIONS.contains(&name) => {
+                    opts.dirs
+                        .insert(name.to_string(), PathBuf::from(value(name)?));
+                }
+                name => {
+                    if let Some(feature) = name.strip_prefix("enable-") {
+                        opts.features.insert(feature.to_string(), true);
+                    } else if let Some(feature) = name.strip_prefix("disable-") {
+                        opts.features.insert(feature.to_string(), false);
+                    } else if let Some(package) = name.strip_prefix("with-") {
+                        let value = inline.take().unwrap_or_else(|| "yes".to_string());
+                        opts.packages.insert(package.to_string(), value);
+                    } else if let Some(package) = name.strip_prefix("without-") {
+                        opts.packages.insert(package.to_string(), "no".to_string());
+                    } else {
+                        return Err(usage_error(format!("Unknown option --{}", name)));
+                    }
+                }
+            }
+        } else if opts.command.is_empty() {
+            opts.command = arg;
+        } else {
+            opts.args.push(arg);
+        }
+    }
+
+    if opts.command.is_empty() {
+        opts.command = "build".to_string();
+    }
+
+    if let [host] = &*opts.hosts {
+        opts.host = Some(host.clone());
+        opts.hosts.clear();
+    }
+
+    if !config_given {
+        if let Some(name) = crate::config::FILE_NAMES
+            .iter()
+            .find(|name| Path::new(name).is_file())
+        {
+            opts.config = PathBuf::from(name);
+        }
+    }
+
+    Ok(opts)
+}
@@ -0,0 +1,133 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{CbindgenStep, InstallDirectory, InstallTarget, PkgConfigStep},
+    configure::Configuration,
+    steps::{manifest_at, substitute},
+};
+
+pub fn header(step: &CbindgenStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(&step.output)
+}
+
+fn cbindgen_config(step: &CbindgenStep, cfg: &Configuration) -> Option<PathBuf> {
+    match &step.config {
+        Some(config) => Some(cfg.src_dir.join(substitute(&config.to_string_lossy(), cfg))),
+        None => Some(cfg.src_dir.join(&step.path).join("cbindgen.toml")).filter(|c| c.is_file()),
+    }
+}
+
+pub fn cbindgen_inputs(step: &CbindgenStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let manifest = manifest_at(&step.path, cfg);
+    let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    let mut inputs = vec![manifest];
+    for package in &metadata.packages {
+        inputs.extend(package.sources());
+    }
+    inputs.extend(cbindgen_config(step, cfg));
+    Ok(inputs)
+}
+
+pub fn cbindgen(step: &CbindgenStep, cfg: &Configuration) -> std::io::Result<()> {
+    let cbindgen = cfg
+        .cbindgen
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "No cbindgen was configured"))?;
+    let output = header(step, cfg);
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut cmd = Command::new(cbindgen);
+    if let Some(config) = cbindgen_config(step, cfg) {
+        cmd.arg("--config").arg(config);
+    }
+    if let Some(lang) = &step.lang {
+        cmd.arg("--lang").arg(lang);
+    }
+    cmd.arg("--output")
+        .arg(&output)
+        .arg(cfg.src_dir.join(&step.path));
+    crate::steps::run(cmd.env("CARGO", &cfg.cargo))
+}
+
+pub fn pc_file(step: &PkgConfigStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(format!("{}.pc", step.name))
+}
+
+fn version(step: &PkgConfigStep, cfg: &Configuration) -> std::io::Result<String> {
+    if let Some(version) = &step.version {
+        return Ok(substitute(version, cfg));
+    }
+    let manifest = manifest_at(&step.path, cfg);
+    let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    let package = metadata
+        .packages
+        .iter()
+        .find(|p| p.manifest_path == manifest)
+        .or_else(|| metadata.packages.first())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{} has no package to take a version from",
+                    manifest.display()
+                ),
+            )
+        })?;
+    Ok(package.version.clone())
+}
+
+// Directories under the prefix are written relative to it, so the file survives a relocated
+// install
+fn pc_dir(dir: InstallDirectory, prefix: &Path, cfg: &Configuration) -> std::io::Result<String> {
+    let path = cfg.install_path(&InstallTarget::Base {
+        base: dir,
+        path: PathBuf::new(),
+    })?;
+    Ok(match path.strip_prefix(prefix) {
+        Ok(rel) => format!("${{prefix}}/{}", rel.display()),
+        Err(_) => path.display().to_string(),
+    })
+}
+
+pub fn pkg_config(step: &PkgConfigStep, cfg: &Configuration) -> std::io::Result<()> {
+    let prefix = cfg.install_path(&InstallTarget::Base {
+        base: InstallDirectory::Prefix,
+        path: PathBuf::new(),
+    })?;
+    let mut text = format!(
+        "prefix={}\nlibdir={}\nincludedir={}\n\n",
+        prefix.display(),
+        pc_dir(InstallDirectory::LibDir, &prefix, cfg)?,
+        pc_dir(InstallDirectory::IncludeDir, &prefix, cfg)?
+    );
+    text.push_str(&format!("Name: {}\n", step.name));
+    text.push_str(&format!("Description: {}\n", step.description));
+    text.push_str(&format!("Version: {}\n", version(step, cfg)?));
+    let fields = [
+        ("Requires", step.requires.join(", ")),
+        ("Requires.private", step.requires_private.join(", ")),
+        ("Libs", format!("-L${{libdir}} {}", step.libs.join(" "))),
+        ("Libs.private", step.libs_private.join(" ")),
+        (
+            "Cflags",
+            format!("-I${{includedir}} {}", step.cflags.join(" ")),
+        ),
+    ];
+    for (field, value) in &fields {
+        let value = value.trim();
+        if !value.is_empty() {
+            text.push_str(&format!("{}: {}\n", field, substitute(value, cfg)));
+        }
+    }
+
+    let output = pc_file(step, cfg);
+    crate::steps::note(&format!("Writing {}", output.display()));
+    std::fs::create_dir_all(&cfg.build_dir)?;
+    std::fs::write(output, text)
+}
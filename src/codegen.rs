@@ -0,0 +1,164 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{FlatcStep, GenerateParserStep, ParserGenerator, ProtocStep},
+    configure::Configuration,
+    steps::substitute,
+};
+
+fn paths<'a>(paths: impl Iterator<Item = &'a PathBuf>, cfg: &Configuration) -> Vec<PathBuf> {
+    paths
+        .map(|p| cfg.src_dir.join(substitute(&p.to_string_lossy(), cfg)))
+        .collect()
+}
+
+pub fn output(output: &Path, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(output)
+}
+
+pub fn protoc_inputs(step: &ProtocStep, cfg: &Configuration) -> Vec<PathBuf> {
+    paths(step.schemas.iter().chain(&step.include_dirs), cfg)
+}
+
+pub fn flatc_inputs(step: &FlatcStep, cfg: &Configuration) -> Vec<PathBuf> {
+    paths(step.schemas.iter().chain(&step.include_dirs), cfg)
+}
+
+fn output_dir(output: &Path, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    let dir = self::output(output, cfg);
+    std::fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+pub fn protoc(step: &ProtocStep, cfg: &Configuration) -> std::io::Result<()> {
+    let protoc = cfg
+        .protoc
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "No protoc was configured"))?;
+    let output = output_dir(&step.output, cfg)?;
+
+    let mut cmd = Command::new(protoc);
+    for dir in paths(step.include_dirs.iter(), cfg) {
+        cmd.arg(format!("-I{}", dir.display()));
+    }
+    for plugin in &step.plugins {
+        if let Some(path) = cfg.protoc_plugins.get(&plugin.name) {
+            cmd.arg(format!(
+                "--plugin=protoc-gen-{}={}",
+                plugin.name,
+                path.display()
+            ));
+        }
+        cmd.arg(format!("--{}_out={}", plugin.name, output.display()));
+        if !plugin.options.is_empty() {
+            cmd.arg(format!(
+                "--{}_opt={}",
+                plugin.name,
+                plugin.options.join(",")
+            ));
+        }
+    }
+    cmd.args(&step.args).args(paths(step.schemas.iter(), cfg));
+    crate::steps::run(&mut cmd)
+}
+
+pub fn flatc(step: &FlatcStep, cfg: &Configuration) -> std::io::Result<()> {
+    let flatc = cfg
+        .flatc
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "No flatc was configured"))?;
+    let output = output_dir(&step.output, cfg)?;
+
+    let mut cmd = Command::new(flatc);
+    cmd.arg("--rust").arg("-o").arg(&output);
+    for dir in paths(step.include_dirs.iter(), cfg) {
+        cmd.arg("-I").arg(dir);
+    }
+    cmd.args(&step.args).args(paths(step.schemas.iter(), cfg));
+    crate::steps::run(&mut cmd)
+}
+
+pub fn parser_input(step: &GenerateParserStep, cfg: &Configuration) -> PathBuf {
+    cfg.src_dir
+        .join(substitute(&step.input.to_string_lossy(), cfg))
+}
+
+fn is_named(tool: &Path, name: &str) -> bool {
+    tool.file_stem()
+        .is_some_and(|n| n.to_string_lossy().contains(name))
+}
+
+// A [programs] entry gets `args` with @input@ and @output@ filled in; yacc and lex get the flags
+// that bison, byacc and flex understand
+pub fn generate_parser(step: &GenerateParserStep, cfg: &Configuration) -> std::io::Result<()> {
+    let input = parser_input(step, cfg);
+    let output = self::output(&step.output, cfg);
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let header = step.header.as_ref().map(|h| self::output(h, cfg));
+
+    if let Some(key) = &step.program {
+        let program = cfg.programs.get(key).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("No program is configured as {}", key),
+            )
+        })?;
+        let default_args = [
+            "-o".to_string(),
+            "@output@".to_string(),
+            "@input@".to_string(),
+        ];
+        let args = if step.args.is_empty() {
+            &default_args[..]
+        } else {
+            &step.args[..]
+        };
+        let mut cmd = Command::new(program);
+        for arg in args {
+            let arg = arg
+                .replace("@input@", &input.to_string_lossy())
+                .replace("@output@", &output.to_string_lossy());
+            cmd.arg(substitute(&arg, cfg));
+        }
+        return crate::steps::run(&mut cmd);
+    }
+
+    let (tool, name) = match step.generator {
+        ParserGenerator::Yacc => (&cfg.yacc, "yacc"),
+        ParserGenerator::Lex => (&cfg.lex, "lex"),
+    };
+    let tool = tool.as_ref().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, format!("No {} was configured", name))
+    })?;
+    let mut cmd = Command::new(tool);
+    match step.generator {
+        ParserGenerator::Yacc => {
+            if let Some(header) = &header {
+                if is_named(tool, "bison") {
+                    cmd.arg(format!("--defines={}", header.display()));
+                } else {
+                    cmd.arg("-H").arg(header);
+                }
+            }
+            cmd.arg("-o").arg(&output);
+        }
+        ParserGenerator::Lex if is_named(tool, "flex") => {
+            if let Some(header) = &header {
+                cmd.arg(format!("--header-file={}", header.display()));
+            }
+            cmd.arg("-o").arg(&output);
+        }
+        // POSIX lex only writes to stdout
+        ParserGenerator::Lex => {
+            cmd.arg("-t").stdout(std::fs::File::create(&output)?);
+        }
+    }
+    cmd.args(&step.args).arg(&input);
+    crate::steps::run(&mut cmd)
+}
@@ -0,0 +1,444 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use target_tuples::Target;
+
+use crate::{
+    cargo::{CrateTarget, Package},
+    config::BuildTarget,
+    configure::Configuration,
+    tests::rustc::{RustcTargetInfo, RustcTestsResult, RustcVersion},
+};
+
+// gccrs refuses to compile anything without it
+const GCCRS_EXPERIMENTAL: &str = "-frust-incomplete-and-experimental-compiler-do-not-use";
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Flavor {
+    Rustc,
+    Lccc,
+    Gccrs,
+    Mrustc,
+}
+
+impl Flavor {
+    pub fn of(version: &RustcVersion) -> Flavor {
+        match &*version.prgname {
+            "lcrustc" => Flavor::Lccc,
+            "mrust" => Flavor::Mrustc,
+            name if name.ends_with("gccrs") => Flavor::Gccrs,
+            _ => Flavor::Rustc,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Flavor::Rustc => "rustc",
+            Flavor::Lccc => "lccc",
+            Flavor::Gccrs => "gccrs",
+            Flavor::Mrustc => "mrustc",
+        }
+    }
+
+    // cargo can only drive compilers that take rustc's command line
+    pub fn uses_cargo(self) -> bool {
+        self.accepts_rustc_flags()
+    }
+
+    pub fn accepts_rustc_flags(self) -> bool {
+        matches!(self, Flavor::Rustc | Flavor::Lccc)
+    }
+
+    pub fn supports_crate_type(self, kind: &str) -> bool {
+        match self {
+            Flavor::Rustc | Flavor::Lccc => true,
+            Flavor::Gccrs => matches!(kind, "bin" | "lib" | "rlib" | "staticlib"),
+            Flavor::Mrustc => matches!(kind, "bin" | "lib" | "rlib" | "proc-macro"),
+        }
+    }
+
+    // Editions are not probed for compilers that lack `--edition`
+    pub fn max_edition(self) -> &'static str {
+        match self {
+            Flavor::Mrustc => "2018",
+            _ => "2021",
+        }
+    }
+
+    // The options that compile one crate of the given type to `output`
+    pub fn compile_args(self, name: &str, crate_type: &str, output: &Path) -> Vec<OsString> {
+        let mut args: Vec<OsString> = match self {
+            Flavor::Rustc | Flavor::Lccc => vec![
+                "--crate-type".into(),
+                crate_type.into(),
+                "--crate-name".into(),
+                name.into(),
+                "--emit".into(),
+                format!("link={}", output.display()).into(),
+            ],
+            Flavor::Gccrs => vec![
+                GCCRS_EXPERIMENTAL.into(),
+                format!("-frust-crate={}", name).into(),
+                format!("-frust-crate-type={}", crate_type).into(),
+                "-o".into(),
+            ],
+            Flavor::Mrustc => vec![
+                "--crate-type".into(),
+                crate_type.into(),
+                "--crate-name".into(),
+                name.into(),
+                "-o".into(),
+            ],
+        };
+        if !self.accepts_rustc_flags() {
+            args.push(output.into());
+        }
+        args
+    }
+
+    // Returns None for flags the compiler has no equivalent of
+    pub fn translate(self, flag: &str) -> Option<Vec<String>> {
+        if self.accepts_rustc_flags() {
+            return Some(vec![flag.to_string()]);
+        }
+        if flag == "-g" {
+            return Some(vec![flag.to_string()]);
+        }
+        let option = flag.strip_prefix("-C")?.trim_start();
+        let (option, value) = match option.find('=') {
+            Some(i) => (&option[..i], &option[i + 1..]),
+            None => (option, ""),
+        };
+        match (self, option) {
+            (Flavor::Gccrs, "opt-level") => Some(vec![match value {
+                "s" | "z" => "-Os".to_string(),
+                level => format!("-O{}", level),
+            }]),
+            (Flavor::Mrustc, "opt-level") if value == "0" => Some(Vec::new()),
+            (Flavor::Mrustc, "opt-level") => Some(vec!["-O".to_string()]),
+            (_, "debuginfo") if value == "0" => Some(Vec::new()),
+            (_, "debuginfo") => Some(vec!["-g".to_string()]),
+            (Flavor::Gccrs, "target-cpu") => Some(vec![format!("-march={}", value)]),
+            (Flavor::Gccrs, "target-feature") => Some(
+                value
+                    .split(',')
+                    .filter_map(|f| match f.split_at(f.len().min(1)) {
+                        ("+", "crt-static") => Some("-static".to_string()),
+                        ("+", feature) => Some(format!("-m{}", feature)),
+                        ("-", feature) => Some(format!("-mno-{}", feature)),
+                        _ => None,
+                    })
+                    .collect(),
+            ),
+            // gccrs is its own linker driver, so link arguments go to it unchanged
+            (Flavor::Gccrs, "link-arg") => Some(vec![value.to_string()]),
+            (Flavor::Gccrs, "link-args") => {
+                Some(value.split_whitespace().map(String::from).collect())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn translate_all<'a, I: IntoIterator<Item = &'a str>>(self, flags: I) -> Vec<String> {
+        let mut translated = Vec::new();
+        let mut flags = flags.into_iter().filter(|f| !f.is_empty());
+        while let Some(flag) = flags.next() {
+            let flag = match flag {
+                "-C" | "--cfg" => format!("{} {}", flag, flags.next().unwrap_or_default()),
+                flag => flag.to_string(),
+            };
+            if self.accepts_rustc_flags() {
+                translated.extend(flag.split(' ').map(String::from));
+                continue;
+            }
+            if let Some(cfg) = flag.strip_prefix("--cfg ") {
+                translated.push(match self {
+                    Flavor::Gccrs => format!("-frust-cfg={}", cfg),
+                    _ => format!("--cfg={}", cfg),
+                });
+                continue;
+            }
+            match self.translate(&flag) {
+                Some(flags) => translated.extend(flags),
+                None => eprintln!(
+                    "cargo-autobuild: warning: {} has no equivalent of {}; it is left out",
+                    self.name(),
+                    flag
+                ),
+            }
+        }
+        translated
+    }
+}
+
+// Compilers without `--print file-names` follow the platform's usual naming
+pub fn target_info(target: &Target) -> RustcTargetInfo {
+    let name = target.get_name();
+    let (exe, dylib_prefix, dylib) = if name.contains("windows") {
+        (".exe", "", ".dll")
+    } else if name.contains("apple") {
+        ("", "lib", ".dylib")
+    } else {
+        ("", "lib", ".so")
+    };
+    RustcTargetInfo {
+        target: name.to_string(),
+        exe_suffix: exe.into(),
+        rlib_prefix: "lib".into(),
+        rlib_suffix: ".rlib".into(),
+        dylib_prefix: dylib_prefix.into(),
+        dylib_suffix: dylib.into(),
+        staticlib_prefix: "lib".into(),
+        staticlib_suffix: ".a".into(),
+        cdylib_prefix: dylib_prefix.into(),
+        cdylib_suffix: dylib.into(),
+        procmacro_prefix: dylib_prefix.into(),
+        procmacro_suffix: dylib.into(),
+    }
+}
+
+pub fn output_dir(rustc: &RustcTestsResult, cfg: &Configuration) -> PathBuf {
+    let flavor = Flavor::of(&rustc.version);
+    cfg.build_dir
+        .join(flavor.name())
+        .join(cfg.profile.target_subdir())
+}
+
+fn buildable(target: &CrateTarget) -> bool {
+    !target
+        .kind
+        .iter()
+        .any(|k| matches!(&**k, "example" | "test" | "bench" | "custom-build"))
+}
+
+fn check(package: &Package, flavor: Flavor) -> std::io::Result<()> {
+    let unsupported = |what: String| {
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!("{} cannot build {}", flavor.name(), what),
+        ))
+    };
+    for target in &package.targets {
+        if flavor == Flavor::Gccrs && target.kind.iter().any(|k| k == "custom-build") {
+            return unsupported(format!("the build script of {}", package.name));
+        }
+        if !buildable(target) {
+            continue;
+        }
+        if let Some(kind) = target
+            .crate_types
+            .iter()
+            .find(|k| !flavor.supports_crate_type(k))
+        {
+            return unsupported(format!(
+                "{} crates ({}/{})",
+                kind, package.name, target.name
+            ));
+        }
+    }
+    let deps = package
+        .dependencies
+        .iter()
+        .filter(|d| d.kind.as_deref() != Some("dev"))
+        .count();
+    if flavor == Flavor::Gccrs && deps != 0 {
+        return unsupported(format!(
+            "{}, which has dependencies that only cargo can resolve",
+            package.name
+        ));
+    }
+    Ok(())
+}
+
+// Features named by the step and everything they enable within the package, starting from the
+// defaults as cargo does unless they were turned off
+fn enabled_features(package: &Package, requested: &[String], defaults: bool) -> Vec<String> {
+    let mut pending: Vec<&str> = requested
+        .iter()
+        .filter(|f| !f.contains('/'))
+        .map(|f| &**f)
+        .collect();
+    if defaults {
+        pending.push("default");
+    }
+    let mut enabled = Vec::new();
+    while let Some(feature) = pending.pop() {
+        if enabled.iter().any(|f| f == feature) {
+            continue;
+        }
+        match package.features.get(feature) {
+            Some(implied) => pending.extend(
+                implied
+                    .iter()
+                    .filter(|f| !f.contains('/') && !f.starts_with("dep:"))
+                    .map(|f| &**f),
+            ),
+            None if feature == "default" => continue,
+            None => {}
+        }
+        enabled.push(feature.to_string());
+    }
+    enabled.sort();
+    enabled
+}
+
+fn gccrs(
+    package: &Package,
+    dir: &Path,
+    enabled: &[String],
+    rustflags: &[String],
+    env: &HashMap<String, String>,
+    rustc: &RustcTestsResult,
+    cfg: &Configuration,
+) -> std::io::Result<()> {
+    let opt_level = format!("-Copt-level={}", cfg.profile.opt_level);
+    let mut flags = Flavor::Gccrs.translate_all(
+        std::iter::once(&*opt_level)
+            .chain(if cfg.profile.debug { Some("-g") } else { None })
+            .chain(cfg.profile.rustflags.iter().map(|f| &**f))
+            .chain(rustflags.iter().map(|f| &**f)),
+    );
+    flags.push(format!("-frust-edition={}", package.edition));
+    flags.extend(
+        enabled
+            .iter()
+            .map(|f| format!("-frust-cfg=feature=\"{}\"", f)),
+    );
+    let manifest_dir = package
+        .manifest_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+
+    // The library goes first, so that the binaries find it in `dir`
+    let mut targets: Vec<&CrateTarget> = package.targets.iter().filter(|t| buildable(t)).collect();
+    targets.sort_by_key(|t| t.kind.iter().any(|k| k == "bin"));
+    for target in targets {
+        let name = target.name.replace('-', "_");
+        for artifact in crate::steps::target_artifacts(package, target, dir, &rustc.target_info) {
+            let mut cmd = Command::new(&rustc.rustc);
+            cmd.args(rustc.rustflags.iter().filter(|f| !f.is_empty()))
+                .args(&flags)
+                .arg("-L")
+                .arg(dir)
+                .args(Flavor::Gccrs.compile_args(&name, &artifact.kind, &artifact.path))
+                .arg(&target.src_path)
+                .envs(&cfg.toolchain_env)
+                .envs(&cfg.build_env)
+                .env("CARGO_PKG_NAME", &package.name)
+                .env("CARGO_PKG_VERSION", &package.version)
+                .env("CARGO_MANIFEST_DIR", manifest_dir)
+                .env("CARGO_CRATE_NAME", &name)
+                .envs(env);
+            crate::steps::run(&mut cmd)?;
+        }
+    }
+    Ok(())
+}
+
+fn minicargo_path(rustc: &RustcTestsResult) -> std::io::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("MINICARGO") {
+        return Ok(PathBuf::from(path));
+    }
+    let sibling = rustc.rustc.with_file_name("minicargo");
+    if sibling.is_file() {
+        return Ok(sibling);
+    }
+    crate::tests::which_any(&["minicargo"])
+}
+
+// minicargo reads the manifest itself and resolves dependencies from a vendor directory
+fn minicargo(
+    package: &Package,
+    dir: &Path,
+    features: &[String],
+    rustflags: &[String],
+    env: &HashMap<String, String>,
+    rustc: &RustcTestsResult,
+    cfg: &Configuration,
+) -> std::io::Result<()> {
+    if !rustflags.is_empty() || !cfg.profile.rustflags.is_empty() {
+        eprintln!(
+            "cargo-autobuild: warning: minicargo takes no compiler flags; the profile and step \
+             rustflags are left out"
+        );
+    }
+    let mut cmd = Command::new(minicargo_path(rustc)?);
+    cmd.arg(
+        package
+            .manifest_path
+            .parent()
+            .unwrap_or_else(|| Path::new(".")),
+    )
+    .arg("--output-dir")
+    .arg(dir)
+    .env("MRUSTC_PATH", &rustc.rustc)
+    .envs(&cfg.toolchain_env)
+    .envs(&cfg.build_env)
+    .envs(env);
+    let vendor = cfg.src_dir.join("vendor");
+    if vendor.is_dir() {
+        cmd.arg("--vendor-dir").arg(vendor);
+    }
+    let features: Vec<&str> = features
+        .iter()
+        .filter(|f| !f.contains('/'))
+        .map(|f| &**f)
+        .collect();
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features.join(","));
+    }
+    crate::steps::run(&mut cmd)
+}
+
+// Builds without cargo, for compilers it cannot drive
+pub fn build(
+    packages: &[&Package],
+    target: &BuildTarget,
+    features: &[String],
+    defaults: bool,
+    rustflags: &[String],
+    env: &HashMap<String, String>,
+    cfg: &Configuration,
+) -> std::io::Result<()> {
+    let features = &crate::steps::resolve_features(features, cfg);
+    let rustc = cfg.rustc_for(target);
+    let flavor = Flavor::of(&rustc.version);
+    let triple = cfg.resolve_target(target).get_name();
+    if cfg.spec_for(target).is_some() || rustc.target_info.target != triple {
+        return Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "{} only builds for {}, the target it was configured for",
+                flavor.name(),
+                rustc.target_info.target
+            ),
+        ));
+    }
+    let dir = output_dir(rustc, cfg);
+    std::fs::create_dir_all(&dir)?;
+    for package in packages {
+        check(package, flavor)?;
+        match flavor {
+            Flavor::Gccrs => {
+                let enabled = enabled_features(package, features, defaults);
+                gccrs(package, &dir, &enabled, rustflags, env, rustc, cfg)?
+            }
+            _ => {
+                if !defaults {
+                    eprintln!(
+                        "cargo-autobuild: warning: minicargo always enables the default features \
+                         of {}",
+                        package.name
+                    );
+                }
+                minicargo(package, &dir, features, rustflags, env, rustc, cfg)?
+            }
+        }
+    }
+    Ok(())
+}
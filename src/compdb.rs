@@ -0,0 +1,158 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    config::{CommandStep, Step, SubdirectoryStep},
+    configure::Configuration,
+    plan::Plan,
+    steps::substitute,
+};
+
+const SOURCE_EXTENSIONS: &[&str] = &[
+    "c", "cc", "cpp", "cxx", "c++", "m", "mm", "s", "S", "sx", "asm",
+];
+
+fn entry(dir: &Path, args: Vec<String>, file: &Path, output: Option<&Path>) -> Value {
+    let mut entry = json!({
+        "directory": dir.display().to_string(),
+        "arguments": args,
+        "file": file.display().to_string(),
+    });
+    if let Some(output) = output {
+        entry["output"] = json!(output.display().to_string());
+    }
+    entry
+}
+
+fn arguments(cmd: &Command) -> Vec<String> {
+    std::iter::once(cmd.get_program())
+        .chain(cmd.get_args())
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn is_compiler(program: &Path) -> bool {
+    let name = program
+        .file_stem()
+        .map(|n| n.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    [
+        "cc", "c++", "gcc", "g++", "clang", "clang++", "cl", "clang-cl",
+    ]
+    .iter()
+    .any(|c| name == *c || name.ends_with(&format!("-{}", c)))
+}
+
+fn is_source(arg: &str) -> bool {
+    Path::new(arg)
+        .extension()
+        .is_some_and(|ext| SOURCE_EXTENSIONS.iter().any(|e| ext == *e))
+}
+
+// Command steps are recorded only when they run a C or C++ compiler directly; one entry is made
+// for each source file on the command line
+fn command_entries(step: &CommandStep, cfg: &Configuration) -> Vec<Value> {
+    let program = substitute(&step.cmd.to_string_lossy(), cfg);
+    if !is_compiler(Path::new(&program)) {
+        return Vec::new();
+    }
+    let mut args = vec![program];
+    args.extend(
+        step.args
+            .iter()
+            .map(|arg| substitute(&arg.to_string_lossy(), cfg)),
+    );
+    let output = args
+        .iter()
+        .position(|a| a == "-o")
+        .and_then(|i| args.get(i + 1))
+        .map(|o| cfg.src_dir.join(o));
+    args.iter()
+        .skip(1)
+        .filter(|a| is_source(a))
+        .map(|src| {
+            entry(
+                &cfg.src_dir,
+                args.clone(),
+                &cfg.src_dir.join(src),
+                output.as_deref(),
+            )
+        })
+        .collect()
+}
+
+// A subdirectory builds on its own, so its database is taken from where CMake, Meson or Bear
+// leave one
+fn subdirectory_entries(
+    step: &SubdirectoryStep,
+    cfg: &Configuration,
+) -> std::io::Result<Vec<Value>> {
+    let dir = cfg.src_dir.join(&step.path);
+    for candidate in &[
+        dir.join("compile_commands.json"),
+        dir.join("build/compile_commands.json"),
+    ] {
+        if !candidate.is_file() {
+            continue;
+        }
+        let text = std::fs::read_to_string(candidate)?;
+        return match serde_json::from_str(&text) {
+            Ok(Value::Array(entries)) => Ok(entries),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} is not a compilation database", candidate.display()),
+            )),
+        };
+    }
+    eprintln!(
+        "cargo-autobuild: warning: {} has no compile_commands.json; its sources are left out",
+        dir.display()
+    );
+    Ok(Vec::new())
+}
+
+fn entries(plan: &Plan, cfg: &Configuration) -> std::io::Result<Vec<Value>> {
+    let mut entries = Vec::new();
+    for step in &plan.steps {
+        match &*step.step {
+            Step::Assemble(step) => {
+                for src in crate::assemble::sources(step, cfg)? {
+                    let cmd = crate::assemble::command(step, &src, cfg)?;
+                    entries.push(entry(
+                        &cfg.src_dir,
+                        arguments(&cmd),
+                        &src,
+                        Some(&crate::assemble::object(&src, cfg)),
+                    ));
+                }
+            }
+            Step::Command(step) => entries.extend(command_entries(step, cfg)),
+            Step::Subdirectory(step) => entries.extend(subdirectory_entries(step, cfg)?),
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+// Written to the source directory by default, where clangd looks first
+pub fn write(plan: &Plan, cfg: &Configuration, dir: Option<&str>) -> std::io::Result<()> {
+    let entries = entries(plan, cfg)?;
+    let path = match dir {
+        Some(dir) => PathBuf::from(dir),
+        None => cfg.src_dir.clone(),
+    }
+    .join("compile_commands.json");
+    let text = serde_json::to_string_pretty(&entries).map_err(std::io::Error::other)?;
+    std::fs::write(&path, text + "\n")?;
+    println!(
+        "Wrote {} compile commands to {}",
+        entries.len(),
+        path.display()
+    );
+    Ok(())
+}
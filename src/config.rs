@@ -1,9 +1,15 @@
-use std::{collections::HashMap, ffi::OsString, path::PathBuf, str::FromStr};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use serde::{Deserialize, Deserializer};
 use target_tuples::Target;
 
-#[derive(Hash, PartialEq, Eq, Debug)]
+#[derive(Hash, PartialEq, Eq, Debug, Clone)]
 pub enum InstallDirectory {
     Prefix,
     ExecPrefix,
@@ -28,40 +34,230 @@ pub enum InstallDirectory {
     Custom(String),
 }
 
-fn parse_target<'de, D: Deserializer<'de>>(de: D) -> Result<Target, D::Error> {
-    let st = <&str>::deserialize(de)?;
-    Target::from_str(st)
-        .map_err(|_| <D::Error as serde::de::Error>::custom(format_args!("Unknown target {}", st)))
-}
-
-#[derive(Deserialize)]
-#[serde(untagged)]
+#[derive(Clone)]
 pub enum BuildTarget {
-    #[serde(rename = "$build")]
     Build,
-    #[serde(rename = "$host")]
     Host,
-    #[serde(rename = "$target")]
     Target,
-    Input(#[serde(deserialize_with = "parse_target")] Target),
+    Input(Target),
+    Spec(PathBuf),
+}
+
+impl<'de> Deserialize<'de> for BuildTarget {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let st = String::deserialize(de)?;
+        match &*st {
+            "$build" => Ok(BuildTarget::Build),
+            "$host" => Ok(BuildTarget::Host),
+            "$target" => Ok(BuildTarget::Target),
+            st if st.ends_with(".json") => Ok(BuildTarget::Spec(PathBuf::from(st))),
+            st => Target::from_str(st).map(BuildTarget::Input).map_err(|_| {
+                <D::Error as serde::de::Error>::custom(format_args!("Unknown target {}", st))
+            }),
+        }
+    }
+}
+
+impl InstallDirectory {
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "prefix" => InstallDirectory::Prefix,
+            "exec-prefix" => InstallDirectory::ExecPrefix,
+            "bindir" => InstallDirectory::BinDir,
+            "sbindir" => InstallDirectory::SbinDir,
+            "libdir" => InstallDirectory::LibDir,
+            "libexecdir" => InstallDirectory::LibexecDir,
+            "includedir" => InstallDirectory::IncludeDir,
+            "datadir" => InstallDirectory::DataDir,
+            "datarootdir" => InstallDirectory::DatarootDir,
+            "docdir" => InstallDirectory::DocDir,
+            "infodir" => InstallDirectory::InfoDir,
+            "mandir" => InstallDirectory::ManDir,
+            "htmldir" => InstallDirectory::HtmlDir,
+            "pdfdir" => InstallDirectory::PdfDir,
+            "dvidir" => InstallDirectory::DviDir,
+            "localedir" => InstallDirectory::LocaleDir,
+            "localstatedir" => InstallDirectory::LocalStateDir,
+            "sharedstatedir" => InstallDirectory::SharedStateDir,
+            "runstatedir" => InstallDirectory::RunStateDir,
+            "sysconfdir" => InstallDirectory::SysconfDir,
+            x => InstallDirectory::Custom(x.to_string()),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            InstallDirectory::Prefix => "prefix",
+            InstallDirectory::ExecPrefix => "exec-prefix",
+            InstallDirectory::BinDir => "bindir",
+            InstallDirectory::SbinDir => "sbindir",
+            InstallDirectory::LibDir => "libdir",
+            InstallDirectory::LibexecDir => "libexecdir",
+            InstallDirectory::IncludeDir => "includedir",
+            InstallDirectory::DataDir => "datadir",
+            InstallDirectory::DatarootDir => "datarootdir",
+            InstallDirectory::DocDir => "docdir",
+            InstallDirectory::InfoDir => "infodir",
+            InstallDirectory::ManDir => "mandir",
+            InstallDirectory::HtmlDir => "htmldir",
+            InstallDirectory::PdfDir => "pdfdir",
+            InstallDirectory::DviDir => "dvidir",
+            InstallDirectory::LocaleDir => "localedir",
+            InstallDirectory::LocalStateDir => "localstatedir",
+            InstallDirectory::SharedStateDir => "sharedstatedir",
+            InstallDirectory::RunStateDir => "runstatedir",
+            InstallDirectory::SysconfDir => "sysconfdir",
+            InstallDirectory::Custom(x) => x,
+        }
+    }
 }
 
 fn host() -> BuildTarget {
     BuildTarget::Host
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
 #[serde(tag = "type")]
+#[serde(remote = "Self")]
 pub enum Step {
     BuildCrate(BuildCrateStep),
+    BuildWorkspace(BuildWorkspaceStep),
     Subdirectory(SubdirectoryStep),
     Install(InstallStep),
+    InstallSymlink(InstallSymlinkStep),
     Command(CommandStep),
     GenerateDocs(GenerateDocsStep),
     ConfigureFile(ConfigureFileStep),
+    Codesign(CodesignStep),
+    Objcopy(ObjcopyStep),
+    CompileTranslations(CompileTranslationsStep),
+    InstallDesktop(InstallDesktopStep),
+    InstallSystemd(InstallSystemdStep),
+    Test(TestStep),
+    Bench(BenchStep),
+    Fuzz(FuzzStep),
+    Lint(LintStep),
+    FormatCheck(FormatCheckStep),
+    Audit(AuditStep),
+    ThirdPartyLicenses(ThirdPartyLicensesStep),
+    Fetch(FetchStep),
+    ApplyPatches(ApplyPatchesStep),
+    Extract(ExtractStep),
+    Bindgen(BindgenStep),
+    Protoc(ProtocStep),
+    Flatc(FlatcStep),
+    GenerateParser(GenerateParserStep),
+    Assemble(AssembleStep),
+    Archive(ArchiveStep),
+    Sysroot(SysrootStep),
+    Cbindgen(CbindgenStep),
+    PkgConfig(PkgConfigStep),
+    Plugin(PluginStep),
+    #[serde(skip)]
+    Custom(CustomStep),
+}
+
+pub const BUILTIN_STEPS: &[&str] = &[
+    "build-crate",
+    "build-workspace",
+    "subdirectory",
+    "install",
+    "install-symlink",
+    "command",
+    "generate-docs",
+    "configure-file",
+    "codesign",
+    "objcopy",
+    "compile-translations",
+    "install-desktop",
+    "install-systemd",
+    "test",
+    "bench",
+    "fuzz",
+    "lint",
+    "format-check",
+    "audit",
+    "third-party-licenses",
+    "fetch",
+    "apply-patches",
+    "extract",
+    "bindgen",
+    "protoc",
+    "flatc",
+    "generate-parser",
+    "assemble",
+    "archive",
+    "sysroot",
+    "cbindgen",
+    "pkg-config",
+    "plugin",
+];
+
+impl Step {
+    pub fn kind(&self) -> &str {
+        match self {
+            Step::BuildCrate(_) => "build-crate",
+            Step::BuildWorkspace(_) => "build-workspace",
+            Step::Subdirectory(_) => "subdirectory",
+            Step::Install(_) => "install",
+            Step::InstallSymlink(_) => "install-symlink",
+            Step::Command(_) => "command",
+            Step::GenerateDocs(_) => "generate-docs",
+            Step::ConfigureFile(_) => "configure-file",
+            Step::Codesign(_) => "codesign",
+            Step::Objcopy(_) => "objcopy",
+            Step::CompileTranslations(_) => "compile-translations",
+            Step::InstallDesktop(_) => "install-desktop",
+            Step::InstallSystemd(_) => "install-systemd",
+            Step::Test(_) => "test",
+            Step::Bench(_) => "bench",
+            Step::Fuzz(_) => "fuzz",
+            Step::Lint(_) => "lint",
+            Step::FormatCheck(_) => "format-check",
+            Step::Audit(_) => "audit",
+            Step::ThirdPartyLicenses(_) => "third-party-licenses",
+            Step::Fetch(_) => "fetch",
+            Step::ApplyPatches(_) => "apply-patches",
+            Step::Extract(_) => "extract",
+            Step::Bindgen(_) => "bindgen",
+            Step::Protoc(_) => "protoc",
+            Step::Flatc(_) => "flatc",
+            Step::GenerateParser(_) => "generate-parser",
+            Step::Assemble(_) => "assemble",
+            Step::Archive(_) => "archive",
+            Step::Sysroot(_) => "sysroot",
+            Step::Cbindgen(_) => "cbindgen",
+            Step::PkgConfig(_) => "pkg-config",
+            Step::Plugin(_) => "plugin",
+            Step::Custom(step) => &step.kind,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Step {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = toml::Value::deserialize(de)?;
+        let kind = value.get("type").and_then(|t| t.as_str()).ok_or_else(|| {
+            <D::Error as serde::de::Error>::custom("Steps require a string `type` field")
+        })?;
+        if BUILTIN_STEPS.contains(&kind) {
+            Step::deserialize(value).map_err(<D::Error as serde::de::Error>::custom)
+        } else {
+            CustomStep::deserialize(value)
+                .map(Step::Custom)
+                .map_err(<D::Error as serde::de::Error>::custom)
+        }
+    }
 }
 
+#[derive(Clone)]
 pub enum InstallTarget {
     Base {
         base: InstallDirectory,
@@ -75,34 +271,12 @@ impl<'de> Deserialize<'de> for InstallTarget {
     where
         D: Deserializer<'de>,
     {
-        let st = <&str>::deserialize(de)?;
-        if st.starts_with("$") {
-            let mut parts = st[1..].split('/');
+        let st = String::deserialize(de)?;
+        if let Some(st) = st.strip_prefix('$') {
+            let mut parts = st.split('/');
             let dir = parts.next().unwrap();
             let path = parts.collect();
-            let base = match dir {
-                "prefix" => InstallDirectory::Prefix,
-                "exec-prefix" => InstallDirectory::ExecPrefix,
-                "bindir" => InstallDirectory::BinDir,
-                "sbindir" => InstallDirectory::SbinDir,
-                "libdir" => InstallDirectory::LibDir,
-                "libexecdir" => InstallDirectory::LibexecDir,
-                "includedir" => InstallDirectory::IncludeDir,
-                "datadir" => InstallDirectory::DataDir,
-                "datarootdir" => InstallDirectory::DatarootDir,
-                "docdir" => InstallDirectory::DocDir,
-                "infodir" => InstallDirectory::InfoDir,
-                "mandir" => InstallDirectory::ManDir,
-                "htmldir" => InstallDirectory::HtmlDir,
-                "pdfdir" => InstallDirectory::PdfDir,
-                "dvidir" => InstallDirectory::DviDir,
-                "localedir" => InstallDirectory::LocaleDir,
-                "localstatedir" => InstallDirectory::LocalStateDir,
-                "sharedstatedir" => InstallDirectory::SharedStateDir,
-                "runstatedir" => InstallDirectory::RunStateDir,
-                "sysconfdir" => InstallDirectory::SysconfDir,
-                x => InstallDirectory::Custom(x.to_string()),
-            };
+            let base = InstallDirectory::from_name(dir);
             Ok(Self::Base { base, path })
         } else {
             Ok(InstallTarget::Absolute(PathBuf::from(st)))
@@ -110,15 +284,15 @@ impl<'de> Deserialize<'de> for InstallTarget {
     }
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Default, Clone)]
 pub struct Directories {
     #[serde(flatten)]
     pub dirs: HashMap<String, InstallTarget>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
-
+#[serde(deny_unknown_fields)]
 pub struct BuildCrateStep {
     #[serde(default)]
     pub path: OsString,
@@ -126,80 +300,1820 @@ pub struct BuildCrateStep {
     pub features: Vec<String>,
     #[serde(default = "host")]
     pub target: BuildTarget,
+    // Added after the flags and environment configured for every build
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    #[serde(default)]
+    pub rustdocflags: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub install_dirs: Vec<String>,
+    #[serde(default)]
+    pub no_default_features: bool,
+    #[serde(default)]
+    pub crate_types: Vec<String>,
+    // Naming binaries builds only those; examples and benches are built only when named
+    #[serde(default)]
+    pub bins: Vec<String>,
+    #[serde(default)]
+    pub examples: Vec<String>,
+    #[serde(default)]
+    pub benches: Vec<String>,
+    #[serde(default)]
+    pub universal: bool,
+    #[serde(default)]
+    pub resources: Option<PathBuf>,
+    #[serde(default)]
+    pub lib_version: Option<String>,
+    #[serde(default)]
+    pub soversion: Option<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct BuildWorkspaceStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub members: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
 pub struct GenerateDocsStep {}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct SubdirectoryStep {}
+#[serde(deny_unknown_fields)]
+pub struct SubdirectoryStep {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub cmd: Option<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<OsString>,
+}
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(untagged)]
 pub enum Mode {
     Octal(i32),
     Chmod(String),
 }
 
-#[derive(Deserialize)]
+// What becomes of a file an install replaces, as with GNU install's --backup
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum Backup {
+    #[default]
+    None,
+    // Kept as file~
+    Simple,
+    // Kept as file.~1~, file.~2~ and so on
+    Numbered,
+}
+
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
 pub struct InstallStep {
+    #[serde(default)]
     pub file: PathBuf,
+    #[serde(default)]
+    pub from_step: Option<String>,
+    #[serde(default)]
+    pub kinds: Option<Vec<String>>,
     pub target: InstallTarget,
     #[serde(default)]
     pub mode: Option<Mode>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default)]
+    pub flatten: bool,
+    #[serde(default)]
+    pub rename: Option<String>,
+    #[serde(default)]
+    pub caps: Option<String>,
+    #[serde(default)]
+    pub setuid: bool,
+    #[serde(default)]
+    pub setgid: bool,
+    #[serde(default)]
+    pub backup: Backup,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct InstallSymlinkStep {
+    pub link: InstallTarget,
+    pub to: InstallTarget,
+    #[serde(default)]
+    pub hard: bool,
+    #[serde(default)]
+    pub absolute: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
 pub struct CommandStep {
     pub cmd: PathBuf,
     #[serde(default)]
     pub args: Vec<OsString>,
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    // Runs the command under bwrap or unshare on Linux; the network stays off unless allowed
+    #[serde(default)]
+    pub sandbox: bool,
+    #[serde(default)]
+    pub network: bool,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
 pub struct ConfigureFileStep {
     pub base: PathBuf,
     #[serde(default)]
     pub input: Option<PathBuf>,
 }
 
-#[derive(Deserialize)]
-#[serde(untagged, rename_all = "kebab-case")]
-pub enum ProgramType {
-    Rustc,
-    Cargo,
-    Cc,
-    Cxx,
-    As,
-    Ar,
-    Ld,
-    Objdump,
-    Objcopy,
-    Strip,
-    Ln,
-    LnS,
-    Install,
-    Yacc,
-    Lex,
-    Other(String),
+fn adhoc() -> String {
+    "-".to_string()
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 #[serde(rename_all = "kebab-case")]
-pub struct Program {
-    #[serde(rename = "type")]
-    pub ty: ProgramType,
+#[serde(deny_unknown_fields)]
+pub struct CodesignStep {
+    pub files: Vec<PathBuf>,
+    #[serde(default = "adhoc")]
+    pub identity: String,
     #[serde(default)]
-    pub names: Option<Vec<String>>,
+    pub entitlements: Option<PathBuf>,
     #[serde(default)]
-    pub test_steps: Vec<Step>,
+    pub hardened_runtime: bool,
     #[serde(default)]
-    pub compiler_target: Option<BuildTarget>,
-    #[serde(flatten)]
-    pub extra: HashMap<String, String>,
+    pub notarize: bool,
+    #[serde(default)]
+    pub keychain_profile: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(rename_all = "kebab-case")]
+pub enum ImageFormat {
+    #[default]
+    Binary,
+    Ihex,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ObjcopyStep {
+    pub input: PathBuf,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub format: ImageFormat,
+}
+
+fn po_dir() -> PathBuf {
+    PathBuf::from("po")
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CompileTranslationsStep {
+    #[serde(default = "po_dir")]
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub domain: Option<String>,
+    #[serde(default)]
+    pub languages: Vec<String>,
+}
+
+fn validate() -> bool {
+    true
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct InstallDesktopStep {
+    #[serde(default)]
+    pub desktop: Vec<PathBuf>,
+    #[serde(default)]
+    pub icons: Vec<PathBuf>,
+    #[serde(default)]
+    pub icon_name: Option<String>,
+    #[serde(default)]
+    pub metainfo: Vec<PathBuf>,
+    #[serde(default = "validate")]
+    pub validate: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct InstallSystemdStep {
+    #[serde(default)]
+    pub units: Vec<PathBuf>,
+    #[serde(default)]
+    pub user_units: Vec<PathBuf>,
+    #[serde(default)]
+    pub tmpfiles: Vec<PathBuf>,
+    #[serde(default)]
+    pub sysusers: Vec<PathBuf>,
+    #[serde(default)]
+    pub dbus_services: Vec<PathBuf>,
+    #[serde(default)]
+    pub dbus_system_services: Vec<PathBuf>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct TestStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    #[serde(default)]
+    pub junit: Option<PathBuf>,
+    #[serde(default)]
+    pub tap: Option<PathBuf>,
+    // Unset uses cargo-nextest when it was found; true requires it and false never uses it
+    #[serde(default)]
+    pub nextest: Option<bool>,
+    #[serde(default)]
+    pub partition: Option<String>,
+    #[serde(default)]
+    pub retries: Option<u32>,
+}
+
+fn threshold() -> f64 {
+    5.0
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct BenchStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub packages: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    #[serde(default)]
+    pub baseline: Option<String>,
+    #[serde(default)]
+    pub save_baseline: Option<String>,
+    // Percent slower than the baseline that counts as a regression
+    #[serde(default = "threshold")]
+    pub threshold: f64,
+}
+
+fn fuzz_dir() -> PathBuf {
+    PathBuf::from("fuzz")
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct FuzzStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default = "fuzz_dir")]
+    pub fuzz_dir: PathBuf,
+    #[serde(default)]
+    pub targets: Vec<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    // Like nextest on test steps: unset uses cargo-fuzz when it was found
+    #[serde(default)]
+    pub cargo_fuzz: Option<bool>,
+    // Runs each target this long during `check`
+    #[serde(default)]
+    pub smoke_seconds: Option<u64>,
+    #[serde(default)]
+    pub corpus: Option<PathBuf>,
+}
+
+// Runs cargo clippy; the lint lists become -D/-W/-A flags in that order
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct LintStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub all_targets: bool,
+    #[serde(default)]
+    pub deny: Vec<String>,
+    #[serde(default)]
+    pub warn: Vec<String>,
+    #[serde(default)]
+    pub allow: Vec<String>,
+    // Without clippy installed the step is skipped, unless it is required
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct FormatCheckStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub required: bool,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditTool {
+    CargoAudit,
+    CargoDeny,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct AuditStep {
+    #[serde(default)]
+    pub path: OsString,
+    // Unset prefers cargo-deny, which can also check licenses
+    #[serde(default)]
+    pub tool: Option<AuditTool>,
+    // Advisory IDs that are known and accepted
+    #[serde(default)]
+    pub ignore: Vec<String>,
+    // SPDX identifiers dependencies may use; unset skips the license check
+    #[serde(default)]
+    pub licenses: Option<Vec<String>>,
+    #[serde(default)]
+    pub deny_warnings: bool,
+    #[serde(default)]
+    pub required: bool,
+}
+
+fn install() -> bool {
+    true
+}
+
+fn third_party_licenses() -> PathBuf {
+    PathBuf::from("THIRD-PARTY-LICENSES")
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ThirdPartyLicensesStep {
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default = "third_party_licenses")]
+    pub output: PathBuf,
+    // Installs the notice to $docdir; otherwise it is only written to the build directory
+    #[serde(default = "install")]
+    pub install: bool,
+}
+
+// Downloads into $builddir/downloads; `dest` defaults to the last part of the URL
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct FetchStep {
+    pub url: String,
+    #[serde(default)]
+    pub mirrors: Vec<String>,
+    #[serde(deserialize_with = "sha256")]
+    pub sha256: String,
+    #[serde(default)]
+    pub dest: Option<PathBuf>,
+}
+
+// Checksums are 64 hex digits; anything else could never match a download
+fn sha256<'de, D>(de: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let sha256 = String::deserialize(de)?;
+    if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(serde::de::Error::custom(format!(
+            "{:?} is not a SHA-256 checksum",
+            sha256
+        )));
+    }
+    Ok(sha256)
+}
+
+fn optional_sha256<'de, D>(de: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    sha256(de).map(Some)
+}
+
+// A directory under $builddir that extraction may replace wholesale
+fn subdirectory<'de, D>(de: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let dir = PathBuf::deserialize(de)?;
+    let normal = dir
+        .components()
+        .all(|c| matches!(c, std::path::Component::Normal(_)));
+    if dir.as_os_str().is_empty() || !normal {
+        return Err(serde::de::Error::custom(format!(
+            "{:?} is not a relative directory below the build directory",
+            dir
+        )));
+    }
+    Ok(dir)
+}
+
+fn strip() -> u32 {
+    1
+}
+
+// Patches apply in order to `dir`; ones that are already applied are skipped
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ApplyPatchesStep {
+    #[serde(default)]
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub patches: Vec<PathBuf>,
+    // A quilt series file, read after `patches`
+    #[serde(default)]
+    pub series: Option<PathBuf>,
+    #[serde(default = "strip")]
+    pub strip: u32,
+    // Skips the patch program even when it is found
+    #[serde(default)]
+    pub builtin: bool,
+}
+
+// Unpacks into $builddir/`dest`; an archive from a fetch step is checked against its sha256
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ExtractStep {
+    pub archive: PathBuf,
+    #[serde(deserialize_with = "subdirectory")]
+    pub dest: PathBuf,
+    #[serde(default, deserialize_with = "optional_sha256")]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub strip_components: u32,
+}
+
+// Writes $builddir/`output`; `env` names a variable that carries its path into cargo builds
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct BindgenStep {
+    pub headers: Vec<PathBuf>,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub env: Option<String>,
+    // Packages whose include paths are passed on to clang
+    #[serde(default)]
+    pub pkg_config: Vec<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub clang_args: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+}
+
+// A protoc plugin run as protoc-gen-`name`, such as prost or tonic
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ProtocPlugin {
+    pub name: String,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+fn prost() -> Vec<ProtocPlugin> {
+    vec![ProtocPlugin {
+        name: "prost".to_string(),
+        options: Vec::new(),
+    }]
+}
+
+// Generates into the $builddir/`output` directory; `env` carries its path into cargo builds
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ProtocStep {
+    pub schemas: Vec<PathBuf>,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub env: Option<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<PathBuf>,
+    #[serde(default = "prost")]
+    pub plugins: Vec<ProtocPlugin>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct FlatcStep {
+    pub schemas: Vec<PathBuf>,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub env: Option<String>,
+    #[serde(default)]
+    pub include_dirs: Vec<PathBuf>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "kebab-case")]
+pub enum ParserGenerator {
+    Yacc,
+    Lex,
+}
+
+// Writes $builddir/`output` (and `header`, for yacc) from `input`
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct GenerateParserStep {
+    pub generator: ParserGenerator,
+    pub input: PathBuf,
+    pub output: PathBuf,
+    #[serde(default)]
+    pub header: Option<PathBuf>,
+    // A [programs] entry to run instead of yacc or lex, such as a Rust parser generator
+    #[serde(default)]
+    pub program: Option<String>,
+    #[serde(default)]
+    pub env: Option<String>,
+    #[serde(default)]
+    pub args: Vec<String>,
+}
+
+// Objects go to $builddir/asm; `archive` names a static library to collect them into, which
+// `link` passes to the linker of every cargo build
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct AssembleStep {
+    pub sources: Vec<PathBuf>,
+    #[serde(default)]
+    pub flags: Vec<String>,
+    #[serde(default)]
+    pub archive: Option<String>,
+    #[serde(default)]
+    pub link: bool,
+    #[serde(default)]
+    pub env: Option<String>,
+}
+
+fn deterministic() -> bool {
+    true
+}
+
+// Collects objects, and the members of static libraries, into $builddir/lib`name`.a (or
+// `name`.lib for MSVC)
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ArchiveStep {
+    pub name: String,
+    #[serde(default)]
+    pub objects: Vec<PathBuf>,
+    // Steps whose object and staticlib artifacts are included
+    #[serde(default)]
+    pub from_steps: Vec<String>,
+    #[serde(default)]
+    pub thin: bool,
+    #[serde(default = "deterministic")]
+    pub deterministic: bool,
+}
+
+fn sysroot_dir() -> PathBuf {
+    PathBuf::from("sysroot")
+}
+
+// Lays out $builddir/`dest` for `rustc --sysroot`, with the libraries of `from-steps` and `files`
+// such as startup objects and libc in lib/rustlib/<target>/lib
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct SysrootStep {
+    #[serde(default = "sysroot_dir")]
+    pub dest: PathBuf,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub from_steps: Vec<String>,
+    #[serde(default)]
+    pub files: Vec<PathBuf>,
+    // Symbolic links in place of copies
+    #[serde(default)]
+    pub link: bool,
+}
+
+// Writes a C header for the crate at `path` to $builddir/`output`
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CbindgenStep {
+    #[serde(default)]
+    pub path: OsString,
+    pub output: PathBuf,
+    // cbindgen.toml in the crate directory is used when this is unset
+    #[serde(default)]
+    pub config: Option<PathBuf>,
+    #[serde(default)]
+    pub lang: Option<String>,
+}
+
+// Writes $builddir/`name`.pc; the version defaults to that of the crate at `path`
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct PkgConfigStep {
+    pub name: String,
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub version: Option<String>,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub requires_private: Vec<String>,
+    #[serde(default)]
+    pub libs: Vec<String>,
+    #[serde(default)]
+    pub libs_private: Vec<String>,
+    #[serde(default)]
+    pub cflags: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct PluginStep {
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<OsString>,
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    #[serde(default)]
+    pub options: toml::value::Table,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct PluginProbe {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub program: PathBuf,
+    #[serde(default)]
+    pub args: Vec<OsString>,
+    #[serde(default)]
+    pub options: toml::value::Table,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CustomStep {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub options: toml::value::Table,
+}
+
+#[derive(Clone)]
+pub enum ProgramType {
+    Rustc,
+    Cargo,
+    Cc,
+    Cxx,
+    As,
+    Ar,
+    Ld,
+    Objdump,
+    Objcopy,
+    Strip,
+    Ln,
+    LnS,
+    Install,
+    Yacc,
+    Lex,
+    Codesign,
+    Notarytool,
+    Rc,
+    Msgfmt,
+    CargoNextest,
+    CargoFuzz,
+    CargoAudit,
+    CargoDeny,
+    Patch,
+    Bindgen,
+    Protoc,
+    Flatc,
+    Cbindgen,
+    Other(String),
+}
+
+// The types autobuild knows, which take none of a program's extra fields
+pub const PROGRAM_TYPES: &[&str] = &[
+    "rustc",
+    "cargo",
+    "cc",
+    "cxx",
+    "as",
+    "ar",
+    "ld",
+    "objdump",
+    "objcopy",
+    "strip",
+    "ln",
+    "ln-s",
+    "install",
+    "yacc",
+    "lex",
+    "codesign",
+    "notarytool",
+    "rc",
+    "msgfmt",
+    "cargo-nextest",
+    "cargo-fuzz",
+    "cargo-audit",
+    "cargo-deny",
+    "patch",
+    "bindgen",
+    "protoc",
+    "flatc",
+    "cbindgen",
+];
+
+impl<'de> Deserialize<'de> for ProgramType {
+    fn deserialize<D>(de: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let st = String::deserialize(de)?;
+        Ok(match &*st {
+            "rustc" => ProgramType::Rustc,
+            "cargo" => ProgramType::Cargo,
+            "cc" => ProgramType::Cc,
+            "cxx" => ProgramType::Cxx,
+            "as" => ProgramType::As,
+            "ar" => ProgramType::Ar,
+            "ld" => ProgramType::Ld,
+            "objdump" => ProgramType::Objdump,
+            "objcopy" => ProgramType::Objcopy,
+            "strip" => ProgramType::Strip,
+            "ln" => ProgramType::Ln,
+            "ln-s" => ProgramType::LnS,
+            "install" => ProgramType::Install,
+            "yacc" => ProgramType::Yacc,
+            "lex" => ProgramType::Lex,
+            "codesign" => ProgramType::Codesign,
+            "notarytool" => ProgramType::Notarytool,
+            "rc" => ProgramType::Rc,
+            "msgfmt" => ProgramType::Msgfmt,
+            "cargo-nextest" => ProgramType::CargoNextest,
+            "cargo-fuzz" => ProgramType::CargoFuzz,
+            "cargo-audit" => ProgramType::CargoAudit,
+            "cargo-deny" => ProgramType::CargoDeny,
+            "patch" => ProgramType::Patch,
+            "bindgen" => ProgramType::Bindgen,
+            "protoc" => ProgramType::Protoc,
+            "flatc" => ProgramType::Flatc,
+            "cbindgen" => ProgramType::Cbindgen,
+            x => ProgramType::Other(x.to_string()),
+        })
+    }
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Program {
+    #[serde(rename = "type")]
+    pub ty: ProgramType,
+    #[serde(default)]
+    pub names: Option<Vec<String>>,
+    #[serde(default)]
+    pub test_steps: Vec<Step>,
+    #[serde(default)]
+    pub compiler_target: Option<BuildTarget>,
+    // Fields of a program of a custom type, for the plugins and embedders that look it up
+    #[serde(flatten)]
+    pub extra: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Phase {
+    #[serde(default)]
+    pub step: Vec<Step>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct ConfigOptions {
+    #[serde(default)]
+    pub enable_sccache: Option<bool>,
+    #[serde(default)]
+    pub enable_ccache: Option<bool>,
+    #[serde(default)]
+    pub enable_pgo: Option<bool>,
+    #[serde(default)]
+    pub sanitizers: Vec<String>,
+    #[serde(default)]
+    pub static_link: bool,
+    #[serde(default)]
+    pub hardened: bool,
+    // The platforms to build for, each getting a run of the whole plan, as with --host a,b
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub download_cache: Option<PathBuf>,
+    // -Z flags to probe for, each published as the variable rustc-z-<flag>
+    #[serde(default)]
+    pub unstable_flags: Vec<String>,
+    // Runs every rustc invocation, in place of sccache
+    #[serde(default)]
+    pub rustc_wrapper: Option<PathBuf>,
+    #[serde(default)]
+    pub rustflags: InjectedRustflags,
+    #[serde(default)]
+    pub rustdocflags: Vec<String>,
+    // Shared by every cargo step, so that dependencies are compiled once
+    #[serde(default)]
+    pub target_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub isolate: Vec<Isolation>,
+    // Where steps and probes keep their temporary files; the build directory by default
+    #[serde(default)]
+    pub scratch_dir: Option<PathBuf>,
+}
+
+// Subdirectories of the target directory that keep builds cargo would put in the same place apart
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Isolation {
+    Triple,
+    Profile,
+}
+
+#[derive(Deserialize, Default, Clone, Hash)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct InjectedFlags {
+    #[serde(default)]
+    pub prepend: Vec<String>,
+    #[serde(default)]
+    pub append: Vec<String>,
+}
+
+impl InjectedFlags {
+    pub fn is_empty(&self) -> bool {
+        self.prepend.is_empty() && self.append.is_empty()
+    }
+}
+
+// Which crates count as host crates is settled in wrappers::rustc
+#[derive(Deserialize, Default, Clone, Hash)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct InjectedRustflags {
+    #[serde(default)]
+    pub target: InjectedFlags,
+    #[serde(default)]
+    pub host: InjectedFlags,
+    #[serde(default)]
+    pub build_script: InjectedFlags,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Pgo {
+    #[serde(default)]
+    pub train: Vec<Step>,
+}
+
+fn stages() -> u32 {
+    3
+}
+
+// For `cargo autobuild bootstrap`: each stage builds `artifact` with the one the stage before it
+// built, put in place of the program named by `program`
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Bootstrap {
+    pub program: String,
+    pub artifact: String,
+    #[serde(default = "stages")]
+    pub stages: u32,
+    // Artifacts the last two stages must agree on; just `artifact` when unset
+    #[serde(default)]
+    pub compare: Option<Vec<String>>,
+}
+
+fn remote_dir() -> String {
+    ".cache/cargo-autobuild".to_string()
+}
+
+fn remote_program() -> String {
+    "cargo-autobuild".to_string()
+}
+
+// Machines that steps with `distribute = true` run on, reached with ssh and rsync. Each worker
+// needs the toolchain and cargo-autobuild, as `program`; the source tree goes to `dir`, relative
+// to the worker's home directory
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Remote {
+    #[serde(default)]
+    pub workers: Vec<String>,
+    #[serde(default = "remote_dir")]
+    pub dir: String,
+    #[serde(default = "remote_program")]
+    pub program: String,
+}
+
+impl Default for Remote {
+    fn default() -> Self {
+        Remote {
+            workers: Vec::new(),
+            dir: remote_dir(),
+            program: remote_program(),
+        }
+    }
+}
+
+// For --in-container: the command runs again in `image`, with the source and build directories
+// mounted where they are here. Unless `program` names the cargo-autobuild of the image, this
+// executable is mounted into it
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Container {
+    #[serde(default)]
+    pub image: Option<String>,
+    // podman or docker; whichever is found first when unset
+    #[serde(default)]
+    pub engine: Option<PathBuf>,
+    #[serde(default)]
+    pub program: Option<String>,
+    // More mounts, as host-path:container-path
+    #[serde(default)]
+    pub volumes: Vec<String>,
+    // Variables passed on from the environment
+    #[serde(default)]
+    pub env: Vec<String>,
+}
+
+fn scalar<'de, D>(de: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Scalar {
+        Bool(bool),
+        Int(i64),
+        Str(String),
+    }
+    Ok(Some(match Scalar::deserialize(de)? {
+        Scalar::Bool(b) => b.to_string(),
+        Scalar::Int(i) => i.to_string(),
+        Scalar::Str(s) => s,
+    }))
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub inherits: Option<String>,
+    #[serde(default, deserialize_with = "scalar")]
+    pub opt_level: Option<String>,
+    #[serde(default)]
+    pub debug: Option<bool>,
+    #[serde(default, deserialize_with = "scalar")]
+    pub lto: Option<String>,
+    #[serde(default)]
+    pub codegen_units: Option<u32>,
+    #[serde(default)]
+    pub linker_plugin_lto: Option<bool>,
+    #[serde(default)]
+    pub panic: Option<String>,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct TargetOverride {
+    // cpu through linker-script are read while configuring, before the host's cfg is known, so
+    // only a section named by the exact triple may set them
+    #[serde(default)]
+    pub cpu: Option<String>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default)]
+    pub api_level: Option<u32>,
+    #[serde(default)]
+    pub min_os_version: Option<String>,
+    #[serde(default)]
+    pub spec: Option<PathBuf>,
+    #[serde(default)]
+    pub linker_script: Option<PathBuf>,
+    #[serde(default)]
+    pub rustflags: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub dirs: Directories,
+    #[serde(default)]
+    pub step: HashMap<String, toml::value::Table>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct LinkAudit {
+    #[serde(default)]
+    pub enable: Option<bool>,
+    #[serde(default)]
+    pub warn_only: bool,
+    #[serde(default)]
+    pub allow: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(default)]
+    pub pre_configure: Vec<String>,
+    #[serde(default)]
+    pub post_configure: Vec<String>,
+    #[serde(default)]
+    pub pre_build: Vec<String>,
+    #[serde(default)]
+    pub post_build: Vec<String>,
+    #[serde(default)]
+    pub pre_install: Vec<String>,
+    #[serde(default)]
+    pub post_install: Vec<String>,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub format_version: u32,
+    #[serde(default)]
+    pub options: ConfigOptions,
+    #[serde(default)]
+    pub profiles: HashMap<String, Profile>,
+    #[serde(default)]
+    pub target: HashMap<String, TargetOverride>,
+    #[serde(default)]
+    pub dirs: Directories,
+    #[serde(default)]
+    pub programs: HashMap<String, Program>,
+    // Compilers for particular targets, keyed by triple or by $build, $host or $target
+    #[serde(default)]
+    pub compilers: BTreeMap<String, PathBuf>,
+    #[serde(default)]
+    pub build: Phase,
+    #[serde(default)]
+    pub install: Phase,
+    #[serde(default)]
+    pub test: Phase,
+    #[serde(default)]
+    pub bench: Phase,
+    #[serde(default)]
+    pub pgo: Pgo,
+    #[serde(default)]
+    pub bootstrap: Option<Bootstrap>,
+    #[serde(default)]
+    pub probe: Vec<PluginProbe>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    #[serde(default)]
+    pub link_audit: LinkAudit,
+    #[serde(default)]
+    pub remote: Remote,
+    #[serde(default)]
+    pub container: Container,
+    #[serde(default)]
+    pub product: Vec<Product>,
+    #[serde(skip)]
+    pub source: Option<toml::Value>,
+    // The `if` of each step that has one, by step name
+    #[serde(skip)]
+    pub conditions: HashMap<String, String>,
+    #[serde(skip)]
+    pub policies: HashMap<String, StepPolicy>,
+    // The `id` of each step that has one, by phase and index
+    #[serde(skip)]
+    pub ids: HashMap<String, String>,
+    // This file and every file it includes, which remote workers need to load it
+    #[serde(skip)]
+    pub files: Vec<PathBuf>,
+}
+
+impl Config {
+    // What a step is planned as: its id, or its phase and index, such as build.0
+    pub fn step_name(&self, position: &str) -> String {
+        self.ids
+            .get(position)
+            .cloned()
+            .unwrap_or_else(|| position.to_string())
+    }
+
+    // The phase and index of a step named either way
+    pub fn step_position(&self, name: &str) -> String {
+        self.ids
+            .iter()
+            .find(|(_, id)| *id == name)
+            .map_or_else(|| name.to_string(), |(position, _)| position.clone())
+    }
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnFailure {
+    #[default]
+    Abort,
+    Continue,
+    SkipDependents,
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputMode {
+    #[default]
+    Stream,
+    // Written to .autobuild/logs/<step>.log
+    Log,
+    // Written to the log, and shown only when the step fails
+    OnFailure,
+}
+
+// How a step is run, given by the step's `retries`, `timeout` (in seconds), `on-failure`,
+// `output-mode` and `distribute` keys
+#[derive(Deserialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct StepPolicy {
+    #[serde(default)]
+    pub retries: u32,
+    #[serde(default)]
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub on_failure: OnFailure,
+    #[serde(default)]
+    pub output_mode: OutputMode,
+    // Runs the step on one of the remote workers, when there are any
+    #[serde(default)]
+    pub distribute: bool,
+}
+
+// A product stands for the steps that build and install it; they are added to the build and
+// install phases when the configuration is loaded
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(tag = "type")]
+pub enum Product {
+    CLibrary(CLibraryProduct),
+}
+
+// A Rust library consumed from C: cdylib and staticlib, a cbindgen header and a .pc file. The
+// crate has to list cdylib and staticlib in its crate-type
+#[derive(Deserialize, Clone)]
+#[serde(rename_all = "kebab-case")]
+#[serde(deny_unknown_fields)]
+pub struct CLibraryProduct {
+    // The library name, as in -l`name`
+    pub name: String,
+    #[serde(default)]
+    pub path: OsString,
+    #[serde(default)]
+    pub features: Vec<String>,
+    #[serde(default = "host")]
+    pub target: BuildTarget,
+    #[serde(default)]
+    pub lib_version: Option<String>,
+    #[serde(default)]
+    pub soversion: Option<String>,
+    // Defaults to `name`.h
+    #[serde(default)]
+    pub header: Option<PathBuf>,
+    #[serde(default)]
+    pub cbindgen_config: Option<PathBuf>,
+    #[serde(default)]
+    pub lang: Option<String>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub requires: Vec<String>,
+    #[serde(default)]
+    pub requires_private: Vec<String>,
+    #[serde(default)]
+    pub libs_private: Vec<String>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> std::io::Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Ok(Format::Toml),
+            Some("json") => Ok(Format::Json),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            _ => Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Cannot tell the format of {} from its extension (expected .toml, .json, or .yaml)",
+                    path.display()
+                ),
+            )),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Format::Toml => "TOML",
+            Format::Json => "JSON",
+            Format::Yaml => "YAML",
+        }
+    }
+}
+
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const FILE_NAMES: &[&str] = &[
+    "autobuild.toml",
+    "autobuild.json",
+    "autobuild.yaml",
+    "autobuild.yml",
+];
+
+pub fn format_version(path: &Path, value: &toml::Value) -> std::io::Result<Option<u32>> {
+    match value.get("format-version") {
+        None => Ok(None),
+        Some(toml::Value::Integer(version)) if *version as u32 as i64 == *version => {
+            let version = *version as u32;
+            if version > FORMAT_VERSION {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "{} uses format-version {}, but this version of cargo-autobuild only understands format-version {}; please upgrade cargo-autobuild",
+                        path.display(),
+                        version,
+                        FORMAT_VERSION
+                    ),
+                ));
+            }
+            Ok(Some(version))
+        }
+        Some(_) => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "format-version in {} must be a non-negative integer",
+                path.display()
+            ),
+        )),
+    }
+}
+
+pub fn parse<T: serde::de::DeserializeOwned>(
+    format: Format,
+    path: &Path,
+    st: &str,
+) -> std::io::Result<T> {
+    let result = match format {
+        Format::Toml => toml::from_str(st).map_err(|e| e.to_string()),
+        Format::Json => serde_json::from_str(st).map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::from_str(st).map_err(|e| e.to_string()),
+    };
+    result.map_err(|e| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Cannot parse {} as {}: {}",
+                path.display(),
+                format.name(),
+                e
+            ),
+        )
+    })
+}
+
+// Tables merge key by key, arrays (steps, probes, hooks) are appended after the included ones,
+// and any other value in the including file replaces the included one.
+pub fn merge(base: &mut toml::Value, over: toml::Value) {
+    match (base, over) {
+        (toml::Value::Table(base), toml::Value::Table(over)) => {
+            for (key, value) in over {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (toml::Value::Array(base), toml::Value::Array(over)) => base.extend(over),
+        (base, over) => *base = over,
+    }
+}
+
+fn load_value(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> std::io::Result<toml::Value> {
+    let canonical = std::fs::canonicalize(path)?;
+    if stack.contains(&canonical) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} includes itself", path.display()),
+        ));
+    }
+    if !files.contains(&canonical) {
+        files.push(canonical.clone());
+    }
+    let st = std::fs::read_to_string(path)?;
+    let mut value: toml::Value = parse(Format::from_path(path)?, path, &st)?;
+    format_version(path, &value)?;
+    let includes = match value.as_table_mut().and_then(|t| t.remove("include")) {
+        Some(toml::Value::String(include)) => vec![include],
+        Some(toml::Value::Array(includes)) => includes
+            .into_iter()
+            .map(|v| match v {
+                toml::Value::String(include) => Ok(include),
+                _ => Err(()),
+            })
+            .collect::<Result<_, _>>()
+            .map_err(|()| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("include in {} must be a list of paths", path.display()),
+                )
+            })?,
+        Some(_) => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("include in {} must be a list of paths", path.display()),
+            ))
+        }
+        None => Vec::new(),
+    };
+
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut merged = toml::Value::Table(toml::value::Table::new());
+    for include in includes {
+        merge(&mut merged, load_value(&dir.join(include), stack, files)?);
+    }
+    stack.pop();
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+// The steps of every phase, by the names the plan gives them
+fn phase_steps(value: &mut toml::Value) -> Vec<(String, &mut toml::value::Table)> {
+    let mut steps = Vec::new();
+    let phases = match value.as_table_mut() {
+        Some(phases) => phases,
+        None => return steps,
+    };
+    for (phase, phase_value) in phases.iter_mut() {
+        let (prefix, key) = match &**phase {
+            "build" | "install" | "test" | "bench" => (&**phase, "step"),
+            "pgo" => ("pgo.train", "train"),
+            _ => continue,
+        };
+        let list = match phase_value.get_mut(key).and_then(|s| s.as_array_mut()) {
+            Some(list) => list,
+            None => continue,
+        };
+        for (i, step) in list.iter_mut().enumerate() {
+            if let Some(step) = step.as_table_mut() {
+                steps.push((format!("{}.{}", prefix, i), step));
+            }
+        }
+    }
+    steps
+}
+
+// An `id` names a step for from-step, --only, [target] overrides and the like. Ids cannot hold a
+// dot, so they never clash with names such as build.0. PGO steps are named by the PGO plan
+fn take_ids(value: &mut toml::Value) -> std::io::Result<HashMap<String, String>> {
+    let mut ids: HashMap<String, String> = HashMap::new();
+    for (name, step) in phase_steps(value) {
+        if name.starts_with("pgo.") {
+            continue;
+        }
+        let id = match step.remove("id") {
+            Some(toml::Value::String(id)) => id,
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("The id of step {} must be a string", name),
+                ))
+            }
+            None => continue,
+        };
+        if id.is_empty()
+            || !id
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+        {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "The id of step {} may only hold letters, digits, - and _",
+                    name
+                ),
+            ));
+        }
+        if let Some((other, _)) = ids.iter().find(|(_, other)| **other == id) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Steps {} and {} have the same id {}", other, name, id),
+            ));
+        }
+        ids.insert(name, id);
+    }
+    Ok(ids)
+}
+
+// Steps are parsed strictly, so their `if` keys are taken out beforehand
+fn take_conditions(value: &mut toml::Value) -> std::io::Result<HashMap<String, String>> {
+    let mut conditions = HashMap::new();
+    for (name, step) in phase_steps(value) {
+        let condition = match step.remove("if") {
+            Some(toml::Value::String(condition)) => condition,
+            Some(_) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("The if of step {} must be a string", name),
+                ))
+            }
+            None => continue,
+        };
+        if crate::overrides::condition(&condition, &HashMap::new()).is_none() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid condition for step {}: {}", name, condition),
+            ));
+        }
+        conditions.insert(name, condition);
+    }
+    Ok(conditions)
+}
+
+// Test steps keep their `retries`, which cargo-nextest uses to rerun flaky tests
+fn take_policies(value: &mut toml::Value) -> std::io::Result<HashMap<String, StepPolicy>> {
+    let mut policies = HashMap::new();
+    for (name, step) in phase_steps(value) {
+        let test = step.get("type").and_then(|t| t.as_str()) == Some("test");
+        let mut policy = toml::value::Table::new();
+        for key in &[
+            "retries",
+            "timeout",
+            "on-failure",
+            "output-mode",
+            "distribute",
+        ] {
+            if *key == "retries" && test {
+                continue;
+            }
+            if let Some(value) = step.remove(*key) {
+                policy.insert(key.to_string(), value);
+            }
+        }
+        if policy.is_empty() {
+            continue;
+        }
+        let policy = toml::Value::Table(policy).try_into().map_err(|e| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Invalid policy for step {}: {}", name, e),
+            )
+        })?;
+        policies.insert(name, policy);
+    }
+    Ok(policies)
+}
+
+pub fn load(path: &Path) -> std::io::Result<Config> {
+    let format = Format::from_path(path)?;
+    let st = std::fs::read_to_string(path)?;
+    let value: toml::Value = parse(format, path, &st)?;
+    match format_version(path, &value)? {
+        Some(FORMAT_VERSION) => {}
+        Some(version) => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} uses format-version {}; run `cargo autobuild migrate` to upgrade it to format-version {}",
+                    path.display(),
+                    version,
+                    FORMAT_VERSION
+                ),
+            ))
+        }
+        None => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "{} has no format-version; run `cargo autobuild migrate` to upgrade it to format-version {}",
+                    path.display(),
+                    FORMAT_VERSION
+                ),
+            ))
+        }
+    }
+    let included = value.get("include").is_some();
+    let mut files = Vec::new();
+    let mut source = if included {
+        load_value(path, &mut Vec::new(), &mut files)?
+    } else {
+        files.push(std::fs::canonicalize(path)?);
+        value
+    };
+    let ids = take_ids(&mut source)?;
+    let conditions = take_conditions(&mut source)?;
+    let policies = take_policies(&mut source)?;
+    let mut config: Config =
+        if included || !ids.is_empty() || !conditions.is_empty() || !policies.is_empty() {
+            source.clone().try_into().map_err(|e| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    if included {
+                        format!("Cannot parse {} with its includes: {}", path.display(), e)
+                    } else {
+                        format!("Cannot parse {}: {}", path.display(), e)
+                    },
+                )
+            })?
+        } else {
+            parse(format, path, &st)?
+        };
+    config.source = Some(source);
+    config.conditions = conditions;
+    config.policies = policies;
+    config.ids = ids;
+    config.files = files;
+    check_programs(path, &config)?;
+    crate::product::expand(&mut config);
+    Ok(config)
+}
+
+// Programs of the types autobuild knows would ignore any extra field, so one there is a typo
+fn check_programs(path: &Path, config: &Config) -> std::io::Result<()> {
+    for (key, program) in &config.programs {
+        if matches!(program.ty, ProgramType::Other(_)) {
+            continue;
+        }
+        if let Some(field) = program.extra.keys().min() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Cannot parse {}: programs.{} has unknown field `{}`; only programs of a custom type take fields of their own",
+                    path.display(),
+                    key,
+                    field
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(st: &str) -> toml::Value {
+        toml::from_str(st).unwrap()
+    }
+
+    // A scratch directory holding the given files
+    fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("autobuild-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        for (file, contents) in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, contents).unwrap();
+        }
+        dir
+    }
+
+    fn commands(config: &Config) -> Vec<String> {
+        config
+            .build
+            .step
+            .iter()
+            .map(|step| match step {
+                Step::Command(step) => step.cmd.display().to_string(),
+                _ => panic!("not a command step"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_appends_arrays() {
+        let mut base = value("hooks = { pre-build = [\"a\"] }\nlist = [1, 2]");
+        merge(
+            &mut base,
+            value("hooks = { pre-build = [\"b\"] }\nlist = [3]"),
+        );
+        assert_eq!(
+            base,
+            value("hooks = { pre-build = [\"a\", \"b\"] }\nlist = [1, 2, 3]")
+        );
+    }
+
+    #[test]
+    fn merge_tables_deeply() {
+        let mut base = value("[options]\nprefix = \"/usr\"\n[options.env]\nA = \"1\"\nB = \"2\"");
+        merge(
+            &mut base,
+            value("[options]\nstatic-link = true\n[options.env]\nB = \"3\""),
+        );
+        assert_eq!(
+            base,
+            value(
+                "[options]\nprefix = \"/usr\"\nstatic-link = true\n[options.env]\nA = \"1\"\nB = \"3\""
+            )
+        );
+    }
+
+    #[test]
+    fn merge_replaces_other_values() {
+        let mut base = value("a = 1\nb = [1]\nc = { d = 1 }");
+        merge(&mut base, value("a = \"x\"\nb = 2\nc = 3"));
+        assert_eq!(base, value("a = \"x\"\nb = 2\nc = 3"));
+    }
+
+    #[test]
+    fn includes_resolve_against_the_including_file() {
+        let dir = project(
+            "include",
+            &[
+                (
+                    "autobuild.toml",
+                    "format-version = 1\ninclude = \"conf/a.toml\"\n\
+                     [[build.step]]\ntype = \"command\"\ncmd = \"top\"\n",
+                ),
+                (
+                    "conf/a.toml",
+                    "format-version = 1\ninclude = [\"b.toml\"]\n\
+                     [[build.step]]\ntype = \"command\"\ncmd = \"a\"\n",
+                ),
+                (
+                    "conf/b.toml",
+                    "format-version = 1\n[[build.step]]\ntype = \"command\"\ncmd = \"b\"\n",
+                ),
+            ],
+        );
+        let config = load(&dir.join("autobuild.toml")).unwrap();
+        assert_eq!(commands(&config), vec!["b", "a", "top"]);
+        let dir = std::fs::canonicalize(&dir).unwrap();
+        assert_eq!(
+            config.files,
+            vec![
+                dir.join("autobuild.toml"),
+                dir.join("conf/a.toml"),
+                dir.join("conf/b.toml")
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn include_cycles_are_refused() {
+        let dir = project(
+            "include-cycle",
+            &[
+                (
+                    "autobuild.toml",
+                    "format-version = 1\ninclude = \"a.toml\"\n",
+                ),
+                ("a.toml", "format-version = 1\ninclude = \"b.toml\"\n"),
+                ("b.toml", "format-version = 1\ninclude = \"a.toml\"\n"),
+            ],
+        );
+        let err = load(&dir.join("autobuild.toml")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("includes itself"), "{}", err);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn includes_may_repeat_without_a_cycle() {
+        let dir = project(
+            "include-twice",
+            &[
+                (
+                    "autobuild.toml",
+                    "format-version = 1\ninclude = [\"a.toml\", \"b.toml\"]\n",
+                ),
+                ("a.toml", "format-version = 1\ninclude = \"common.toml\"\n"),
+                ("b.toml", "format-version = 1\ninclude = \"common.toml\"\n"),
+                (
+                    "common.toml",
+                    "format-version = 1\n[[build.step]]\ntype = \"command\"\ncmd = \"c\"\n",
+                ),
+            ],
+        );
+        let config = load(&dir.join("autobuild.toml")).unwrap();
+        assert_eq!(commands(&config), vec!["c", "c"]);
+        assert_eq!(config.files.len(), 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_custom_programs_take_extra_fields() {
+        let dir = project(
+            "program-fields",
+            &[(
+                "autobuild.toml",
+                "format-version = 1\n[programs.CC]\ntype = \"cc\"\nname = \"gcc\"\n",
+            )],
+        );
+        let err = load(&dir.join("autobuild.toml")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!(err.to_string().contains("unknown field `name`"), "{}", err);
+
+        std::fs::write(
+            dir.join("autobuild.toml"),
+            "format-version = 1\n[programs.DOXYGEN]\ntype = \"doxygen\"\nflavor = \"html\"\n",
+        )
+        .unwrap();
+        let config = load(&dir.join("autobuild.toml")).unwrap();
+        assert_eq!(config.programs["DOXYGEN"].extra["flavor"], "html");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
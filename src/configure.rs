@@ -0,0 +1,2719 @@
+use std::{
+    collections::HashMap,
+    ffi::OsStr,
+    io::{BufRead, ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    time::Instant,
+};
+
+use target_tuples::Target;
+
+use crate::{
+    android::Ndk,
+    apple::Sdk,
+    cli::Options,
+    compat::Flavor,
+    config::{
+        AssembleStep, AuditStep, AuditTool, BuildTarget, CodesignStep, Config, InjectedRustflags,
+        InstallDirectory, InstallTarget, Isolation, ParserGenerator, Profile, ProgramType, Step,
+    },
+    filesystem::Capabilities,
+    handler::Registry,
+    link_audit::Audit,
+    steps::Artifact,
+    tests::{
+        rustc::{RustcTargetInfo, RustcTestsResult},
+        which_any,
+    },
+    timings::Timing,
+};
+
+#[derive(Hash)]
+pub struct BuildProfile {
+    pub name: String,
+    pub cargo_profile: String,
+    pub opt_level: String,
+    pub debug: bool,
+    pub lto: String,
+    pub codegen_units: Option<u32>,
+    pub linker_plugin_lto: bool,
+    pub panic: String,
+    pub rustflags: Vec<String>,
+    // Named by --profile rather than taken by default
+    pub explicit: bool,
+}
+
+impl BuildProfile {
+    pub fn rustc_flags(&self) -> String {
+        let mut flags = vec![format!("-C opt-level={}", self.opt_level)];
+        if self.debug {
+            flags.push("-g".to_string());
+        }
+        if !matches!(&*self.lto, "off" | "false") {
+            flags.push(format!("-C lto={}", self.lto));
+        }
+        if let Some(units) = self.codegen_units {
+            flags.push(format!("-C codegen-units={}", units));
+        }
+        if self.panic != "unwind" {
+            flags.push(format!("-C panic={}", self.panic));
+        }
+        flags.extend(self.rustflags.iter().cloned());
+        flags.join(" ")
+    }
+
+    pub fn cargo_env(&self) -> Vec<(String, String)> {
+        let prefix = format!("CARGO_PROFILE_{}", self.cargo_profile.to_uppercase());
+        let mut env = vec![
+            (format!("{}_OPT_LEVEL", prefix), self.opt_level.clone()),
+            (format!("{}_DEBUG", prefix), self.debug.to_string()),
+            (format!("{}_LTO", prefix), self.lto.clone()),
+            (format!("{}_PANIC", prefix), self.panic.clone()),
+        ];
+        if let Some(units) = self.codegen_units {
+            env.push((format!("{}_CODEGEN_UNITS", prefix), units.to_string()));
+        }
+        env
+    }
+
+    pub fn target_subdir(&self) -> &str {
+        if self.cargo_profile == "dev" {
+            "debug"
+        } else {
+            &self.cargo_profile
+        }
+    }
+}
+
+pub struct TargetSpec {
+    pub path: PathBuf,
+    pub target: Target,
+    pub info: RustcTargetInfo,
+}
+
+// What one host of a matrix build found out about the machine running the build, which the
+// hosts after it take rather than probe again
+#[derive(Clone)]
+pub struct BuildMachine {
+    build: Target,
+    kernel_version: Option<String>,
+    libc: Option<(String, String)>,
+    // Compilers for triples other than the host, which are probed the same way for every host
+    compilers: HashMap<String, RustcTestsResult>,
+}
+
+impl BuildMachine {
+    pub fn of(cfg: &Configuration) -> Self {
+        let var = |name: &str| cfg.vars.get(name).cloned();
+        BuildMachine {
+            build: cfg.build.clone(),
+            kernel_version: var("build-kernel-version"),
+            libc: var("build-libc").zip(var("build-libc-version")),
+            compilers: cfg.compilers.clone(),
+        }
+    }
+}
+
+pub struct Configuration {
+    pub epoch: Instant,
+    pub profile: BuildProfile,
+    pub probe_timings: Vec<Timing>,
+    pub log: HashMap<String, Vec<String>>,
+    pub build: Target,
+    pub host: Target,
+    pub target: Target,
+    pub target_spec: Option<PathBuf>,
+    pub host_cfg: Vec<String>,
+    pub specs: HashMap<PathBuf, TargetSpec>,
+    pub handlers: Registry,
+    pub rustc: RustcTestsResult,
+    // Compilers the configuration maps to targets other than the host, by triple
+    pub compilers: HashMap<String, RustcTestsResult>,
+    // File names on the build machine, which differ from the host's when cross compiling
+    pub build_info: RustcTargetInfo,
+    pub cargo: PathBuf,
+    pub rustc_wrapper: Option<PathBuf>,
+    pub injected_rustflags: InjectedRustflags,
+    pub rustdocflags: Vec<String>,
+    pub ccache: Option<PathBuf>,
+    pub llvm_profdata: Option<PathBuf>,
+    pub sanitizers: Vec<String>,
+    pub static_link: bool,
+    // The hardening features the toolchain lacks, when hardening was requested
+    pub hardening: Option<Vec<String>>,
+    pub target_cpu: Option<String>,
+    pub target_features: Vec<String>,
+    pub linker: Option<PathBuf>,
+    pub lipo: Option<PathBuf>,
+    pub codesign: Option<PathBuf>,
+    pub notarytool: Option<PathBuf>,
+    pub rc: Option<PathBuf>,
+    pub objcopy: Option<PathBuf>,
+    pub msgfmt: Option<PathBuf>,
+    pub setcap: Option<PathBuf>,
+    pub nextest: Option<PathBuf>,
+    pub cargo_fuzz: Option<PathBuf>,
+    pub smoke_tests: bool,
+    pub clippy: bool,
+    pub rustfmt: bool,
+    pub cargo_audit: Option<PathBuf>,
+    pub cargo_deny: Option<PathBuf>,
+    pub sandbox: Option<PathBuf>,
+    pub ssh: Option<PathBuf>,
+    pub rsync: Option<PathBuf>,
+    pub offline: bool,
+    pub downloader: Option<PathBuf>,
+    pub download_cache: Option<PathBuf>,
+    pub patch: Option<PathBuf>,
+    pub tar: Option<PathBuf>,
+    pub unzip: Option<PathBuf>,
+    // SHA-256 sums of the files fetch steps download, by path
+    pub checksums: HashMap<PathBuf, String>,
+    pub bindgen: Option<PathBuf>,
+    pub protoc: Option<PathBuf>,
+    pub protoc_plugins: HashMap<String, PathBuf>,
+    pub flatc: Option<PathBuf>,
+    pub cbindgen: Option<PathBuf>,
+    pub yacc: Option<PathBuf>,
+    pub lex: Option<PathBuf>,
+    pub assembler: Option<PathBuf>,
+    pub cc: Option<PathBuf>,
+    pub ar: Option<PathBuf>,
+    pub desktop_file_validate: Option<PathBuf>,
+    pub appstreamcli: Option<PathBuf>,
+    pub link_audit: Option<Audit>,
+    pub toolchain_env: HashMap<String, String>,
+    pub ndk: Option<Ndk>,
+    pub runner: Option<PathBuf>,
+    pub apple_sdk: Option<Sdk>,
+    pub programs: HashMap<String, PathBuf>,
+    pub tool_env: HashMap<String, String>,
+    pub dirs: HashMap<InstallDirectory, PathBuf>,
+    // Versioned library names are copies where the build filesystem cannot hold symbolic links;
+    // the install filesystem is only probed by the steps that install links
+    pub build_fs: Capabilities,
+    pub vars: HashMap<String, String>,
+    // The --enable, --disable, --with and --without options by their spelling in ${option:...}
+    pub options: HashMap<String, String>,
+    pub step_artifacts: HashMap<String, Vec<Artifact>>,
+    // The artifact variables of the instrumented builds, which the PGO steps see in place of the
+    // optimized builds' own
+    pub pgo_vars: HashMap<String, String>,
+    // Variables planned steps export to cargo, such as the path of generated bindings
+    pub build_env: HashMap<String, String>,
+    // Generated code that cargo builds read, so they rebuild when it changes
+    pub generated: Vec<PathBuf>,
+    pub src_dir: PathBuf,
+    pub build_dir: PathBuf,
+    // CARGO_TARGET_DIR for cargo steps; each workspace's own target directory when unset
+    pub target_dir: Option<PathBuf>,
+    pub scratch_dir: PathBuf,
+}
+
+impl Configuration {
+    pub fn resolve_target<'a>(&'a self, target: &'a BuildTarget) -> &'a Target {
+        match target {
+            BuildTarget::Build => &self.build,
+            BuildTarget::Host => &self.host,
+            BuildTarget::Target => &self.target,
+            BuildTarget::Input(target) => target,
+            BuildTarget::Spec(path) => &self.specs[path].target,
+        }
+    }
+
+    pub fn spec_for(&self, target: &BuildTarget) -> Option<&Path> {
+        match target {
+            BuildTarget::Spec(path) => Some(&self.specs[path].path),
+            target if *self.resolve_target(target) == self.host => self.target_spec.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn rustc_for(&self, target: &BuildTarget) -> &RustcTestsResult {
+        self.compilers
+            .get(self.resolve_target(target).get_name())
+            .unwrap_or(&self.rustc)
+    }
+
+    pub fn target_info(&self, target: &BuildTarget) -> &RustcTargetInfo {
+        match target {
+            BuildTarget::Spec(path) => &self.specs[path].info,
+            target
+                if self
+                    .compilers
+                    .contains_key(self.resolve_target(target).get_name()) =>
+            {
+                &self.rustc_for(target).target_info
+            }
+            target
+                if *self.resolve_target(target) == self.build
+                    && self.spec_for(target).is_none() =>
+            {
+                &self.build_info
+            }
+            _ => &self.rustc.target_info,
+        }
+    }
+
+    pub fn install_path(&self, target: &InstallTarget) -> std::io::Result<PathBuf> {
+        resolve_install_target(&self.dirs, target)
+    }
+}
+
+struct Probes {
+    epoch: Instant,
+    current: Option<(String, Instant)>,
+    spinner: Option<crate::ui::Spinner>,
+    timings: Vec<Timing>,
+    log: HashMap<String, Vec<String>>,
+}
+
+impl Probes {
+    fn log(&mut self, subject: &str) -> &mut Vec<String> {
+        self.log.entry(subject.to_string()).or_default()
+    }
+
+    fn finish(&mut self) {
+        self.spinner = None;
+        if let Some((name, start)) = self.current.take() {
+            let mut timing = Timing::new(&name, self.epoch, start, start.elapsed());
+            timing.status = "probe".to_string();
+            self.timings.push(timing);
+        }
+    }
+
+    fn checking(&mut self, what: &str) {
+        self.finish();
+        print!("checking {}... ", what);
+        let _ = std::io::stdout().flush();
+        self.current = Some((what.to_string(), Instant::now()));
+        self.spinner = crate::ui::Spinner::start();
+    }
+}
+
+fn parse_target(name: &str) -> std::io::Result<Target> {
+    Target::from_str(name).map_err(|_| {
+        std::io::Error::new(ErrorKind::InvalidInput, format!("Unknown target {}", name))
+    })
+}
+
+fn load_spec(path: &Path, rustc: &RustcTestsResult, tmpdir: &Path) -> std::io::Result<TargetSpec> {
+    if !path.is_file() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("Target specification {} does not exist", path.display()),
+        ));
+    }
+    let json: serde_json::Value = serde_json::from_slice(&std::fs::read(path)?).map_err(|e| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Cannot parse {}: {}", path.display(), e),
+        )
+    })?;
+    let llvm_target = json
+        .get("llvm-target")
+        .and_then(|t| t.as_str())
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("{} does not name an llvm-target", path.display()),
+            )
+        })?;
+    Ok(TargetSpec {
+        target: parse_target(llvm_target)?,
+        info: crate::tests::rustc::spec_target_info(rustc, path, tmpdir)?,
+        path: path.to_owned(),
+    })
+}
+
+fn guess_build() -> std::io::Result<Target> {
+    let rustc = match std::env::var_os("RUSTC") {
+        Some(rustc) => PathBuf::from(rustc),
+        None => which_any(&["rustc"])?,
+    };
+    let output = Command::new(&rustc).arg("-vV").output()?;
+    for line in output.stdout.lines() {
+        if let Some(host) = line?.strip_prefix("host: ") {
+            return parse_target(host);
+        }
+    }
+
+    Err(std::io::Error::new(
+        ErrorKind::NotFound,
+        "Cannot guess build system type; use --build",
+    ))
+}
+
+fn standard_dirs() -> Vec<(InstallDirectory, Option<InstallDirectory>, &'static str)> {
+    use InstallDirectory::*;
+    vec![
+        (Prefix, None, "/usr/local"),
+        (ExecPrefix, Some(Prefix), ""),
+        (BinDir, Some(ExecPrefix), "bin"),
+        (SbinDir, Some(ExecPrefix), "sbin"),
+        (LibexecDir, Some(ExecPrefix), "libexec"),
+        (LibDir, Some(ExecPrefix), "lib"),
+        (IncludeDir, Some(Prefix), "include"),
+        (DatarootDir, Some(Prefix), "share"),
+        (DataDir, Some(DatarootDir), ""),
+        (DocDir, Some(DatarootDir), "doc"),
+        (InfoDir, Some(DatarootDir), "info"),
+        (ManDir, Some(DatarootDir), "man"),
+        (HtmlDir, Some(DocDir), ""),
+        (PdfDir, Some(DocDir), ""),
+        (DviDir, Some(DocDir), ""),
+        (LocaleDir, Some(DatarootDir), "locale"),
+        (LocalStateDir, Some(Prefix), "var"),
+        (SharedStateDir, Some(Prefix), "com"),
+        (RunStateDir, Some(LocalStateDir), "run"),
+        (SysconfDir, Some(Prefix), "etc"),
+    ]
+}
+
+fn resolve_dirs(
+    config: &Config,
+    opts: &Options,
+    package: &str,
+    probes: &mut Probes,
+) -> std::io::Result<HashMap<InstallDirectory, PathBuf>> {
+    let mut dirs = HashMap::new();
+
+    for (dir, base, path) in standard_dirs() {
+        let log = probes.log(dir.name());
+        let resolved = if let Some(path) = opts.dirs.get(dir.name()) {
+            log.push(format!("--{} was given on the command line", dir.name()));
+            path.clone()
+        } else if let Some(target) = config.dirs.dirs.get(dir.name()) {
+            log.push(format!("set by [dirs] in {}", opts.config.display()));
+            resolve_install_target(&dirs, target)?
+        } else {
+            match base {
+                Some(base) => {
+                    log.push(format!("defaults to ${}/{}", base.name(), path));
+                    let mut resolved = dirs[&base].join(path);
+                    if dir == InstallDirectory::DocDir {
+                        log.push(format!("the package name {} is appended", package));
+                        resolved.push(package);
+                    }
+                    resolved
+                }
+                None => {
+                    log.push(format!("defaults to {}", path));
+                    PathBuf::from(path)
+                }
+            }
+        };
+        log.push(format!("resolved to {}", resolved.display()));
+        dirs.insert(dir, resolved);
+    }
+
+    for (name, target) in &config.dirs.dirs {
+        let dir = InstallDirectory::from_name(name);
+        if let InstallDirectory::Custom(_) = dir {
+            let resolved = resolve_install_target(&dirs, target)?;
+            let log = probes.log(name);
+            log.push(format!("set by [dirs] in {}", opts.config.display()));
+            log.push(format!("resolved to {}", resolved.display()));
+            dirs.insert(dir, resolved);
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn resolve_install_target(
+    dirs: &HashMap<InstallDirectory, PathBuf>,
+    target: &InstallTarget,
+) -> std::io::Result<PathBuf> {
+    match target {
+        InstallTarget::Base { base, path } => {
+            dirs.get(base).map(|dir| dir.join(path)).ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Unknown install directory ${}", base.name()),
+                )
+            })
+        }
+        InstallTarget::Absolute(path) => crate::paths::anchor(
+            path,
+            dirs.get(&InstallDirectory::Prefix).map_or(path, |p| p),
+        ),
+    }
+    .map(|path| crate::paths::long(&path))
+}
+
+fn default_names(ty: &ProgramType, host: &Target) -> Vec<String> {
+    let names: &[&str] = match ty {
+        ProgramType::Rustc => &["rustc"],
+        ProgramType::Cargo => &["cargo"],
+        ProgramType::Cc => &["cc", "gcc", "clang"],
+        ProgramType::Cxx => &["c++", "g++", "clang++"],
+        ProgramType::As => &["as"],
+        ProgramType::Ar if host.get_name().ends_with("msvc") => &["lib", "llvm-lib"],
+        ProgramType::Ar => &["ar"],
+        ProgramType::Ld if host.get_name().ends_with("msvc") => &["lld-link", "link"],
+        ProgramType::Ld => &["mold", "ld.lld", "ld", "ld.gold"],
+        ProgramType::Objdump => &["objdump"],
+        ProgramType::Objcopy => &["objcopy", "llvm-objcopy", "rust-objcopy"],
+        ProgramType::Strip => &["strip"],
+        ProgramType::Ln | ProgramType::LnS => &["ln"],
+        ProgramType::Install => &["install"],
+        ProgramType::Yacc => &["bison", "yacc", "byacc"],
+        ProgramType::Lex => &["flex", "lex"],
+        ProgramType::Codesign => &["codesign"],
+        ProgramType::Notarytool => &["notarytool"],
+        ProgramType::Rc if host.get_name().ends_with("msvc") => &["rc", "llvm-rc"],
+        ProgramType::Rc => &["windres", "llvm-windres"],
+        ProgramType::Msgfmt => &["msgfmt"],
+        ProgramType::CargoNextest => &["cargo-nextest"],
+        ProgramType::CargoFuzz => &["cargo-fuzz"],
+        ProgramType::CargoAudit => &["cargo-audit"],
+        ProgramType::CargoDeny => &["cargo-deny"],
+        ProgramType::Patch => &["patch", "gpatch"],
+        ProgramType::Bindgen => &["bindgen"],
+        ProgramType::Protoc => &["protoc"],
+        ProgramType::Flatc => &["flatc"],
+        ProgramType::Cbindgen => &["cbindgen"],
+        ProgramType::Other(name) => return vec![name.clone()],
+    };
+    names.iter().map(|s| s.to_string()).collect()
+}
+
+fn find_program(
+    key: &str,
+    names: &[String],
+    host: &Target,
+    cross_compiling: bool,
+    log: &mut Vec<String>,
+) -> std::io::Result<PathBuf> {
+    if let Some(path) = std::env::var_os(key) {
+        log.push(format!("using ${} from the environment", key));
+        return Ok(PathBuf::from(path));
+    }
+    log.push(format!("${} is not set", key));
+    let mut candidates = Vec::new();
+    if cross_compiling {
+        candidates.extend(names.iter().map(|n| format!("{}-{}", host.get_name(), n)));
+    }
+    candidates.extend(names.iter().cloned());
+    log.push(format!("searching PATH for {}", candidates.join(", ")));
+    for name in &candidates {
+        if which_any(&[name]).is_err() {
+            log.push(format!("rejected {}: not found in PATH", name));
+        }
+    }
+    let result = which_any(&candidates);
+    match &result {
+        Ok(path) => log.push(format!("selected {}", path.display())),
+        Err(_) => log.push("no candidate was found".to_string()),
+    }
+    result
+}
+
+fn builtin_profile(name: &str) -> Option<Profile> {
+    let (opt_level, debug) = match name {
+        "dev" => ("0", true),
+        "release" => ("3", false),
+        _ => return None,
+    };
+    Some(Profile {
+        inherits: None,
+        opt_level: Some(opt_level.to_string()),
+        debug: Some(debug),
+        lto: Some("false".to_string()),
+        codegen_units: None,
+        linker_plugin_lto: Some(false),
+        panic: Some("unwind".to_string()),
+        rustflags: Vec::new(),
+    })
+}
+
+fn resolve_profile(config: &Config, name: &str) -> std::io::Result<BuildProfile> {
+    let mut chain = Vec::new();
+    let mut cur = name.to_string();
+    let (cargo_profile, mut merged) = loop {
+        if chain.len() > config.profiles.len() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Profile {} inherits from itself", name),
+            ));
+        }
+        let profile = config.profiles.get(&cur);
+        if let Some(builtin) = builtin_profile(&cur) {
+            chain.extend(profile);
+            break (cur, builtin);
+        }
+        let profile = profile.ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, format!("Unknown profile {}", cur))
+        })?;
+        chain.push(profile);
+        cur = profile
+            .inherits
+            .clone()
+            .unwrap_or_else(|| "release".to_string());
+    };
+
+    for profile in chain.into_iter().rev() {
+        merged.opt_level = profile.opt_level.clone().or(merged.opt_level);
+        merged.debug = profile.debug.or(merged.debug);
+        merged.lto = profile.lto.clone().or(merged.lto);
+        merged.codegen_units = profile.codegen_units.or(merged.codegen_units);
+        merged.linker_plugin_lto = profile.linker_plugin_lto.or(merged.linker_plugin_lto);
+        merged.panic = profile.panic.clone().or(merged.panic);
+        merged.rustflags.extend(profile.rustflags.iter().cloned());
+    }
+
+    let lto = merged.lto.unwrap_or_default();
+    if !matches!(
+        &*lto,
+        "off" | "false" | "true" | "thin" | "fat" | "yes" | "no"
+    ) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid lto setting {} in profile {}", lto, name),
+        ));
+    }
+
+    Ok(BuildProfile {
+        name: name.to_string(),
+        cargo_profile,
+        opt_level: merged.opt_level.unwrap_or_default(),
+        debug: merged.debug.unwrap_or_default(),
+        lto,
+        codegen_units: merged.codegen_units,
+        linker_plugin_lto: merged.linker_plugin_lto.unwrap_or_default(),
+        panic: merged.panic.unwrap_or_default(),
+        rustflags: merged.rustflags,
+        explicit: false,
+    })
+}
+
+fn find_linker(
+    key: &str,
+    names: &[String],
+    host: &Target,
+    cross_compiling: bool,
+    rustc: &RustcTestsResult,
+    tmpdir: &Path,
+    log: &mut Vec<String>,
+) -> std::io::Result<(PathBuf, Vec<String>)> {
+    let msvc = host.get_name().ends_with("msvc");
+    let candidates = match std::env::var_os(key) {
+        Some(path) => {
+            log.push(format!("using ${} from the environment", key));
+            vec![PathBuf::from(path)]
+        }
+        None => {
+            log.push(format!("${} is not set", key));
+            let mut names = names.to_vec();
+            if cross_compiling {
+                names = names
+                    .iter()
+                    .map(|n| format!("{}-{}", host.get_name(), n))
+                    .chain(names.iter().cloned())
+                    .collect();
+            }
+            log.push(format!("searching PATH for {}", names.join(", ")));
+            names
+                .iter()
+                .filter_map(|n| match which_any(&[n]) {
+                    Ok(path) => Some(path),
+                    Err(_) => {
+                        log.push(format!("rejected {}: not found in PATH", n));
+                        None
+                    }
+                })
+                .collect()
+        }
+    };
+    // Compilers that cannot be told -Clinker are trusted to drive the linker they were given
+    let untested = !Flavor::of(&rustc.version).accepts_rustc_flags();
+    for path in candidates {
+        match crate::tests::linker::rustc_args(&path, msvc) {
+            Some(args) if untested => {
+                log.push(format!("selected {} without a test link", path.display()));
+                return Ok((path, args));
+            }
+            Some(args) => {
+                if crate::tests::linker::links_with(rustc, &args, tmpdir)? {
+                    log.push(format!("selected {} ({})", path.display(), args.join(" ")));
+                    return Ok((path, args));
+                }
+                log.push(format!(
+                    "rejected {}: rustc could not link with it",
+                    path.display()
+                ));
+            }
+            None => log.push(format!(
+                "rejected {}: not a linker rustc can drive",
+                path.display()
+            )),
+        }
+    }
+    Err(std::io::Error::new(
+        ErrorKind::NotFound,
+        format!("Cannot find a working linker for {} among {:?}", key, names),
+    ))
+}
+
+fn program_of_type(
+    config: &Config,
+    programs: &HashMap<String, PathBuf>,
+    pred: impl Fn(&ProgramType) -> bool,
+) -> Option<PathBuf> {
+    config
+        .programs
+        .iter()
+        .find(|(_, prg)| pred(&prg.ty))
+        .and_then(|(key, _)| programs.get(key).cloned())
+}
+
+fn feature(opts: &Options, name: &str, config: Option<bool>) -> Option<bool> {
+    opts.features.get(name).copied().or(config)
+}
+
+// --disable-foo and --without-foo read as `enable-foo = "no"` and `with-foo = "no"`
+fn configure_options(opts: &Options) -> HashMap<String, String> {
+    let mut options: HashMap<String, String> = opts
+        .features
+        .iter()
+        .map(|(name, on)| {
+            let value = if *on { "yes" } else { "no" };
+            (format!("enable-{}", name), value.to_string())
+        })
+        .collect();
+    options.extend(
+        opts.packages
+            .iter()
+            .map(|(name, value)| (format!("with-{}", name), value.clone())),
+    );
+    options
+}
+
+// A program named in the configuration is a script in the source tree or a program in PATH
+fn configured_program(path: &Path, src_dir: &Path) -> std::io::Result<PathBuf> {
+    if path.components().count() > 1 {
+        Ok(src_dir.join(path))
+    } else {
+        which_any(&[path])
+    }
+}
+
+fn find_rustc_wrapper(
+    configured: Option<PathBuf>,
+    enable: Option<bool>,
+) -> std::io::Result<Option<PathBuf>> {
+    if configured.is_some() {
+        return Ok(configured);
+    }
+    if enable == Some(false) {
+        return Ok(None);
+    }
+    match std::env::var_os("RUSTC_WRAPPER") {
+        Some(wrapper) if !wrapper.is_empty() => return Ok(Some(PathBuf::from(wrapper))),
+        _ => {}
+    }
+    match which_any(&["sccache"]) {
+        Ok(sccache) => Ok(Some(sccache)),
+        Err(e) if enable == Some(true) => Err(std::io::Error::new(
+            e.kind(),
+            "sccache was requested with --enable-sccache but could not be found",
+        )),
+        Err(_) => Ok(None),
+    }
+}
+
+// A feature's name with the rustc flags, C compiler flags and linker flags that turn it on
+type Hardening = (
+    &'static str,
+    &'static [&'static str],
+    &'static [&'static str],
+    &'static [&'static str],
+);
+
+const HARDENING: &[Hardening] = &[
+    (
+        "full RELRO",
+        &["-Crelro-level=full"],
+        &[],
+        &["-Wl,-z,relro"],
+    ),
+    (
+        "immediate binding",
+        &["-Clink-arg=-Wl,-z,now"],
+        &[],
+        &["-Wl,-z,now"],
+    ),
+    (
+        "stack protection",
+        &["-Zstack-protector=strong"],
+        &["-fstack-protector-strong"],
+        &[],
+    ),
+    (
+        "position-independent executables",
+        &["-Crelocation-model=pie"],
+        &["-fPIE"],
+        &["-pie"],
+    ),
+    ("source fortification", &[], &["-D_FORTIFY_SOURCE=2"], &[]),
+];
+
+fn sanitizer_name(name: &str) -> std::io::Result<&'static str> {
+    match name {
+        "address" | "asan" => Ok("address"),
+        "thread" | "tsan" => Ok("thread"),
+        "memory" | "msan" => Ok("memory"),
+        "undefined" | "ubsan" => Ok("undefined"),
+        "leak" | "lsan" => Ok("leak"),
+        name => Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Unknown sanitizer {}", name),
+        )),
+    }
+}
+
+fn append_flags(tool_env: &mut HashMap<String, String>, var: &str, flags: &str) {
+    let mut value = tool_env
+        .get(var)
+        .cloned()
+        .or_else(|| std::env::var(var).ok())
+        .unwrap_or_default();
+    if !value.is_empty() {
+        value.push(' ');
+    }
+    value.push_str(flags);
+    tool_env.insert(var.to_string(), value);
+}
+
+fn find_llvm_profdata(rustc: &RustcTestsResult) -> std::io::Result<PathBuf> {
+    if let Some(path) = std::env::var_os("LLVM_PROFDATA") {
+        return Ok(PathBuf::from(path));
+    }
+    let output = Command::new(&rustc.rustc)
+        .arg("--print")
+        .arg("sysroot")
+        .output()?;
+    if output.status.success() {
+        let sysroot = String::from_utf8_lossy(&output.stdout);
+        let mut path = PathBuf::from(sysroot.trim());
+        path.push("lib/rustlib");
+        path.push(&rustc.target_info.target);
+        path.push("bin");
+        path.push("llvm-profdata");
+        path.set_extension(std::env::consts::EXE_EXTENSION);
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    which_any(&["llvm-profdata"]).map_err(|e| {
+        std::io::Error::new(
+            e.kind(),
+            "PGO was requested but llvm-profdata could not be found; install the llvm-tools component",
+        )
+    })
+}
+
+pub fn is_sccache(wrapper: &Path) -> bool {
+    wrapper.file_stem().is_some_and(|stem| stem == "sccache")
+}
+
+pub fn print_summary(cfg: &Configuration) {
+    println!();
+    println!("Configuration summary:");
+    println!("  build:             {}", cfg.build);
+    println!("  host:              {}", cfg.host);
+    println!("  target:            {}", cfg.target);
+    if let Some(spec) = &cfg.target_spec {
+        println!("  target spec:       {}", spec.display());
+    }
+    if cfg.rustc.no_std {
+        println!("  standard library:  no (core only)");
+    }
+    println!("  profile:           {}", cfg.profile.name);
+    println!("  rustc:             {}", cfg.rustc.rustc.display());
+    println!(
+        "  prefix:            {}",
+        cfg.dirs[&InstallDirectory::Prefix].display()
+    );
+    match &cfg.rustc_wrapper {
+        Some(wrapper) if is_sccache(wrapper) => {
+            println!("  compilation cache: sccache ({})", wrapper.display())
+        }
+        Some(wrapper) => println!("  compilation cache: none (wrapper {})", wrapper.display()),
+        None => println!("  compilation cache: none"),
+    }
+    if let Some(linker) = &cfg.linker {
+        println!("  linker:            {}", linker.display());
+    }
+    if let Some(runner) = &cfg.runner {
+        println!("  runner:            {}", runner.display());
+    }
+    if let Some(sdk) = &cfg.apple_sdk {
+        println!(
+            "  SDK:               {} {}",
+            sdk.name,
+            sdk.min_version.as_deref().unwrap_or("")
+        );
+    }
+    if let Some(ndk) = &cfg.ndk {
+        println!(
+            "  Android NDK:       {} (API level {})",
+            ndk.root.display(),
+            ndk.api
+        );
+    }
+    match &cfg.ccache {
+        Some(ccache) => println!("  C/C++ cache:       ccache ({})", ccache.display()),
+        None => println!("  C/C++ cache:       none"),
+    }
+    if cfg.llvm_profdata.is_some() {
+        println!("  PGO:               yes");
+    }
+    if !cfg.sanitizers.is_empty() {
+        println!("  sanitizers:        {}", cfg.sanitizers.join(", "));
+    }
+    if cfg.static_link {
+        println!("  linking:           static");
+    }
+    match &cfg.hardening {
+        Some(missing) if missing.is_empty() => println!("  hardening:         yes"),
+        Some(missing) => println!(
+            "  hardening:         partial (lacks {})",
+            missing.join(", ")
+        ),
+        None => {}
+    }
+    if let Some(audit) = &cfg.link_audit {
+        println!("  link audit:        {}", audit.tool.display());
+    }
+    if let Some(nextest) = &cfg.nextest {
+        println!("  test runner:       cargo-nextest ({})", nextest.display());
+    }
+    if let Some(cpu) = &cfg.target_cpu {
+        println!("  target cpu:        {}", cpu);
+    }
+    if !cfg.target_features.is_empty() {
+        println!("  target features:   {}", cfg.target_features.join(","));
+    }
+    println!();
+}
+
+// --build-dir is taken relative to the source directory, not to where we were run from
+pub fn build_dir(opts: &Options, src_dir: &Path) -> PathBuf {
+    crate::paths::long(&match &opts.build_dir {
+        Some(dir) => src_dir.join(dir),
+        None => src_dir.join(".autobuild"),
+    })
+}
+
+pub fn configure(
+    config: &Config,
+    opts: &Options,
+    machine: Option<&BuildMachine>,
+) -> std::io::Result<Configuration> {
+    let epoch = Instant::now();
+    let mut probes = Probes {
+        epoch,
+        current: None,
+        spinner: None,
+        timings: Vec::new(),
+        log: HashMap::new(),
+    };
+    let src_dir = std::fs::canonicalize(opts.src_dir())?;
+    let build_dir = build_dir(opts, &src_dir);
+    let scratch_dir = match opts
+        .scratch_dir
+        .as_ref()
+        .or(config.options.scratch_dir.as_ref())
+    {
+        Some(dir) => crate::paths::long(&src_dir.join(dir)),
+        None => build_dir.clone(),
+    };
+    let tmpdir = scratch_dir.join("tmp");
+    std::fs::create_dir_all(&tmpdir)?;
+
+    probes.checking("build system type");
+    let build = match (&opts.build, machine) {
+        (Some(name), _) => parse_target(name)?,
+        (None, Some(machine)) => {
+            print!("(cached) ");
+            machine.build.clone()
+        }
+        (None, None) => guess_build()?,
+    };
+    println!("{}", build);
+
+    probes.checking("host system type");
+    let mut host = match &opts.host {
+        Some(name) => parse_target(name)?,
+        None => build.clone(),
+    };
+    println!("{}", host);
+
+    probes.checking("target system type");
+    let mut target = match &opts.target {
+        Some(name) => parse_target(name)?,
+        None => host.clone(),
+    };
+    println!("{}", target);
+
+    let mut cross_compiling = host != build;
+
+    let mut profile = resolve_profile(config, opts.profile.as_deref().unwrap_or("dev"))?;
+    profile.explicit = opts.profile.is_some();
+    let static_link = opts.static_link || config.options.static_link;
+
+    let ndk = if crate::android::is_android(&host) {
+        probes.checking("for the Android NDK");
+        let api = config
+            .target
+            .get(host.get_name())
+            .and_then(|t| t.api_level)
+            .unwrap_or(crate::android::DEFAULT_API_LEVEL);
+        let ndk = crate::android::detect(&host, api)?;
+        println!("{}", ndk.root.display());
+        profile
+            .rustflags
+            .push(format!("-Clinker={}", ndk.clang.display()));
+        Some(ndk)
+    } else {
+        None
+    };
+
+    let apple_sdk = if crate::apple::is_apple_mobile(&host) {
+        probes.checking(&format!("for the {} SDK", host));
+        let min_version = config
+            .target
+            .get(host.get_name())
+            .and_then(|t| t.min_os_version.clone());
+        let sdk = crate::apple::detect(&host, min_version)?;
+        if let Some(sdk) = &sdk {
+            println!("{}", sdk.path.display());
+        }
+        sdk
+    } else {
+        None
+    };
+    let can_execute = !cross_compiling && apple_sdk.is_none();
+
+    let host_options = config.target.get(host.get_name());
+    let target_spec = match host_options.and_then(|t| t.spec.as_ref()) {
+        Some(spec) => {
+            let spec = src_dir.join(spec);
+            if !spec.is_file() {
+                return Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!("Target specification {} does not exist", spec.display()),
+                ));
+            }
+            Some(spec)
+        }
+        None => None,
+    };
+
+    let rustc_var = config
+        .programs
+        .iter()
+        .find(|(_, prg)| matches!(prg.ty, ProgramType::Rustc))
+        .map(|(key, _)| key.as_str())
+        .unwrap_or("RUSTC");
+
+    let mut compilers = Vec::new();
+    for (key, path) in &config.compilers {
+        let triple = match &**key {
+            "$build" => build.clone(),
+            "$host" => host.clone(),
+            "$target" => target.clone(),
+            name => parse_target(name)?,
+        };
+        compilers.push((triple, configured_program(path, &src_dir)?));
+    }
+    let compiler_for = |triple: &Target| {
+        compilers
+            .iter()
+            .find(|(t, _)| t == triple)
+            .map(|(_, path)| path.as_path())
+    };
+
+    probes.checking("for a Rust compiler");
+    let mut rustc = crate::tests::rustc::find_compiler(
+        OsStr::new(rustc_var),
+        &profile.rustc_flags(),
+        &host,
+        target_spec.as_deref(),
+        !can_execute,
+        compiler_for(&host),
+        &tmpdir,
+    )?;
+    println!("{}", rustc.rustc.display());
+    probes.checking("which kind of Rust compiler it is");
+    let rustc_flavor = Flavor::of(&rustc.version);
+    println!("{}", rustc_flavor.name());
+    probes.checking(&format!("whether {} has a standard library", host));
+    println!(
+        "{}",
+        if rustc.no_std {
+            "no (core only)"
+        } else {
+            "yes"
+        }
+    );
+    let build_info = if build == host {
+        rustc.target_info.clone()
+    } else {
+        probes.checking(&format!("for the executable suffix of {}", build));
+        let info = crate::tests::rustc::build_target_info(&rustc, &build, &tmpdir)?;
+        match info.exe_suffix.to_str() {
+            Some("") | None => println!("none"),
+            Some(suffix) => println!("{}", suffix),
+        }
+        info
+    };
+
+    if let Some(script) = host_options.and_then(|t| t.linker_script.as_ref()) {
+        let script = src_dir.join(script);
+        if !script.is_file() {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("Linker script {} does not exist", script.display()),
+            ));
+        }
+        profile
+            .rustflags
+            .push(format!("-Clink-arg=-T{}", script.display()));
+    }
+
+    if static_link && opts.host.is_none() && host.get_name().contains("-linux-gnu") {
+        let musl = parse_target(&host.get_name().replace("-linux-gnu", "-linux-musl"))?;
+        probes.checking(&format!("whether rustc can build for {}", musl));
+        match crate::tests::rustc::find_compiler(
+            OsStr::new(rustc_var),
+            &profile.rustc_flags(),
+            &musl,
+            None,
+            true,
+            compiler_for(&musl),
+            &tmpdir,
+        ) {
+            Ok(musl_rustc) => {
+                println!("yes");
+                if opts.target.is_none() {
+                    target = musl.clone();
+                }
+                host = musl;
+                rustc = musl_rustc;
+                cross_compiling = true;
+            }
+            Err(_) => println!("no"),
+        }
+    }
+
+    if static_link {
+        profile
+            .rustflags
+            .push("-Ctarget-feature=+crt-static".to_string());
+        probes.checking("whether binaries are fully static");
+        // Kept until the binary has been inspected
+        let work = crate::tests::WorkDir::new(&tmpdir, "statictest")?;
+        let test_binary = work.check(crate::tests::static_link::build_test_binary(
+            &rustc,
+            work.path(),
+        ))?;
+        match work.check(crate::elf::dynamic_info(&test_binary))? {
+            Some(info) if !info.is_static() => {
+                println!("no");
+                return Err(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Cannot link fully static binaries for {}; test program depends on {}",
+                        host,
+                        if info.needed.is_empty() {
+                            "a dynamic loader".to_string()
+                        } else {
+                            info.needed.join(", ")
+                        }
+                    ),
+                ));
+            }
+            Some(_) => println!("yes"),
+            None => println!("unknown"),
+        }
+    }
+
+    // Each is probed like the host's; RUSTC_<triple> overrides the configured path
+    let mut target_compilers = HashMap::new();
+    for (triple, path) in compilers.iter().filter(|(t, _)| *t != host) {
+        probes.checking(&format!("for a Rust compiler for {}", triple));
+        let found = match machine.and_then(|m| m.compilers.get(triple.get_name())) {
+            Some(found) => {
+                print!("(cached) ");
+                found.clone()
+            }
+            None => crate::tests::rustc::find_compiler(
+                OsStr::new(&format!("RUSTC_{}", triple.get_name().replace('-', "_"))),
+                &profile.rustc_flags(),
+                triple,
+                None,
+                *triple != build,
+                Some(path),
+                &tmpdir,
+            )?,
+        };
+        println!(
+            "{} ({})",
+            found.rustc.display(),
+            Flavor::of(&found.version).name()
+        );
+        target_compilers.insert(triple.get_name().to_string(), found);
+    }
+
+    let host_cfg = if config.target.keys().any(|k| k.starts_with("cfg(")) {
+        probes.checking(&format!("cfg values for {}", host));
+        if rustc_flavor.accepts_rustc_flags() {
+            let host_cfg = crate::tests::codegen::cfg(&rustc)?;
+            println!("{} found", host_cfg.len());
+            host_cfg
+        } else {
+            println!("unknown ({} cannot print them)", rustc_flavor.name());
+            Vec::new()
+        }
+    } else {
+        Vec::new()
+    };
+
+    let target_options = config.target.get(host.get_name());
+    let mut target_cpu = opts
+        .target_cpu
+        .clone()
+        .or_else(|| target_options.and_then(|t| t.cpu.clone()));
+    let target_features = opts
+        .target_features
+        .clone()
+        .or_else(|| target_options.map(|t| t.features.clone()))
+        .unwrap_or_default();
+    if target_cpu.is_some() || !target_features.is_empty() {
+        let mut args = Vec::new();
+        if let Some(cpu) = &target_cpu {
+            args.push(format!("-Ctarget-cpu={}", cpu));
+        }
+        if !target_features.is_empty() {
+            args.push(format!("-Ctarget-feature={}", target_features.join(",")));
+        }
+        probes.checking("whether rustc accepts the target cpu and features");
+        if !rustc_flavor.accepts_rustc_flags() {
+            // Translated when the crate is built
+            println!("not checked ({})", rustc_flavor.name());
+        } else if let Some(complaint) =
+            crate::tests::codegen::rejected_flags(&rustc, &args, &tmpdir)?
+        {
+            println!("no");
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unsupported target cpu or features for {}: {}",
+                    host, complaint
+                ),
+            ));
+        } else {
+            println!("yes");
+        }
+        if target_cpu.as_deref() == Some("native") && rustc_flavor.accepts_rustc_flags() {
+            probes.checking("which cpu native selects");
+            match crate::tests::codegen::native_cpu(&rustc)? {
+                Some(cpu) => {
+                    println!("{}", cpu);
+                    target_cpu = Some(format!("native ({})", cpu));
+                }
+                None => println!("unknown"),
+            }
+        }
+        profile.rustflags.extend(args);
+    }
+
+    // Published as variables, which step conditions test
+    probes.checking("for the newest edition rustc supports");
+    let max_edition = crate::tests::language::max_edition(&rustc, &tmpdir)?;
+    println!("{}", max_edition);
+    probes.checking("whether rustc supports --check-cfg");
+    let check_cfg = crate::tests::language::check_cfg(&rustc, &tmpdir)?;
+    println!("{}", if check_cfg { "yes" } else { "no" });
+    let mut unstable_flags = Vec::new();
+    for flag in &config.options.unstable_flags {
+        probes.checking(&format!("whether rustc accepts -Z{}", flag));
+        let accepted = crate::tests::language::unstable_flag(&rustc, flag, &tmpdir)?;
+        println!("{}", if accepted { "yes" } else { "no" });
+        let name = flag.split('=').next().unwrap_or_default();
+        unstable_flags.push((format!("rustc-z-{}", name), accepted));
+    }
+    probes.checking("for rustdoc");
+    let rustdoc = crate::tests::language::rustdoc(&rustc);
+    match &rustdoc {
+        Some(rustdoc) => println!("{}", rustdoc.display()),
+        None => println!("no"),
+    }
+
+    let wasm = crate::wasm::flavor(&host);
+    let mut runner = None;
+    if let Some(flavor) = wasm {
+        probes.checking("for a WebAssembly runtime");
+        match crate::wasm::runner(flavor) {
+            Some(path) => {
+                println!("{}", path.display());
+                probes.checking(&format!(
+                    "whether test programs run under {}",
+                    path.display()
+                ));
+                if crate::tests::runner::runs(&rustc, &path, &tmpdir)? {
+                    println!("yes");
+                    runner = Some(path);
+                } else {
+                    println!("no");
+                }
+            }
+            None => println!("none"),
+        }
+    }
+
+    probes.checking("for cargo");
+    let cargo = match std::env::var_os("CARGO") {
+        Some(cargo) => PathBuf::from(cargo),
+        None => which_any(&["cargo"])?,
+    };
+    println!("{}", cargo.display());
+
+    probes.checking("for a rustc wrapper");
+    let configured = match (&opts.rustc_wrapper, &config.options.rustc_wrapper) {
+        (Some(wrapper), _) => Some(wrapper.clone()),
+        (None, Some(wrapper)) => Some(configured_program(wrapper, &src_dir)?),
+        (None, None) => None,
+    };
+    let rustc_wrapper = find_rustc_wrapper(
+        configured,
+        feature(opts, "sccache", config.options.enable_sccache),
+    )?;
+    match &rustc_wrapper {
+        Some(wrapper) => println!("{}", wrapper.display()),
+        None => println!("none"),
+    }
+    let mut injected_rustflags = config.options.rustflags.clone();
+    injected_rustflags
+        .target
+        .append
+        .extend(opts.rustflags.iter().cloned());
+    injected_rustflags
+        .host
+        .append
+        .extend(opts.host_rustflags.iter().cloned());
+    injected_rustflags
+        .build_script
+        .append
+        .extend(opts.build_script_rustflags.iter().cloned());
+    let mut rustdocflags = config.options.rustdocflags.clone();
+    rustdocflags.extend(opts.rustdocflags.iter().cloned());
+
+    let mut linker = None;
+    let mut programs = HashMap::new();
+    programs.insert(rustc_var.to_string(), rustc.rustc.clone());
+    for (key, prg) in &config.programs {
+        if let ProgramType::Rustc = prg.ty {
+            continue;
+        }
+        let names = match &prg.names {
+            Some(names) => {
+                probes
+                    .log(key)
+                    .push(format!("names from [programs.{}]", key));
+                names.clone()
+            }
+            None => default_names(&prg.ty, &host),
+        };
+        if let ProgramType::Ld = prg.ty {
+            probes.checking(&format!("for a working linker ({})", key));
+            let (path, args) = find_linker(
+                key,
+                &names,
+                &host,
+                cross_compiling,
+                &rustc,
+                &tmpdir,
+                probes.log(key),
+            )?;
+            println!("{}", path.display());
+            profile.rustflags.extend(args.iter().cloned());
+            linker = Some((path.clone(), args));
+            programs.insert(key.clone(), path);
+            continue;
+        }
+        probes.checking(&format!("for {}", key));
+        let path = find_program(key, &names, &host, cross_compiling, probes.log(key))?;
+        println!("{}", path.display());
+        programs.insert(key.clone(), path);
+    }
+
+    let lipo = if config
+        .build
+        .step
+        .iter()
+        .any(|s| matches!(s, Step::BuildCrate(s) if s.universal))
+    {
+        probes.checking("for lipo");
+        let lipo = find_program(
+            "LIPO",
+            &["lipo".to_string(), "llvm-lipo".to_string()],
+            &build,
+            false,
+            probes.log("LIPO"),
+        )?;
+        println!("{}", lipo.display());
+        Some(lipo)
+    } else {
+        None
+    };
+
+    let codesign_steps: Vec<&CodesignStep> = config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .filter_map(|s| match s {
+            Step::Codesign(s) => Some(s),
+            _ => None,
+        })
+        .collect();
+    let codesign = if codesign_steps.is_empty() {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Codesign)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for codesign");
+                let path = find_program(
+                    "CODESIGN",
+                    &default_names(&ProgramType::Codesign, &host),
+                    &build,
+                    false,
+                    probes.log("CODESIGN"),
+                )?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+    let notarytool = if !codesign_steps.iter().any(|s| s.notarize) {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| {
+            matches!(ty, ProgramType::Notarytool)
+        }) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for notarytool");
+                let path = find_program(
+                    "NOTARYTOOL",
+                    &default_names(&ProgramType::Notarytool, &host),
+                    &build,
+                    false,
+                    probes.log("NOTARYTOOL"),
+                )
+                .ok()
+                .or_else(|| crate::apple::xcrun_find("notarytool"))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::NotFound,
+                        "Notarization was requested but notarytool could not be found",
+                    )
+                })?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+
+    let rc = if !config
+        .build
+        .step
+        .iter()
+        .any(|s| matches!(s, Step::BuildCrate(s) if s.resources.is_some()))
+    {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Rc)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for a resource compiler");
+                let path = find_program(
+                    "RC",
+                    &default_names(&ProgramType::Rc, &host),
+                    &host,
+                    cross_compiling,
+                    probes.log("RC"),
+                )?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+
+    let objcopy = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::Objcopy(_)))
+    {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Objcopy)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for objcopy");
+                let path = find_program(
+                    "OBJCOPY",
+                    &default_names(&ProgramType::Objcopy, &host),
+                    &host,
+                    cross_compiling,
+                    probes.log("OBJCOPY"),
+                )?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+
+    let msgfmt = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::CompileTranslations(_)))
+    {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Msgfmt)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for msgfmt");
+                let path = find_program(
+                    "MSGFMT",
+                    &default_names(&ProgramType::Msgfmt, &host),
+                    &build,
+                    false,
+                    probes.log("MSGFMT"),
+                )?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+
+    // Without setcap the capabilities are skipped with a warning at install time
+    let setcap = if !config
+        .install
+        .step
+        .iter()
+        .any(|s| matches!(s, Step::Install(s) if s.caps.is_some()))
+    {
+        None
+    } else {
+        probes.checking("for setcap");
+        let setcap = find_program(
+            "SETCAP",
+            &["setcap".to_string()],
+            &build,
+            false,
+            probes.log("SETCAP"),
+        )
+        .ok()
+        .or_else(|| {
+            ["/usr/sbin/setcap", "/sbin/setcap"]
+                .iter()
+                .map(PathBuf::from)
+                .find(|p| p.is_file())
+        });
+        match &setcap {
+            Some(path) => println!("{}", path.display()),
+            None => println!("no"),
+        }
+        setcap
+    };
+
+    // Test steps fall back to cargo test unless one of them insists on cargo-nextest
+    let nextest_steps = || {
+        config.test.step.iter().filter_map(|s| match s {
+            Step::Test(s) => Some(s.nextest),
+            _ => None,
+        })
+    };
+    let nextest = if !nextest_steps().any(|n| n != Some(false)) {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| {
+            matches!(ty, ProgramType::CargoNextest)
+        }) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for cargo-nextest");
+                let path = find_program(
+                    "CARGO_NEXTEST",
+                    &default_names(&ProgramType::CargoNextest, &host),
+                    &build,
+                    false,
+                    probes.log("CARGO_NEXTEST"),
+                );
+                match path {
+                    Ok(path) => {
+                        println!("{}", path.display());
+                        Some(path)
+                    }
+                    Err(e) if nextest_steps().any(|n| n == Some(true)) => return Err(e),
+                    Err(_) => {
+                        println!("no");
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    let cargo_fuzz_steps = || {
+        config
+            .build
+            .step
+            .iter()
+            .chain(&config.test.step)
+            .filter_map(|s| match s {
+                Step::Fuzz(s) => Some(s.cargo_fuzz),
+                _ => None,
+            })
+    };
+    let cargo_fuzz = if !cargo_fuzz_steps().any(|n| n != Some(false)) {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::CargoFuzz)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for cargo-fuzz");
+                let path = find_program(
+                    "CARGO_FUZZ",
+                    &default_names(&ProgramType::CargoFuzz, &host),
+                    &build,
+                    false,
+                    probes.log("CARGO_FUZZ"),
+                );
+                match path {
+                    Ok(path) => {
+                        println!("{}", path.display());
+                        Some(path)
+                    }
+                    Err(e) if cargo_fuzz_steps().any(|n| n == Some(true)) => return Err(e),
+                    Err(_) => {
+                        println!("no");
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    // clippy and rustfmt are toolchain components; steps are skipped without them unless they
+    // require them
+    let mut component = |subcommand: &str, required: Option<bool>| match required {
+        None => Ok(false),
+        Some(required) => {
+            probes.checking(&format!("for cargo {}", subcommand));
+            let found = Command::new(&cargo)
+                .arg(subcommand)
+                .arg("--version")
+                .output()
+                .is_ok_and(|output| output.status.success());
+            println!("{}", if found { "yes" } else { "no" });
+            if !found && required {
+                return Err(std::io::Error::new(
+                    ErrorKind::NotFound,
+                    format!(
+                        "A step requires cargo {}, which is not installed for this toolchain",
+                        subcommand
+                    ),
+                ));
+            }
+            Ok(found)
+        }
+    };
+    let steps = || config.build.step.iter().chain(&config.test.step);
+    let clippy = component(
+        "clippy",
+        steps()
+            .filter_map(|s| match s {
+                Step::Lint(s) => Some(s.required),
+                _ => None,
+            })
+            .reduce(|a, b| a || b),
+    )?;
+    let rustfmt = component(
+        "fmt",
+        steps()
+            .filter_map(|s| match s {
+                Step::FormatCheck(s) => Some(s.required),
+                _ => None,
+            })
+            .reduce(|a, b| a || b),
+    )?;
+
+    let audit_steps: Vec<&AuditStep> = steps()
+        .filter_map(|s| match s {
+            Step::Audit(s) => Some(s),
+            _ => None,
+        })
+        .collect();
+    let mut auditor = |ty: ProgramType, pred: fn(&ProgramType) -> bool, key: &str, wanted: bool| {
+        if !wanted {
+            return None;
+        }
+        program_of_type(config, &programs, pred).or_else(|| {
+            let name = default_names(&ty, &host).remove(0);
+            probes.checking(&format!("for {}", name));
+            let path = find_program(key, &[name], &build, false, probes.log(key)).ok();
+            match &path {
+                Some(path) => println!("{}", path.display()),
+                None => println!("no"),
+            }
+            path
+        })
+    };
+    let cargo_deny = auditor(
+        ProgramType::CargoDeny,
+        |ty| matches!(ty, ProgramType::CargoDeny),
+        "CARGO_DENY",
+        audit_steps
+            .iter()
+            .any(|s| s.tool != Some(AuditTool::CargoAudit)),
+    );
+    let cargo_audit = auditor(
+        ProgramType::CargoAudit,
+        |ty| matches!(ty, ProgramType::CargoAudit),
+        "CARGO_AUDIT",
+        audit_steps.iter().any(|s| match s.tool {
+            Some(tool) => tool == AuditTool::CargoAudit,
+            None => cargo_deny.is_none(),
+        }),
+    );
+    for step in &audit_steps {
+        let found = match step.tool {
+            Some(AuditTool::CargoAudit) => cargo_audit.is_some(),
+            Some(AuditTool::CargoDeny) => cargo_deny.is_some(),
+            None => cargo_audit.is_some() || cargo_deny.is_some(),
+        };
+        if step.required && !found {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                "An audit step requires cargo-audit or cargo-deny, which could not be found",
+            ));
+        }
+    }
+
+    let sandbox = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .chain(&config.test.step)
+        .chain(&config.bench.step)
+        .chain(&config.pgo.train)
+        .any(|s| matches!(s, Step::Command(s) if s.sandbox))
+    {
+        None
+    } else {
+        probes.checking("for a sandbox tool");
+        let tool = crate::sandbox::find_tool(probes.log("sandbox"));
+        match &tool {
+            Some(tool) => println!("{}", tool.display()),
+            None => println!("no"),
+        }
+        tool
+    };
+
+    let workers = opts.workers.as_ref().unwrap_or(&config.remote.workers);
+    let (ssh, rsync) = if workers.is_empty() || !config.policies.values().any(|p| p.distribute) {
+        (None, None)
+    } else {
+        let mut tool = |key: &str, name: &str| {
+            probes.checking(&format!("for {}", name));
+            let path = find_program(key, &[name.to_string()], &build, false, probes.log(key)).ok();
+            match &path {
+                Some(path) => println!("{}", path.display()),
+                None => println!("no"),
+            }
+            path
+        };
+        (tool("SSH", "ssh"), tool("RSYNC", "rsync"))
+    };
+
+    let downloader = if opts.offline
+        || !config
+            .build
+            .step
+            .iter()
+            .chain(&config.install.step)
+            .any(|s| matches!(s, Step::Fetch(_)))
+    {
+        None
+    } else {
+        probes.checking("for a download tool");
+        let tool = find_program(
+            "DOWNLOAD",
+            &["curl".to_string(), "wget".to_string()],
+            &build,
+            false,
+            probes.log("DOWNLOAD"),
+        )?;
+        println!("{}", tool.display());
+        Some(tool)
+    };
+
+    // Without patch, patches go through the built-in implementation
+    let patch = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::ApplyPatches(s) if !s.builtin))
+    {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Patch)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for patch");
+                match find_program(
+                    "PATCH",
+                    &default_names(&ProgramType::Patch, &host),
+                    &build,
+                    false,
+                    probes.log("PATCH"),
+                ) {
+                    Ok(path) => {
+                        println!("{}", path.display());
+                        Some(path)
+                    }
+                    Err(_) => {
+                        println!("no");
+                        None
+                    }
+                }
+            }
+        }
+    };
+
+    let extract_steps = || {
+        config
+            .build
+            .step
+            .iter()
+            .chain(&config.install.step)
+            .filter_map(|s| match s {
+                Step::Extract(s) => Some(s),
+                _ => None,
+            })
+    };
+    let tar = if extract_steps().all(|s| crate::extract::is_zip(&s.archive)) {
+        None
+    } else {
+        probes.checking("for tar");
+        let tool = find_program(
+            "TAR",
+            &["tar".to_string(), "gtar".to_string(), "bsdtar".to_string()],
+            &build,
+            false,
+            probes.log("TAR"),
+        )?;
+        println!("{}", tool.display());
+        Some(tool)
+    };
+    let unzip = if !extract_steps().any(|s| crate::extract::is_zip(&s.archive)) {
+        None
+    } else {
+        probes.checking("for unzip");
+        let tool = find_program(
+            "UNZIP",
+            &["unzip".to_string()],
+            &build,
+            false,
+            probes.log("UNZIP"),
+        )?;
+        println!("{}", tool.display());
+        Some(tool)
+    };
+
+    let bindgen = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::Bindgen(_)))
+    {
+        None
+    } else {
+        match program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Bindgen)) {
+            Some(path) => Some(path),
+            None => {
+                probes.checking("for bindgen");
+                let path = find_program(
+                    "BINDGEN",
+                    &default_names(&ProgramType::Bindgen, &host),
+                    &build,
+                    false,
+                    probes.log("BINDGEN"),
+                )?;
+                println!("{}", path.display());
+                Some(path)
+            }
+        }
+    };
+
+    let codegen_steps = || config.build.step.iter().chain(&config.install.step);
+    let mut codegen_tool = |ty: ProgramType,
+                            pred: fn(&ProgramType) -> bool,
+                            key: &str,
+                            wanted: bool|
+     -> std::io::Result<Option<PathBuf>> {
+        if !wanted {
+            return Ok(None);
+        }
+        if let Some(path) = program_of_type(config, &programs, pred) {
+            return Ok(Some(path));
+        }
+        let names = default_names(&ty, &host);
+        probes.checking(&format!("for {}", names[0]));
+        let path = find_program(key, &names, &build, false, probes.log(key))?;
+        println!("{}", path.display());
+        Ok(Some(path))
+    };
+    let protoc = codegen_tool(
+        ProgramType::Protoc,
+        |ty| matches!(ty, ProgramType::Protoc),
+        "PROTOC",
+        codegen_steps().any(|s| matches!(s, Step::Protoc(_))),
+    )?;
+    let flatc = codegen_tool(
+        ProgramType::Flatc,
+        |ty| matches!(ty, ProgramType::Flatc),
+        "FLATC",
+        codegen_steps().any(|s| matches!(s, Step::Flatc(_))),
+    )?;
+    let cbindgen = codegen_tool(
+        ProgramType::Cbindgen,
+        |ty| matches!(ty, ProgramType::Cbindgen),
+        "CBINDGEN",
+        codegen_steps().any(|s| matches!(s, Step::Cbindgen(_))),
+    )?;
+    let parser_steps = |generator: ParserGenerator| {
+        codegen_steps().any(|s| {
+            matches!(s, Step::GenerateParser(s) if s.generator == generator && s.program.is_none())
+        })
+    };
+    let yacc = codegen_tool(
+        ProgramType::Yacc,
+        |ty| matches!(ty, ProgramType::Yacc),
+        "YACC",
+        parser_steps(ParserGenerator::Yacc),
+    )?;
+    let lex = codegen_tool(
+        ProgramType::Lex,
+        |ty| matches!(ty, ProgramType::Lex),
+        "LEX",
+        parser_steps(ParserGenerator::Lex),
+    )?;
+    // protoc looks plugins up on PATH itself; finding them here reports a missing one early
+    let mut protoc_plugins = HashMap::new();
+    for plugin in codegen_steps().flat_map(|s| match s {
+        Step::Protoc(s) => &s.plugins[..],
+        _ => &[][..],
+    }) {
+        if protoc_plugins.contains_key(&plugin.name) {
+            continue;
+        }
+        let name = format!("protoc-gen-{}", plugin.name);
+        let key = name.to_uppercase().replace('-', "_");
+        let path = match program_of_type(
+            config,
+            &programs,
+            |t| matches!(t, ProgramType::Other(n) if *n == name),
+        ) {
+            Some(path) => path,
+            None => {
+                probes.checking(&format!("for {}", name));
+                let path = find_program(
+                    &key,
+                    std::slice::from_ref(&name),
+                    &build,
+                    false,
+                    probes.log(&key),
+                )?;
+                println!("{}", path.display());
+                path
+            }
+        };
+        protoc_plugins.insert(plugin.name.clone(), path);
+    }
+
+    // Standalone assembly is built for the host with the same tools C code would use
+    let assemble_steps: Vec<&AssembleStep> = codegen_steps()
+        .filter_map(|s| match s {
+            Step::Assemble(s) => Some(s),
+            _ => None,
+        })
+        .collect();
+    let mut preprocessed = Vec::new();
+    for step in &assemble_steps {
+        preprocessed.extend(step.sources.iter().map(|s| {
+            Path::new(s)
+                .extension()
+                .is_some_and(|ext| ext == "S" || ext == "sx")
+        }));
+    }
+    let mut assembly_tool = |ty: ProgramType,
+                             pred: fn(&ProgramType) -> bool,
+                             key: &str,
+                             wanted: bool|
+     -> std::io::Result<Option<PathBuf>> {
+        if !wanted {
+            return Ok(None);
+        }
+        if let Some(path) = program_of_type(config, &programs, pred) {
+            return Ok(Some(path));
+        }
+        probes.checking(&format!("for {}", key.to_lowercase()));
+        let names = default_names(&ty, &host);
+        let path = find_program(key, &names, &host, cross_compiling, probes.log(key))?;
+        println!("{}", path.display());
+        Ok(Some(path))
+    };
+    let assembler = assembly_tool(
+        ProgramType::As,
+        |ty| matches!(ty, ProgramType::As),
+        "AS",
+        preprocessed.iter().any(|p| !p),
+    )?;
+    let cc = assembly_tool(
+        ProgramType::Cc,
+        |ty| matches!(ty, ProgramType::Cc),
+        "CC",
+        preprocessed.iter().any(|p| *p),
+    )?;
+    let ar = assembly_tool(
+        ProgramType::Ar,
+        |ty| matches!(ty, ProgramType::Ar),
+        "AR",
+        assemble_steps.iter().any(|s| s.archive.is_some())
+            || codegen_steps().any(|s| matches!(s, Step::Archive(_))),
+    )?;
+
+    // The validators are optional; installs go ahead unchecked without them
+    let (desktop_file_validate, appstreamcli) = if !config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::InstallDesktop(s) if s.validate))
+    {
+        (None, None)
+    } else {
+        let mut optional = |name: &str| {
+            probes.checking(&format!("for {}", name));
+            let key = name.to_uppercase().replace('-', "_");
+            let path = find_program(&key, &[name.to_string()], &build, false, probes.log(&key));
+            match &path {
+                Ok(path) => println!("{}", path.display()),
+                Err(_) => println!("no"),
+            }
+            path.ok()
+        };
+        (optional("desktop-file-validate"), optional("appstreamcli"))
+    };
+
+    let link_audit = match feature(opts, "link-audit", config.link_audit.enable) {
+        Some(true) => {
+            probes.checking("for a tool to list dynamic dependencies");
+            let objdump =
+                program_of_type(config, &programs, |ty| matches!(ty, ProgramType::Objdump));
+            let tool = crate::link_audit::find_tool(
+                &host,
+                objdump,
+                cross_compiling,
+                probes.log("link-audit"),
+            )
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::NotFound,
+                    "The link audit was requested but no ldd, otool or objdump could be found",
+                )
+            })?;
+            println!("{}", tool.display());
+            Some(Audit {
+                tool,
+                allow: config
+                    .link_audit
+                    .allow
+                    .clone()
+                    .unwrap_or_else(|| crate::link_audit::default_allow(&host)),
+                warn_only: config.link_audit.warn_only,
+            })
+        }
+        _ => None,
+    };
+
+    let c_compilers: Vec<(&str, &String)> = config
+        .programs
+        .iter()
+        .filter_map(|(key, prg)| match prg.ty {
+            ProgramType::Cc => Some(("CC", key)),
+            ProgramType::Cxx => Some(("CXX", key)),
+            _ => None,
+        })
+        .collect();
+
+    let ccache = match feature(opts, "ccache", config.options.enable_ccache) {
+        Some(true) if !c_compilers.is_empty() => {
+            probes.checking("for ccache");
+            let ccache = find_program(
+                "CCACHE",
+                &["ccache".to_string()],
+                &build,
+                false,
+                probes.log("CCACHE"),
+            )?;
+            println!("{}", ccache.display());
+            Some(ccache)
+        }
+        _ => None,
+    };
+
+    let llvm_profdata = match feature(opts, "pgo", config.options.enable_pgo) {
+        Some(true) => {
+            if config.pgo.train.is_empty() {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    "PGO was requested but no [[pgo.train]] steps are configured",
+                ));
+            }
+            probes.checking("for llvm-profdata");
+            let profdata = find_llvm_profdata(&rustc)?;
+            println!("{}", profdata.display());
+            Some(profdata)
+        }
+        _ => None,
+    };
+
+    let mut tool_env = HashMap::new();
+    for &(var, key) in &c_compilers {
+        let compiler = programs[key].display().to_string();
+        let command = match &ccache {
+            Some(ccache) => format!("{} {}", ccache.display(), compiler),
+            None => compiler,
+        };
+        tool_env.insert(var.to_string(), command);
+    }
+
+    let mut toolchain_env = HashMap::new();
+    if host.get_name().ends_with("msvc") {
+        probes.checking("for Visual Studio");
+        match crate::msvc::detect(&build, &host)? {
+            Some(msvc) => {
+                println!("{}", msvc.link.display());
+                toolchain_env.extend(msvc.env);
+                toolchain_env.insert(
+                    format!(
+                        "CARGO_TARGET_{}_LINKER",
+                        host.get_name().to_uppercase().replace('-', "_")
+                    ),
+                    msvc.link.display().to_string(),
+                );
+                for (var, tool) in &[("CC", &msvc.cl), ("CXX", &msvc.cl), ("AR", &msvc.lib)] {
+                    tool_env
+                        .entry(var.to_string())
+                        .or_insert_with(|| tool.display().to_string());
+                }
+            }
+            None => println!("using the current environment"),
+        }
+    }
+    if let Some(ndk) = &ndk {
+        let triple = host.get_name().replace('-', "_");
+        toolchain_env.insert(
+            format!("CARGO_TARGET_{}_LINKER", triple.to_uppercase()),
+            ndk.clang.display().to_string(),
+        );
+        toolchain_env.insert(format!("CC_{}", triple), ndk.clang.display().to_string());
+        toolchain_env.insert(format!("CXX_{}", triple), ndk.clangxx.display().to_string());
+        toolchain_env.insert(format!("AR_{}", triple), ndk.ar.display().to_string());
+        toolchain_env.insert(
+            "ANDROID_NDK_HOME".to_string(),
+            ndk.root.display().to_string(),
+        );
+        for (var, tool) in &[("CC", &ndk.clang), ("CXX", &ndk.clangxx), ("AR", &ndk.ar)] {
+            tool_env
+                .entry(var.to_string())
+                .or_insert_with(|| tool.display().to_string());
+        }
+        append_flags(
+            &mut tool_env,
+            "CFLAGS",
+            &format!("--sysroot={}", ndk.sysroot.display()),
+        );
+    }
+    if let Some(flavor) = wasm {
+        probes.checking("for WebAssembly C compilers");
+        match crate::wasm::c_tools(flavor, &host) {
+            Some(tools) => {
+                println!("{}", tools.cc.display());
+                for (var, tool) in &[("CC", &tools.cc), ("CXX", &tools.cxx), ("AR", &tools.ar)] {
+                    tool_env
+                        .entry(var.to_string())
+                        .or_insert_with(|| tool.display().to_string());
+                }
+                if let Some(cflags) = &tools.cflags {
+                    append_flags(&mut tool_env, "CFLAGS", cflags);
+                    append_flags(&mut tool_env, "CXXFLAGS", cflags);
+                }
+            }
+            None => println!("none"),
+        }
+    }
+    if let Some(sdk) = &apple_sdk {
+        toolchain_env.insert("SDKROOT".to_string(), sdk.path.display().to_string());
+        let mut cflags = format!("-isysroot {}", sdk.path.display());
+        if let Some(version) = &sdk.min_version {
+            toolchain_env.insert(sdk.deployment_var.to_string(), version.clone());
+            cflags.push_str(&format!(" {}={}", sdk.min_version_flag, version));
+        }
+        if let Some(clang) = &sdk.clang {
+            toolchain_env.insert(
+                format!(
+                    "CARGO_TARGET_{}_LINKER",
+                    host.get_name().to_uppercase().replace('-', "_")
+                ),
+                clang.display().to_string(),
+            );
+            tool_env
+                .entry("CC".to_string())
+                .or_insert_with(|| clang.display().to_string());
+        }
+        append_flags(&mut tool_env, "CFLAGS", &cflags);
+        append_flags(&mut tool_env, "CXXFLAGS", &cflags);
+        append_flags(&mut tool_env, "LDFLAGS", &cflags);
+    }
+    if let Some(runner) = &runner {
+        toolchain_env.insert(
+            format!(
+                "CARGO_TARGET_{}_RUNNER",
+                host.get_name().to_uppercase().replace('-', "_")
+            ),
+            runner.display().to_string(),
+        );
+    }
+    tool_env.extend(toolchain_env.clone());
+
+    if static_link {
+        append_flags(&mut tool_env, "LDFLAGS", "-static");
+    }
+
+    if let Some((path, args)) = &linker {
+        tool_env.insert("LD".to_string(), path.display().to_string());
+        for flag in args.iter().filter_map(|f| f.strip_prefix("-Clink-arg=")) {
+            append_flags(&mut tool_env, "LDFLAGS", flag);
+        }
+    }
+
+    let mut hardening = None;
+    if opts.hardened || config.options.hardened {
+        let cc = c_compilers
+            .iter()
+            .min_by_key(|(var, _)| *var != "CC")
+            .map(|(_, key)| programs[*key].clone());
+        let mut missing = Vec::new();
+        for (name, rust_flags, cflags, ldflags) in HARDENING {
+            if !rust_flags.is_empty() {
+                probes.checking(&format!("whether rustc supports {}", name));
+                let mut args = profile.rustflags.clone();
+                args.extend(rust_flags.iter().map(|f| f.to_string()));
+                if rustc_flavor.accepts_rustc_flags()
+                    && crate::tests::codegen::rejected_flags(&rustc, &args, &tmpdir)?.is_none()
+                {
+                    println!("yes");
+                    profile
+                        .rustflags
+                        .extend(rust_flags.iter().map(|f| f.to_string()));
+                } else {
+                    println!("no");
+                    missing.push(format!("{} (rustc)", name));
+                }
+            }
+            let cc = match &cc {
+                Some(cc) if !cflags.is_empty() || !ldflags.is_empty() => cc,
+                _ => continue,
+            };
+            probes.checking(&format!("whether the C compiler supports {}", name));
+            if crate::tests::hardening::cc_accepts(
+                cc,
+                &rustc.target_info.exe_suffix,
+                cflags,
+                ldflags,
+                &tmpdir,
+            )? {
+                println!("yes");
+                if !cflags.is_empty() {
+                    for var in &["CFLAGS", "CXXFLAGS"] {
+                        append_flags(&mut tool_env, var, &cflags.join(" "));
+                    }
+                }
+                if !ldflags.is_empty() {
+                    append_flags(&mut tool_env, "LDFLAGS", &ldflags.join(" "));
+                }
+            } else {
+                println!("no");
+                missing.push(format!("{} (C compiler)", name));
+            }
+        }
+        if !missing.is_empty() {
+            eprintln!(
+                "cargo-autobuild: warning: the toolchain lacks these hardening features: {}",
+                missing.join(", ")
+            );
+        }
+        hardening = Some(missing);
+    }
+
+    if profile.linker_plugin_lto {
+        probes.checking("whether cross-language LTO works");
+        let clang = match c_compilers.iter().find(|(var, _)| *var == "CC") {
+            Some((_, key)) => Some(programs[*key].clone()),
+            None => which_any(&["clang"]).ok(),
+        };
+        let clang = match clang {
+            Some(clang) if crate::tests::lto::is_clang(&clang)? => Some(clang),
+            _ => None,
+        };
+        match clang {
+            Some(clang)
+                if rustc_flavor.accepts_rustc_flags()
+                    && crate::tests::lto::linker_plugin_lto(&rustc, &clang, &tmpdir)? =>
+            {
+                println!("yes");
+                profile.rustflags.extend(vec![
+                    "-Clinker-plugin-lto".to_string(),
+                    format!("-Clinker={}", clang.display()),
+                    "-Clink-arg=-fuse-ld=lld".to_string(),
+                ]);
+                // cc-rs falls back to cc, which may be gcc, so the clang found here is exported
+                // for whichever of CC and CXX is not configured
+                let clangxx =
+                    clang.with_file_name(format!("clang++{}", std::env::consts::EXE_SUFFIX));
+                for (var, flags, fallback) in [
+                    ("CC", "CFLAGS", Some(clang)),
+                    (
+                        "CXX",
+                        "CXXFLAGS",
+                        Some(clangxx).filter(|path| path.is_file()),
+                    ),
+                ] {
+                    let is_clang = match c_compilers.iter().find(|(v, _)| *v == var) {
+                        Some((_, key)) => crate::tests::lto::is_clang(&programs[*key])?,
+                        None => match fallback {
+                            Some(path) => {
+                                tool_env.insert(var.to_string(), path.display().to_string());
+                                true
+                            }
+                            None => false,
+                        },
+                    };
+                    if is_clang {
+                        append_flags(&mut tool_env, flags, "-flto=thin");
+                    }
+                }
+            }
+            _ => {
+                println!("no");
+                profile.linker_plugin_lto = false;
+            }
+        }
+    }
+
+    let mut sanitizers = Vec::new();
+    for name in opts
+        .sanitizers
+        .as_ref()
+        .unwrap_or(&config.options.sanitizers)
+    {
+        let sanitizer = sanitizer_name(name)?;
+        if !sanitizers.contains(&sanitizer) {
+            sanitizers.push(sanitizer);
+        }
+    }
+    let exclusive = ["address", "thread", "memory"];
+    if sanitizers.iter().filter(|s| exclusive.contains(s)).count() > 1 {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            format!("Sanitizers {} cannot be combined", sanitizers.join(", ")),
+        ));
+    }
+    for &sanitizer in &sanitizers {
+        if sanitizer != "undefined" {
+            probes.checking(&format!(
+                "whether rustc supports the {} sanitizer",
+                sanitizer
+            ));
+            if !rustc_flavor.accepts_rustc_flags()
+                || !crate::tests::sanitizer::supported(&rustc, sanitizer, &tmpdir)?
+            {
+                println!("no");
+                return Err(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} does not support -Zsanitizer={} for {}",
+                        rustc.rustc.display(),
+                        sanitizer,
+                        host
+                    ),
+                ));
+            }
+            println!("yes");
+            profile.rustflags.push(format!("-Zsanitizer={}", sanitizer));
+        }
+        let flag = format!("-fsanitize={}", sanitizer);
+        for var in &["CFLAGS", "CXXFLAGS", "LDFLAGS"] {
+            append_flags(&mut tool_env, var, &flag);
+        }
+        let options_var = match sanitizer {
+            "address" => "ASAN_OPTIONS",
+            "thread" => "TSAN_OPTIONS",
+            "memory" => "MSAN_OPTIONS",
+            "undefined" => "UBSAN_OPTIONS",
+            _ => "LSAN_OPTIONS",
+        };
+        if std::env::var_os(options_var).is_none() {
+            let options = match sanitizer {
+                "address" => "detect_odr_violation=0:abort_on_error=1",
+                "undefined" => "print_stacktrace=1:halt_on_error=1",
+                _ => "abort_on_error=1",
+            };
+            tool_env.insert(options_var.to_string(), options.to_string());
+        }
+    }
+    let sanitizers: Vec<String> = sanitizers.into_iter().map(str::to_string).collect();
+
+    let package = src_dir
+        .file_name()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut dirs = resolve_dirs(config, opts, &package, &mut probes)?;
+
+    if config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .any(|s| matches!(s, Step::InstallSystemd(_)))
+    {
+        probes.checking("for systemd and D-Bus directories");
+        for &(name, package, variable, fallback) in crate::systemd::DIRS {
+            let dir = InstallDirectory::from_name(name);
+            // [dirs] already placed it
+            if dirs.contains_key(&dir) {
+                continue;
+            }
+            let resolved = match crate::systemd::pkg_config_variable(package, variable) {
+                Some(path) => {
+                    probes
+                        .log(name)
+                        .push(format!("pkg-config --variable={} {}", variable, package));
+                    path
+                }
+                None => {
+                    probes.log(name).push(format!(
+                        "pkg-config knows no {}; defaults to $prefix/{}",
+                        package, fallback
+                    ));
+                    dirs[&InstallDirectory::Prefix].join(fallback)
+                }
+            };
+            probes
+                .log(name)
+                .push(format!("resolved to {}", resolved.display()));
+            dirs.insert(dir, resolved);
+        }
+        println!("done");
+    }
+
+    let mut specs = HashMap::new();
+    for step in config
+        .build
+        .step
+        .iter()
+        .chain(&config.install.step)
+        .chain(&config.test.step)
+        .chain(&config.bench.step)
+        .chain(&config.pgo.train)
+    {
+        let target = match step {
+            Step::BuildCrate(s) => &s.target,
+            Step::BuildWorkspace(s) => &s.target,
+            Step::Test(s) => &s.target,
+            Step::Bench(s) => &s.target,
+            Step::Lint(s) => &s.target,
+            Step::ThirdPartyLicenses(s) => &s.target,
+            Step::Bindgen(s) => &s.target,
+            Step::Sysroot(s) => &s.target,
+            _ => continue,
+        };
+        let path = match target {
+            BuildTarget::Spec(path) if !specs.contains_key(path) => path,
+            _ => continue,
+        };
+        probes.checking(&format!("for the target described by {}", path.display()));
+        let spec = load_spec(&src_dir.join(path), &rustc, &tmpdir)?;
+        println!("{}", spec.target);
+        specs.insert(path.clone(), spec);
+    }
+
+    let mut vars = HashMap::new();
+    vars.insert("build".to_string(), build.to_string());
+    vars.insert("host".to_string(), host.to_string());
+    vars.insert("target".to_string(), target.to_string());
+    vars.insert("CARGO".to_string(), cargo.display().to_string());
+    for (prefix, info) in &[("", &rustc.target_info), ("build-", &build_info)] {
+        for (name, value) in &[
+            ("exe-suffix", &info.exe_suffix),
+            ("dll-prefix", &info.cdylib_prefix),
+            ("dll-suffix", &info.cdylib_suffix),
+            ("staticlib-prefix", &info.staticlib_prefix),
+            ("staticlib-suffix", &info.staticlib_suffix),
+        ] {
+            vars.insert(
+                format!("{}{}", prefix, name),
+                value.to_string_lossy().into_owned(),
+            );
+        }
+    }
+    vars.insert("profile".to_string(), profile.name.clone());
+    vars.insert("rustc-flavor".to_string(), rustc_flavor.name().to_string());
+    vars.insert("rustc-edition".to_string(), max_edition.to_string());
+    vars.insert("rustc-check-cfg".to_string(), check_cfg.to_string());
+    for (name, accepted) in &unstable_flags {
+        vars.insert(name.clone(), accepted.to_string());
+    }
+    if let Some(rustdoc) = &rustdoc {
+        vars.insert("rustdoc".to_string(), rustdoc.display().to_string());
+    }
+    if let Some(cpu) = &target_cpu {
+        vars.insert("target-cpu".to_string(), cpu.clone());
+    }
+    if let Some(runner) = &runner {
+        vars.insert("runner".to_string(), runner.display().to_string());
+    }
+    for (key, path) in &programs {
+        vars.insert(key.clone(), path.display().to_string());
+    }
+    for (key, command) in &tool_env {
+        vars.insert(key.clone(), command.clone());
+    }
+    for (dir, path) in &dirs {
+        vars.insert(dir.name().to_string(), path.display().to_string());
+    }
+
+    // So that conditions and configured files need not run uname
+    for (prefix, triple) in [("build-", &build), ("host-", &host), ("target-", &target)] {
+        vars.extend(crate::platform::target_vars(&rustc.rustc, prefix, triple));
+    }
+    probes.checking("for the kernel version of the build machine");
+    let kernel_version = match machine {
+        Some(machine) => {
+            print!("(cached) ");
+            machine.kernel_version.clone()
+        }
+        None => crate::platform::kernel_version(),
+    };
+    match kernel_version {
+        Some(version) => {
+            println!("{}", version);
+            if host == build {
+                vars.insert("host-kernel-version".to_string(), version.clone());
+            }
+            vars.insert("build-kernel-version".to_string(), version);
+        }
+        None => println!("unknown"),
+    }
+    probes.checking("for the libc of the build machine");
+    let libc = match machine {
+        Some(machine) => {
+            print!("(cached) ");
+            machine.libc.clone()
+        }
+        None => crate::platform::libc(),
+    };
+    match libc {
+        Some((flavor, version)) => {
+            println!("{} {}", flavor, version);
+            // What is installed wins over what the triple names
+            vars.insert("build-libc".to_string(), flavor.clone());
+            vars.insert("build-libc-version".to_string(), version.clone());
+            if host == build {
+                vars.insert("host-libc".to_string(), flavor);
+                vars.insert("host-libc-version".to_string(), version);
+            }
+        }
+        None => println!("unknown"),
+    }
+    probes.checking("what the build filesystem supports");
+    let build_fs = crate::filesystem::probe(&build_dir);
+    println!("{}", build_fs.describe());
+    vars.extend(build_fs.vars("build-fs-"));
+
+    for probe in &config.probe {
+        probes.checking(&match &probe.description {
+            Some(description) => description.clone(),
+            None => format!("plugin {}", probe.program.display()),
+        });
+        let response = crate::plugin::call(
+            &probe.program,
+            &probe.args,
+            &crate::plugin::Request {
+                protocol: crate::plugin::PROTOCOL_VERSION,
+                action: "probe",
+                options: &probe.options,
+                vars: &vars,
+                src_dir: &src_dir,
+                build_dir: &build_dir,
+            },
+        )?;
+        let mut found: Vec<String> = response
+            .vars
+            .iter()
+            .map(|(k, v)| format!("{}={}", k, v))
+            .collect();
+        found.sort();
+        println!(
+            "{}",
+            if found.is_empty() {
+                "done".to_string()
+            } else {
+                found.join(" ")
+            }
+        );
+        vars.extend(response.vars);
+    }
+
+    probes.finish();
+
+    let isolate = opts.isolate.as_ref().unwrap_or(&config.options.isolate);
+    let target_dir = match opts
+        .target_dir
+        .as_ref()
+        .or(config.options.target_dir.as_ref())
+    {
+        Some(dir) => Some(src_dir.join(dir)),
+        None if !isolate.is_empty() => Some(build_dir.join("target")),
+        None => None,
+    }
+    .map(|mut dir| {
+        if isolate.contains(&Isolation::Triple) {
+            dir.push(host.get_name());
+        }
+        if isolate.contains(&Isolation::Profile) {
+            dir.push(&profile.name);
+        }
+        crate::paths::long(&dir)
+    });
+
+    let cfg = Configuration {
+        epoch,
+        profile,
+        probe_timings: probes.timings,
+        log: probes.log,
+        build,
+        host,
+        target,
+        target_spec,
+        host_cfg,
+        specs,
+        handlers: Registry::new(),
+        rustc,
+        compilers: target_compilers,
+        build_info,
+        cargo,
+        rustc_wrapper,
+        injected_rustflags,
+        rustdocflags,
+        ccache,
+        llvm_profdata,
+        sanitizers,
+        static_link,
+        hardening,
+        target_cpu,
+        target_features,
+        linker: linker.map(|(path, _)| path),
+        lipo,
+        codesign,
+        notarytool,
+        rc,
+        objcopy,
+        msgfmt,
+        setcap,
+        nextest,
+        cargo_fuzz,
+        smoke_tests: opts.command == "check",
+        clippy,
+        rustfmt,
+        cargo_audit,
+        cargo_deny,
+        sandbox,
+        ssh,
+        rsync,
+        offline: opts.offline,
+        downloader,
+        download_cache: config
+            .options
+            .download_cache
+            .as_ref()
+            .map(|cache| src_dir.join(cache)),
+        patch,
+        tar,
+        unzip,
+        checksums: HashMap::new(),
+        bindgen,
+        protoc,
+        protoc_plugins,
+        flatc,
+        cbindgen,
+        yacc,
+        lex,
+        assembler,
+        cc,
+        ar,
+        desktop_file_validate,
+        appstreamcli,
+        link_audit,
+        toolchain_env,
+        ndk,
+        runner,
+        apple_sdk,
+        programs,
+        tool_env,
+        dirs,
+        build_fs,
+        vars,
+        options: configure_options(opts),
+        step_artifacts: HashMap::new(),
+        pgo_vars: HashMap::new(),
+        build_env: HashMap::new(),
+        generated: Vec::new(),
+        src_dir,
+        build_dir,
+        target_dir,
+        scratch_dir,
+    };
+    print_summary(&cfg);
+    Ok(cfg)
+}
@@ -0,0 +1,154 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{cli::Options, config::Config};
+
+// Set inside the container, so the command run there does not start another one
+const INSIDE: &str = "CARGO_AUTOBUILD_IN_CONTAINER";
+
+pub fn wanted(opts: &Options) -> bool {
+    opts.in_container.is_some() && std::env::var_os(INSIDE).is_none()
+}
+
+fn engine(config: &Config) -> std::io::Result<PathBuf> {
+    match &config.container.engine {
+        Some(engine) => Ok(engine.clone()),
+        None => crate::tests::which_any(&["podman", "docker"]).map_err(|_| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                "--in-container requires podman or docker, neither of which was found",
+            )
+        }),
+    }
+}
+
+fn is_podman(engine: &Path) -> bool {
+    engine
+        .file_stem()
+        .is_some_and(|n| n.to_string_lossy().contains("podman"))
+}
+
+fn mount(cmd: &mut Command, dir: &Path) {
+    let mut volume = dir.as_os_str().to_owned();
+    volume.push(":");
+    volume.push(dir);
+    cmd.arg("-v").arg(volume);
+}
+
+// Everything but --in-container itself is passed on, so the probes, the plan and the steps all
+// run inside the image
+fn arguments(opts: &Options, config_file: &Path) -> Vec<String> {
+    let mut args = vec![
+        opts.command.clone(),
+        format!("--config={}", config_file.display()),
+    ];
+    args.extend(crate::remote::forwarded(opts));
+    if !opts.hosts.is_empty() {
+        args.push(format!("--host={}", opts.hosts.join(",")));
+    }
+    for (name, list) in [("only", &opts.only), ("skip", &opts.skip)] {
+        if !list.is_empty() {
+            args.push(format!("--{}={}", name, list.join(",")));
+        }
+    }
+    if let Some(until) = &opts.until {
+        args.push(format!("--until={}", until));
+    }
+    if let Some(dir) = &opts.build_dir {
+        args.push(format!("--build-dir={}", dir.display()));
+    }
+    if let Some(dir) = &opts.target_dir {
+        args.push(format!("--target-dir={}", dir.display()));
+    }
+    if let Some(dir) = &opts.scratch_dir {
+        args.push(format!("--scratch-dir={}", dir.display()));
+    }
+    if let Some(timings) = &opts.timings {
+        args.push(format!("--timings={}", timings));
+    }
+    for (flag, set) in [
+        ("force", opts.force),
+        ("keep-going", opts.keep_going),
+        ("dry-run", opts.dry_run),
+        ("hermetic", opts.hermetic),
+    ] {
+        if set {
+            args.push(format!("--{}", flag));
+        }
+    }
+    if ["graph", "sbom", "dev-env"].contains(&opts.command.as_str()) {
+        args.push(format!("--format={}", opts.format));
+    }
+    args.extend(opts.args.iter().cloned());
+    args
+}
+
+// The source directory is mounted at the same path, so the paths in the build state and in the
+// artifacts stay valid on either side; files are created as the owner of the source directory
+pub fn run(opts: &Options, config: &Config) -> std::io::Result<()> {
+    let image = match opts.in_container.as_deref() {
+        Some("") | None => config.container.image.clone().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "--in-container needs an image, given as --in-container=IMAGE or in [container]",
+            )
+        })?,
+        Some(image) => image.to_string(),
+    };
+    let engine = engine(config)?;
+    let src_dir = std::fs::canonicalize(opts.src_dir())?;
+    let config_file = src_dir.join(opts.config.file_name().unwrap_or_default());
+
+    let mut cmd = Command::new(&engine);
+    cmd.arg("run")
+        .arg("--rm")
+        .arg("-e")
+        .arg(format!("{}=1", INSIDE));
+    mount(&mut cmd, &src_dir);
+    for dir in [&opts.build_dir, &opts.target_dir, &opts.scratch_dir]
+        .iter()
+        .copied()
+        .flatten()
+    {
+        let dir = src_dir.join(dir);
+        if !dir.starts_with(&src_dir) {
+            std::fs::create_dir_all(&dir)?;
+            mount(&mut cmd, &dir);
+        }
+    }
+    for volume in &config.container.volumes {
+        cmd.arg("-v").arg(volume);
+    }
+    for var in &config.container.env {
+        cmd.arg("-e").arg(var);
+    }
+    if is_podman(&engine) {
+        cmd.arg("--userns=keep-id");
+    } else {
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let meta = src_dir.metadata()?;
+            cmd.arg("--user")
+                .arg(format!("{}:{}", meta.uid(), meta.gid()));
+        }
+    }
+    let program = match &config.container.program {
+        Some(program) => program.clone(),
+        None => {
+            let exe = std::env::current_exe()?;
+            let mut volume = exe.into_os_string();
+            volume.push(":/usr/local/bin/cargo-autobuild:ro");
+            cmd.arg("-v").arg(volume);
+            "/usr/local/bin/cargo-autobuild".to_string()
+        }
+    };
+    cmd.arg("-w").arg(&src_dir).arg(&image).arg(program);
+    cmd.args(arguments(opts, &config_file));
+
+    println!("=== {} ===", image);
+    crate::steps::run(&mut cmd)
+}
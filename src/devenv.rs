@@ -0,0 +1,305 @@
+use std::{
+    collections::BTreeSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::Step, configure::Configuration, plan::Plan, tests::rustc::RustcChannel};
+
+// Packages for programs configure found, as (nixpkgs attribute, Guix package)
+fn tool_package(name: &str) -> Option<(&'static str, Option<&'static str>)> {
+    Some(match name {
+        "rustc" => ("rustc", Some("rust")),
+        "cargo" => ("cargo", Some("rust:cargo")),
+        "cc" | "gcc" | "g++" | "c++" => ("gcc", Some("gcc-toolchain")),
+        "clang" | "clang++" => ("clang", Some("clang-toolchain")),
+        "ar" | "as" | "ld" | "objcopy" | "objdump" | "strip" => ("binutils", Some("binutils")),
+        "ld.lld" | "lld" => ("lld", Some("lld")),
+        "mold" => ("mold", Some("mold")),
+        "ccache" => ("ccache", Some("ccache")),
+        "sccache" => ("sccache", None),
+        "cargo-nextest" => ("cargo-nextest", None),
+        "cargo-fuzz" => ("cargo-fuzz", None),
+        "cargo-audit" => ("cargo-audit", Some("rust-cargo-audit")),
+        "cargo-deny" => ("cargo-deny", None),
+        "patch" | "gpatch" => ("gnupatch", Some("patch")),
+        "tar" | "gtar" | "bsdtar" => ("gnutar", Some("tar")),
+        "unzip" => ("unzip", Some("unzip")),
+        "curl" => ("curl", Some("curl")),
+        "wget" => ("wget", Some("wget")),
+        "bindgen" => ("rust-bindgen", Some("rust-bindgen-cli")),
+        "cbindgen" => ("rust-cbindgen", Some("rust-cbindgen")),
+        "protoc" => ("protobuf", Some("protobuf")),
+        "flatc" => ("flatbuffers", Some("flatbuffers")),
+        "yacc" | "bison" | "byacc" => ("bison", Some("bison")),
+        "lex" | "flex" => ("flex", Some("flex")),
+        "msgfmt" => ("gettext", Some("gettext-minimal")),
+        "setcap" => ("libcap", Some("libcap")),
+        "desktop-file-validate" => ("desktop-file-utils", Some("desktop-file-utils")),
+        "appstreamcli" => ("appstream", Some("appstream")),
+        "llvm-profdata" => ("llvm", Some("llvm")),
+        "ssh" => ("openssh", Some("openssh")),
+        "rsync" => ("rsync", Some("rsync")),
+        "pkg-config" | "pkgconf" => ("pkg-config", Some("pkg-config")),
+        _ => return None,
+    })
+}
+
+// Packages for native libraries, by `links` key or pkg-config name
+fn library_package(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        "ssl" | "crypto" | "openssl" | "libssl" => ("openssl", "openssl"),
+        "z" | "zlib" => ("zlib", "zlib"),
+        "sqlite3" => ("sqlite", "sqlite"),
+        "curl" | "libcurl" => ("curl", "curl"),
+        "git2" | "libgit2" => ("libgit2", "libgit2"),
+        "ssh2" | "libssh2" => ("libssh2", "libssh2"),
+        "zstd" | "libzstd" => ("zstd", "zstd:lib"),
+        "lzma" | "liblzma" => ("xz", "xz"),
+        "bz2" | "bzip2" => ("bzip2", "bzip2"),
+        "ffi" | "libffi" => ("libffi", "libffi"),
+        "dbus" | "dbus-1" => ("dbus", "dbus"),
+        "pq" | "libpq" => ("postgresql", "postgresql"),
+        "udev" | "libudev" => ("systemd", "eudev"),
+        "alsa" | "asound" => ("alsa-lib", "alsa-lib"),
+        "xcb" => ("xorg.libxcb", "libxcb"),
+        "fontconfig" => ("fontconfig", "fontconfig"),
+        "freetype" | "freetype2" => ("freetype", "freetype"),
+        "gtk-3" | "gtk+-3.0" => ("gtk3", "gtk+"),
+        "gtk4" => ("gtk4", "gtk"),
+        _ => return None,
+    })
+}
+
+struct Environment {
+    name: String,
+    comments: Vec<String>,
+    // nixpkgs attributes and Guix packages of the programs and of the libraries
+    tools: BTreeSet<(&'static str, Option<&'static str>)>,
+    libraries: BTreeSet<(&'static str, &'static str)>,
+}
+
+fn tools(cfg: &Configuration) -> Vec<&Path> {
+    [
+        Some(&cfg.rustc.rustc),
+        Some(&cfg.cargo),
+        cfg.rustc_wrapper.as_ref(),
+        cfg.ccache.as_ref(),
+        cfg.llvm_profdata.as_ref(),
+        cfg.linker.as_ref(),
+        cfg.objcopy.as_ref(),
+        cfg.msgfmt.as_ref(),
+        cfg.setcap.as_ref(),
+        cfg.nextest.as_ref(),
+        cfg.cargo_fuzz.as_ref(),
+        cfg.cargo_audit.as_ref(),
+        cfg.cargo_deny.as_ref(),
+        cfg.ssh.as_ref(),
+        cfg.rsync.as_ref(),
+        cfg.downloader.as_ref(),
+        cfg.patch.as_ref(),
+        cfg.tar.as_ref(),
+        cfg.unzip.as_ref(),
+        cfg.bindgen.as_ref(),
+        cfg.protoc.as_ref(),
+        cfg.flatc.as_ref(),
+        cfg.cbindgen.as_ref(),
+        cfg.yacc.as_ref(),
+        cfg.lex.as_ref(),
+        cfg.assembler.as_ref(),
+        cfg.cc.as_ref(),
+        cfg.ar.as_ref(),
+        cfg.desktop_file_validate.as_ref(),
+        cfg.appstreamcli.as_ref(),
+    ]
+    .iter()
+    .flatten()
+    .map(|p| p.as_path())
+    .chain(cfg.programs.values().map(PathBuf::as_path))
+    .collect()
+}
+
+// Cross tools carry the host triple in their names, as in x86_64-linux-gnu-gcc
+fn program_name(path: &Path, cfg: &Configuration) -> String {
+    let name = path
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    match name.strip_prefix(&format!("{}-", cfg.host.get_name())) {
+        Some(name) => name.to_string(),
+        None => name,
+    }
+}
+
+fn environment(plan: &Plan, cfg: &Configuration) -> std::io::Result<Environment> {
+    let rustc = &cfg.rustc.version;
+    let channel = match rustc.channel {
+        RustcChannel::Stable => "stable",
+        RustcChannel::Beta => "beta",
+        RustcChannel::Nightly => "nightly",
+        RustcChannel::Dev => "dev",
+        RustcChannel::Unstable => "unstable",
+    };
+    let mut env = Environment {
+        name: cfg
+            .src_dir
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        comments: vec![format!(
+            "{} {}.{}.{} ({}) for {}",
+            rustc.prgname, rustc.major, rustc.minor, rustc.patch, channel, cfg.host
+        )],
+        tools: BTreeSet::new(),
+        libraries: BTreeSet::new(),
+    };
+    if cfg.host != cfg.build {
+        env.comments.push(format!(
+            "cross compiling from {}: the libraries are needed for the host",
+            cfg.build
+        ));
+    }
+
+    for path in tools(cfg) {
+        let name = program_name(path, cfg);
+        match tool_package(&name) {
+            Some(package) => {
+                env.tools.insert(package);
+            }
+            None => env
+                .comments
+                .push(format!("no known package provides {}", path.display())),
+        }
+    }
+
+    let mut libraries = crate::sbom::native_libraries(plan, cfg)?;
+    for step in &plan.steps {
+        match &*step.step {
+            Step::Bindgen(s) => libraries.extend(s.pkg_config.iter().cloned()),
+            Step::PkgConfig(s) => libraries.extend(
+                s.requires
+                    .iter()
+                    .chain(&s.requires_private)
+                    .map(|r| r.split_whitespace().next().unwrap_or_default().to_string()),
+            ),
+            _ => continue,
+        }
+        env.tools.extend(tool_package("pkg-config"));
+    }
+    for library in libraries.iter().filter(|l| !l.is_empty()) {
+        match library_package(library) {
+            Some(package) => {
+                env.libraries.insert(package);
+            }
+            None => env
+                .comments
+                .push(format!("no known package provides the library {}", library)),
+        }
+    }
+    Ok(env)
+}
+
+fn nix_list(out: &mut String, indent: &str, attr: &str, packages: &[&str]) {
+    out.push_str(&format!("{}{} = with pkgs; [\n", indent, attr));
+    for package in packages {
+        out.push_str(&format!("{}  {}\n", indent, package));
+    }
+    out.push_str(&format!("{}];\n", indent));
+}
+
+fn mk_shell(env: &Environment, indent: &str) -> String {
+    let tools: Vec<&str> = env.tools.iter().map(|(nix, _)| *nix).collect();
+    let libraries: Vec<&str> = env.libraries.iter().map(|(nix, _)| *nix).collect();
+    let mut out = format!("{}pkgs.mkShell {{\n", indent);
+    let inner = format!("{}  ", indent);
+    nix_list(&mut out, &inner, "nativeBuildInputs", &tools);
+    nix_list(&mut out, &inner, "buildInputs", &libraries);
+    out.push_str(&format!("{}}}", indent));
+    out
+}
+
+fn comments(env: &Environment, prefix: &str) -> String {
+    let mut out = format!(
+        "{} Generated by cargo-autobuild from the configure results of {}\n",
+        prefix, env.name
+    );
+    for comment in &env.comments {
+        out.push_str(&format!("{} {}\n", prefix, comment));
+    }
+    out
+}
+
+fn shell_nix(env: &Environment) -> String {
+    let mut out = comments(env, "#");
+    out.push_str("{ pkgs ? import <nixpkgs> { } }:\n\n");
+    out.push_str(&mk_shell(env, ""));
+    out.push('\n');
+    out
+}
+
+fn flake_nix(env: &Environment) -> String {
+    let mut out = comments(env, "#");
+    out.push_str("{\n");
+    out.push_str(&format!(
+        "  description = \"Build environment for {}\";\n\n",
+        env.name
+    ));
+    out.push_str("  inputs.nixpkgs.url = \"github:NixOS/nixpkgs/nixos-unstable\";\n");
+    out.push_str("  inputs.flake-utils.url = \"github:numtide/flake-utils\";\n\n");
+    out.push_str("  outputs = { self, nixpkgs, flake-utils }:\n");
+    out.push_str("    flake-utils.lib.eachDefaultSystem (system:\n");
+    out.push_str("      let pkgs = nixpkgs.legacyPackages.${system}; in {\n");
+    out.push_str("        devShells.default =\n");
+    out.push_str(&mk_shell(env, "          "));
+    out.push_str(";\n      });\n}\n");
+    out
+}
+
+fn guix_manifest(env: &Environment) -> String {
+    let mut out = comments(env, ";;");
+    let mut packages: Vec<&str> = env.tools.iter().filter_map(|(_, guix)| *guix).collect();
+    packages.extend(env.libraries.iter().map(|(_, guix)| *guix));
+    for (nix, guix) in &env.tools {
+        if guix.is_none() {
+            out.push_str(&format!(";; no Guix package is known for {}\n", nix));
+        }
+    }
+    out.push_str("(specifications->manifest\n (list");
+    for (i, package) in packages.iter().enumerate() {
+        if i > 0 {
+            out.push_str("\n       ");
+        } else {
+            out.push(' ');
+        }
+        out.push_str(&format!("\"{}\"", package));
+    }
+    out.push_str("))\n");
+    out
+}
+
+// The files are skeletons written to the build directory, to be copied into the source tree and
+// adjusted, for example to pin the toolchain version
+pub fn write(plan: &Plan, cfg: &Configuration, format: &str) -> std::io::Result<()> {
+    let env = environment(plan, cfg)?;
+    // --format defaults to dot for `graph`, which means shell.nix here
+    let (text, file) = match format {
+        "shell-nix" | "dot" => (shell_nix(&env), "shell.nix"),
+        "flake" => (flake_nix(&env), "flake.nix"),
+        "guix" => (guix_manifest(&env), "manifest.scm"),
+        format => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Unknown environment format {}; use shell-nix, flake or guix",
+                    format
+                ),
+            ))
+        }
+    };
+    let path = cfg.build_dir.join(file);
+    std::fs::write(&path, text)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
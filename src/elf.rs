@@ -0,0 +1,160 @@
+use std::{convert::TryInto, io::ErrorKind, path::Path};
+
+const PT_LOAD: u32 = 1;
+const PT_DYNAMIC: u32 = 2;
+const PT_INTERP: u32 = 3;
+const DT_NULL: u64 = 0;
+const DT_NEEDED: u64 = 1;
+const DT_STRTAB: u64 = 5;
+
+pub struct DynamicInfo {
+    pub interp: bool,
+    pub needed: Vec<String>,
+}
+
+impl DynamicInfo {
+    pub fn is_static(&self) -> bool {
+        !self.interp && self.needed.is_empty()
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    wide: bool,
+    little: bool,
+}
+
+impl Reader<'_> {
+    fn bytes<const N: usize>(&self, off: usize) -> Option<[u8; N]> {
+        self.data.get(off..off + N)?.try_into().ok()
+    }
+
+    fn u16(&self, off: usize) -> Option<u16> {
+        let b = self.bytes(off)?;
+        Some(if self.little {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, off: usize) -> Option<u32> {
+        let b = self.bytes(off)?;
+        Some(if self.little {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn u64(&self, off: usize) -> Option<u64> {
+        let b = self.bytes(off)?;
+        Some(if self.little {
+            u64::from_le_bytes(b)
+        } else {
+            u64::from_be_bytes(b)
+        })
+    }
+
+    fn word(&self, off: usize) -> Option<u64> {
+        if self.wide {
+            self.u64(off)
+        } else {
+            self.u32(off).map(u64::from)
+        }
+    }
+
+    fn cstr(&self, off: usize) -> Option<String> {
+        let rest = self.data.get(off..)?;
+        let end = rest.iter().position(|&b| b == 0)?;
+        Some(String::from_utf8_lossy(&rest[..end]).into_owned())
+    }
+}
+
+struct Segment {
+    ty: u32,
+    offset: u64,
+    vaddr: u64,
+    filesz: u64,
+}
+
+fn segments(r: &Reader) -> Option<Vec<Segment>> {
+    let (phoff, phentsize, phnum) = if r.wide {
+        (r.u64(0x20)?, r.u16(0x36)?, r.u16(0x38)?)
+    } else {
+        (u64::from(r.u32(0x1c)?), r.u16(0x2a)?, r.u16(0x2c)?)
+    };
+    let mut segments = Vec::new();
+    for i in 0..usize::from(phnum) {
+        let base = phoff as usize + i * usize::from(phentsize);
+        segments.push(if r.wide {
+            Segment {
+                ty: r.u32(base)?,
+                offset: r.u64(base + 0x08)?,
+                vaddr: r.u64(base + 0x10)?,
+                filesz: r.u64(base + 0x20)?,
+            }
+        } else {
+            Segment {
+                ty: r.u32(base)?,
+                offset: u64::from(r.u32(base + 0x04)?),
+                vaddr: u64::from(r.u32(base + 0x08)?),
+                filesz: u64::from(r.u32(base + 0x10)?),
+            }
+        });
+    }
+    Some(segments)
+}
+
+fn parse(data: &[u8]) -> Option<DynamicInfo> {
+    let r = Reader {
+        data,
+        wide: data.get(4)? == &2,
+        little: data.get(5)? == &1,
+    };
+    let segments = segments(&r)?;
+    let interp = segments.iter().any(|s| s.ty == PT_INTERP);
+
+    let mut needed = Vec::new();
+    if let Some(dynamic) = segments.iter().find(|s| s.ty == PT_DYNAMIC) {
+        let entsize = if r.wide { 16 } else { 8 };
+        let mut offsets = Vec::new();
+        let mut strtab = None;
+        for i in 0..(dynamic.filesz / entsize) {
+            let base = (dynamic.offset + i * entsize) as usize;
+            let tag = r.word(base)?;
+            let val = r.word(base + entsize as usize / 2)?;
+            match tag {
+                DT_NULL => break,
+                DT_NEEDED => offsets.push(val),
+                DT_STRTAB => strtab = Some(val),
+                _ => {}
+            }
+        }
+        if !offsets.is_empty() {
+            let strtab = strtab?;
+            let load = segments
+                .iter()
+                .find(|s| s.ty == PT_LOAD && s.vaddr <= strtab && strtab < s.vaddr + s.filesz)?;
+            let strtab = strtab - load.vaddr + load.offset;
+            for off in offsets {
+                needed.push(r.cstr((strtab + off) as usize)?);
+            }
+        }
+    }
+
+    Some(DynamicInfo { interp, needed })
+}
+
+pub fn dynamic_info(path: &Path) -> std::io::Result<Option<DynamicInfo>> {
+    let data = std::fs::read(path)?;
+    if !data.starts_with(b"\x7fELF") {
+        return Ok(None);
+    }
+    parse(&data).map(Some).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Malformed ELF file {}", path.display()),
+        )
+    })
+}
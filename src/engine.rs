@@ -0,0 +1,583 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    cli::Options,
+    config::{Config, OnFailure, OutputMode, Step, StepPolicy},
+    configure::{BuildMachine, Configuration},
+    handler::{Registry, StepHandler},
+    plan::{Plan, PlannedStep},
+    remote::{Backend, Local},
+    state::BuildState,
+    steps::StepContext,
+    timings::{Report, Timing},
+};
+
+pub struct Runner<'a> {
+    cfg: &'a Configuration,
+    state: BuildState,
+    timings: Vec<Timing>,
+    policies: HashMap<String, StepPolicy>,
+    keep_going: bool,
+    // Where steps marked `distribute` run; here when unset
+    remote: Option<Box<dyn Backend>>,
+}
+
+// Output that is not streamed is held back until the step is over, so that it never interleaves
+// with that of other steps
+fn report_log(step: &PlannedStep, log: &Path, mode: OutputMode) -> std::io::Result<()> {
+    if mode == OutputMode::OnFailure {
+        let output = std::fs::read(log)?;
+        eprintln!("cargo-autobuild: output of {}:", step.name);
+        std::io::stderr().write_all(&output)?;
+    } else {
+        eprintln!(
+            "cargo-autobuild: the output of {} is in {}",
+            step.name,
+            log.display()
+        );
+    }
+    Ok(())
+}
+
+// The PGO steps build and train in the instrumented builds' own target directory
+fn step_context(step: &PlannedStep, cfg: &Configuration) -> StepContext {
+    if !step.name.starts_with("pgo.") {
+        return StepContext::default();
+    }
+    StepContext {
+        target_dir: Some(crate::plan::pgo_target_dir(cfg)),
+        vars: cfg.pgo_vars.clone(),
+        ..StepContext::default()
+    }
+}
+
+// Runs the step again while it fails, up to its `retries`, giving each run its own timeout. The
+// log holds the output of every attempt
+fn attempt(
+    step: &PlannedStep,
+    cfg: &Configuration,
+    policy: &StepPolicy,
+    backend: &dyn Backend,
+) -> std::io::Result<()> {
+    let log = match policy.output_mode {
+        OutputMode::Stream => None,
+        _ => Some(crate::steps::log_path(&step.name, cfg)),
+    };
+    let file = match &log {
+        Some(log) => {
+            if let Some(dir) = log.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            Some(File::create(log)?)
+        }
+        None => None,
+    };
+    let mut attempts = 0;
+    loop {
+        let context = StepContext {
+            deadline: policy
+                .timeout
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+            log: file.as_ref().map(File::try_clone).transpose()?,
+            ..step_context(step, cfg)
+        };
+        let result = crate::steps::with_context(context, || backend.execute(step, cfg));
+        match result {
+            Err(e) if attempts < policy.retries => {
+                attempts += 1;
+                eprintln!(
+                    "cargo-autobuild: {} failed: {}; retrying ({} of {})",
+                    step.name, e, attempts, policy.retries
+                );
+            }
+            result => {
+                if let (Err(_), Some(log)) = (&result, &log) {
+                    report_log(step, log, policy.output_mode)?;
+                }
+                return result;
+            }
+        }
+    }
+}
+
+impl<'a> Runner<'a> {
+    pub fn new(cfg: &'a Configuration, force: bool) -> Self {
+        let state = if force {
+            BuildState::default()
+        } else {
+            BuildState::load(&cfg.build_dir)
+        };
+        Runner {
+            cfg,
+            state,
+            timings: Vec::new(),
+            policies: HashMap::new(),
+            keep_going: false,
+            remote: None,
+        }
+    }
+
+    // With `keep_going`, steps that would abort the run only stop the steps that depend on them,
+    // as with `make -k`
+    pub fn with_policies(
+        mut self,
+        policies: HashMap<String, StepPolicy>,
+        keep_going: bool,
+    ) -> Self {
+        self.policies = policies;
+        self.keep_going = keep_going;
+        self
+    }
+
+    pub fn with_remote(mut self, remote: Option<Box<dyn Backend>>) -> Self {
+        self.remote = remote;
+        self
+    }
+
+    pub fn timings(&self) -> &[Timing] {
+        &self.timings
+    }
+
+    pub fn run(&mut self, plan: &Plan) -> std::io::Result<()> {
+        let cfg = self.cfg;
+        let no_policy = StepPolicy::default();
+        // Steps that failed, or were never run because a step they depend on failed
+        let mut broken = vec![false; plan.steps.len()];
+        let mut failed = Vec::new();
+        for (index, step) in plan.steps.iter().enumerate() {
+            let start = Instant::now();
+            let policy = self.policies.get(&step.name).unwrap_or(&no_policy);
+            if let Some(&dep) = step.deps.iter().find(|&&dep| broken[dep]) {
+                println!(
+                    "Not running {} ({} failed)",
+                    step.name, plan.steps[dep].name
+                );
+                broken[index] = true;
+                let mut timing = Timing::new(&step.name, cfg.epoch, start, start.elapsed());
+                timing.status = "blocked".to_string();
+                timing.deps = step.deps.clone();
+                self.timings.push(timing);
+                continue;
+            }
+            let record = crate::state::step_record(&step.name, &step.step, cfg)?;
+            let fresh = match &record {
+                Some(record) => self.state.is_fresh(&step.name, record),
+                None => false,
+            };
+
+            let mut result = Ok(());
+            let status = if fresh {
+                crate::ui::step_skipped(&step.name);
+                "skipped"
+            } else {
+                crate::ui::step_started(
+                    index,
+                    plan.steps.len(),
+                    &step.name,
+                    &crate::steps::describe_step(&step.step, cfg),
+                );
+                self.state.invalidate(&step.name);
+                let first_instrumented = step.name.starts_with("pgo.instrument.")
+                    && !plan.steps[..index]
+                        .iter()
+                        .any(|s| s.name.starts_with("pgo.instrument."));
+                if first_instrumented {
+                    crate::plan::clear_profiles(cfg)?;
+                }
+                let backend = match &self.remote {
+                    Some(remote) if policy.distribute => &**remote,
+                    _ => &Local,
+                };
+                result = attempt(step, cfg, policy, backend);
+                if let (Ok(()), Some(record)) = (&result, record) {
+                    self.state.record(&step.name, record);
+                }
+                if result.is_ok() {
+                    let outputs = crate::steps::with_context(step_context(step, cfg), || {
+                        crate::steps::step_artifacts(&step.step, cfg)
+                    })?;
+                    self.state
+                        .record_outputs(&step.name, outputs.into_iter().map(|a| a.path).collect());
+                }
+                self.state.save(&cfg.build_dir)?;
+                crate::ui::step_finished(&step.name, result.is_ok(), start.elapsed());
+                if result.is_ok() {
+                    "ok"
+                } else {
+                    "failed"
+                }
+            };
+
+            let mut timing = Timing::new(&step.name, cfg.epoch, start, start.elapsed());
+            timing.status = status.to_string();
+            timing.deps = step.deps.clone();
+            self.timings.push(timing);
+            if let Err(e) = result {
+                match policy.on_failure {
+                    OnFailure::Continue => eprintln!(
+                        "cargo-autobuild: warning: {} failed: {}; continuing",
+                        step.name, e
+                    ),
+                    OnFailure::Abort if !self.keep_going => return Err(e),
+                    _ => {
+                        eprintln!("cargo-autobuild: {} failed: {}", step.name, e);
+                        broken[index] = true;
+                        failed.push(step.name.clone());
+                    }
+                }
+            }
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "Steps failed: {}",
+                failed.join(", ")
+            )))
+        }
+    }
+}
+
+pub struct Engine {
+    opts: Options,
+    config: Config,
+    handlers: Registry,
+    // Kept from configuring, for the next host of a matrix build
+    machine: Mutex<Option<BuildMachine>>,
+}
+
+impl Engine {
+    pub fn new(opts: Options) -> std::io::Result<Self> {
+        let config = crate::config::load(&opts.config)?;
+        Ok(Engine::with_config(opts, config))
+    }
+
+    pub fn with_config(opts: Options, config: Config) -> Self {
+        if opts.no_color {
+            crate::ui::disable();
+        }
+        Engine {
+            opts,
+            config,
+            handlers: Registry::new(),
+            machine: Mutex::new(None),
+        }
+    }
+
+    pub fn register<H: StepHandler + 'static>(&mut self, kind: &str, handler: H) {
+        self.handlers.register(kind, handler);
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.opts
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // Policies are written against a step's phase and index, but steps are planned under their ids
+    fn policies(&self) -> HashMap<String, StepPolicy> {
+        self.config
+            .policies
+            .iter()
+            .map(|(position, policy)| (self.config.step_name(position), policy.clone()))
+            .collect()
+    }
+
+    pub fn configure(&self) -> std::io::Result<Configuration> {
+        let src_dir = self.opts.src_dir();
+        for hook in &self.config.hooks.pre_configure {
+            let (shell, args) = crate::plan::shell_command(hook);
+            let status = Command::new(shell)
+                .args(args)
+                .current_dir(src_dir)
+                .status()?;
+            if !status.success() {
+                return Err(std::io::Error::other(format!(
+                    "pre-configure hook `{}` failed ({})",
+                    hook, status
+                )));
+            }
+        }
+
+        let mut machine = self.machine.lock().unwrap();
+        let mut cfg = crate::configure::configure(&self.config, &self.opts, machine.as_ref())?;
+        *machine = Some(BuildMachine::of(&cfg));
+        drop(machine);
+        cfg.handlers = self.handlers.clone();
+
+        for hook in &self.config.hooks.post_configure {
+            crate::steps::run_step(&crate::plan::hook_step(hook), &cfg)?;
+        }
+        Ok(cfg)
+    }
+
+    pub fn plan(&self, phases: &[&str], cfg: &mut Configuration) -> std::io::Result<Plan<'_>> {
+        let overrides = crate::overrides::apply(&self.config, cfg)?;
+        let mut plan = crate::plan::plan(&self.config, phases, cfg, &overrides);
+        for step in &plan.steps {
+            let artifacts = crate::steps::with_context(step_context(step, cfg), || {
+                crate::steps::step_artifacts(&step.step, cfg)
+            })?;
+            let vars = if step.name.starts_with("pgo.instrument.") {
+                &mut cfg.pgo_vars
+            } else {
+                &mut cfg.vars
+            };
+            for artifact in &artifacts {
+                vars.insert(
+                    format!("artifact:{}", artifact.key),
+                    artifact.path.display().to_string(),
+                );
+            }
+            cfg.step_artifacts.insert(step.name.clone(), artifacts);
+            if let Step::Fetch(fetch) = &*step.step {
+                cfg.checksums
+                    .insert(crate::fetch::dest(fetch, cfg), fetch.sha256.clone());
+            }
+            let generated = match &*step.step {
+                Step::Bindgen(s) => Some((crate::bindgen::output(s, cfg), &s.env)),
+                Step::Protoc(s) => Some((crate::codegen::output(&s.output, cfg), &s.env)),
+                Step::Flatc(s) => Some((crate::codegen::output(&s.output, cfg), &s.env)),
+                Step::GenerateParser(s) => Some((crate::codegen::output(&s.output, cfg), &s.env)),
+                Step::Assemble(s) => crate::assemble::archive(s, cfg).map(|a| (a, &s.env)),
+                _ => None,
+            };
+            if let Some((output, env)) = generated {
+                if let Some(var) = env {
+                    cfg.build_env
+                        .insert(var.clone(), output.display().to_string());
+                }
+                cfg.generated.push(output);
+            }
+            if let Step::Assemble(s) = &*step.step {
+                if let (true, Some(archive)) = (s.link, crate::assemble::archive(s, cfg)) {
+                    cfg.profile
+                        .rustflags
+                        .push(format!("-Clink-arg={}", archive.display()));
+                }
+            }
+            let vars = cfg
+                .handlers
+                .get(step.step.kind())?
+                .variables(&step.step, cfg)?;
+            cfg.vars.extend(vars);
+        }
+        crate::plan::link(&mut plan, cfg)?;
+        crate::plan::select(
+            plan,
+            &self.opts.only,
+            &self.opts.skip,
+            self.opts.until.as_deref(),
+        )
+    }
+
+    // Each host gets its own configuration and build directory; cargo already keeps the
+    // artifacts of different --target triples apart. What is probed about the build machine is
+    // probed once, for the first host
+    fn run_matrix(&self, hosts: &[String]) -> std::io::Result<()> {
+        let mut failed = Vec::new();
+        let mut machine = None;
+        for host in hosts {
+            println!("=== {} ===", host);
+            let mut opts = self.opts.clone();
+            opts.host = Some(host.clone());
+            opts.hosts.clear();
+            let base = self
+                .opts
+                .build_dir
+                .as_deref()
+                .unwrap_or(Path::new(".autobuild"));
+            opts.build_dir = Some(base.join(host));
+            let engine = Engine {
+                opts,
+                config: self.config.clone(),
+                handlers: self.handlers.clone(),
+                machine: Mutex::new(machine.take()),
+            };
+            if let Err(e) = engine.run() {
+                eprintln!("cargo-autobuild: {}: {}", host, e);
+                failed.push(host.as_str());
+            }
+            machine = engine.machine.into_inner().unwrap();
+        }
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(std::io::Error::other(format!(
+                "{} failed for {}",
+                self.opts.command,
+                failed.join(", ")
+            )))
+        }
+    }
+
+    // The build phase on its own, handing back the configuration so its artifacts can be found
+    fn build(&self) -> std::io::Result<Configuration> {
+        let mut cfg = self.configure()?;
+        let plan = self.plan(&["build"], &mut cfg)?;
+        let mut runner = Runner::new(&cfg, self.opts.force)
+            .with_policies(self.policies(), self.opts.keep_going)
+            .with_remote(crate::remote::backend(&self.config, &self.opts, &cfg));
+        let start = Instant::now();
+        let result = runner.run(&plan);
+        crate::ui::summary(runner.timings(), start.elapsed());
+        result?;
+        Ok(cfg)
+    }
+
+    // Every stage gets its own build and target directory, so cargo never mistakes the output of
+    // one compiler for that of another
+    fn run_bootstrap(&self) -> std::io::Result<()> {
+        let bootstrap = self.config.bootstrap.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                "The configuration has no [bootstrap] section",
+            )
+        })?;
+        let base = self
+            .opts
+            .build_dir
+            .clone()
+            .unwrap_or_else(|| PathBuf::from(".autobuild"));
+        let mut compiler = None;
+        let mut stages = Vec::new();
+        for stage in 0..bootstrap.stages {
+            println!("=== stage{} ===", stage);
+            let mut opts = self.opts.clone();
+            let dir = base.join(format!("stage{}", stage));
+            opts.target_dir = Some(dir.join("target"));
+            opts.build_dir = Some(dir);
+            let engine = Engine {
+                opts,
+                config: crate::bootstrap::stage_config(&self.config, compiler.as_deref()),
+                handlers: self.handlers.clone(),
+                machine: Mutex::new(None),
+            };
+            let cfg = engine.build()?;
+            compiler = Some(crate::bootstrap::artifact(&cfg, &bootstrap.artifact)?);
+            stages.push(cfg);
+        }
+        let keys = match &bootstrap.compare {
+            Some(keys) => keys.clone(),
+            None => vec![bootstrap.artifact.clone()],
+        };
+        match &*stages {
+            [.., earlier, later] => crate::bootstrap::compare(&keys, earlier, later),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn run(&self) -> std::io::Result<()> {
+        let phases: &[&str] = match &*self.opts.command {
+            "clean" => return crate::clean::clean(&self.opts, &self.config, false),
+            "distclean" => return crate::clean::clean(&self.opts, &self.config, true),
+            "uninstall" => return crate::uninstall::uninstall(&self.opts, &self.config),
+            "lint" => return crate::lint::lint(&self.opts, &self.config, &self.handlers),
+            "bootstrap" => return self.run_bootstrap(),
+            "ci" => return crate::ci::ci(&self.opts, &self.config),
+            "configure" => &[],
+            "build" | "watch" | "compile-commands" | "rust-project" => &["build"],
+            "test" | "check" => &["build", "test"],
+            "bench" => &["build", "bench"],
+            // What a worker runs for a step marked `distribute`
+            "run-step" => &["build", "test", "bench"],
+            "install" | "check-install" | "graph" | "explain" | "sbom" | "meson-wrap"
+            | "dev-env" => &["build", "install"],
+            cmd => {
+                return Err(std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Unknown command {}", cmd),
+                ))
+            }
+        };
+
+        if crate::container::wanted(&self.opts) {
+            return crate::container::run(&self.opts, &self.config);
+        }
+
+        let hosts = match &self.opts.hosts {
+            hosts if !hosts.is_empty() => hosts,
+            _ => &self.config.options.hosts,
+        };
+        if self.opts.host.is_none() && !hosts.is_empty() {
+            return self.run_matrix(hosts);
+        }
+
+        let mut cfg = self.configure()?;
+        if self.opts.hermetic {
+            crate::hermetic::enter(&self.config, &cfg)?;
+        }
+        let plan = self.plan(phases, &mut cfg)?;
+
+        if self.opts.command == "graph" {
+            return crate::graph::write(&plan, &cfg, &self.opts.format);
+        }
+        if self.opts.command == "sbom" {
+            return crate::sbom::write(&plan, &cfg, &self.opts.format);
+        }
+        if self.opts.command == "dev-env" {
+            return crate::devenv::write(&plan, &cfg, &self.opts.format);
+        }
+        if self.opts.command == "rust-project" {
+            return crate::rust_project::write(&plan, &cfg);
+        }
+        if self.opts.command == "compile-commands" {
+            return crate::compdb::write(&plan, &cfg, self.opts.args.first().map(|s| &**s));
+        }
+        if self.opts.command == "meson-wrap" {
+            return crate::meson::write(&plan, &cfg, &self.config, &self.opts);
+        }
+        if self.opts.command == "explain" {
+            return crate::explain::explain(&plan, &cfg, &self.opts.args);
+        }
+        if self.opts.command == "run-step" {
+            return crate::remote::run_step(&plan, &cfg, &self.opts.args);
+        }
+        if self.opts.command == "watch" {
+            return crate::watch::watch(&plan.steps, &cfg);
+        }
+        if self.opts.command == "check-install" {
+            return crate::check_install::check(&plan, &cfg, self.opts.force);
+        }
+
+        let mut runner = Runner::new(&cfg, self.opts.force)
+            .with_policies(self.policies(), self.opts.keep_going)
+            .with_remote(crate::remote::backend(&self.config, &self.opts, &cfg));
+        let start = Instant::now();
+        let result = runner.run(&plan);
+        crate::ui::summary(runner.timings(), start.elapsed());
+        crate::build_info::write(&cfg, &self.opts.command, runner.timings(), result.is_ok())?;
+
+        if let Some(formats) = &self.opts.timings {
+            let report = Report::new(
+                cfg.probe_timings.clone(),
+                runner.timings().to_vec(),
+                cfg.epoch.elapsed(),
+            );
+            report.write(&cfg.build_dir.join("timings"), formats)?;
+        }
+        result?;
+
+        if self.opts.command == "install" {
+            crate::link_audit::audit(&cfg)?;
+        }
+        if plan.builds_rust() {
+            crate::steps::report_cache_stats(&cfg);
+            // Keeps rust-analyzer working when the compiler is not rustc
+            if self.opts.command == "build" && cfg.rustc.version.prgname != "rustc" {
+                crate::rust_project::write(&plan, &cfg)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+use std::{io::ErrorKind, path::Path};
+
+use crate::{
+    config::{InstallTarget, Step},
+    configure::Configuration,
+    plan::{Plan, PlannedStep},
+    state::BuildState,
+};
+
+fn print_log(cfg: &Configuration, subject: &str) -> bool {
+    match cfg.log.get(subject) {
+        Some(lines) => {
+            println!("{}:", subject);
+            for line in lines {
+                println!("    {}", line);
+            }
+            true
+        }
+        None => false,
+    }
+}
+
+fn explain_step(plan: &Plan, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()> {
+    println!("step {} ({})", step.name, step.step.kind());
+    println!("    {}", crate::steps::describe_step(&step.step, cfg));
+    for &dep in &step.deps {
+        println!("    runs after {}", plan.steps[dep].name);
+    }
+    for input in crate::steps::step_inputs(&step.step, cfg)? {
+        println!("    reads {}", input.display());
+    }
+    for artifact in crate::steps::step_artifacts(&step.step, cfg)? {
+        println!(
+            "    produces {} ({}) at {}",
+            artifact.key,
+            artifact.kind,
+            artifact.path.display()
+        );
+    }
+    let state = BuildState::load(&cfg.build_dir);
+    match crate::state::step_record(&step.name, &step.step, cfg)? {
+        Some(record) if state.is_fresh(&step.name, &record) => {
+            println!("    is up to date")
+        }
+        Some(_) => println!("    is out of date"),
+        None => println!("    always runs"),
+    }
+    Ok(())
+}
+
+fn explain_install_path(plan: &Plan, cfg: &Configuration, path: &Path) -> std::io::Result<bool> {
+    let mut found = false;
+    for step in &plan.steps {
+        let target = match &*step.step {
+            Step::Install(install) => &install.target,
+            Step::InstallSymlink(link) => &link.link,
+            _ => continue,
+        };
+        let dest = cfg.install_path(target)?;
+        if !path.starts_with(&dest) && !dest.starts_with(path) {
+            continue;
+        }
+        found = true;
+        explain_step(plan, step, cfg)?;
+        if let InstallTarget::Base { base, .. } = target {
+            print_log(cfg, base.name());
+        }
+    }
+    Ok(found)
+}
+
+pub fn explain(plan: &Plan, cfg: &Configuration, things: &[String]) -> std::io::Result<()> {
+    if things.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "explain requires a program, step, install directory, or install path",
+        ));
+    }
+    for thing in things {
+        let explained = match plan.steps.iter().find(|s| s.name == *thing) {
+            Some(step) => {
+                explain_step(plan, step, cfg)?;
+                true
+            }
+            None => {
+                print_log(cfg, thing.trim_start_matches('$'))
+                    || explain_install_path(plan, cfg, Path::new(thing))?
+            }
+        };
+        if !explained {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("Nothing named {} was resolved", thing),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,173 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config::ExtractStep, configure::Configuration, steps::substitute};
+
+pub fn archive(step: &ExtractStep, cfg: &Configuration) -> PathBuf {
+    cfg.src_dir
+        .join(substitute(&step.archive.to_string_lossy(), cfg))
+}
+
+pub fn dest(step: &ExtractStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(&step.dest)
+}
+
+pub fn is_zip(archive: &Path) -> bool {
+    archive
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn decompress_flag(archive: &Path) -> std::io::Result<Option<&'static str>> {
+    let name = archive
+        .file_name()
+        .map(|n| n.to_string_lossy().to_ascii_lowercase())
+        .unwrap_or_default();
+    let formats: &[(&[&str], Option<&str>)] = &[
+        (&[".tar.gz", ".tgz"], Some("-z")),
+        (&[".tar.xz", ".txz"], Some("-J")),
+        (&[".tar.bz2", ".tbz2", ".tbz"], Some("-j")),
+        (&[".tar.zst", ".tzst"], Some("--zstd")),
+        (&[".tar"], None),
+    ];
+    formats
+        .iter()
+        .find(|(suffixes, _)| suffixes.iter().any(|s| name.ends_with(s)))
+        .map(|(_, flag)| *flag)
+        .ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not a known archive format", archive.display()),
+            )
+        })
+}
+
+fn verify(step: &ExtractStep, archive: &Path, cfg: &Configuration) -> std::io::Result<()> {
+    // An archive downloaded by a fetch step is held to that step's checksum
+    let expected = step.sha256.as_ref().or_else(|| cfg.checksums.get(archive));
+    let expected = match expected {
+        Some(expected) => expected,
+        None => {
+            eprintln!(
+                "cargo-autobuild: warning: {} has no checksum; extracting it unchecked",
+                archive.display()
+            );
+            return Ok(());
+        }
+    };
+    let actual = crate::sha256::file(archive)?;
+    if actual.eq_ignore_ascii_case(expected) {
+        Ok(())
+    } else {
+        Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "{} has SHA-256 {}, expected {}",
+                archive.display(),
+                actual,
+                expected
+            ),
+        ))
+    }
+}
+
+// The entries `depth` directories down, which is where strip-components starts the tree
+fn entries(dir: &Path, depth: u32) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)? {
+        let path = entry?.path();
+        if depth == 0 {
+            paths.push(path);
+        } else if path.is_dir() {
+            paths.extend(entries(&path, depth - 1)?);
+        }
+    }
+    Ok(paths)
+}
+
+// Unpacks into a staging directory next to `dest` and moves the stripped tree into place, so a
+// failed extraction never leaves a partial tree behind
+pub fn extract(step: &ExtractStep, cfg: &Configuration) -> std::io::Result<()> {
+    let archive = archive(step, cfg);
+    verify(step, &archive, cfg)?;
+    let dest = dest(step, cfg);
+    let staging = sibling(&dest, ".tmp");
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)?;
+    }
+    std::fs::create_dir_all(&staging)?;
+
+    let mut cmd = if is_zip(&archive) {
+        let unzip = cfg.unzip.as_ref().ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                "Extracting zip archives requires unzip",
+            )
+        })?;
+        let mut cmd = Command::new(unzip);
+        cmd.arg("-q").arg(&archive).arg("-d").arg(&staging);
+        cmd
+    } else {
+        let tar = cfg.tar.as_ref().ok_or_else(|| {
+            std::io::Error::new(ErrorKind::NotFound, "Extracting archives requires tar")
+        })?;
+        let mut cmd = Command::new(tar);
+        cmd.arg("-x");
+        if let Some(flag) = decompress_flag(&archive)? {
+            cmd.arg(flag);
+        }
+        cmd.arg("-f").arg(&archive).arg("-C").arg(&staging);
+        cmd
+    };
+    crate::steps::note(&format!(
+        "Extracting {} -> {}",
+        archive.display(),
+        dest.display()
+    ));
+    crate::steps::run(&mut cmd)?;
+
+    let tree = if step.strip_components == 0 {
+        staging.clone()
+    } else {
+        let tree = sibling(&dest, ".new");
+        strip_components(&staging, &tree, step.strip_components)?;
+        std::fs::remove_dir_all(&staging)?;
+        tree
+    };
+    if dest.exists() {
+        std::fs::remove_dir_all(&dest)?;
+    }
+    std::fs::rename(&tree, &dest)
+}
+
+fn sibling(dest: &Path, suffix: &str) -> PathBuf {
+    let mut path = dest.as_os_str().to_owned();
+    path.push(suffix);
+    PathBuf::from(path)
+}
+
+// Gathers the stripped tree in `tree`, leaving `dest` untouched until the tree is whole
+fn strip_components(staging: &Path, tree: &Path, depth: u32) -> std::io::Result<()> {
+    if tree.exists() {
+        std::fs::remove_dir_all(tree)?;
+    }
+    std::fs::create_dir_all(tree)?;
+    for path in entries(staging, depth)? {
+        let to = tree.join(path.file_name().unwrap());
+        if to.exists() {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} appears more than once after stripping {} components",
+                    path.file_name().unwrap().to_string_lossy(),
+                    depth
+                ),
+            ));
+        }
+        std::fs::rename(&path, to)?;
+    }
+    Ok(())
+}
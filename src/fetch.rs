@@ -0,0 +1,107 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config::FetchStep, configure::Configuration};
+
+pub fn file_name(step: &FetchStep) -> PathBuf {
+    match &step.dest {
+        Some(dest) => dest.clone(),
+        None => PathBuf::from(
+            step.url
+                .rsplit('/')
+                .next()
+                .and_then(|name| name.split(&['?', '#'][..]).next())
+                .filter(|name| !name.is_empty())
+                .unwrap_or("download"),
+        ),
+    }
+}
+
+pub fn dest(step: &FetchStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join("downloads").join(file_name(step))
+}
+
+fn verified(path: &Path, sha256: &str) -> std::io::Result<bool> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+    Ok(crate::sha256::file(path)?.eq_ignore_ascii_case(sha256))
+}
+
+fn download(tool: &Path, url: &str, out: &Path) -> std::io::Result<bool> {
+    let mut cmd = Command::new(tool);
+    if tool.file_stem().is_some_and(|n| n == "wget") {
+        cmd.arg("-q").arg("-O").arg(out).arg(url);
+    } else {
+        cmd.arg("-fsSL")
+            .arg("--retry")
+            .arg("2")
+            .arg("-o")
+            .arg(out)
+            .arg(url);
+    }
+    Ok(cmd.status()?.success())
+}
+
+// Uses the copy in the build directory or the download cache when its checksum matches, and only
+// then goes to the network, trying each mirror in turn
+pub fn fetch(step: &FetchStep, cfg: &Configuration) -> std::io::Result<()> {
+    let dest = dest(step, cfg);
+    if verified(&dest, &step.sha256)? {
+        crate::steps::note(&format!("{} is already downloaded", dest.display()));
+        return Ok(());
+    }
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let cached = cfg.download_cache.as_ref().map(|c| c.join(&step.sha256));
+    if let Some(cached) = &cached {
+        if verified(cached, &step.sha256)? {
+            crate::steps::note(&format!("Using {} from the download cache", dest.display()));
+            std::fs::copy(cached, &dest)?;
+            return Ok(());
+        }
+    }
+    if cfg.offline {
+        return Err(std::io::Error::other(format!(
+            "{} has to be downloaded, but --offline was given",
+            file_name(step).display()
+        )));
+    }
+    let tool = cfg.downloader.as_ref().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, "Downloads require curl or wget")
+    })?;
+
+    let mut part = dest.file_name().unwrap_or_default().to_owned();
+    part.push(".part");
+    let part = dest.with_file_name(part);
+    let mut failures = Vec::new();
+    for url in std::iter::once(&step.url).chain(&step.mirrors) {
+        crate::steps::note(&format!("Downloading {}", url));
+        if !download(tool, url, &part)? {
+            failures.push(format!("{} (download failed)", url));
+            continue;
+        }
+        if !verified(&part, &step.sha256)? {
+            failures.push(format!("{} (checksum mismatch)", url));
+            continue;
+        }
+        std::fs::rename(&part, &dest)?;
+        if let Some(cached) = &cached {
+            if let Some(parent) = cached.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::copy(&dest, cached)?;
+        }
+        return Ok(());
+    }
+    let _ = std::fs::remove_file(&part);
+    Err(std::io::Error::other(format!(
+        "Could not download {}: {}",
+        file_name(step).display(),
+        failures.join(", ")
+    )))
+}
@@ -0,0 +1,99 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+// What the filesystem holding a directory can do; None where the probe could not tell
+#[derive(Clone, Copy, Default)]
+pub struct Capabilities {
+    pub symlinks: Option<bool>,
+    pub case_sensitive: Option<bool>,
+    pub xattrs: Option<bool>,
+}
+
+impl Capabilities {
+    // Links are made unless the probe saw them fail
+    pub fn symlinks(&self) -> bool {
+        self.symlinks != Some(false)
+    }
+
+    pub fn describe(&self) -> String {
+        let mut found = Vec::new();
+        for (name, value) in [
+            ("symlinks", self.symlinks),
+            ("case-sensitive", self.case_sensitive),
+            ("xattrs", self.xattrs),
+        ] {
+            match value {
+                Some(true) => found.push(name.to_string()),
+                Some(false) => found.push(format!("no {}", name)),
+                None => {}
+            }
+        }
+        if found.is_empty() {
+            "unknown".to_string()
+        } else {
+            found.join(", ")
+        }
+    }
+
+    // Published as <prefix>symlinks, <prefix>case-sensitive and <prefix>xattrs
+    pub fn vars(&self, prefix: &str) -> Vec<(String, String)> {
+        [
+            ("symlinks", self.symlinks),
+            ("case-sensitive", self.case_sensitive),
+            ("xattrs", self.xattrs),
+        ]
+        .iter()
+        .filter_map(|(name, value)| value.map(|v| (format!("{}{}", prefix, name), v.to_string())))
+        .collect()
+    }
+}
+
+// The build directory may not exist yet when scratch files go elsewhere, so the probe runs in
+// the closest one that does
+fn existing(dir: &Path) -> Option<&Path> {
+    dir.ancestors().find(|d| d.is_dir())
+}
+
+fn xattrs(file: &Path) -> Option<bool> {
+    let (program, args): (PathBuf, &[&str]) =
+        if let Ok(setfattr) = crate::tests::which_any(&["setfattr"]) {
+            (setfattr, &["-n", "user.cargo-autobuild", "-v", "1"])
+        } else if let Ok(xattr) = crate::tests::which_any(&["xattr"]) {
+            (xattr, &["-w", "user.cargo-autobuild", "1"])
+        } else {
+            return None;
+        };
+    Command::new(program)
+        .args(args)
+        .arg(file)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .ok()
+        .map(|status| status.success())
+}
+
+// Tries each capability in a scratch directory, which is removed again; a directory that cannot
+// be written to, such as a system prefix when not installing as root, leaves everything unknown
+pub fn probe(dir: &Path) -> Capabilities {
+    let mut caps = Capabilities::default();
+    let scratch = match existing(dir) {
+        Some(dir) => dir.join(format!(".autobuild-probe-{}", std::process::id())),
+        None => return caps,
+    };
+    if std::fs::create_dir(&scratch).is_err() {
+        return caps;
+    }
+    let file = scratch.join("probe");
+    if std::fs::write(&file, "").is_ok() {
+        caps.symlinks =
+            Some(crate::steps::symlink(Path::new("probe"), &scratch.join("link")).is_ok());
+        caps.case_sensitive = Some(!scratch.join("PROBE").exists());
+        caps.xattrs = xattrs(&file);
+    }
+    let _ = std::fs::remove_dir_all(&scratch);
+    caps
+}
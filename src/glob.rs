@@ -0,0 +1,182 @@
+use std::path::{Path, PathBuf};
+
+pub fn is_glob(pattern: &str) -> bool {
+    pattern.contains(['*', '?', '['])
+}
+
+fn class(class: &[char], c: char) -> bool {
+    let (negate, class) = match class.split_first() {
+        Some(('!', rest)) | Some(('^', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negate
+}
+
+fn component(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|i| component(rest, &name[i..])),
+        Some(('?', rest)) => !name.is_empty() && component(rest, &name[1..]),
+        Some(('[', rest)) => match rest.iter().position(|&c| c == ']') {
+            Some(end) => {
+                !name.is_empty()
+                    && class(&rest[..end], name[0])
+                    && component(&rest[end + 1..], &name[1..])
+            }
+            None => name.first() == Some(&'[') && component(rest, &name[1..]),
+        },
+        Some((c, rest)) => name.first() == Some(c) && component(rest, &name[1..]),
+    }
+}
+
+fn components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|i| components(rest, &path[i..])),
+        Some((first, rest)) => match path.split_first() {
+            Some((name, path)) => {
+                let first: Vec<char> = first.chars().collect();
+                let name: Vec<char> = name.chars().collect();
+                component(&first, &name) && components(rest, path)
+            }
+            None => false,
+        },
+    }
+}
+
+// Matches a `/`-separated relative path; `**` matches any number of directories
+pub fn matches(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    components(&pattern, &path)
+}
+
+// The directory that every match of the pattern lives under
+pub fn base(pattern: &str) -> &str {
+    match pattern.find(['*', '?', '[']) {
+        Some(n) => pattern[..n].rfind('/').map_or("", |m| &pattern[..m]),
+        None => pattern,
+    }
+}
+
+fn walk(root: &Path, rel: &str, out: &mut Vec<String>) -> std::io::Result<()> {
+    let mut entries = std::fs::read_dir(root.join(rel))?
+        .map(|e| e.map(|e| e.file_name().to_string_lossy().into_owned()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    for name in entries {
+        let path = if rel.is_empty() {
+            name
+        } else {
+            format!("{}/{}", rel, name)
+        };
+        // Links to directories are listed but not followed, as they may lead back up the tree
+        if root.join(&path).symlink_metadata()?.is_dir() {
+            out.push(path.clone());
+            walk(root, &path, out)?;
+        } else {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+// Every file and directory under `root`, as sorted `/`-separated relative paths
+pub fn tree(root: &Path) -> std::io::Result<Vec<String>> {
+    let mut out = Vec::new();
+    walk(root, "", &mut out)?;
+    Ok(out)
+}
+
+pub fn expand(dir: &Path, pattern: &str) -> std::io::Result<Vec<PathBuf>> {
+    let base = base(pattern);
+    let root = dir.join(base);
+    if !root.is_dir() {
+        return Ok(Vec::new());
+    }
+    let rest = pattern[base.len()..].trim_start_matches('/');
+    Ok(tree(&root)?
+        .into_iter()
+        .filter(|path| matches(rest, path))
+        .map(|path| root.join(path))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_components() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "src/main.rs"));
+        assert!(matches("src/?ain.rs", "src/main.rs"));
+        assert!(matches("lib[0-9].so", "lib3.so"));
+        assert!(!matches("lib[!0-9].so", "lib3.so"));
+        assert!(matches("lib[^0-9].so", "libx.so"));
+        assert!(matches("[abc", "[abc"));
+        assert!(matches("cargo-*", "cargo-autobuild"));
+        assert!(!matches("cargo-*", "autobuild"));
+    }
+
+    #[test]
+    fn matches_any_number_of_directories() {
+        assert!(matches("**/*.h", "a.h"));
+        assert!(matches("**/*.h", "include/sub/a.h"));
+        assert!(matches("include/**", "include/sub/a.h"));
+        assert!(matches("a/**/b", "a/b"));
+        assert!(!matches("a/**/b", "a/c"));
+    }
+
+    #[test]
+    fn base_is_the_directory_before_the_first_wildcard() {
+        assert_eq!(base("include/*.h"), "include");
+        assert_eq!(base("include/sub/**/*.h"), "include/sub");
+        assert_eq!(base("*.h"), "");
+        assert_eq!(base("src/a?/b"), "src");
+        assert_eq!(base("docs/README"), "docs/README");
+    }
+
+    #[test]
+    fn expands_under_the_base() {
+        let dir = std::env::temp_dir().join(format!("autobuild-glob-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("include/sub")).unwrap();
+        for file in &["include/a.h", "include/b.c", "include/sub/c.h", "d.h"] {
+            std::fs::write(dir.join(file), "").unwrap();
+        }
+        assert_eq!(
+            expand(&dir, "include/*.h").unwrap(),
+            vec![dir.join("include/a.h")]
+        );
+        assert_eq!(
+            expand(&dir, "include/**/*.h").unwrap(),
+            vec![dir.join("include/a.h"), dir.join("include/sub/c.h")]
+        );
+        assert!(expand(&dir, "missing/*.h").unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn does_not_follow_links_to_directories() {
+        let dir = std::env::temp_dir().join(format!("autobuild-glob-link-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("a")).unwrap();
+        std::fs::write(dir.join("a/f"), "").unwrap();
+        std::os::unix::fs::symlink("..", dir.join("a/up")).unwrap();
+        assert_eq!(tree(&dir).unwrap(), vec!["a", "a/f", "a/up"]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
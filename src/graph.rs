@@ -0,0 +1,123 @@
+use std::{io::ErrorKind, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::{configure::Configuration, plan::Plan, state::BuildState};
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Node {
+    name: String,
+    kind: String,
+    description: String,
+    condition: &'static str,
+    deps: Vec<String>,
+    artifacts: Vec<Artifact>,
+}
+
+#[derive(Serialize)]
+struct Artifact {
+    key: String,
+    kind: String,
+    path: PathBuf,
+}
+
+fn nodes(plan: &Plan, cfg: &Configuration) -> std::io::Result<Vec<Node>> {
+    let state = BuildState::load(&cfg.build_dir);
+    let mut nodes = Vec::new();
+    for step in &plan.steps {
+        let condition = match crate::state::step_record(&step.name, &step.step, cfg)? {
+            Some(record) if state.is_fresh(&step.name, &record) => "up-to-date",
+            Some(_) => "out-of-date",
+            None => "always",
+        };
+        nodes.push(Node {
+            name: step.name.clone(),
+            kind: step.step.kind().to_string(),
+            description: crate::steps::describe_step(&step.step, cfg),
+            condition,
+            deps: step
+                .deps
+                .iter()
+                .map(|&d| plan.steps[d].name.clone())
+                .collect(),
+            artifacts: crate::steps::step_artifacts(&step.step, cfg)?
+                .into_iter()
+                .map(|a| Artifact {
+                    key: a.key,
+                    kind: a.kind,
+                    path: a.path,
+                })
+                .collect(),
+        });
+    }
+    Ok(nodes)
+}
+
+fn quote(st: &str) -> String {
+    format!(
+        "\"{}\"",
+        st.replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('\n', "\\n")
+    )
+}
+
+fn dot(nodes: &[Node]) -> String {
+    let mut out = String::from("digraph autobuild {\n    rankdir=LR;\n");
+    for node in nodes {
+        let style = match node.condition {
+            "up-to-date" => "solid",
+            _ => "bold",
+        };
+        out += &format!(
+            "    {} [shape=box, style={}, label={}];\n",
+            quote(&node.name),
+            style,
+            quote(&format!(
+                "{}\n{}\n({})",
+                node.name, node.description, node.condition
+            ))
+        );
+        for dep in &node.deps {
+            out += &format!("    {} -> {};\n", quote(dep), quote(&node.name));
+        }
+        for artifact in &node.artifacts {
+            let id = format!("{}:{}", node.name, artifact.key);
+            out += &format!(
+                "    {} [shape=note, label={}];\n",
+                quote(&id),
+                quote(&format!("{}\n{}", artifact.key, artifact.path.display()))
+            );
+            out += &format!(
+                "    {} -> {} [style=dashed];\n",
+                quote(&node.name),
+                quote(&id)
+            );
+        }
+    }
+    out += "}\n";
+    out
+}
+
+pub fn write(plan: &Plan, cfg: &Configuration, format: &str) -> std::io::Result<()> {
+    let nodes = nodes(plan, cfg)?;
+    let (path, buf) = match format {
+        "dot" => (cfg.build_dir.join("graph.dot"), dot(&nodes).into_bytes()),
+        "json" => (
+            cfg.build_dir.join("graph.json"),
+            serde_json::to_vec_pretty(&nodes)
+                .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?,
+        ),
+        format => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown graph format {}", format),
+            ))
+        }
+    };
+    std::fs::create_dir_all(&cfg.build_dir)?;
+    std::fs::write(&path, buf)?;
+    println!("Step graph saved to {}", path.display());
+    Ok(())
+}
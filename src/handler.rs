@@ -0,0 +1,72 @@
+use std::{collections::HashMap, io::ErrorKind, path::PathBuf, sync::Arc};
+
+use crate::{
+    config::{Step, BUILTIN_STEPS},
+    configure::Configuration,
+    steps::{Artifact, Builtin},
+};
+
+pub trait StepHandler: Send + Sync {
+    fn describe(&self, step: &Step, cfg: &Configuration) -> String {
+        let _ = cfg;
+        step.kind().to_string()
+    }
+
+    fn inputs(&self, step: &Step, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+        let _ = (step, cfg);
+        Ok(Vec::new())
+    }
+
+    fn outputs(&self, step: &Step, cfg: &Configuration) -> std::io::Result<Vec<Artifact>> {
+        let _ = (step, cfg);
+        Ok(Vec::new())
+    }
+
+    fn variables(
+        &self,
+        step: &Step,
+        cfg: &Configuration,
+    ) -> std::io::Result<HashMap<String, String>> {
+        let _ = (step, cfg);
+        Ok(HashMap::new())
+    }
+
+    fn execute(&self, step: &Step, cfg: &Configuration) -> std::io::Result<()>;
+}
+
+#[derive(Clone)]
+pub struct Registry {
+    handlers: HashMap<String, Arc<dyn StepHandler>>,
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        let mut registry = Registry {
+            handlers: HashMap::new(),
+        };
+        let builtin = Arc::new(Builtin);
+        for kind in BUILTIN_STEPS {
+            registry.handlers.insert(kind.to_string(), builtin.clone());
+        }
+        registry
+    }
+
+    pub fn register<H: StepHandler + 'static>(&mut self, kind: &str, handler: H) {
+        self.handlers.insert(kind.to_string(), Arc::new(handler));
+    }
+
+    pub fn get(&self, kind: &str) -> std::io::Result<&dyn StepHandler> {
+        self.handlers.get(kind).map(|h| &**h).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!("No handler is registered for steps of type {}", kind),
+            )
+        })
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
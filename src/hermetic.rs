@@ -0,0 +1,237 @@
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{Config, InstallDirectory, Step},
+    configure::Configuration,
+};
+
+// Passed through as they are; everything else is dropped
+const KEEP: &[&str] = &[
+    "DESTDIR",
+    "SOURCE_DATE_EPOCH",
+    "RUSTUP_TOOLCHAIN",
+    "CARGO_HOME",
+    "RUSTUP_HOME",
+    "TERM",
+    "SystemRoot",
+    "SYSTEMROOT",
+];
+
+// Variables that change what gets built, so dropping one is worth a warning
+const BUILD_VARS: &[&str] = &[
+    "RUSTFLAGS",
+    "CFLAGS",
+    "CXXFLAGS",
+    "CPPFLAGS",
+    "LDFLAGS",
+    "CC",
+    "CXX",
+    "AR",
+    "LD",
+    "PKG_CONFIG",
+    "PKG_CONFIG_PATH",
+    "LIBRARY_PATH",
+    "LD_LIBRARY_PATH",
+];
+
+const BUILD_PREFIXES: &[&str] = &[
+    "CARGO_", "RUSTC", "RUSTDOC", "CC_", "CXX_", "AR_", "CFLAGS_",
+];
+
+fn tool_dirs(cfg: &Configuration) -> Vec<PathBuf> {
+    let mut tools: Vec<PathBuf> = [
+        Some(&cfg.cargo),
+        Some(&cfg.rustc.rustc),
+        cfg.rustc_wrapper.as_ref(),
+        cfg.ccache.as_ref(),
+        cfg.llvm_profdata.as_ref(),
+        cfg.linker.as_ref(),
+        cfg.lipo.as_ref(),
+        cfg.codesign.as_ref(),
+        cfg.notarytool.as_ref(),
+        cfg.rc.as_ref(),
+        cfg.objcopy.as_ref(),
+        cfg.msgfmt.as_ref(),
+        cfg.setcap.as_ref(),
+        cfg.nextest.as_ref(),
+        cfg.cargo_fuzz.as_ref(),
+        cfg.cargo_audit.as_ref(),
+        cfg.cargo_deny.as_ref(),
+        cfg.ssh.as_ref(),
+        cfg.rsync.as_ref(),
+        cfg.downloader.as_ref(),
+        cfg.patch.as_ref(),
+        cfg.tar.as_ref(),
+        cfg.unzip.as_ref(),
+        cfg.bindgen.as_ref(),
+        cfg.protoc.as_ref(),
+        cfg.flatc.as_ref(),
+        cfg.cbindgen.as_ref(),
+        cfg.yacc.as_ref(),
+        cfg.lex.as_ref(),
+        cfg.assembler.as_ref(),
+        cfg.cc.as_ref(),
+        cfg.ar.as_ref(),
+        cfg.desktop_file_validate.as_ref(),
+        cfg.appstreamcli.as_ref(),
+        cfg.link_audit.as_ref().map(|audit| &audit.tool),
+    ]
+    .iter()
+    .flatten()
+    .map(|p| p.to_path_buf())
+    .chain(cfg.programs.values().cloned())
+    .chain(cfg.protoc_plugins.values().cloned())
+    .collect();
+    // CC and friends hold commands such as `ccache cc`; each word may be a program
+    for var in &["CC", "CXX", "AR", "LD"] {
+        for word in cfg
+            .tool_env
+            .get(*var)
+            .into_iter()
+            .flat_map(|c| c.split(' '))
+        {
+            let path = Path::new(word);
+            if path.is_absolute() {
+                tools.push(path.to_path_buf());
+            } else if let Ok(path) = crate::tests::which_any(&[word]) {
+                tools.push(path);
+            }
+        }
+    }
+
+    let mut dirs = Vec::new();
+    for dir in tools.iter().filter_map(|t| t.parent()) {
+        if !dir.as_os_str().is_empty() && !dirs.iter().any(|d| d == dir) {
+            dirs.push(dir.to_path_buf());
+        }
+    }
+    dirs
+}
+
+// Hooks run through the shell, which expands $NAME from the environment, and so do command steps
+// that start a shell themselves. Nothing else in the configuration is expanded from it
+fn expanded(config: &Config) -> Vec<String> {
+    let hooks = &config.hooks;
+    let mut strings: Vec<String> = [
+        &hooks.pre_configure,
+        &hooks.post_configure,
+        &hooks.pre_build,
+        &hooks.post_build,
+        &hooks.pre_install,
+        &hooks.post_install,
+    ]
+    .iter()
+    .flat_map(|hooks| hooks.iter().cloned())
+    .collect();
+    let steps = [
+        &config.build.step,
+        &config.install.step,
+        &config.test.step,
+        &config.bench.step,
+        &config.pgo.train,
+    ];
+    for step in steps.iter().flat_map(|steps| steps.iter()) {
+        if let Step::Command(step) = step {
+            let shell = step
+                .cmd
+                .file_stem()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| matches!(name, "sh" | "bash" | "dash" | "ksh" | "zsh"));
+            if shell {
+                strings.extend(step.args.iter().map(|a| a.to_string_lossy().into_owned()));
+            }
+        }
+    }
+    strings
+}
+
+// Collects $NAME and ${NAME} references, leaving out the names of install directories, which
+// install targets spell the same way
+fn references(st: &str, config: &Config, cfg: &Configuration, out: &mut BTreeSet<String>) {
+    let mut rest = st;
+    while let Some(i) = rest.find('$') {
+        rest = &rest[i + 1..];
+        let braced = rest.strip_prefix('{');
+        let name: String = braced
+            .unwrap_or(rest)
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+            .collect();
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            continue;
+        }
+        let dir = InstallDirectory::from_name(&name);
+        let install_dir = !matches!(dir, InstallDirectory::Custom(_))
+            || cfg.dirs.contains_key(&dir)
+            || config.dirs.dirs.contains_key(&name);
+        if !install_dir {
+            out.insert(name);
+        }
+    }
+}
+
+// Replaces the environment every child process inherits with one built from the probe results
+pub fn enter(config: &Config, cfg: &Configuration) -> std::io::Result<()> {
+    let home = cfg.build_dir.join("home");
+    let tmp = cfg.scratch_dir.join("tmp");
+    std::fs::create_dir_all(&home)?;
+    std::fs::create_dir_all(&tmp)?;
+
+    // rustup proxies look for their toolchains under HOME unless told otherwise
+    let real_home = std::env::var_os("HOME").map(PathBuf::from);
+    let cargo_home = std::env::var_os("CARGO_HOME")
+        .map(PathBuf::from)
+        .or_else(|| real_home.as_ref().map(|h| h.join(".cargo")));
+    let rustup_home = std::env::var_os("RUSTUP_HOME")
+        .map(PathBuf::from)
+        .or_else(|| real_home.as_ref().map(|h| h.join(".rustup")));
+
+    let mut dropped = Vec::new();
+    for (key, _) in std::env::vars_os() {
+        let name = key.to_string_lossy();
+        if KEEP.contains(&&*name) {
+            continue;
+        }
+        if BUILD_VARS.contains(&&*name) || BUILD_PREFIXES.iter().any(|p| name.starts_with(p)) {
+            dropped.push(name.into_owned());
+        }
+        std::env::remove_var(&key);
+    }
+
+    let path = std::env::join_paths(tool_dirs(cfg))
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    std::env::set_var("PATH", path);
+    std::env::set_var("HOME", &home);
+    std::env::set_var("TMPDIR", &tmp);
+    std::env::set_var("LC_ALL", "C");
+    std::env::set_var("LANG", "C");
+    std::env::set_var("TZ", "UTC");
+    if let Some(cargo_home) = cargo_home.filter(|p| p.is_dir()) {
+        std::env::set_var("CARGO_HOME", cargo_home);
+    }
+    if let Some(rustup_home) = rustup_home.filter(|p| p.is_dir()) {
+        std::env::set_var("RUSTUP_HOME", rustup_home);
+    }
+
+    dropped.sort();
+    for name in &dropped {
+        eprintln!(
+            "cargo-autobuild: warning: --hermetic dropped {} from the environment",
+            name
+        );
+    }
+    let mut names = BTreeSet::new();
+    for st in expanded(config) {
+        references(&st, config, cfg, &mut names);
+    }
+    for name in names.iter().filter(|n| std::env::var_os(n).is_none()) {
+        eprintln!(
+            "cargo-autobuild: warning: the configuration refers to ${}, which --hermetic does not set",
+            name
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use std::{
+    fmt::Write as _,
+    io::{BufRead, ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    cargo::{CrateTarget, Metadata},
+    cli::Options,
+    config::FORMAT_VERSION,
+    tests::which_any,
+};
+
+struct Prompt {
+    interactive: bool,
+}
+
+impl Prompt {
+    fn ask(&self, question: &str, default: &str) -> std::io::Result<String> {
+        if !self.interactive {
+            return Ok(default.to_string());
+        }
+        print!("{} [{}]: ", question, default);
+        std::io::stdout().flush()?;
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line)?;
+        let line = line.trim();
+        if line.is_empty() {
+            Ok(default.to_string())
+        } else {
+            Ok(line.to_string())
+        }
+    }
+
+    fn confirm(&self, question: &str, default: bool) -> std::io::Result<bool> {
+        loop {
+            let answer = self.ask(question, if default { "Y/n" } else { "y/N" })?;
+            match &*answer.to_lowercase() {
+                "y/n" => return Ok(default),
+                "y" | "yes" => return Ok(true),
+                "n" | "no" => return Ok(false),
+                _ => println!("Please answer yes or no."),
+            }
+        }
+    }
+}
+
+fn files_in(dir: &Path, filter: impl Fn(&str) -> bool) -> Vec<PathBuf> {
+    let mut files = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| p.file_name().is_some_and(|n| filter(&n.to_string_lossy())))
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+fn man_section(name: &str) -> Option<char> {
+    let mut chars = name.rsplit('.').next()?.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c @ '1'..='8'), None) => Some(c),
+        _ => None,
+    }
+}
+
+fn completion_dir(name: &str) -> Option<&'static str> {
+    if name.ends_with(".bash") {
+        Some("$datarootdir/bash-completion/completions/")
+    } else if name.ends_with(".fish") {
+        Some("$datarootdir/fish/vendor_completions.d/")
+    } else if name.starts_with('_') {
+        Some("$datarootdir/zsh/site-functions/")
+    } else {
+        None
+    }
+}
+
+fn relative(path: &Path, base: &Path) -> String {
+    path.strip_prefix(base)
+        .unwrap_or(path)
+        .display()
+        .to_string()
+}
+
+fn install_step(out: &mut String, file: &str, target: &str, mode: Option<&str>) {
+    out.push_str("\n[[install.step]]\ntype = \"install\"\n");
+    let _ = writeln!(out, "file = {:?}", file);
+    let _ = writeln!(out, "target = {:?}", target);
+    if let Some(mode) = mode {
+        let _ = writeln!(out, "mode = {}", mode);
+    }
+}
+
+// Outputs are installed by the build step that makes them, since their file names depend on the
+// platform being built for rather than the one running init
+fn artifacts_step(
+    out: &mut String,
+    build: usize,
+    kinds: &[&str],
+    exclude: &[String],
+    target: &str,
+    mode: &str,
+) {
+    out.push_str("\n[[install.step]]\ntype = \"install\"\n");
+    let _ = writeln!(out, "from-step = \"build.{}\"", build);
+    let _ = writeln!(out, "kinds = {:?}", kinds);
+    if !exclude.is_empty() {
+        let _ = writeln!(out, "exclude = {:?}", exclude);
+    }
+    let _ = writeln!(out, "target = {:?}", target);
+    let _ = writeln!(out, "mode = {}", mode);
+}
+
+fn c_abi_kinds(target: &CrateTarget) -> Vec<&'static str> {
+    ["cdylib", "staticlib"]
+        .iter()
+        .copied()
+        .filter(|kind| target.crate_types.iter().any(|t| t == *kind))
+        .collect()
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+struct AutobuildMetadata {
+    #[serde(default)]
+    docs: Vec<PathBuf>,
+    #[serde(default)]
+    man: Vec<PathBuf>,
+    #[serde(default)]
+    completions: Vec<PathBuf>,
+    #[serde(default)]
+    skip_bins: Vec<String>,
+    #[serde(default)]
+    skip_libs: bool,
+}
+
+fn from_cargo(metadata: &Metadata, root: &Path) -> std::io::Result<String> {
+    let mut out = format!("format-version = {}\n\n", FORMAT_VERSION);
+
+    for package in &metadata.packages {
+        let dir = package.manifest_path.parent().unwrap_or(root);
+        let path = relative(dir, root);
+        out.push_str("[[build.step]]\ntype = \"build-crate\"\n");
+        if !path.is_empty() {
+            let _ = writeln!(out, "path = {:?}", path);
+        }
+        out.push('\n');
+    }
+
+    for (build, package) in metadata.packages.iter().enumerate() {
+        let dir = package.manifest_path.parent().unwrap_or(root);
+        let autobuild: AutobuildMetadata =
+            match package.metadata.as_ref().and_then(|m| m.get("autobuild")) {
+                Some(value) => serde_json::from_value(value.clone()).map_err(|e| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Invalid [package.metadata.autobuild] in {}: {}",
+                            package.manifest_path.display(),
+                            e
+                        ),
+                    )
+                })?,
+                None => AutobuildMetadata::default(),
+            };
+
+        let bins: Vec<&str> = package
+            .targets
+            .iter()
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| &*t.name)
+            .collect();
+        if bins
+            .iter()
+            .any(|b| !autobuild.skip_bins.iter().any(|s| s == b))
+        {
+            // Whatever suffix the executable gets
+            let exclude: Vec<String> = autobuild
+                .skip_bins
+                .iter()
+                .flat_map(|b| [b.clone(), format!("{}.*", b)])
+                .collect();
+            artifacts_step(&mut out, build, &["bin"], &exclude, "$bindir", "0o755");
+        }
+        if !autobuild.skip_libs {
+            let kinds: Vec<&str> = package.targets.iter().flat_map(c_abi_kinds).collect();
+            if !kinds.is_empty() {
+                artifacts_step(&mut out, build, &kinds, &[], "$libdir", "0o644");
+            }
+        }
+
+        let mut docs: Vec<PathBuf> = package.license_file.iter().cloned().collect();
+        if docs.is_empty() {
+            docs.extend(files_in(dir, |n| {
+                n.starts_with("LICENSE") || n.starts_with("COPYING")
+            }));
+        }
+        docs.extend(package.readme.iter().cloned());
+        docs.extend(autobuild.docs.iter().cloned());
+        for doc in docs {
+            install_step(
+                &mut out,
+                &relative(&dir.join(doc), root),
+                "$docdir",
+                Some("0o644"),
+            );
+        }
+
+        for page in &autobuild.man {
+            let name = page
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default();
+            let target = match man_section(&name) {
+                Some(section) => format!("$mandir/man{}/", section),
+                None => "$mandir/".to_string(),
+            };
+            install_step(
+                &mut out,
+                &relative(&dir.join(page), root),
+                &target,
+                Some("0o644"),
+            );
+        }
+
+        for file in &autobuild.completions {
+            let name = file
+                .file_name()
+                .map(|n| n.to_string_lossy())
+                .unwrap_or_default();
+            let target = completion_dir(&name).ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Cannot tell which shell {} is for", file.display()),
+                )
+            })?;
+            install_step(
+                &mut out,
+                &relative(&dir.join(file), root),
+                target,
+                Some("0o644"),
+            );
+        }
+    }
+
+    Ok(out)
+}
+
+fn generate(metadata: &Metadata, root: &Path, prompt: &Prompt) -> std::io::Result<String> {
+    let mut out = format!("format-version = {}\n\n", FORMAT_VERSION);
+
+    let prefix = prompt.ask("Installation prefix", "/usr/local")?;
+    if prefix != "/usr/local" {
+        let _ = writeln!(out, "[dirs]\nprefix = {:?}\n", prefix);
+    }
+
+    for package in &metadata.packages {
+        let dir = package.manifest_path.parent().unwrap_or(root);
+        let path = relative(dir, root);
+        out.push_str("[[build.step]]\ntype = \"build-crate\"\n");
+        if !path.is_empty() {
+            let _ = writeln!(out, "path = {:?}", path);
+        }
+        out.push('\n');
+    }
+
+    if prompt.confirm("Generate API documentation?", false)? {
+        out.push_str("[[build.step]]\ntype = \"generate-docs\"\n");
+    }
+
+    for (build, package) in metadata.packages.iter().enumerate() {
+        if package
+            .targets
+            .iter()
+            .any(|t| t.kind.iter().any(|k| k == "bin"))
+        {
+            artifacts_step(&mut out, build, &["bin"], &[], "$bindir", "0o755");
+        }
+    }
+
+    let man_dir = root.join("man");
+    let man_pages = files_in(&man_dir, |n| man_section(n).is_some());
+    if !man_pages.is_empty()
+        && prompt.confirm(
+            &format!("Install {} man page(s) from man/?", man_pages.len()),
+            true,
+        )?
+    {
+        for page in &man_pages {
+            let name = page.file_name().unwrap().to_string_lossy();
+            let section = man_section(&name).unwrap();
+            let target = format!("$mandir/man{}/", section);
+            install_step(&mut out, &relative(page, root), &target, Some("0o644"));
+        }
+    }
+
+    let completions_dir = root.join("completions");
+    let completions = files_in(&completions_dir, |n| completion_dir(n).is_some());
+    if !completions.is_empty()
+        && prompt.confirm(
+            &format!(
+                "Install {} shell completion(s) from completions/?",
+                completions.len()
+            ),
+            true,
+        )?
+    {
+        for file in &completions {
+            let name = file.file_name().unwrap().to_string_lossy();
+            let target = completion_dir(&name).unwrap();
+            install_step(&mut out, &relative(file, root), target, Some("0o644"));
+        }
+    }
+
+    for (build, package) in metadata.packages.iter().enumerate() {
+        for target in &package.targets {
+            let kinds = c_abi_kinds(target);
+            if kinds.is_empty()
+                || !prompt.confirm(&format!("Install C ABI outputs of {}?", target.name), true)?
+            {
+                continue;
+            }
+            artifacts_step(&mut out, build, &kinds, &[], "$libdir", "0o644");
+            let header = prompt.ask(
+                &format!("C header for {} (empty for none)", target.name),
+                "",
+            )?;
+            if !header.is_empty() {
+                install_step(&mut out, &header, "$includedir", Some("0o644"));
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+pub fn init(opts: &Options) -> std::io::Result<()> {
+    if opts.config.exists() && !opts.force {
+        return Err(std::io::Error::new(
+            ErrorKind::AlreadyExists,
+            format!(
+                "{} already exists; use --force to overwrite it",
+                opts.config.display()
+            ),
+        ));
+    }
+
+    let root = std::fs::canonicalize(
+        opts.config
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or_else(|| Path::new(".")),
+    )?;
+    let cargo = match std::env::var_os("CARGO") {
+        Some(cargo) => PathBuf::from(cargo),
+        None => which_any(&["cargo"])?,
+    };
+    let metadata = crate::cargo::metadata(&cargo, &root.join("Cargo.toml"))?;
+
+    let config = if opts.from_cargo {
+        from_cargo(&metadata, &root)?
+    } else {
+        let prompt = Prompt {
+            interactive: opts.interactive,
+        };
+        generate(&metadata, &root, &prompt)?
+    };
+    std::fs::write(&opts.config, config)?;
+    println!("Wrote {}", opts.config.display());
+    Ok(())
+}
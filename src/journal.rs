@@ -0,0 +1,361 @@
+use std::{
+    ffi::OsString,
+    fs::File,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::config::Backup;
+
+const JOURNAL: &str = "install-journal.txt";
+const COMMIT: &str = "commit";
+
+enum Entry {
+    // A directory the install created
+    Dir(PathBuf),
+    // A file that did not exist before
+    Created(PathBuf),
+    // A file that was replaced, kept under the second name until the install is over
+    Replaced(PathBuf, PathBuf),
+}
+
+// What an install step changed, so that a failure part way through puts the prefix back as it
+// was. Each change is written to the build directory before it is made, so an install that was
+// killed is undone by the next one
+pub struct Journal {
+    build_dir: PathBuf,
+    backup: Backup,
+    file: File,
+    entries: Vec<Entry>,
+}
+
+// A hidden name beside `path`, on the same filesystem so that renames are atomic
+fn sibling(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn suffixed(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+// One past the highest file.~N~ there is, as GNU install numbers them
+fn numbered(path: &Path) -> std::io::Result<PathBuf> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{}.~", name);
+    let mut last = 0;
+    if let Some(dir) = path.parent() {
+        for entry in std::fs::read_dir(dir)? {
+            let entry = entry?.file_name();
+            let n = entry
+                .to_str()
+                .and_then(|e| e.strip_prefix(&prefix))
+                .and_then(|e| e.strip_suffix('~'))
+                .and_then(|n| n.parse::<u32>().ok());
+            if let Some(n) = n {
+                last = last.max(n);
+            }
+        }
+    }
+    Ok(suffixed(path, &format!(".~{}~", last + 1)))
+}
+
+// The entries, and whether the install had got as far as committing them
+fn read(journal: &Path) -> std::io::Result<(Vec<Entry>, bool)> {
+    let text = std::fs::read_to_string(journal)?;
+    let committed = text.lines().any(|line| line == COMMIT);
+    let entries = text
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            match (fields.next()?, fields.next()?, fields.next()) {
+                ("dir", path, None) => Some(Entry::Dir(PathBuf::from(path))),
+                ("created", path, None) => Some(Entry::Created(PathBuf::from(path))),
+                ("replaced", path, Some(backup)) => {
+                    Some(Entry::Replaced(PathBuf::from(path), PathBuf::from(backup)))
+                }
+                _ => None,
+            }
+        })
+        .collect();
+    Ok((entries, committed))
+}
+
+fn undo(entries: &[Entry]) -> std::io::Result<()> {
+    for entry in entries.iter().rev() {
+        match entry {
+            Entry::Dir(dir) => {
+                // Left in place when something else has been put there since
+                let _ = std::fs::remove_dir(dir);
+            }
+            Entry::Created(path) => match std::fs::remove_file(path) {
+                Ok(()) => crate::steps::note(&format!("removing {}", path.display())),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            },
+            // The backup is logged before it is made, so it may never have been
+            Entry::Replaced(path, backup) => match std::fs::rename(backup, path) {
+                Ok(()) => crate::steps::note(&format!("restoring {}", path.display())),
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => return Err(e),
+            },
+        }
+    }
+    Ok(())
+}
+
+// Drops or keeps the old files and records the new ones for `uninstall`. A commit that was
+// interrupted is finished by running this again, so work already done is skipped
+fn finish(build_dir: &Path, policy: Backup, entries: &[Entry]) -> std::io::Result<()> {
+    for entry in entries {
+        match entry {
+            Entry::Dir(_) => {}
+            Entry::Created(path) => crate::uninstall::record(build_dir, path)?,
+            Entry::Replaced(path, backup) => {
+                if backup.symlink_metadata().is_ok() {
+                    let kept = match policy {
+                        Backup::None => None,
+                        Backup::Simple => Some(suffixed(path, "~")),
+                        Backup::Numbered => Some(numbered(path)?),
+                    };
+                    match kept {
+                        Some(kept) => {
+                            crate::steps::note(&format!(
+                                "backup {} -> {}",
+                                path.display(),
+                                kept.display()
+                            ));
+                            std::fs::rename(backup, kept)?;
+                        }
+                        None => std::fs::remove_file(backup)?,
+                    }
+                }
+                crate::uninstall::record(build_dir, path)?;
+            }
+        }
+    }
+    std::fs::remove_file(build_dir.join(JOURNAL))
+}
+
+impl Journal {
+    pub fn begin(build_dir: &Path, backup: Backup) -> std::io::Result<Self> {
+        std::fs::create_dir_all(build_dir)?;
+        let path = build_dir.join(JOURNAL);
+        if path.is_file() {
+            match read(&path)? {
+                (entries, true) => {
+                    eprintln!("cargo-autobuild: finishing an install that was interrupted");
+                    finish(build_dir, backup, &entries)?;
+                }
+                (entries, false) => {
+                    eprintln!("cargo-autobuild: undoing an install that was interrupted");
+                    undo(&entries)?;
+                }
+            }
+        }
+        Ok(Journal {
+            build_dir: build_dir.to_owned(),
+            backup,
+            file: File::create(&path)?,
+            entries: Vec::new(),
+        })
+    }
+
+    fn log(&mut self, entry: Entry) -> std::io::Result<()> {
+        match &entry {
+            Entry::Dir(dir) => writeln!(self.file, "dir\t{}", dir.display()),
+            Entry::Created(path) => writeln!(self.file, "created\t{}", path.display()),
+            Entry::Replaced(path, backup) => writeln!(
+                self.file,
+                "replaced\t{}\t{}",
+                path.display(),
+                backup.display()
+            ),
+        }?;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    // Directories made here are removed again on rollback
+    pub fn create_dirs(&mut self, dir: &Path) -> std::io::Result<()> {
+        let missing: Vec<&Path> = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.is_dir())
+            .collect();
+        for dir in missing.into_iter().rev() {
+            std::fs::create_dir(dir)?;
+            self.log(Entry::Dir(dir.to_owned()))?;
+        }
+        Ok(())
+    }
+
+    // `write` makes the new file under a temporary name beside `dest`, which is then renamed
+    // over it, so `dest` is always either the old file or the whole new one. The old file is
+    // kept as a hard link where the filesystem has them, and moved aside where it does not
+    pub fn place<F>(&mut self, dest: &Path, write: F) -> std::io::Result<()>
+    where
+        F: FnOnce(&Path) -> std::io::Result<()>,
+    {
+        if let Some(parent) = dest.parent() {
+            self.create_dirs(parent)?;
+        }
+        if matches!(dest.symlink_metadata(), Ok(meta) if meta.is_dir()) {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!("{} is a directory", dest.display()),
+            ));
+        }
+        let tmp = sibling(dest, ".autobuild-new");
+        let _ = std::fs::remove_file(&tmp);
+        let result = write(&tmp).and_then(|()| {
+            if dest.symlink_metadata().is_ok() {
+                let backup = sibling(dest, ".autobuild-old");
+                let _ = std::fs::remove_file(&backup);
+                self.log(Entry::Replaced(dest.to_owned(), backup.clone()))?;
+                if std::fs::hard_link(dest, &backup).is_err() {
+                    std::fs::rename(dest, &backup)?;
+                }
+            } else {
+                self.log(Entry::Created(dest.to_owned()))?;
+            }
+            std::fs::rename(&tmp, dest)
+        });
+        if result.is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+        result
+    }
+
+    // The install went through: the old files go, unless they are to be kept as backups, and the
+    // new ones are recorded for `uninstall`. The journal is marked first, so that a commit cut
+    // short is finished by the next install rather than undone
+    pub fn commit(mut self) -> std::io::Result<()> {
+        writeln!(self.file, "{}", COMMIT)?;
+        self.file.sync_all()?;
+        // Windows cannot remove a file that is still open
+        drop(self.file);
+        finish(&self.build_dir, self.backup, &self.entries)
+    }
+
+    pub fn rollback(self) -> std::io::Result<()> {
+        drop(self.file);
+        undo(&self.entries)?;
+        std::fs::remove_file(self.build_dir.join(JOURNAL))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A build directory and a prefix of their own under the system temporary directory
+    fn scratch(name: &str) -> (PathBuf, PathBuf) {
+        let dir =
+            std::env::temp_dir().join(format!("autobuild-journal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("prefix")).unwrap();
+        (dir.join("build"), dir.join("prefix"))
+    }
+
+    fn write_new(tmp: &Path) -> std::io::Result<()> {
+        std::fs::write(tmp, "new")
+    }
+
+    fn contents(path: &Path) -> String {
+        std::fs::read_to_string(path).unwrap()
+    }
+
+    #[test]
+    fn rollback_puts_the_prefix_back() {
+        let (build, prefix) = scratch("rollback");
+        std::fs::write(prefix.join("old"), "old").unwrap();
+        let mut journal = Journal::begin(&build, Backup::None).unwrap();
+        journal.place(&prefix.join("old"), write_new).unwrap();
+        journal.place(&prefix.join("a/b/new"), write_new).unwrap();
+        assert_eq!(contents(&prefix.join("old")), "new");
+        journal.rollback().unwrap();
+
+        assert_eq!(contents(&prefix.join("old")), "old");
+        assert!(!prefix.join("a").exists());
+        assert!(!sibling(&prefix.join("old"), ".autobuild-old").exists());
+        assert!(!build.join(JOURNAL).exists());
+        std::fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn undoes_an_install_that_was_interrupted() {
+        let (build, prefix) = scratch("undo");
+        std::fs::write(prefix.join("old"), "old").unwrap();
+        let mut journal = Journal::begin(&build, Backup::None).unwrap();
+        journal.place(&prefix.join("old"), write_new).unwrap();
+        journal.place(&prefix.join("a/new"), write_new).unwrap();
+        // Neither committed nor rolled back, as when the install is killed
+        drop(journal);
+
+        let journal = Journal::begin(&build, Backup::None).unwrap();
+        assert_eq!(contents(&prefix.join("old")), "old");
+        assert!(!prefix.join("a").exists());
+        journal.rollback().unwrap();
+        std::fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn finishes_a_commit_that_was_interrupted() {
+        let (build, prefix) = scratch("finish");
+        std::fs::create_dir_all(&build).unwrap();
+        let replaced = prefix.join("replaced");
+        let backup = sibling(&replaced, ".autobuild-old");
+        let created = prefix.join("created");
+        std::fs::write(&replaced, "new").unwrap();
+        std::fs::write(&backup, "old").unwrap();
+        std::fs::write(&created, "new").unwrap();
+        std::fs::write(
+            build.join(JOURNAL),
+            format!(
+                "replaced\t{}\t{}\ncreated\t{}\n{}\n",
+                replaced.display(),
+                backup.display(),
+                created.display(),
+                COMMIT
+            ),
+        )
+        .unwrap();
+
+        let journal = Journal::begin(&build, Backup::Simple).unwrap();
+        assert_eq!(contents(&replaced), "new");
+        assert_eq!(contents(&suffixed(&replaced, "~")), "old");
+        assert!(!backup.exists());
+        assert_eq!(
+            crate::uninstall::installed(&build).unwrap(),
+            vec![replaced, created]
+        );
+        journal.rollback().unwrap();
+        std::fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn names_backups_as_gnu_install_does() {
+        let (build, prefix) = scratch("backup");
+        let file = prefix.join("file");
+        std::fs::write(&file, "old").unwrap();
+        let mut journal = Journal::begin(&build, Backup::Simple).unwrap();
+        journal.place(&file, write_new).unwrap();
+        journal.commit().unwrap();
+        assert_eq!(contents(&prefix.join("file~")), "old");
+
+        std::fs::write(prefix.join("file.~1~"), "").unwrap();
+        std::fs::write(prefix.join("file.~3~"), "").unwrap();
+        assert_eq!(numbered(&file).unwrap(), prefix.join("file.~4~"));
+        let mut journal = Journal::begin(&build, Backup::Numbered).unwrap();
+        std::fs::write(&file, "older").unwrap();
+        journal.place(&file, write_new).unwrap();
+        journal.commit().unwrap();
+        assert_eq!(contents(&prefix.join("file.~4~")), "older");
+        assert_eq!(contents(&file), "new");
+        std::fs::remove_dir_all(prefix.parent().unwrap()).unwrap();
+    }
+}
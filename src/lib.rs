@@ -0,0 +1,70 @@
+mod android;
+mod apple;
+mod archive;
+mod assemble;
+mod bench;
+mod bindgen;
+mod bootstrap;
+mod build_info;
+mod cargo;
+mod check_install;
+mod ci;
+mod clean;
+pub mod cli;
+mod clib;
+mod codegen;
+mod compat;
+mod compdb;
+pub mod config;
+pub mod configure;
+mod container;
+mod devenv;
+mod elf;
+mod engine;
+mod explain;
+mod extract;
+mod fetch;
+mod filesystem;
+mod glob;
+mod graph;
+pub mod handler;
+mod hermetic;
+pub mod init;
+mod journal;
+mod licenses;
+mod link_audit;
+mod lint;
+mod meson;
+pub mod migrate;
+mod msvc;
+mod overrides;
+mod patch;
+mod paths;
+pub mod plan;
+mod platform;
+pub mod plugin;
+mod product;
+pub mod remote;
+mod rust_project;
+mod sandbox;
+mod sbom;
+pub mod schema;
+mod sha256;
+mod state;
+pub mod steps;
+mod sysroot;
+mod systemd;
+mod test_report;
+pub mod tests;
+pub mod timings;
+mod ui;
+mod uninstall;
+mod wasm;
+mod watch;
+pub mod wrappers;
+
+pub use config::Config;
+pub use configure::Configuration as Probe;
+pub use engine::{Engine, Runner};
+pub use handler::{Registry, StepHandler};
+pub use plan::Plan;
@@ -0,0 +1,119 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    path::PathBuf,
+};
+
+use crate::cargo::{Metadata, Node, Package};
+
+const LICENSE_FILES: &[&str] = &["LICENSE", "LICENCE", "COPYING", "NOTICE", "UNLICENSE"];
+
+// The packages reachable from the workspace through normal dependencies; dev and build
+// dependencies do not end up in what ships
+pub fn shipped(metadata: &Metadata) -> Vec<&Package> {
+    let members: HashSet<&str> = metadata.workspace_members.iter().map(|s| &**s).collect();
+    let reachable = metadata.resolve.as_ref().map(|resolve| {
+        let nodes: HashMap<&str, &Node> = resolve.nodes.iter().map(|n| (&*n.id, n)).collect();
+        let mut seen = HashSet::new();
+        let mut stack: Vec<&str> = members.iter().copied().collect();
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                continue;
+            }
+            for dep in nodes.get(id).map_or(&[][..], |n| &n.deps) {
+                // Cargo before 1.41 does not report dependency kinds
+                if dep.dep_kinds.is_empty() || dep.dep_kinds.iter().any(|k| k.kind.is_none()) {
+                    stack.push(&dep.pkg);
+                }
+            }
+        }
+        seen
+    });
+    let mut packages: Vec<&Package> = metadata
+        .packages
+        .iter()
+        .filter(|p| !members.contains(&*p.id))
+        .filter(|p| reachable.as_ref().is_none_or(|r| r.contains(&*p.id)))
+        .collect();
+    packages.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+    packages
+}
+
+// crates.io still has packages with the old `MIT/Apache-2.0` form
+pub fn expression(package: &Package) -> String {
+    match &package.license {
+        Some(license) => license
+            .split('/')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" OR "),
+        None if package.license_file.is_some() => "LicenseRef-file".to_string(),
+        None => "NOASSERTION".to_string(),
+    }
+}
+
+fn license_texts(package: &Package) -> std::io::Result<Vec<PathBuf>> {
+    let dir = match package.manifest_path.parent() {
+        Some(dir) => dir,
+        None => return Ok(Vec::new()),
+    };
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir)?
+        .filter_map(Result::ok)
+        .map(|e| e.path())
+        .filter(|p| p.is_file())
+        .filter(|p| {
+            let name = p
+                .file_name()
+                .map(|n| n.to_string_lossy().to_uppercase())
+                .unwrap_or_default();
+            LICENSE_FILES.iter().any(|prefix| name.starts_with(prefix))
+        })
+        .collect();
+    files.extend(
+        package
+            .license_file
+            .iter()
+            .map(|file| dir.join(file))
+            .filter(|p| p.is_file()),
+    );
+    files.sort();
+    files.dedup();
+    Ok(files)
+}
+
+pub fn notice(metadata: &Metadata) -> std::io::Result<String> {
+    let packages = shipped(metadata);
+    let mut summary = BTreeMap::new();
+    for package in &packages {
+        *summary.entry(expression(package)).or_insert(0) += 1;
+    }
+
+    let mut out = String::from("THIRD-PARTY SOFTWARE NOTICES\n\n");
+    out += &format!(
+        "This software includes {} third-party package(s) under these licenses (SPDX):\n\n",
+        packages.len()
+    );
+    for (license, count) in &summary {
+        out += &format!("{:>6}  {}\n", count, license);
+    }
+    for package in &packages {
+        out += &format!(
+            "\n{}\n{} {}\nLicense: {}\n",
+            "=".repeat(78),
+            package.name,
+            package.version,
+            expression(package)
+        );
+        let texts = license_texts(package)?;
+        if texts.is_empty() {
+            out += "\nThe package does not include a license text.\n";
+        }
+        for text in texts {
+            out += &format!(
+                "\n--- {} ---\n\n{}\n",
+                text.file_name().unwrap_or_default().to_string_lossy(),
+                String::from_utf8_lossy(&std::fs::read(&text)?).trim_end()
+            );
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,211 @@
+use std::{
+    collections::HashSet,
+    io::Read,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use target_tuples::Target;
+
+use crate::configure::Configuration;
+
+pub struct Audit {
+    pub tool: PathBuf,
+    pub allow: Vec<String>,
+    pub warn_only: bool,
+}
+
+const ELF_SYSTEM: &[&str] = &[
+    "ld-linux*",
+    "ld-musl*",
+    "libc.so.*",
+    "libm.so.*",
+    "libdl.so.*",
+    "libpthread.so.*",
+    "librt.so.*",
+    "libutil.so.*",
+    "libgcc_s.so.*",
+];
+
+const APPLE_SYSTEM: &[&str] = &["/usr/lib/**", "/System/Library/**"];
+
+const WINDOWS_SYSTEM: &[&str] = &[
+    "kernel32.dll",
+    "ntdll.dll",
+    "advapi32.dll",
+    "bcrypt*.dll",
+    "userenv.dll",
+    "ws2_32.dll",
+    "synchronization.dll",
+    "api-ms-win-*",
+    "msvcrt.dll",
+    "ucrtbase.dll",
+    "vcruntime*.dll",
+];
+
+// The libraries every program for `host` may link against when [link-audit] sets no allow list
+pub fn default_allow(host: &Target) -> Vec<String> {
+    let name = host.get_name();
+    let allow = if name.contains("-apple-") {
+        APPLE_SYSTEM
+    } else if name.contains("-windows") {
+        WINDOWS_SYSTEM
+    } else {
+        ELF_SYSTEM
+    };
+    allow.iter().map(|s| s.to_string()).collect()
+}
+
+// Prefers tools that read the file over ldd, which runs the dynamic loader and only works natively
+pub fn find_tool(
+    host: &Target,
+    objdump: Option<PathBuf>,
+    cross_compiling: bool,
+    log: &mut Vec<String>,
+) -> Option<PathBuf> {
+    if host.get_name().contains("-apple-") {
+        log.push("Mach-O binaries are read with otool -L".to_string());
+        return crate::tests::which_any(&["otool", "llvm-otool"])
+            .ok()
+            .or_else(|| crate::apple::xcrun_find("otool"));
+    }
+    if let Some(objdump) = objdump {
+        log.push(format!(
+            "using the configured objdump {}",
+            objdump.display()
+        ));
+        return Some(objdump);
+    }
+    let mut names = vec!["objdump".to_string(), "llvm-objdump".to_string()];
+    if cross_compiling {
+        names.insert(0, format!("{}-objdump", host.get_name()));
+    } else {
+        names.push("ldd".to_string());
+    }
+    log.push(format!("searching PATH for {}", names.join(", ")));
+    crate::tests::which_any(&names).ok()
+}
+
+fn is_binary(path: &Path) -> std::io::Result<bool> {
+    let mut magic = [0; 4];
+    let mut file = std::fs::File::open(path)?;
+    if file.read(&mut magic)? < 4 {
+        return Ok(false);
+    }
+    Ok(magic == *b"\x7fELF"
+        || magic.starts_with(b"MZ")
+        || matches!(
+            u32::from_be_bytes(magic),
+            0xfeed_face | 0xfeed_facf | 0xcefa_edfe | 0xcffa_edfe | 0xcafe_babe
+        ))
+}
+
+fn dependencies(tool: &Path, path: &Path) -> std::io::Result<Vec<String>> {
+    let name = tool
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut cmd = Command::new(tool);
+    if name.contains("otool") {
+        cmd.arg("-L");
+    } else if !name.starts_with("ldd") {
+        cmd.arg("-p");
+    }
+    let output = cmd.arg(path).output()?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    // ldd fails on static binaries, which have nothing to audit
+    let not_dynamic = "not a dynamic executable";
+    if stdout.contains(not_dynamic) || stderr.contains(not_dynamic) {
+        return Ok(Vec::new());
+    }
+    if !output.status.success() {
+        return Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, output.status
+        )));
+    }
+    let lines = stdout.lines().map(str::trim);
+    Ok(if name.contains("otool") {
+        // The first line names the file itself
+        lines
+            .skip(1)
+            .filter_map(|l| l.split(" (").next())
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect()
+    } else if name.starts_with("ldd") {
+        lines
+            .filter(|l| !l.starts_with("linux-vdso") && !l.contains("statically linked"))
+            .filter_map(|l| l.split_whitespace().next())
+            .map(str::to_string)
+            .collect()
+    } else {
+        lines
+            .filter_map(|l| {
+                l.strip_prefix("NEEDED")
+                    .or_else(|| l.strip_prefix("DLL Name:"))
+            })
+            .map(|l| l.trim().to_string())
+            .collect()
+    })
+}
+
+fn allowed(dep: &str, allow: &[String], installed: &HashSet<String>) -> bool {
+    let name = dep.rsplit('/').next().unwrap_or(dep);
+    installed.contains(name)
+        || allow.iter().any(|pattern| {
+            if pattern.contains('/') {
+                crate::glob::matches(pattern, dep)
+            } else {
+                crate::glob::matches(&pattern.to_lowercase(), &name.to_lowercase())
+            }
+        })
+}
+
+// Checks every binary recorded in the install manifest against the allowed libraries
+pub fn audit(cfg: &Configuration) -> std::io::Result<()> {
+    let audit = match &cfg.link_audit {
+        Some(audit) => audit,
+        None => return Ok(()),
+    };
+    let mut paths = crate::uninstall::installed(&cfg.build_dir)?;
+    paths.sort();
+    paths.dedup();
+    let installed: HashSet<String> = paths
+        .iter()
+        .filter_map(|p| p.file_name())
+        .map(|n| n.to_string_lossy().into_owned())
+        .collect();
+
+    let mut offenders = Vec::new();
+    for path in &paths {
+        let regular = path
+            .symlink_metadata()
+            .is_ok_and(|m| m.file_type().is_file());
+        if !regular || !is_binary(path)? {
+            continue;
+        }
+        let unexpected: Vec<String> = dependencies(&audit.tool, path)?
+            .into_iter()
+            .filter(|dep| !allowed(dep, &audit.allow, &installed))
+            .collect();
+        if !unexpected.is_empty() {
+            offenders.push(format!("{} ({})", path.display(), unexpected.join(", ")));
+        }
+    }
+
+    if offenders.is_empty() {
+        return Ok(());
+    }
+    let message = format!(
+        "These installed binaries link against libraries outside [link-audit] allow: {}",
+        offenders.join("; ")
+    );
+    if audit.warn_only {
+        eprintln!("cargo-autobuild: warning: {}", message);
+        Ok(())
+    } else {
+        Err(std::io::Error::other(message))
+    }
+}
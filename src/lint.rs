@@ -0,0 +1,184 @@
+use std::io::ErrorKind;
+
+use crate::{
+    cli::Options,
+    config::{Config, FlatcStep, InstallDirectory, InstallTarget, ProtocStep, Step},
+    handler::Registry,
+};
+
+fn check_step(
+    name: &str,
+    step: &Step,
+    config: &Config,
+    handlers: &Registry,
+    problems: &mut Vec<String>,
+) {
+    if handlers.get(step.kind()).is_err() {
+        problems.push(format!(
+            "{}: no handler for step type `{}`",
+            name,
+            step.kind()
+        ));
+    }
+    let targets = match step {
+        Step::Install(step) => {
+            if step.file.as_os_str().is_empty() == step.from_step.is_none() {
+                problems.push(format!("{}: set exactly one of file and from-step", name));
+            }
+            vec![&step.target]
+        }
+        Step::InstallSymlink(step) => vec![&step.link, &step.to],
+        Step::Archive(step) => {
+            if step.objects.is_empty() && step.from_steps.is_empty() {
+                problems.push(format!("{}: set objects or from-steps", name));
+            }
+            Vec::new()
+        }
+        Step::Sysroot(step) => {
+            if step.files.is_empty() && step.from_steps.is_empty() {
+                problems.push(format!("{}: set files or from-steps", name));
+            }
+            Vec::new()
+        }
+        Step::Assemble(step) => {
+            if step.link && step.archive.is_none() {
+                problems.push(format!("{}: link requires archive", name));
+            }
+            Vec::new()
+        }
+        Step::Bindgen(step) => {
+            if step.headers.is_empty() {
+                problems.push(format!("{}: headers is empty", name));
+            }
+            Vec::new()
+        }
+        Step::Protoc(ProtocStep { schemas, .. }) | Step::Flatc(FlatcStep { schemas, .. }) => {
+            if schemas.is_empty() {
+                problems.push(format!("{}: schemas is empty", name));
+            }
+            Vec::new()
+        }
+        Step::ApplyPatches(step) => {
+            if step.patches.is_empty() && step.series.is_none() {
+                problems.push(format!("{}: set patches or series", name));
+            }
+            Vec::new()
+        }
+        Step::Cbindgen(step) => {
+            if let Some(lang) = &step.lang {
+                if !["c", "c++", "cython"].contains(&&**lang) {
+                    problems.push(format!("{}: cbindgen has no language {}", name, lang));
+                }
+            }
+            Vec::new()
+        }
+        Step::Command(step) => {
+            if step.network && !step.sandbox {
+                problems.push(format!("{}: network only applies with sandbox", name));
+            }
+            Vec::new()
+        }
+        Step::BuildCrate(step) => {
+            if step.soversion.is_some() && step.lib_version.is_none() {
+                problems.push(format!("{}: soversion requires lib-version", name));
+            }
+            for kind in &step.crate_types {
+                if !crate::steps::LIB_CRATE_TYPES.contains(&&**kind) {
+                    problems.push(format!("{}: unknown crate type {}", name, kind));
+                }
+            }
+            for dir in &step.install_dirs {
+                let known = match InstallDirectory::from_name(dir) {
+                    InstallDirectory::Custom(dir) => config.dirs.dirs.contains_key(&dir),
+                    _ => true,
+                };
+                if !known {
+                    problems.push(format!("{}: unknown install directory {}", name, dir));
+                }
+            }
+            Vec::new()
+        }
+        _ => Vec::new(),
+    };
+    for target in targets {
+        if let InstallTarget::Base { base, .. } = target {
+            let known = match base {
+                InstallDirectory::Custom(dir) => config.dirs.dirs.contains_key(dir),
+                _ => true,
+            };
+            if !known {
+                problems.push(format!(
+                    "{}: unknown install directory ${}",
+                    name,
+                    base.name()
+                ));
+            }
+        }
+    }
+}
+
+pub fn lint(opts: &Options, config: &Config, handlers: &Registry) -> std::io::Result<()> {
+    let mut problems = Vec::new();
+    let phases = [
+        ("build", &config.build.step),
+        ("install", &config.install.step),
+        ("test", &config.test.step),
+        ("bench", &config.bench.step),
+        ("pgo.train", &config.pgo.train),
+    ];
+    for (phase, steps) in phases.iter() {
+        for (i, step) in steps.iter().enumerate() {
+            check_step(
+                &format!("{}.{}", phase, i),
+                step,
+                config,
+                handlers,
+                &mut problems,
+            );
+        }
+    }
+    for (key, program) in &config.programs {
+        for (i, step) in program.test_steps.iter().enumerate() {
+            let name = format!("programs.{}.test-steps.{}", key, i);
+            check_step(&name, step, config, handlers, &mut problems);
+        }
+    }
+    for (name, profile) in &config.profiles {
+        if let Some(parent) = &profile.inherits {
+            let builtin = ["dev", "release"].contains(&&**parent);
+            if !builtin && !config.profiles.contains_key(parent) {
+                problems.push(format!(
+                    "profiles.{}: inherits unknown profile {}",
+                    name, parent
+                ));
+            }
+        }
+    }
+    // Workers only plan the build, test and bench phases
+    for (name, policy) in &config.policies {
+        let phase = name.split('.').next().unwrap_or_default();
+        if policy.distribute && !["build", "test", "bench"].contains(&phase) {
+            problems.push(format!(
+                "{}: only build, test and bench steps can be distributed",
+                name
+            ));
+        }
+    }
+    for dir in config.dirs.dirs.keys() {
+        if dir.contains('/') || dir.starts_with('$') {
+            problems.push(format!("dirs.{}: not a valid directory name", dir));
+        }
+    }
+
+    if problems.is_empty() {
+        println!("{}: ok", opts.config.display());
+        return Ok(());
+    }
+    for problem in &problems {
+        println!("{}: {}", opts.config.display(), problem);
+    }
+    Err(std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{} problem(s) found", problems.len()),
+    ))
+}
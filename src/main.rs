@@ -1,8 +1,33 @@
-mod config;
-mod tests;
+use cargo_autobuild_core::{cli, init, migrate, schema, wrappers, Engine};
 
-mod wrappers;
+fn run() -> std::io::Result<()> {
+    let opts = cli::parse(std::env::args())?;
+    if opts.command == "init" {
+        return init::init(&opts);
+    }
+    if opts.command == "migrate" {
+        return migrate::migrate(&opts);
+    }
+    if opts.command == "schema" {
+        println!("{:#}", schema::json_schema());
+        return Ok(());
+    }
+    Engine::new(opts)?.run()
+}
 
 fn main() {
-    println!("Hello, world!");
+    // cargo runs us in place of rustc when flags are injected by crate kind
+    if wrappers::is_wrapping() {
+        match wrappers::rustc(std::env::args_os().skip(1)) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("cargo-autobuild: cannot run rustc: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Err(e) = run() {
+        eprintln!("cargo-autobuild: {}", e);
+        std::process::exit(1);
+    }
 }
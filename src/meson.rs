@@ -0,0 +1,185 @@
+use std::{
+    ffi::OsStr,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    cli::Options,
+    config::{Config, Product, Step},
+    configure::Configuration,
+    plan::Plan,
+};
+
+// Marks the shim as ours, so that regenerating it never clobbers a hand-written meson.build
+const GENERATED: &str = "# Generated by cargo-autobuild meson-wrap";
+
+struct Outputs {
+    libs: Vec<String>,
+    headers: Vec<String>,
+}
+
+fn outputs(plan: &Plan, cfg: &Configuration) -> std::io::Result<Outputs> {
+    let mut outputs = Outputs {
+        libs: Vec::new(),
+        headers: Vec::new(),
+    };
+    for step in plan.steps.iter().filter(|s| s.name.starts_with("build.")) {
+        for artifact in crate::steps::step_artifacts(&step.step, cfg)? {
+            let list = match (&*step.step, &*artifact.kind) {
+                (_, "cdylib") | (_, "staticlib") => &mut outputs.libs,
+                (Step::Cbindgen(_), _) => &mut outputs.headers,
+                _ => continue,
+            };
+            let name = artifact
+                .path
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .into_owned();
+            if !list.contains(&name) {
+                list.push(name);
+            }
+        }
+    }
+    if outputs.libs.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            "The build phase produces no library for Meson to link",
+        ));
+    }
+    Ok(outputs)
+}
+
+// c-library products name the dependency; otherwise it is named after the crate
+fn dependency_name(config: &Config, package: &crate::cargo::Package) -> String {
+    config
+        .product
+        .iter()
+        .map(|product| match product {
+            Product::CLibrary(lib) => lib.name.clone(),
+        })
+        .next()
+        .unwrap_or_else(|| package.name.clone())
+}
+
+fn quote(st: &str) -> String {
+    format!("'{}'", st.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn list(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|i| quote(i)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+// The shim builds through an install whose libdir and includedir are the custom target's own
+// directory, since Meson only accepts outputs placed there
+fn shim(name: &str, version: &str, config: &Path, outputs: &Outputs) -> String {
+    let var = name.replace(|c: char| !c.is_ascii_alphanumeric(), "_");
+    let mut files = outputs.libs.clone();
+    files.extend(outputs.headers.iter().cloned());
+    let headers: Vec<String> = (outputs.libs.len()..files.len())
+        .map(|i| format!("{}_outputs[{}]", var, i))
+        .collect();
+    let headers = format!("[{}]", headers.join(", "));
+    format!(
+        "{generated}
+project({name}, 'c', version: {version}, meson_version: '>=0.54.0')
+
+autobuild = find_program('cargo-autobuild')
+
+{var}_outputs = custom_target(
+  {target},
+  output: {files},
+  command: [
+    autobuild,
+    '--config', meson.current_source_dir() / {config},
+    '--prefix', meson.current_build_dir() / 'stage',
+    '--libdir', '@OUTDIR@',
+    '--includedir', '@OUTDIR@',
+    'install',
+  ],
+  console: true,
+  build_by_default: true,
+)
+
+{var}_dep = declare_dependency(
+  link_with: {var}_outputs[0],
+  sources: {headers},
+  include_directories: include_directories('.'),
+)
+meson.override_dependency({name}, {var}_dep)
+",
+        generated = GENERATED,
+        name = quote(name),
+        version = quote(version),
+        var = var,
+        target = quote(&format!("{}-autobuild", name)),
+        files = list(&files),
+        config = quote(&config.display().to_string()),
+        headers = headers,
+    )
+}
+
+fn wrap(name: &str, package: &crate::cargo::Package, cfg: &Configuration) -> String {
+    let url = match &package.repository {
+        Some(url) => url.clone(),
+        None => cfg.src_dir.display().to_string(),
+    };
+    format!(
+        "[wrap-git]\nurl = {}\nrevision = HEAD\ndepth = 1\n\n[provide]\ndependency_names = {}\n",
+        url, name
+    )
+}
+
+// Writes meson.build into the source directory and `name`.wrap into the directory given on the
+// command line, which is usually the consuming project's subprojects directory
+pub fn write(
+    plan: &Plan,
+    cfg: &Configuration,
+    config: &Config,
+    opts: &Options,
+) -> std::io::Result<()> {
+    let metadata =
+        crate::cargo::metadata(&cfg.cargo, &crate::steps::manifest_at(OsStr::new(""), cfg))?;
+    let package = metadata.packages.first().ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} has no package", cfg.src_dir.display()),
+        )
+    })?;
+    let name = dependency_name(config, package);
+    let outputs = outputs(plan, cfg)?;
+
+    let meson_build = cfg.src_dir.join("meson.build");
+    if let Ok(existing) = std::fs::read_to_string(&meson_build) {
+        if !existing.starts_with(GENERATED) && !opts.force {
+            return Err(std::io::Error::new(
+                ErrorKind::AlreadyExists,
+                format!(
+                    "{} was not generated by cargo-autobuild; pass --force to replace it",
+                    meson_build.display()
+                ),
+            ));
+        }
+    }
+    let config_file = opts
+        .config
+        .file_name()
+        .map_or_else(|| PathBuf::from("autobuild.toml"), PathBuf::from);
+    std::fs::write(
+        &meson_build,
+        shim(&name, &package.version, &config_file, &outputs),
+    )?;
+    println!("Wrote {}", meson_build.display());
+
+    let dir = match opts.args.first() {
+        Some(dir) => PathBuf::from(dir),
+        None => cfg.build_dir.clone(),
+    };
+    std::fs::create_dir_all(&dir)?;
+    let wrap_file = dir.join(format!("{}.wrap", name));
+    std::fs::write(&wrap_file, wrap(&name, package, cfg))?;
+    println!("Wrote {}", wrap_file.display());
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use std::io::ErrorKind;
+
+use crate::{
+    cli::Options,
+    config::{Format, FORMAT_VERSION},
+};
+
+// MIGRATIONS[n] rewrites a format-version n config into format-version n + 1
+const MIGRATIONS: &[fn(&mut toml::value::Table)] = &[unversioned];
+
+// Configs written before format-version existed need nothing beyond the version key itself.
+fn unversioned(_: &mut toml::value::Table) {}
+
+fn serialize(format: Format, value: &toml::Value) -> std::io::Result<String> {
+    let result = match format {
+        Format::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+        Format::Json => serde_json::to_string_pretty(value)
+            .map(|st| st + "\n")
+            .map_err(|e| e.to_string()),
+        Format::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+    };
+    result.map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))
+}
+
+pub fn migrate(opts: &Options) -> std::io::Result<()> {
+    let path = &opts.config;
+    let format = Format::from_path(path)?;
+    let st = std::fs::read_to_string(path)?;
+    let mut value: toml::Value = crate::config::parse(format, path, &st)?;
+    let from = crate::config::format_version(path, &value)?.unwrap_or(0);
+    if from == FORMAT_VERSION {
+        println!(
+            "{} is already at format-version {}",
+            path.display(),
+            FORMAT_VERSION
+        );
+        return Ok(());
+    }
+
+    let table = value.as_table_mut().ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is not a table", path.display()),
+        )
+    })?;
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(table);
+    }
+    table.insert(
+        "format-version".to_string(),
+        toml::Value::Integer(i64::from(FORMAT_VERSION)),
+    );
+    let out = serialize(format, &value)?;
+
+    if opts.dry_run {
+        print!("{}", out);
+        return Ok(());
+    }
+    let mut backup = path.clone().into_os_string();
+    backup.push(".orig");
+    std::fs::copy(path, &backup)?;
+    std::fs::write(path, out)?;
+    println!(
+        "Migrated {} from format-version {} to {} (the original is saved as {}; comments are not preserved)",
+        path.display(),
+        from,
+        FORMAT_VERSION,
+        backup.to_string_lossy()
+    );
+    Ok(())
+}
@@ -0,0 +1,162 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use target_tuples::Target;
+
+pub struct MsvcEnv {
+    pub env: Vec<(String, String)>,
+    pub cl: PathBuf,
+    pub link: PathBuf,
+    pub lib: PathBuf,
+}
+
+fn msvc_arch(target: &Target) -> Option<&'static str> {
+    let name = target.get_name();
+    if name.starts_with("x86_64") {
+        Some("x64")
+    } else if name.starts_with("i686") || name.starts_with("i586") {
+        Some("x86")
+    } else if name.starts_with("aarch64") {
+        Some("arm64")
+    } else if name.starts_with("thumbv7a") || name.starts_with("arm") {
+        Some("arm")
+    } else {
+        None
+    }
+}
+
+fn vswhere() -> Option<PathBuf> {
+    let program_files = std::env::var_os("ProgramFiles(x86)")?;
+    let vswhere = Path::new(&program_files).join("Microsoft Visual Studio\\Installer\\vswhere.exe");
+    let output = Command::new(vswhere)
+        .args(["-latest", "-products", "*", "-requires"])
+        .arg("Microsoft.VisualStudio.Component.VC.Tools.x86.x64")
+        .args(["-property", "installationPath"])
+        .output()
+        .ok()?;
+    let path = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !path.is_empty() {
+        Some(PathBuf::from(path))
+    } else {
+        None
+    }
+}
+
+fn registry_value(key: &str, value: &str) -> Option<String> {
+    let output = Command::new("reg")
+        .arg("query")
+        .arg(key)
+        .arg("/v")
+        .arg(value)
+        .output()
+        .ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|l| {
+            let mut parts = l.trim().splitn(3, "    ");
+            match (parts.next(), parts.next(), parts.next()) {
+                (Some(name), Some("REG_SZ"), Some(data)) if name == value => {
+                    Some(data.trim().to_string())
+                }
+                _ => None,
+            }
+        })
+}
+
+fn newest_subdir(dir: &Path) -> Option<String> {
+    std::fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|e| e.path().is_dir())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .filter(|n| n.starts_with("10."))
+        .max()
+}
+
+fn join_paths(paths: Vec<PathBuf>, existing: Option<&str>) -> String {
+    let mut parts: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+    parts.extend(existing.filter(|e| !e.is_empty()).map(str::to_string));
+    parts.join(";")
+}
+
+pub fn detect(build: &Target, target: &Target) -> std::io::Result<Option<MsvcEnv>> {
+    if !target.get_name().ends_with("msvc") || std::env::var_os("VCINSTALLDIR").is_some() {
+        return Ok(None);
+    }
+    let (host_arch, arch) = match (msvc_arch(build), msvc_arch(target)) {
+        (Some(host), Some(arch)) => (host, arch),
+        _ => return Ok(None),
+    };
+
+    let install = match vswhere() {
+        Some(install) => install,
+        None => return Ok(None),
+    };
+    let version_file = install.join("VC\\Auxiliary\\Build\\Microsoft.VCToolsVersion.default.txt");
+    let version = std::fs::read_to_string(&version_file)?;
+    let tools = install.join("VC\\Tools\\MSVC").join(version.trim());
+    let bin = tools
+        .join("bin")
+        .join(format!("Host{}", host_arch))
+        .join(arch);
+    if !bin.join("link.exe").is_file() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "Visual Studio at {} has no {} tools for a {} host",
+                install.display(),
+                arch,
+                host_arch
+            ),
+        ));
+    }
+
+    let mut path = vec![bin.clone()];
+    let mut include = vec![tools.join("include")];
+    let mut lib = vec![tools.join("lib").join(arch)];
+
+    if let Some(kits) = registry_value(
+        "HKLM\\SOFTWARE\\Microsoft\\Windows Kits\\Installed Roots",
+        "KitsRoot10",
+    ) {
+        let kits = PathBuf::from(kits);
+        if let Some(sdk) = newest_subdir(&kits.join("Include")) {
+            for dir in &["ucrt", "um", "shared", "winrt"] {
+                include.push(kits.join("Include").join(&sdk).join(dir));
+            }
+            for dir in &["ucrt", "um"] {
+                lib.push(kits.join("Lib").join(&sdk).join(dir).join(arch));
+            }
+            path.push(kits.join("bin").join(&sdk).join(host_arch));
+        }
+    }
+
+    let env = vec![
+        (
+            "PATH".to_string(),
+            join_paths(path, std::env::var("PATH").ok().as_deref()),
+        ),
+        (
+            "INCLUDE".to_string(),
+            join_paths(include, std::env::var("INCLUDE").ok().as_deref()),
+        ),
+        (
+            "LIB".to_string(),
+            join_paths(lib, std::env::var("LIB").ok().as_deref()),
+        ),
+        (
+            "VCINSTALLDIR".to_string(),
+            install.join("VC").display().to_string(),
+        ),
+    ];
+
+    Ok(Some(MsvcEnv {
+        env,
+        cl: bin.join("cl.exe"),
+        link: bin.join("link.exe"),
+        lib: bin.join("lib.exe"),
+    }))
+}
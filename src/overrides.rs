@@ -0,0 +1,284 @@
+use std::{collections::HashMap, io::ErrorKind};
+
+use crate::{
+    config::{Config, InstallDirectory, Step},
+    configure::Configuration,
+};
+
+struct Parser<'a> {
+    rest: &'a str,
+    cfg: &'a [String],
+}
+
+impl<'a> Parser<'a> {
+    fn eat(&mut self, c: char) -> bool {
+        self.rest = self.rest.trim_start();
+        match self.rest.strip_prefix(c) {
+            Some(rest) => {
+                self.rest = rest;
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn ident(&mut self) -> Option<&'a str> {
+        self.rest = self.rest.trim_start();
+        let end = self
+            .rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-')
+            .unwrap_or(self.rest.len());
+        let (ident, rest) = self.rest.split_at(end);
+        self.rest = rest;
+        Some(ident).filter(|i| !i.is_empty())
+    }
+
+    fn string(&mut self) -> Option<&'a str> {
+        if !self.eat('"') {
+            return None;
+        }
+        let end = self.rest.find('"')?;
+        let (st, rest) = self.rest.split_at(end);
+        self.rest = &rest[1..];
+        Some(st)
+    }
+
+    fn list(&mut self) -> Option<Vec<bool>> {
+        let mut values = Vec::new();
+        while !self.eat(')') {
+            values.push(self.predicate()?);
+            if !self.eat(',') && !self.rest.trim_start().starts_with(')') {
+                return None;
+            }
+        }
+        Some(values)
+    }
+
+    fn predicate(&mut self) -> Option<bool> {
+        let ident = self.ident()?;
+        if self.eat('(') {
+            let values = self.list()?;
+            match ident {
+                "all" => Some(values.iter().all(|&v| v)),
+                "any" => Some(values.iter().any(|&v| v)),
+                "not" if values.len() == 1 => Some(!values[0]),
+                _ => None,
+            }
+        } else if self.eat('=') {
+            let value = self.string()?;
+            let pair = format!("{}=\"{}\"", ident, value);
+            Some(self.cfg.contains(&pair))
+        } else {
+            Some(self.cfg.iter().any(|c| c == ident))
+        }
+    }
+}
+
+// Evaluates the inside of `cfg(...)` against `rustc --print cfg` output; None if it is malformed
+fn cfg_matches(expr: &str, cfg: &[String]) -> Option<bool> {
+    let mut parser = Parser { rest: expr, cfg };
+    let value = parser.predicate()?;
+    Some(value).filter(|_| parser.eat(')') && parser.rest.trim().is_empty())
+}
+
+fn applies(key: &str, cfg: &Configuration) -> std::io::Result<bool> {
+    let expr = match key.strip_prefix("cfg(") {
+        Some(expr) => expr,
+        None => return Ok(key == cfg.host.get_name()),
+    };
+    cfg_matches(expr, &cfg.host_cfg).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid cfg expression in [target.'{}']", key),
+        )
+    })
+}
+
+// Step conditions use the cfg syntax over the configuration variables: `name = "value"` compares
+// a variable and a bare `name` holds when it is set to anything but "false"
+pub fn condition(expr: &str, vars: &HashMap<String, String>) -> Option<bool> {
+    let mut atoms = Vec::new();
+    for (name, value) in vars {
+        atoms.push(format!("{}=\"{}\"", name, value));
+        if !value.is_empty() && value != "false" {
+            atoms.push(name.clone());
+        }
+    }
+    let mut parser = Parser {
+        rest: expr,
+        cfg: &atoms,
+    };
+    parser.predicate().filter(|_| parser.rest.trim().is_empty())
+}
+
+fn source_step(config: &Config, name: &str) -> Option<toml::Value> {
+    let (phase, index) = name.rsplit_once('.')?;
+    let index: usize = index.parse().ok()?;
+    let source = config.source.as_ref()?;
+    let steps = match phase {
+        "build" => source.get("build")?.get("step")?,
+        "install" => source.get("install")?.get("step")?,
+        "test" => source.get("test")?.get("step")?,
+        "bench" => source.get("bench")?.get("step")?,
+        "pgo.train" => source.get("pgo")?.get("train")?,
+        _ => return None,
+    };
+    steps.get(index).cloned()
+}
+
+// Applies the [target.*] sections matching the host and returns the steps they override,
+// keyed by planned step name.
+pub fn apply(config: &Config, cfg: &mut Configuration) -> std::io::Result<HashMap<String, Step>> {
+    let mut keys: Vec<&String> = config.target.keys().collect();
+    keys.sort();
+
+    let mut steps: HashMap<String, toml::Value> = HashMap::new();
+    for key in keys {
+        let overrides = &config.target[key];
+        let triple_only = overrides.cpu.is_some()
+            || !overrides.features.is_empty()
+            || overrides.api_level.is_some()
+            || overrides.min_os_version.is_some()
+            || overrides.spec.is_some()
+            || overrides.linker_script.is_some();
+        if triple_only && key.starts_with("cfg(") {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "[target.'{}'] cannot set cpu, features, api-level, min-os-version, spec or \
+                     linker-script; name the target triple instead",
+                    key
+                ),
+            ));
+        }
+        if !applies(key, cfg)? {
+            continue;
+        }
+        cfg.log
+            .entry("target".to_string())
+            .or_default()
+            .push(format!("[target.'{}'] applies to {}", key, cfg.host));
+        cfg.profile
+            .rustflags
+            .extend(overrides.rustflags.iter().cloned());
+        for (var, value) in &overrides.env {
+            cfg.toolchain_env.insert(var.clone(), value.clone());
+            cfg.vars.insert(var.clone(), value.clone());
+        }
+        for (name, target) in &overrides.dirs.dirs {
+            let path = cfg.install_path(target)?;
+            cfg.log.entry(name.clone()).or_default().push(format!(
+                "overridden by [target.'{}'] to {}",
+                key,
+                path.display()
+            ));
+            cfg.vars.insert(name.clone(), path.display().to_string());
+            cfg.dirs.insert(InstallDirectory::from_name(name), path);
+        }
+        for (name, fields) in &overrides.step {
+            let name = &config.step_position(name);
+            let step = match steps.remove(name) {
+                Some(step) => Some(step),
+                None => source_step(config, name),
+            };
+            let mut step = step.ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("[target.'{}'.step] names unknown step {}", key, name),
+                )
+            })?;
+            crate::config::merge(&mut step, toml::Value::Table(fields.clone()));
+            steps.insert(name.clone(), step);
+        }
+    }
+
+    steps
+        .into_iter()
+        .map(|(name, step)| {
+            let step = Step::deserialize(step).map_err(|e| {
+                std::io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid override for step {}: {}", name, e),
+                )
+            })?;
+            Ok((name, step))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn host() -> Vec<String> {
+        ["unix", "target_os=\"linux\"", "target_pointer_width=\"64\""]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn cfg_atoms() {
+        let host = host();
+        assert_eq!(cfg_matches("unix)", &host), Some(true));
+        assert_eq!(cfg_matches("windows)", &host), Some(false));
+        assert_eq!(cfg_matches("target_os = \"linux\")", &host), Some(true));
+        assert_eq!(cfg_matches("target_os=\"macos\")", &host), Some(false));
+    }
+
+    #[test]
+    fn cfg_combinators() {
+        let host = host();
+        assert_eq!(
+            cfg_matches("all(unix, target_pointer_width = \"64\"))", &host),
+            Some(true)
+        );
+        assert_eq!(cfg_matches("all(unix, windows))", &host), Some(false));
+        assert_eq!(cfg_matches("all())", &host), Some(true));
+        assert_eq!(cfg_matches("any(windows, unix))", &host), Some(true));
+        assert_eq!(cfg_matches("any())", &host), Some(false));
+        assert_eq!(cfg_matches("not(windows))", &host), Some(true));
+        assert_eq!(
+            cfg_matches("not(any(windows, target_os = \"linux\")))", &host),
+            Some(false)
+        );
+        assert_eq!(cfg_matches("all(unix,))", &host), Some(true));
+    }
+
+    #[test]
+    fn cfg_malformed() {
+        let host = host();
+        for expr in [
+            "",
+            ")",
+            "unix",
+            "unix) extra",
+            "all(unix",
+            "all(unix windows))",
+            "not(unix, windows))",
+            "not())",
+            "maybe(unix))",
+            "target_os = linux)",
+            "target_os = \"linux)",
+        ] {
+            assert_eq!(cfg_matches(expr, &host), None, "{:?}", expr);
+        }
+    }
+
+    #[test]
+    fn conditions() {
+        let mut vars = HashMap::new();
+        vars.insert("docs".to_string(), "true".to_string());
+        vars.insert("lto".to_string(), "false".to_string());
+        vars.insert("profile".to_string(), "release".to_string());
+        assert_eq!(condition("docs", &vars), Some(true));
+        assert_eq!(condition("lto", &vars), Some(false));
+        assert_eq!(condition("missing", &vars), Some(false));
+        assert_eq!(
+            condition("all(docs, profile = \"release\")", &vars),
+            Some(true)
+        );
+        assert_eq!(condition("not(lto)", &vars), Some(true));
+        assert_eq!(condition("docs extra", &vars), None);
+    }
+}
@@ -0,0 +1,501 @@
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+use crate::{config::ApplyPatchesStep, configure::Configuration};
+
+struct Hunk {
+    old_start: usize,
+    new_start: usize,
+    // Context and removed lines, and context and added lines, in order
+    old: Vec<String>,
+    new: Vec<String>,
+}
+
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    hunks: Vec<Hunk>,
+    // Cleared by "\ No newline at end of file" after the last line of that side
+    old_newline: bool,
+    new_newline: bool,
+}
+
+fn invalid(patch: &Path, msg: &str) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::InvalidData,
+        format!("{}: {}", patch.display(), msg),
+    )
+}
+
+fn strip_path(patch: &Path, name: &str, strip: usize) -> std::io::Result<Option<String>> {
+    let name = name.split('\t').next().unwrap_or(name).trim_end();
+    if name == "/dev/null" {
+        return Ok(None);
+    }
+    let parts: Vec<&str> = name.split('/').filter(|p| !p.is_empty()).collect();
+    let path = match parts.get(strip..) {
+        Some(parts) => parts.join("/"),
+        None => return Ok(None),
+    };
+    // The files patched must be inside the directory the patches apply to
+    let inside = Path::new(&path)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+    if !inside || (strip == 0 && name.starts_with('/')) {
+        return Err(invalid(
+            patch,
+            &format!("{} is outside the directory being patched", name),
+        ));
+    }
+    Ok(Some(path))
+}
+
+// `@@ -start[,len] +start[,len] @@`
+fn hunk_header(line: &str) -> Option<(usize, usize, usize, usize)> {
+    let mut words = line.strip_prefix("@@ ")?.split(' ');
+    let range = |word: &str| -> Option<(usize, usize)> {
+        let mut parts = word.splitn(2, ',');
+        let start = parts.next()?.parse().ok()?;
+        let len = parts.next().map_or(Some(1), |l| l.parse().ok())?;
+        Some((start, len))
+    };
+    let (old_start, old_len) = range(words.next()?.strip_prefix('-')?)?;
+    let (new_start, new_len) = range(words.next()?.strip_prefix('+')?)?;
+    Some((old_start, old_len, new_start, new_len))
+}
+
+// The marker applies to the line before it: a context line is the last of both sides
+fn no_newline(file: &mut FilePatch, last: &str) {
+    match last {
+        "-" => file.old_newline = false,
+        "+" => file.new_newline = false,
+        _ => {
+            file.old_newline = false;
+            file.new_newline = false;
+        }
+    }
+}
+
+fn parse(patch: &Path, text: &str, strip: usize) -> std::io::Result<Vec<FilePatch>> {
+    let mut files = Vec::new();
+    let mut lines = text.lines().peekable();
+    while let Some(line) = lines.next() {
+        let old = match line.strip_prefix("--- ") {
+            Some(old) => old,
+            None => continue,
+        };
+        let new = lines
+            .next()
+            .and_then(|l| l.strip_prefix("+++ "))
+            .ok_or_else(|| invalid(patch, "--- without +++"))?;
+        let mut file = FilePatch {
+            old_path: strip_path(patch, old, strip)?,
+            new_path: strip_path(patch, new, strip)?,
+            hunks: Vec::new(),
+            old_newline: true,
+            new_newline: true,
+        };
+        let mut last = "";
+        while let Some((old_start, mut old_len, new_start, mut new_len)) =
+            lines.peek().and_then(|l| hunk_header(l))
+        {
+            lines.next();
+            let mut hunk = Hunk {
+                old_start,
+                new_start,
+                old: Vec::new(),
+                new: Vec::new(),
+            };
+            while old_len > 0 || new_len > 0 {
+                let line = lines
+                    .next()
+                    .ok_or_else(|| invalid(patch, "hunk ends early"))?;
+                let (kind, rest) = line.split_at(line.len().min(1));
+                if kind != "\\" {
+                    last = kind;
+                }
+                match kind {
+                    " " | "" => {
+                        hunk.old.push(rest.to_string());
+                        hunk.new.push(rest.to_string());
+                        old_len = old_len.saturating_sub(1);
+                        new_len = new_len.saturating_sub(1);
+                    }
+                    "-" => {
+                        hunk.old.push(rest.to_string());
+                        old_len = old_len.saturating_sub(1);
+                    }
+                    "+" => {
+                        hunk.new.push(rest.to_string());
+                        new_len = new_len.saturating_sub(1);
+                    }
+                    "\\" => no_newline(&mut file, last),
+                    _ => return Err(invalid(patch, "malformed hunk line")),
+                }
+            }
+            if lines.peek().is_some_and(|l| l.starts_with('\\')) {
+                lines.next();
+                no_newline(&mut file, last);
+            }
+            file.hunks.push(hunk);
+        }
+        files.push(file);
+    }
+    if files.is_empty() {
+        return Err(invalid(patch, "not a unified diff"));
+    }
+    Ok(files)
+}
+
+// A line of the file without the carriage return of a CRLF ending, which the patch does not have
+fn content(line: &str) -> &str {
+    line.strip_suffix('\r').unwrap_or(line)
+}
+
+// Applies the hunks in order, letting each move as far as it needs to from its stated line
+fn apply_hunks(lines: &[String], hunks: &[Hunk], reverse: bool) -> Option<Vec<String>> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut delta: isize = 0;
+    for hunk in hunks {
+        let (start, from, to) = if reverse {
+            (hunk.new_start, &hunk.new, &hunk.old)
+        } else {
+            (hunk.old_start, &hunk.old, &hunk.new)
+        };
+        // An empty range, as in -U0 diffs, names the line it goes after rather than its first
+        let start = if from.is_empty() {
+            start
+        } else {
+            start.saturating_sub(1)
+        };
+        let expected = (start as isize + delta).max(pos as isize) as usize;
+        let fits = |at: usize| {
+            at >= pos
+                && at + from.len() <= lines.len()
+                && lines[at..at + from.len()]
+                    .iter()
+                    .map(|l| content(l))
+                    .eq(from.iter().map(|l| &**l))
+        };
+        let at = (0..=lines.len())
+            .flat_map(|d| vec![expected.checked_add(d), expected.checked_sub(d)])
+            .flatten()
+            .find(|&at| fits(at))?;
+        out.extend_from_slice(&lines[pos..at]);
+        // Lines the hunk keeps are the file's own, with their endings; added lines end like the
+        // lines where they go
+        let crlf = lines
+            .get(at)
+            .or(at.checked_sub(1).and_then(|at| lines.get(at)))
+            .is_some_and(|l| l.ends_with('\r'));
+        let mut old = lines[at..at + from.len()].iter();
+        for line in to {
+            match old.clone().position(|l| content(l) == line) {
+                Some(i) => out.push(old.nth(i).unwrap().clone()),
+                None if crlf => out.push(format!("{}\r", line)),
+                None => out.push(line.clone()),
+            }
+        }
+        pos = at + from.len();
+        delta += to.len() as isize - from.len() as isize;
+    }
+    out.extend_from_slice(&lines[pos..]);
+    Some(out)
+}
+
+// A file with its new contents, or None where the patch deletes it
+type Patched = (PathBuf, Option<String>);
+
+// The new contents of every file, or None when some hunk does not apply
+fn apply_files(
+    dir: &Path,
+    files: &[FilePatch],
+    reverse: bool,
+) -> std::io::Result<Option<Vec<Patched>>> {
+    let mut results = Vec::new();
+    for file in files {
+        let (source, target, from_newline, to_newline) = if reverse {
+            (
+                &file.new_path,
+                &file.old_path,
+                file.new_newline,
+                file.old_newline,
+            )
+        } else {
+            (
+                &file.old_path,
+                &file.new_path,
+                file.old_newline,
+                file.new_newline,
+            )
+        };
+        let text = match source {
+            Some(source) => match std::fs::read_to_string(dir.join(source)) {
+                Ok(text) => text,
+                Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+                Err(e) => return Err(e),
+            },
+            None if target.as_ref().is_some_and(|t| dir.join(t).exists()) => return Ok(None),
+            None => String::new(),
+        };
+        // Lines keep any carriage return, so that each keeps the ending it had
+        let lines: Vec<String> = text.split_terminator('\n').map(str::to_string).collect();
+        // The patch says how the last line ends where it touches it; elsewhere the file keeps
+        // its own ending
+        let newline = to_newline && (!from_newline || text.is_empty() || text.ends_with('\n'));
+        let lines = match apply_hunks(&lines, &file.hunks, reverse) {
+            Some(lines) => lines,
+            None => return Ok(None),
+        };
+        match (source, target) {
+            (_, Some(target)) => {
+                let mut text = lines.join("\n");
+                if newline && !lines.is_empty() {
+                    text.push('\n');
+                }
+                results.push((dir.join(target), Some(text)));
+            }
+            (Some(source), None) => results.push((dir.join(source), None)),
+            (None, None) => {}
+        }
+    }
+    Ok(Some(results))
+}
+
+fn builtin(dir: &Path, patch: &Path, strip: usize) -> std::io::Result<()> {
+    let files = parse(patch, &std::fs::read_to_string(patch)?, strip)?;
+    let results = match apply_files(dir, &files, false)? {
+        Some(results) => results,
+        None if apply_files(dir, &files, true)?.is_some() => {
+            crate::steps::note(&format!("{} is already applied", patch.display()));
+            return Ok(());
+        }
+        None => return Err(invalid(patch, "does not apply")),
+    };
+    crate::steps::note(&format!("Applying {}", patch.display()));
+    for (path, contents) in results {
+        match contents {
+            Some(contents) => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(path, contents)?
+            }
+            None => std::fs::remove_file(path)?,
+        }
+    }
+    Ok(())
+}
+
+fn external(tool: &Path, dir: &Path, patch: &Path, strip: usize) -> std::io::Result<()> {
+    let patch_cmd = |args: &[&str]| {
+        let mut cmd = Command::new(tool);
+        cmd.arg(format!("-p{}", strip))
+            .arg("--batch")
+            .arg("-d")
+            .arg(dir)
+            .arg("-i")
+            .arg(patch)
+            .args(args);
+        cmd
+    };
+    let quiet = |mut cmd: Command| -> std::io::Result<bool> { Ok(cmd.output()?.status.success()) };
+    if quiet(patch_cmd(&["--dry-run", "--forward"]))? {
+        crate::steps::note(&format!("Applying {}", patch.display()));
+        let mut cmd = patch_cmd(&["--forward"]);
+        let status = cmd.status()?;
+        if status.success() {
+            return Ok(());
+        }
+        return Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, status
+        )));
+    }
+    if quiet(patch_cmd(&["--dry-run", "--reverse"]))? {
+        crate::steps::note(&format!("{} is already applied", patch.display()));
+        return Ok(());
+    }
+    Err(invalid(patch, "does not apply"))
+}
+
+// A quilt-style series file lists one patch per line, relative to itself
+fn series(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let base = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(|l| l.split('#').next().unwrap_or_default().trim())
+        .filter(|l| !l.is_empty())
+        .map(|l| base.join(l.split_whitespace().next().unwrap_or(l)))
+        .collect())
+}
+
+pub fn dir(step: &ApplyPatchesStep, cfg: &Configuration) -> PathBuf {
+    cfg.src_dir
+        .join(crate::steps::substitute(&step.dir.to_string_lossy(), cfg))
+}
+
+pub fn apply(step: &ApplyPatchesStep, cfg: &Configuration) -> std::io::Result<()> {
+    let dir = dir(step, cfg);
+    let mut patches: Vec<PathBuf> = step.patches.iter().map(|p| cfg.src_dir.join(p)).collect();
+    if let Some(path) = &step.series {
+        patches.extend(series(&cfg.src_dir.join(path))?);
+    }
+    let strip = step.strip as usize;
+    for patch in &patches {
+        match (&cfg.patch, step.builtin) {
+            (Some(tool), false) => external(tool, &dir, patch, strip)?,
+            _ => builtin(&dir, patch, strip)?,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    fn parse_str(text: &str) -> Vec<FilePatch> {
+        parse(Path::new("test.patch"), text, 1).unwrap()
+    }
+
+    // A directory of its own under the system temporary directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("autobuild-patch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn parses_paths_and_hunks() {
+        let files =
+            parse_str("--- a/src/lib.rs\n+++ b/src/lib.rs\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n");
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].old_path.as_deref(), Some("src/lib.rs"));
+        assert_eq!(files[0].new_path.as_deref(), Some("src/lib.rs"));
+        let hunk = &files[0].hunks[0];
+        assert_eq!((hunk.old_start, hunk.new_start), (1, 1));
+        assert_eq!(hunk.old, lines("one\ntwo"));
+        assert_eq!(hunk.new, lines("one\n2"));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_diff() {
+        assert!(parse(Path::new("test.patch"), "hello\n", 1).is_err());
+        assert!(parse(Path::new("test.patch"), "--- a/x\n", 1).is_err());
+        assert!(parse(
+            Path::new("test.patch"),
+            "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n",
+            1
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn inserts_after_the_line_of_an_empty_range() {
+        let files = parse_str("--- a/x\n+++ b/x\n@@ -2,0 +3,1 @@\n+new\n");
+        let out = apply_hunks(&lines("a\nb\nc"), &files[0].hunks, false).unwrap();
+        assert_eq!(out, lines("a\nb\nnew\nc"));
+        let back = apply_hunks(&out, &files[0].hunks, true).unwrap();
+        assert_eq!(back, lines("a\nb\nc"));
+    }
+
+    #[test]
+    fn removes_at_the_line_of_an_empty_range() {
+        let files = parse_str("--- a/x\n+++ b/x\n@@ -3,1 +2,0 @@\n-c\n");
+        let out = apply_hunks(&lines("a\nb\nc\nd"), &files[0].hunks, false).unwrap();
+        assert_eq!(out, lines("a\nb\nd"));
+        let back = apply_hunks(&out, &files[0].hunks, true).unwrap();
+        assert_eq!(back, lines("a\nb\nc\nd"));
+    }
+
+    #[test]
+    fn moves_hunks_that_are_offset() {
+        let files = parse_str("--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n b\n-c\n+C\n d\n");
+        let out = apply_hunks(&lines("x\ny\na\nb\nc\nd"), &files[0].hunks, false).unwrap();
+        assert_eq!(out, lines("x\ny\na\nb\nC\nd"));
+        assert!(apply_hunks(&lines("a\nb\nX\nd"), &files[0].hunks, false).is_none());
+    }
+
+    #[test]
+    fn detects_patches_that_are_applied() {
+        let dir = scratch("applied");
+        std::fs::write(dir.join("x"), "a\nB\nc\n").unwrap();
+        let files = parse_str("--- a/x\n+++ b/x\n@@ -1,3 +1,3 @@\n a\n-b\n+B\n c\n");
+        assert!(apply_files(&dir, &files, false).unwrap().is_none());
+        assert!(apply_files(&dir, &files, true).unwrap().is_some());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_a_missing_newline() {
+        let dir = scratch("newline");
+        std::fs::write(dir.join("x"), "a\nb").unwrap();
+        let files = parse_str(
+            "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n",
+        );
+        assert!(!files[0].old_newline && !files[0].new_newline);
+        let results = apply_files(&dir, &files, false).unwrap().unwrap();
+        assert_eq!(results[0].1.as_deref(), Some("a\nc"));
+
+        let files = parse_str(
+            "--- a/x\n+++ b/x\n@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n",
+        );
+        let results = apply_files(&dir, &files, false).unwrap().unwrap();
+        assert_eq!(results[0].1.as_deref(), Some("a\nc\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn keeps_crlf_endings() {
+        let dir = scratch("crlf");
+        std::fs::write(dir.join("x"), "a\r\nb\r\nc\n").unwrap();
+        let files = parse_str("--- a/x\n+++ b/x\n@@ -1,3 +1,4 @@\n a\n-b\n+B\n+b2\n c\n");
+        let results = apply_files(&dir, &files, false).unwrap().unwrap();
+        assert_eq!(results[0].1.as_deref(), Some("a\r\nB\r\nb2\r\nc\n"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_paths_outside_the_directory() {
+        let patch = Path::new("test.patch");
+        assert_eq!(
+            strip_path(patch, "a/src/lib.rs", 1).unwrap().as_deref(),
+            Some("src/lib.rs")
+        );
+        assert!(strip_path(patch, "a/../lib.rs", 1).is_err());
+        assert!(strip_path(patch, "/etc/passwd", 0).is_err());
+        assert_eq!(strip_path(patch, "/dev/null", 1).unwrap(), None);
+    }
+
+    #[test]
+    fn creates_and_deletes_files() {
+        let dir = scratch("files");
+        std::fs::write(dir.join("old"), "gone\n").unwrap();
+        let files = parse_str(
+            "--- /dev/null\n+++ b/new\n@@ -0,0 +1,2 @@\n+one\n+two\n--- a/old\n+++ /dev/null\n@@ -1 +0,0 @@\n-gone\n",
+        );
+        assert_eq!(files[0].old_path, None);
+        assert_eq!(files[1].new_path, None);
+        let results = apply_files(&dir, &files, false).unwrap().unwrap();
+        assert_eq!(
+            results[0],
+            (dir.join("new"), Some("one\ntwo\n".to_string()))
+        );
+        assert_eq!(results[1], (dir.join("old"), None));
+        // A file the patch creates must not be there yet
+        std::fs::write(dir.join("new"), "one\ntwo\n").unwrap();
+        assert!(apply_files(&dir, &files, false).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,92 @@
+use std::path::{Path, PathBuf};
+
+// Windows refuses longer paths, directories included, unless they are in the verbatim \\?\ form
+#[cfg(windows)]
+const MAX_PATH: usize = 248;
+
+// Verbatim paths are used exactly as written, so forward slashes, . and .. are resolved here
+// rather than by Windows. Paths that are short and not verbatim already are left alone, since
+// some programs cannot read the verbatim form
+#[cfg(windows)]
+pub fn long(path: &Path) -> PathBuf {
+    use std::path::{Component, Prefix};
+    let verbatim = match path.components().next() {
+        Some(Component::Prefix(prefix)) => prefix.kind().is_verbatim(),
+        _ => false,
+    };
+    if !path.is_absolute() || (!verbatim && path.as_os_str().len() < MAX_PATH) {
+        return path.to_owned();
+    }
+    let mut long = String::new();
+    let mut parts: Vec<String> = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(prefix) => {
+                long = match prefix.kind() {
+                    Prefix::Disk(drive) | Prefix::VerbatimDisk(drive) => {
+                        format!(r"\\?\{}:", drive as char)
+                    }
+                    Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => format!(
+                        r"\\?\UNC\{}\{}",
+                        server.to_string_lossy(),
+                        share.to_string_lossy()
+                    ),
+                    // Device namespace paths such as \\.\pipe\name are left as they are
+                    _ => return path.to_owned(),
+                }
+            }
+            Component::RootDir | Component::CurDir => {}
+            Component::ParentDir => {
+                parts.pop();
+            }
+            // Inside a verbatim path a forward slash does not separate components
+            Component::Normal(part) => {
+                for part in part.to_string_lossy().split('/') {
+                    match part {
+                        "" | "." => {}
+                        ".." => {
+                            parts.pop();
+                        }
+                        part => parts.push(part.to_string()),
+                    }
+                }
+            }
+        }
+    }
+    for part in parts {
+        long.push('\\');
+        long.push_str(&part);
+    }
+    PathBuf::from(long)
+}
+
+#[cfg(not(windows))]
+pub fn long(path: &Path) -> PathBuf {
+    path.to_owned()
+}
+
+// An absolute install target as Windows sees it: /usr/share, which has no drive, goes on the
+// drive of the prefix, and C:share, relative to the current directory of drive C, is resolved
+// against it. UNC paths are complete as they are
+#[cfg(windows)]
+pub fn anchor(path: &Path, prefix: &Path) -> std::io::Result<PathBuf> {
+    use std::path::Component;
+    let mut components = path.components();
+    match components.next() {
+        Some(Component::RootDir) => {
+            let mut anchored = PathBuf::new();
+            if let Some(drive @ Component::Prefix(_)) = prefix.components().next() {
+                anchored.push(drive);
+            }
+            anchored.push(path);
+            Ok(anchored)
+        }
+        Some(Component::Prefix(_)) if !path.has_root() => std::path::absolute(path),
+        _ => Ok(path.to_owned()),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn anchor(path: &Path, _: &Path) -> std::io::Result<PathBuf> {
+    Ok(path.to_owned())
+}
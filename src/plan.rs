@@ -0,0 +1,303 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    ffi::OsString,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    config::{CommandStep, Config, Step},
+    configure::Configuration,
+};
+
+pub struct PlannedStep<'a> {
+    pub name: String,
+    pub step: Cow<'a, Step>,
+    pub deps: Vec<usize>,
+}
+
+pub struct Plan<'a> {
+    pub steps: Vec<PlannedStep<'a>>,
+}
+
+impl<'a> Plan<'a> {
+    pub fn builds_rust(&self) -> bool {
+        self.steps
+            .iter()
+            .any(|s| matches!(*s.step, Step::BuildCrate(_) | Step::BuildWorkspace(_)))
+    }
+}
+
+fn matches(name: &str, pattern: &str) -> bool {
+    name == pattern
+        || name
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('.'))
+}
+
+fn declares_inputs(step: &Step) -> bool {
+    match step {
+        Step::Command(step) => !step.inputs.is_empty(),
+        Step::Subdirectory(_)
+        | Step::GenerateDocs(_)
+        | Step::InstallSymlink(_)
+        | Step::Custom(_) => false,
+        _ => true,
+    }
+}
+
+fn overlaps(a: &Path, b: &Path) -> bool {
+    a.starts_with(b) || b.starts_with(a)
+}
+
+// Steps that declare their inputs depend on the earlier steps producing them; the rest keep
+// depending on whatever ran just before them.
+pub fn link(plan: &mut Plan, cfg: &Configuration) -> std::io::Result<()> {
+    let mut outputs: Vec<Vec<PathBuf>> = Vec::with_capacity(plan.steps.len());
+    for (i, planned) in plan.steps.iter_mut().enumerate() {
+        if declares_inputs(&planned.step) {
+            let inputs = crate::steps::step_inputs(&planned.step, cfg)?;
+            planned.deps = (0..i)
+                .filter(|&j| {
+                    outputs[j]
+                        .iter()
+                        .any(|o| inputs.iter().any(|p| overlaps(o, p)))
+                })
+                .collect();
+        }
+        outputs.push(
+            crate::steps::step_artifacts(&planned.step, cfg)?
+                .into_iter()
+                .map(|a| a.path)
+                .collect(),
+        );
+    }
+    Ok(())
+}
+
+pub fn select<'a>(
+    plan: Plan<'a>,
+    only: &[String],
+    skip: &[String],
+    until: Option<&str>,
+) -> std::io::Result<Plan<'a>> {
+    for pattern in only.iter().chain(skip).map(|s| &**s).chain(until) {
+        if !plan.steps.iter().any(|s| matches(&s.name, pattern)) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("No planned step is named {}", pattern),
+            ));
+        }
+    }
+
+    let mut keep = vec![only.is_empty(); plan.steps.len()];
+    let mut pending: Vec<usize> = (0..plan.steps.len())
+        .filter(|&i| only.iter().any(|p| matches(&plan.steps[i].name, p)))
+        .collect();
+    while let Some(i) = pending.pop() {
+        if !keep[i] {
+            keep[i] = true;
+            pending.extend(&plan.steps[i].deps);
+        }
+    }
+    if let Some(until) = until {
+        let last = plan
+            .steps
+            .iter()
+            .rposition(|s| matches(&s.name, until))
+            .unwrap();
+        for k in &mut keep[last + 1..] {
+            *k = false;
+        }
+    }
+    for (i, step) in plan.steps.iter().enumerate() {
+        if skip.iter().any(|p| matches(&step.name, p)) {
+            keep[i] = false;
+        }
+    }
+
+    let mut index = vec![None; plan.steps.len()];
+    let mut steps = Vec::new();
+    for (i, mut step) in plan.steps.into_iter().enumerate() {
+        if !keep[i] {
+            continue;
+        }
+        step.deps = step.deps.iter().filter_map(|&d| index[d]).collect();
+        index[i] = Some(steps.len());
+        steps.push(step);
+    }
+    Ok(Plan { steps })
+}
+
+fn with_rustflags(step: &Step, flag: String) -> Option<Step> {
+    let mut step = step.clone();
+    match &mut step {
+        Step::BuildCrate(s) => s.rustflags.push(flag),
+        Step::BuildWorkspace(s) => s.rustflags.push(flag),
+        _ => return None,
+    }
+    Some(step)
+}
+
+fn enabled(config: &Config, name: &str, cfg: &Configuration) -> bool {
+    match config.conditions.get(name) {
+        Some(condition) => crate::overrides::condition(condition, &cfg.vars).unwrap_or(false),
+        None => true,
+    }
+}
+
+fn configured<'a>(overrides: &HashMap<String, Step>, name: &str, step: &'a Step) -> Cow<'a, Step> {
+    match overrides.get(name) {
+        Some(step) => Cow::Owned(step.clone()),
+        None => Cow::Borrowed(step),
+    }
+}
+
+fn push<'a>(steps: &mut Vec<PlannedStep<'a>>, name: String, step: Cow<'a, Step>) {
+    let deps = steps.len().checked_sub(1).into_iter().collect();
+    steps.push(PlannedStep { name, step, deps });
+}
+
+pub fn shell_command(command: &str) -> (&'static str, [&str; 2]) {
+    if cfg!(windows) {
+        ("cmd", ["/C", command])
+    } else {
+        ("sh", ["-c", command])
+    }
+}
+
+pub fn hook_step(hook: &str) -> Step {
+    let (shell, args) = shell_command(hook);
+    Step::Command(CommandStep {
+        cmd: shell.into(),
+        args: args.iter().map(OsString::from).collect(),
+        inputs: Vec::new(),
+        sandbox: false,
+        network: false,
+    })
+}
+
+fn push_hooks(steps: &mut Vec<PlannedStep>, name: &str, hooks: &[String]) {
+    for (i, hook) in hooks.iter().enumerate() {
+        let name = format!("hook.{}.{}", name, i);
+        push(steps, name, Cow::Owned(hook_step(hook)));
+    }
+}
+
+// The instrumented builds get a target directory of their own, so that the optimized builds are
+// never replaced by them
+pub fn pgo_target_dir(cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join("pgo-target")
+}
+
+// Run before the first instrumented build, so that the merge sees only the profiles of this run
+pub fn clear_profiles(cfg: &Configuration) -> std::io::Result<()> {
+    let dir = cfg.build_dir.join("pgo");
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    std::fs::create_dir_all(&dir)
+}
+
+fn plan_pgo<'a>(
+    steps: &mut Vec<PlannedStep<'a>>,
+    config: &'a Config,
+    cfg: &Configuration,
+    overrides: &HashMap<String, Step>,
+    profdata: &std::path::Path,
+) {
+    let dir = cfg.build_dir.join("pgo");
+    let merged = cfg.build_dir.join("pgo.profdata");
+
+    for (i, step) in config.build.step.iter().enumerate() {
+        if !enabled(config, &format!("build.{}", i), cfg) {
+            continue;
+        }
+        let step = configured(overrides, &format!("build.{}", i), step);
+        let flag = format!("-Cprofile-generate={}", dir.display());
+        let step = match with_rustflags(&step, flag) {
+            Some(step) => Cow::Owned(step),
+            None => step,
+        };
+        push(steps, format!("pgo.instrument.{}", i), step);
+    }
+    for (i, step) in config.pgo.train.iter().enumerate() {
+        let name = format!("pgo.train.{}", i);
+        if !enabled(config, &name, cfg) {
+            continue;
+        }
+        let step = configured(overrides, &name, step);
+        push(steps, name, step);
+    }
+
+    let merge = Step::Command(CommandStep {
+        cmd: profdata.to_owned(),
+        args: vec![
+            OsString::from("merge"),
+            OsString::from("-o"),
+            merged.clone().into_os_string(),
+            dir.into_os_string(),
+        ],
+        inputs: Vec::new(),
+        sandbox: false,
+        network: false,
+    });
+    push(steps, "pgo.merge".to_string(), Cow::Owned(merge));
+
+    for (i, step) in config.build.step.iter().enumerate() {
+        let name = format!("build.{}", i);
+        if !enabled(config, &name, cfg) {
+            continue;
+        }
+        let step = configured(overrides, &name, step);
+        let flag = format!("-Cprofile-use={}", merged.display());
+        let step = match with_rustflags(&step, flag) {
+            Some(step) => Cow::Owned(step),
+            None => step,
+        };
+        push(steps, config.step_name(&name), step);
+    }
+}
+
+pub fn plan<'a>(
+    config: &'a Config,
+    phases: &[&str],
+    cfg: &Configuration,
+    overrides: &HashMap<String, Step>,
+) -> Plan<'a> {
+    let hooks = &config.hooks;
+    let no_hooks = Vec::new();
+    let mut steps = Vec::new();
+    for &phase in phases {
+        let (pre, post, phase_steps) = match phase {
+            "build" => (&hooks.pre_build, &hooks.post_build, &config.build.step),
+            "install" => (
+                &hooks.pre_install,
+                &hooks.post_install,
+                &config.install.step,
+            ),
+            "test" => (&no_hooks, &no_hooks, &config.test.step),
+            "bench" => (&no_hooks, &no_hooks, &config.bench.step),
+            _ => continue,
+        };
+        push_hooks(&mut steps, &format!("pre-{}", phase), pre);
+        match &cfg.llvm_profdata {
+            Some(profdata) if phase == "build" => {
+                plan_pgo(&mut steps, config, cfg, overrides, profdata)
+            }
+            _ => {
+                for (i, step) in phase_steps.iter().enumerate() {
+                    let name = format!("{}.{}", phase, i);
+                    if !enabled(config, &name, cfg) {
+                        continue;
+                    }
+                    let step = configured(overrides, &name, step);
+                    push(&mut steps, config.step_name(&name), step);
+                }
+            }
+        }
+        push_hooks(&mut steps, &format!("post-{}", phase), post);
+    }
+    Plan { steps }
+}
@@ -0,0 +1,133 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    process::{Command, Stdio},
+};
+
+use target_tuples::Target;
+
+// What `rustc --print cfg` says about a target, or nothing when the compiler cannot tell
+fn target_cfg(rustc: &Path, target: &Target) -> HashMap<String, String> {
+    let output = Command::new(rustc)
+        .arg("--print")
+        .arg("cfg")
+        .arg("--target")
+        .arg(target.get_name())
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => return HashMap::new(),
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .map(|(key, value)| (key.to_string(), value.trim_matches('"').to_string()))
+        .collect()
+}
+
+fn is_big_endian(arch: &str) -> bool {
+    matches!(
+        arch,
+        "powerpc" | "powerpc64" | "mips" | "mips64" | "sparc" | "sparc64" | "s390x" | "m68k"
+    ) || arch.ends_with("_be")
+        || arch.ends_with("eb")
+}
+
+fn pointer_width(arch: &str) -> &'static str {
+    if arch.contains("64") || arch == "s390x" {
+        "64"
+    } else if arch == "avr" || arch == "msp430" {
+        "16"
+    } else {
+        "32"
+    }
+}
+
+fn libc_of(env: &str) -> &str {
+    match env {
+        "gnu" => "glibc",
+        "" => "none",
+        env => env,
+    }
+}
+
+// OS, libc flavour, endianness and pointer width of a triple, as <prefix>os, <prefix>libc,
+// <prefix>endian and <prefix>pointer-width. The triple's own names stand in for what a compiler
+// that cannot print its cfg would have said
+pub fn target_vars(rustc: &Path, prefix: &str, target: &Target) -> HashMap<String, String> {
+    let cfg = target_cfg(rustc, target);
+    let arch = target.arch_name();
+    let os = match cfg.get("target_os") {
+        Some(os) => os.clone(),
+        None => target
+            .operating_system()
+            .map_or("none", |os| os.canonical_name())
+            .to_string(),
+    };
+    let env = match cfg.get("target_env") {
+        Some(env) => env.clone(),
+        None => target
+            .environment()
+            .map_or("", |env| env.canonical_name())
+            .to_string(),
+    };
+    let endian = match cfg.get("target_endian") {
+        Some(endian) => endian.clone(),
+        None if is_big_endian(arch) => "big".to_string(),
+        None => "little".to_string(),
+    };
+    let width = match cfg.get("target_pointer_width") {
+        Some(width) => width.clone(),
+        None => pointer_width(arch).to_string(),
+    };
+    let mut vars = HashMap::new();
+    vars.insert(format!("{}os", prefix), os);
+    vars.insert(format!("{}libc", prefix), libc_of(&env).to_string());
+    vars.insert(format!("{}endian", prefix), endian);
+    vars.insert(format!("{}pointer-width", prefix), width);
+    vars
+}
+
+fn output_of(program: &str, args: &[&str]) -> Option<(bool, String)> {
+    let output = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+    text.push_str(&String::from_utf8_lossy(&output.stderr));
+    Some((output.status.success(), text))
+}
+
+// The release of the running kernel, as `uname -r` prints it
+pub fn kernel_version() -> Option<String> {
+    if let Ok(release) = std::fs::read_to_string("/proc/sys/kernel/osrelease") {
+        return Some(release.trim().to_string());
+    }
+    match output_of("uname", &["-r"])? {
+        (true, release) if !release.trim().is_empty() => Some(release.trim().to_string()),
+        _ => None,
+    }
+}
+
+// The libc of the build machine, as (flavour, version). glibc answers getconf; musl has no
+// getconf of its own, but its ldd announces it, and exits with an error, when given --version
+pub fn libc() -> Option<(String, String)> {
+    if let Some((true, text)) = output_of("getconf", &["GNU_LIBC_VERSION"]) {
+        if let Some(version) = text.trim().strip_prefix("glibc ") {
+            return Some(("glibc".to_string(), version.to_string()));
+        }
+    }
+    let (_, text) = output_of("ldd", &["--version"])?;
+    if text.starts_with("musl") {
+        let version = text
+            .lines()
+            .find_map(|line| line.strip_prefix("Version "))
+            .unwrap_or_default();
+        Some(("musl".to_string(), version.trim().to_string()))
+    } else {
+        None
+    }
+}
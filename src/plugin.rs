@@ -0,0 +1,173 @@
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    io::{ErrorKind, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{config::PluginStep, configure::Configuration, steps::Artifact};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Request<'a> {
+    pub protocol: u32,
+    pub action: &'a str,
+    pub options: &'a toml::value::Table,
+    pub vars: &'a HashMap<String, String>,
+    pub src_dir: &'a Path,
+    pub build_dir: &'a Path,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Output {
+    pub key: String,
+    #[serde(default = "file_kind")]
+    pub kind: String,
+    pub path: PathBuf,
+}
+
+fn file_kind() -> String {
+    "file".to_string()
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Diagnostic {
+    pub level: String,
+    pub message: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(rename_all = "kebab-case")]
+pub struct Response {
+    #[serde(default)]
+    pub inputs: Vec<PathBuf>,
+    #[serde(default)]
+    pub outputs: Vec<Output>,
+    #[serde(default)]
+    pub vars: HashMap<String, String>,
+    #[serde(default)]
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+fn program_path(program: &Path, src_dir: &Path) -> PathBuf {
+    if program.components().count() > 1 {
+        src_dir.join(program)
+    } else {
+        program.to_owned()
+    }
+}
+
+pub fn call(program: &Path, args: &[OsString], request: &Request) -> std::io::Result<Response> {
+    let program = program_path(program, request.src_dir);
+    let mut child = Command::new(&program)
+        .args(args)
+        .current_dir(request.src_dir)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            std::io::Error::new(
+                e.kind(),
+                format!("Cannot start plugin {}: {}", program.display(), e),
+            )
+        })?;
+    let body =
+        serde_json::to_vec(request).map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+    child.stdin.take().unwrap().write_all(&body)?;
+    let output = child.wait_with_output()?;
+
+    let response: Response = if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        Response::default()
+    } else {
+        serde_json::from_slice(&output.stdout).map_err(|e| {
+            std::io::Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Plugin {} sent a malformed {} response: {}",
+                    program.display(),
+                    request.action,
+                    e
+                ),
+            )
+        })?
+    };
+
+    let name = program.file_name().unwrap_or_default().to_string_lossy();
+    let mut errors = 0;
+    for diag in &response.diagnostics {
+        if diag.level == "error" {
+            errors += 1;
+        }
+        eprintln!("{}: {}: {}", name, diag.level, diag.message);
+    }
+    if !output.status.success() || errors > 0 {
+        return Err(std::io::Error::other(format!(
+            "Plugin {} failed to {} ({})",
+            program.display(),
+            request.action,
+            output.status
+        )));
+    }
+    Ok(response)
+}
+
+fn call_step(step: &PluginStep, action: &str, cfg: &Configuration) -> std::io::Result<Response> {
+    call(
+        &step.program,
+        &step.args,
+        &Request {
+            protocol: PROTOCOL_VERSION,
+            action,
+            options: &step.options,
+            vars: &cfg.vars,
+            src_dir: &cfg.src_dir,
+            build_dir: &cfg.build_dir,
+        },
+    )
+}
+
+pub fn describe(step: &PluginStep) -> String {
+    format!("run plugin {}", step.program.display())
+}
+
+pub fn inputs(step: &PluginStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let mut inputs: Vec<PathBuf> = step.inputs.iter().map(|p| cfg.src_dir.join(p)).collect();
+    inputs.push(program_path(&step.program, &cfg.src_dir));
+    inputs.extend(
+        call_step(step, "inputs", cfg)?
+            .inputs
+            .into_iter()
+            .map(|p| cfg.src_dir.join(p)),
+    );
+    Ok(inputs)
+}
+
+pub fn outputs(step: &PluginStep, cfg: &Configuration) -> std::io::Result<Vec<Artifact>> {
+    Ok(call_step(step, "outputs", cfg)?
+        .outputs
+        .into_iter()
+        .map(|o| Artifact {
+            key: o.key,
+            kind: o.kind,
+            path: cfg.src_dir.join(o.path),
+        })
+        .collect())
+}
+
+pub fn variables(
+    step: &PluginStep,
+    cfg: &Configuration,
+) -> std::io::Result<HashMap<String, String>> {
+    Ok(call_step(step, "variables", cfg)?.vars)
+}
+
+pub fn execute(step: &PluginStep, cfg: &Configuration) -> std::io::Result<()> {
+    call_step(step, "execute", cfg).map(|_| ())
+}
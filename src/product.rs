@@ -0,0 +1,92 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::config::{
+    Backup, BuildCrateStep, CLibraryProduct, CbindgenStep, Config, InstallDirectory, InstallStep,
+    InstallTarget, PkgConfigStep, Product, Step,
+};
+
+fn install(from_step: String, kinds: &[&str], base: InstallDirectory, path: &str) -> Step {
+    Step::Install(InstallStep {
+        file: PathBuf::new(),
+        from_step: Some(from_step),
+        kinds: Some(kinds.iter().map(|k| k.to_string()).collect()),
+        target: InstallTarget::Base {
+            base,
+            path: PathBuf::from(path),
+        },
+        mode: None,
+        exclude: Vec::new(),
+        flatten: false,
+        rename: None,
+        caps: None,
+        setuid: false,
+        setgid: false,
+        backup: Backup::None,
+    })
+}
+
+// Versioned cdylibs install along with their soname links, since the build step lists each name
+// as an artifact
+fn c_library(lib: &CLibraryProduct, config: &mut Config) {
+    let header = lib
+        .header
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(format!("{}.h", lib.name)));
+    let build = config.build.step.len();
+    config.build.step.push(Step::BuildCrate(BuildCrateStep {
+        path: lib.path.clone(),
+        features: lib.features.clone(),
+        target: lib.target.clone(),
+        rustflags: Vec::new(),
+        rustdocflags: Vec::new(),
+        env: HashMap::new(),
+        install_dirs: Vec::new(),
+        no_default_features: false,
+        crate_types: Vec::new(),
+        bins: Vec::new(),
+        examples: Vec::new(),
+        benches: Vec::new(),
+        universal: false,
+        resources: None,
+        lib_version: lib.lib_version.clone(),
+        soversion: lib.soversion.clone(),
+    }));
+    config.build.step.push(Step::Cbindgen(CbindgenStep {
+        path: lib.path.clone(),
+        output: PathBuf::from("include").join(&header),
+        config: lib.cbindgen_config.clone(),
+        lang: lib.lang.clone(),
+    }));
+    config.build.step.push(Step::PkgConfig(PkgConfigStep {
+        name: lib.name.clone(),
+        path: lib.path.clone(),
+        description: lib.description.clone(),
+        version: None,
+        requires: lib.requires.clone(),
+        requires_private: lib.requires_private.clone(),
+        libs: vec![format!("-l{}", lib.name)],
+        libs_private: lib.libs_private.clone(),
+        cflags: Vec::new(),
+    }));
+
+    let step = |i: usize| format!("build.{}", build + i);
+    config.install.step.extend(vec![
+        install(
+            step(0),
+            &["cdylib", "staticlib"],
+            InstallDirectory::LibDir,
+            "",
+        ),
+        install(step(1), &["file"], InstallDirectory::IncludeDir, ""),
+        install(step(2), &["file"], InstallDirectory::LibDir, "pkgconfig/"),
+    ]);
+}
+
+// Products become ordinary steps, so planning, state and the other phases need not know of them
+pub fn expand(config: &mut Config) {
+    for product in config.product.clone() {
+        match &product {
+            Product::CLibrary(lib) => c_library(lib, config),
+        }
+    }
+}
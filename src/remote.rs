@@ -0,0 +1,265 @@
+use std::{
+    cell::Cell,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    cli::Options,
+    config::Config,
+    configure::Configuration,
+    plan::{Plan, PlannedStep},
+};
+
+// Where the runner sends the steps it does not skip
+pub trait Backend {
+    fn execute(&self, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()>;
+}
+
+pub struct Local;
+
+impl Backend for Local {
+    fn execute(&self, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()> {
+        crate::steps::run_step(&step.step, cfg)
+    }
+}
+
+// Ships the inputs a step declares to a worker, runs the step there with `run-step` and copies its
+// artifacts back. Steps go to the workers in turn
+pub struct Ssh {
+    workers: Vec<String>,
+    dir: String,
+    program: String,
+    config: PathBuf,
+    // The configuration and its includes
+    config_files: Vec<PathBuf>,
+    args: Vec<String>,
+    ssh: PathBuf,
+    rsync: PathBuf,
+    next: Cell<usize>,
+}
+
+// Without workers, or without ssh and rsync, every step runs here
+pub fn backend(config: &Config, opts: &Options, cfg: &Configuration) -> Option<Box<dyn Backend>> {
+    let workers = opts.workers.as_ref().unwrap_or(&config.remote.workers);
+    if workers.is_empty() || !config.policies.values().any(|p| p.distribute) {
+        return None;
+    }
+    let (ssh, rsync) = match (&cfg.ssh, &cfg.rsync) {
+        (Some(ssh), Some(rsync)) => (ssh.clone(), rsync.clone()),
+        _ => {
+            eprintln!(
+                "cargo-autobuild: warning: ssh or rsync was not found; running distributed steps here"
+            );
+            return None;
+        }
+    };
+    let project = cfg
+        .src_dir
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    Some(Box::new(Ssh {
+        workers: workers.clone(),
+        dir: format!("{}/{}", config.remote.dir, project),
+        program: config.remote.program.clone(),
+        config: PathBuf::from(opts.config.file_name().unwrap_or_default()),
+        config_files: config.files.clone(),
+        args: forwarded(opts),
+        ssh,
+        rsync,
+        next: Cell::new(0),
+    }))
+}
+
+// The options that change what the worker, or the container, builds
+pub fn forwarded(opts: &Options) -> Vec<String> {
+    let mut args = Vec::new();
+    for (name, value) in [
+        ("profile", &opts.profile),
+        ("build", &opts.build),
+        ("host", &opts.host),
+        ("target", &opts.target),
+        ("with-cpu", &opts.target_cpu),
+    ] {
+        if let Some(value) = value {
+            args.push(format!("--{}={}", name, value));
+        }
+    }
+    for (name, list) in [
+        ("sanitizer", &opts.sanitizers),
+        ("with-target-features", &opts.target_features),
+    ] {
+        if let Some(list) = list {
+            args.push(format!("--{}={}", name, list.join(",")));
+        }
+    }
+    for (name, flags) in [
+        ("rustflags", &opts.rustflags),
+        ("host-rustflags", &opts.host_rustflags),
+        ("build-script-rustflags", &opts.build_script_rustflags),
+        ("rustdocflags", &opts.rustdocflags),
+    ] {
+        if !flags.is_empty() {
+            args.push(format!("--{}={}", name, flags.join(" ")));
+        }
+    }
+    for (name, dir) in &opts.dirs {
+        args.push(format!("--{}={}", name, dir.display()));
+    }
+    for (feature, on) in &opts.features {
+        let prefix = if *on { "enable" } else { "disable" };
+        args.push(format!("--{}-{}", prefix, feature));
+    }
+    for (package, value) in &opts.packages {
+        args.push(format!("--with-{}={}", package, value));
+    }
+    for (flag, set) in [
+        ("offline", opts.offline),
+        ("static", opts.static_link),
+        ("hardened", opts.hardened),
+        ("no-color", opts.no_color),
+    ] {
+        if set {
+            args.push(format!("--{}", flag));
+        }
+    }
+    args
+}
+
+// For the shell that ssh starts on the worker
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+// The worker keeps the layout of the source tree, so only paths inside it can be shipped
+fn relative(path: &Path, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    path.strip_prefix(&cfg.src_dir)
+        .map(Path::to_owned)
+        .map_err(|_| {
+            std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} is outside the source directory, so it cannot be shipped to a worker",
+                    path.display()
+                ),
+            )
+        })
+}
+
+impl Ssh {
+    fn rsync(&self) -> Command {
+        let mut cmd = Command::new(&self.rsync);
+        cmd.arg("-a")
+            .arg("--relative")
+            .arg("--ignore-missing-args")
+            .arg("-e")
+            .arg(&self.ssh);
+        cmd
+    }
+
+    fn ssh(&self, worker: &str, command: String) -> std::io::Result<()> {
+        crate::steps::run(Command::new(&self.ssh).arg(worker).arg(command))
+    }
+
+    // Source directories are sent without the build and target directories in them; what the
+    // step reads from the build directory, such as generated code, is sent on its own
+    fn ship(&self, worker: &str, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()> {
+        let (generated, sources): (Vec<PathBuf>, Vec<PathBuf>) =
+            crate::steps::step_inputs(&step.step, cfg)?
+                .into_iter()
+                .partition(|input| input.starts_with(&cfg.build_dir));
+        let dest = format!("{}:{}/", worker, self.dir);
+
+        let mut cmd = self.rsync();
+        cmd.current_dir(&cfg.src_dir).arg("--exclude=/target/");
+        for dir in std::iter::once(&cfg.build_dir).chain(&cfg.target_dir) {
+            if let Ok(dir) = relative(dir, cfg) {
+                cmd.arg(format!("--exclude=/{}/", dir.display()));
+            }
+        }
+        for file in &self.config_files {
+            cmd.arg(relative(file, cfg)?);
+        }
+        for input in &sources {
+            let input = relative(input, cfg)?;
+            // The lock file is not an input, but keeps the worker on the same dependencies
+            if input.ends_with("Cargo.toml") {
+                cmd.arg(input.with_file_name("Cargo.lock"));
+            }
+            cmd.arg(input);
+        }
+        crate::steps::run(cmd.arg(&dest))?;
+
+        if !generated.is_empty() {
+            let mut cmd = self.rsync();
+            cmd.current_dir(&cfg.src_dir);
+            for input in &generated {
+                cmd.arg(relative(input, cfg)?);
+            }
+            crate::steps::run(cmd.arg(&dest))?;
+        }
+        Ok(())
+    }
+
+    fn fetch(&self, worker: &str, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()> {
+        let artifacts = crate::steps::step_artifacts(&step.step, cfg)?;
+        if artifacts.is_empty() {
+            return Ok(());
+        }
+        let mut cmd = self.rsync();
+        cmd.current_dir(&cfg.src_dir);
+        for artifact in &artifacts {
+            let path = relative(&artifact.path, cfg)?;
+            cmd.arg(format!("{}:{}/./{}", worker, self.dir, path.display()));
+        }
+        crate::steps::run(cmd.arg("."))
+    }
+}
+
+impl Backend for Ssh {
+    fn execute(&self, step: &PlannedStep, cfg: &Configuration) -> std::io::Result<()> {
+        let next = self.next.get();
+        self.next.set(next + 1);
+        let worker = &self.workers[next % self.workers.len()];
+        println!("{}: running on {}", step.name, worker);
+
+        self.ssh(worker, format!("mkdir -p {}", quote(&self.dir)))?;
+        self.ship(worker, step, cfg)?;
+        let mut command = format!(
+            "cd {} && {} run-step {} --config={}",
+            quote(&self.dir),
+            quote(&self.program),
+            quote(&step.name),
+            quote(&self.config.to_string_lossy())
+        );
+        for arg in &self.args {
+            command.push(' ');
+            command.push_str(&quote(arg));
+        }
+        self.ssh(worker, command)?;
+        self.fetch(worker, step, cfg)
+    }
+}
+
+// The worker's side of the protocol: runs the named step of the plan, whatever the state of the
+// last build
+pub fn run_step(plan: &Plan, cfg: &Configuration, args: &[String]) -> std::io::Result<()> {
+    let name = match args {
+        [name] => name,
+        _ => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "run-step takes the name of one step",
+            ))
+        }
+    };
+    let step = plan.steps.iter().find(|s| s.name == *name).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("No planned step is named {}", name),
+        )
+    })?;
+    crate::steps::run_step(&step.step, cfg)
+}
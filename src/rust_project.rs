@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Value};
+
+use crate::{
+    cargo::{Metadata, Package},
+    config::{BuildTarget, Step},
+    configure::Configuration,
+    plan::Plan,
+};
+
+const LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib", "proc-macro"];
+
+#[derive(Default)]
+struct Crates {
+    list: Vec<Value>,
+    // The library crate of each package, by package id
+    libs: HashMap<String, usize>,
+    bins: HashMap<(String, String), usize>,
+}
+
+fn crate_entry(
+    package: &Package,
+    name: &str,
+    root: &Path,
+    proc_macro: bool,
+    member: bool,
+    cfgs: &[String],
+    triple: &str,
+) -> Value {
+    let dir = package
+        .manifest_path
+        .parent()
+        .unwrap_or_else(|| Path::new("."));
+    json!({
+        "display_name": name,
+        "root_module": root.display().to_string(),
+        "edition": package.edition,
+        "deps": [],
+        "cfg": cfgs,
+        "target": triple,
+        "is_workspace_member": member,
+        "is_proc_macro": proc_macro,
+        "env": {
+            "CARGO_PKG_NAME": package.name,
+            "CARGO_PKG_VERSION": package.version,
+            "CARGO_MANIFEST_DIR": dir.display().to_string(),
+            "CARGO_CRATE_NAME": name.replace('-', "_"),
+        },
+    })
+}
+
+// Adds a crate for the library of every package and for the binaries of the workspace members,
+// then links each one to the libraries of its normal dependencies
+fn add_manifest(crates: &mut Crates, metadata: &Metadata, features: &[String], triple: &str) {
+    let nodes: HashMap<&str, _> = metadata
+        .resolve
+        .iter()
+        .flat_map(|r| &r.nodes)
+        .map(|n| (&*n.id, n))
+        .collect();
+    let mut added = Vec::new();
+    for package in &metadata.packages {
+        let member = metadata.workspace_members.contains(&package.id);
+        let mut cfgs: Vec<String> = nodes
+            .get(&*package.id)
+            .map(|n| n.features.clone())
+            .unwrap_or_default();
+        if member {
+            cfgs.extend(features.iter().filter(|f| !f.contains('/')).cloned());
+        }
+        cfgs.sort();
+        cfgs.dedup();
+        let cfgs: Vec<String> = cfgs.iter().map(|f| format!("feature=\"{}\"", f)).collect();
+        for target in &package.targets {
+            let is_lib = target.kind.iter().any(|k| LIB_KINDS.contains(&&**k));
+            let is_bin = member && target.kind.iter().any(|k| k == "bin");
+            if is_lib && !crates.libs.contains_key(&package.id) {
+                crates.libs.insert(package.id.clone(), crates.list.len());
+            } else if is_bin {
+                let key = (package.id.clone(), target.name.clone());
+                if crates.bins.contains_key(&key) {
+                    continue;
+                }
+                crates.bins.insert(key, crates.list.len());
+            } else {
+                continue;
+            }
+            added.push((crates.list.len(), &package.id, is_bin));
+            crates.list.push(crate_entry(
+                package,
+                &target.name,
+                &target.src_path,
+                target.kind.iter().any(|k| k == "proc-macro"),
+                member,
+                &cfgs,
+                triple,
+            ));
+        }
+    }
+
+    for (index, id, is_bin) in added {
+        let mut deps = Vec::new();
+        if is_bin {
+            if let Some(&lib) = crates.libs.get(id) {
+                let name = crates.list[lib]["display_name"]
+                    .as_str()
+                    .unwrap_or_default()
+                    .replace('-', "_");
+                deps.push(json!({ "crate": lib, "name": name }));
+            }
+        }
+        for dep in nodes.get(&**id).iter().flat_map(|n| &n.deps) {
+            if !dep.dep_kinds.iter().any(|k| k.kind.is_none()) {
+                continue;
+            }
+            if let Some(&lib) = crates.libs.get(&dep.pkg) {
+                deps.push(json!({ "crate": lib, "name": dep.name }));
+            }
+        }
+        crates.list[index]["deps"] = json!(deps);
+    }
+}
+
+fn sysroot(cfg: &Configuration) -> Option<PathBuf> {
+    let output = Command::new(&cfg.rustc.rustc)
+        .arg("--print")
+        .arg("sysroot")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())?;
+    let sysroot = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    Some(sysroot).filter(|s| s.is_dir())
+}
+
+fn project(plan: &Plan, cfg: &Configuration) -> std::io::Result<Value> {
+    let mut builds: Vec<(PathBuf, Vec<String>, &BuildTarget)> = plan
+        .steps
+        .iter()
+        .filter_map(|s| match &*s.step {
+            Step::BuildCrate(s) => Some((&s.path, &s.features[..], &s.target)),
+            Step::BuildWorkspace(s) => Some((&s.path, &s.features[..], &s.target)),
+            _ => None,
+        })
+        .map(|(path, features, target)| {
+            (
+                crate::steps::manifest_at(path, cfg),
+                crate::steps::resolve_features(features, cfg),
+                target,
+            )
+        })
+        .collect();
+    if builds.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            "The build phase builds no Rust crates",
+        ));
+    }
+    builds.sort_by(|a, b| a.0.cmp(&b.0));
+    builds.dedup_by(|a, b| a.0 == b.0);
+
+    let mut crates = Crates::default();
+    for (manifest, features, target) in builds {
+        let triple = cfg.resolve_target(target).get_name();
+        let metadata = crate::cargo::dependencies(&cfg.cargo, &manifest, triple)?;
+        add_manifest(&mut crates, &metadata, &features, triple);
+    }
+
+    let mut project = json!({ "crates": crates.list });
+    if let Some(sysroot) = sysroot(cfg) {
+        let src = sysroot.join("lib/rustlib/src/rust/library");
+        if src.is_dir() {
+            project["sysroot_src"] = json!(src.display().to_string());
+        }
+        project["sysroot"] = json!(sysroot.display().to_string());
+    }
+    Ok(project)
+}
+
+// rust-analyzer only understands cargo projects built by rustc; other compilers get this file
+// instead, which it reads from the project root
+pub fn write(plan: &Plan, cfg: &Configuration) -> std::io::Result<()> {
+    let project = project(plan, cfg)?;
+    let path = cfg.src_dir.join("rust-project.json");
+    let text = serde_json::to_string_pretty(&project).map_err(std::io::Error::other)?;
+    std::fs::write(&path, text + "\n")?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
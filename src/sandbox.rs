@@ -0,0 +1,71 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::configure::Configuration;
+
+// bubblewrap can make the whole filesystem read-only; unshare alone only cuts off the network
+pub fn find_tool(log: &mut Vec<String>) -> Option<PathBuf> {
+    if !cfg!(target_os = "linux") {
+        log.push("sandboxing is only available on Linux".to_string());
+        return None;
+    }
+    log.push("searching PATH for bwrap, unshare".to_string());
+    crate::tests::which_any(&["bwrap", "unshare"]).ok()
+}
+
+fn is_bwrap(tool: &Path) -> bool {
+    tool.file_name().is_some_and(|n| n == "bwrap")
+}
+
+// Runs `program` with the source tree read-only and only the build directory (and DESTDIR, for
+// install steps) writable; falls back to running it directly without a sandbox tool
+pub fn command(program: OsString, network: bool, cfg: &Configuration) -> Command {
+    let tool = match &cfg.sandbox {
+        Some(tool) => tool,
+        None => {
+            eprintln!(
+                "cargo-autobuild: warning: no bwrap or unshare was found; running {} without a sandbox",
+                Path::new(&program).display()
+            );
+            return Command::new(program);
+        }
+    };
+    let mut cmd = Command::new(tool);
+    if is_bwrap(tool) {
+        cmd.arg("--ro-bind")
+            .arg("/")
+            .arg("/")
+            .arg("--dev")
+            .arg("/dev")
+            .arg("--proc")
+            .arg("/proc")
+            .arg("--tmpfs")
+            .arg("/tmp")
+            .arg("--bind")
+            .arg(&cfg.build_dir)
+            .arg(&cfg.build_dir);
+        if let Some(destdir) = std::env::var_os("DESTDIR").filter(|d| !d.is_empty()) {
+            std::fs::create_dir_all(&destdir).ok();
+            cmd.arg("--bind").arg(&destdir).arg(&destdir);
+        }
+        if !network {
+            cmd.arg("--unshare-net");
+        }
+        cmd.arg("--die-with-parent").arg("--");
+    } else {
+        eprintln!(
+            "cargo-autobuild: warning: bwrap was not found; {} can still write outside the build directory",
+            Path::new(&program).display()
+        );
+        cmd.arg("--map-root-user");
+        if !network {
+            cmd.arg("--net");
+        }
+        cmd.arg("--");
+    }
+    cmd.arg(program);
+    cmd
+}
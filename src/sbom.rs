@@ -0,0 +1,371 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::ErrorKind,
+    path::PathBuf,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+
+use crate::{cargo::Package, config::Step, configure::Configuration, plan::Plan};
+
+struct Component {
+    id: String,
+    name: String,
+    version: String,
+    license: String,
+    deps: BTreeSet<String>,
+}
+
+struct Tool {
+    name: String,
+    version: String,
+}
+
+// Honors SOURCE_DATE_EPOCH so that reproducible builds get reproducible documents
+fn timestamp() -> String {
+    let secs = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs())
+        });
+    format_time(secs)
+}
+
+// An RFC 3339 UTC time for seconds since the epoch
+pub fn format_time(secs: u64) -> String {
+    // Converts days since the epoch to a civil date, after Howard Hinnant's algorithm
+    let days = (secs / 86400) as i64 + 719_468;
+    let era = days.div_euclid(146_097);
+    let doe = days.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs % 86400 / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
+
+pub fn version_of(program: &std::path::Path) -> String {
+    Command::new(program)
+        .arg("--version")
+        .output()
+        .ok()
+        .filter(|o| o.status.success())
+        .and_then(|o| {
+            String::from_utf8_lossy(&o.stdout)
+                .lines()
+                .next()
+                .map(str::to_string)
+        })
+        .unwrap_or_default()
+}
+
+fn pkg_config_version(name: &str) -> Option<String> {
+    let pkg_config = crate::tests::which_any(&["pkg-config", "pkgconf"]).ok()?;
+    let output = Command::new(pkg_config)
+        .arg("--modversion")
+        .arg(name)
+        .output()
+        .ok()?;
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !version.is_empty() {
+        Some(version)
+    } else {
+        None
+    }
+}
+
+fn tools(cfg: &Configuration) -> Vec<Tool> {
+    let rustc = &cfg.rustc.version;
+    let mut tools = vec![
+        Tool {
+            name: "cargo-autobuild".to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        },
+        Tool {
+            name: rustc.prgname.clone(),
+            version: format!("{}.{}.{}", rustc.major, rustc.minor, rustc.patch),
+        },
+        Tool {
+            name: "cargo".to_string(),
+            version: version_of(&cfg.cargo),
+        },
+    ];
+    let mut programs: Vec<(&String, &PathBuf)> = cfg.programs.iter().collect();
+    programs.sort();
+    tools.extend(
+        cfg.linker
+            .iter()
+            .chain(programs.into_iter().map(|(_, p)| p))
+            .map(|p| Tool {
+                name: p.display().to_string(),
+                version: version_of(p),
+            }),
+    );
+    tools
+}
+
+// The workspace crates the plan builds and everything they ship, keyed by package id, plus the
+// native libraries that -sys crates declare with `links`
+fn components(
+    plan: &Plan,
+    cfg: &Configuration,
+) -> std::io::Result<(Vec<String>, BTreeMap<String, Component>)> {
+    let mut manifests: Vec<PathBuf> = plan
+        .steps
+        .iter()
+        .filter_map(|s| match &*s.step {
+            Step::BuildCrate(s) => Some(&s.path),
+            Step::BuildWorkspace(s) => Some(&s.path),
+            _ => None,
+        })
+        .map(|path| cfg.src_dir.join(path).join("Cargo.toml"))
+        .collect();
+    if manifests.is_empty() {
+        manifests.push(cfg.src_dir.join("Cargo.toml"));
+    }
+    manifests.sort();
+    manifests.dedup();
+
+    let mut roots = Vec::new();
+    let mut components = BTreeMap::new();
+    for manifest in &manifests {
+        let metadata = crate::cargo::dependencies(&cfg.cargo, manifest, cfg.host.get_name())?;
+        let shipped = crate::licenses::shipped(&metadata);
+        let members = metadata
+            .packages
+            .iter()
+            .filter(|p| metadata.workspace_members.contains(&p.id));
+        let packages: Vec<&Package> = members.chain(shipped.iter().copied()).collect();
+        roots.extend(
+            metadata
+                .workspace_members
+                .iter()
+                .filter(|id| !roots.contains(*id))
+                .cloned()
+                .collect::<Vec<_>>(),
+        );
+        let ids: BTreeSet<&str> = packages.iter().map(|p| &*p.id).collect();
+        for package in packages {
+            let mut deps: BTreeSet<String> = metadata
+                .resolve
+                .iter()
+                .flat_map(|r| &r.nodes)
+                .filter(|n| n.id == package.id)
+                .flat_map(|n| &n.deps)
+                .filter(|d| ids.contains(&*d.pkg))
+                .map(|d| d.pkg.clone())
+                .collect();
+            if let Some(links) = &package.links {
+                let id = format!("native:{}", links);
+                deps.insert(id.clone());
+                components.entry(id.clone()).or_insert_with(|| Component {
+                    id,
+                    name: links.clone(),
+                    version: pkg_config_version(links).unwrap_or_default(),
+                    license: "NOASSERTION".to_string(),
+                    deps: BTreeSet::new(),
+                });
+            }
+            components.insert(
+                package.id.clone(),
+                Component {
+                    id: package.id.clone(),
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    license: crate::licenses::expression(package),
+                    deps,
+                },
+            );
+        }
+    }
+    Ok((roots, components))
+}
+
+// The native libraries that -sys crates of the plan declare with `links`
+pub fn native_libraries(plan: &Plan, cfg: &Configuration) -> std::io::Result<Vec<String>> {
+    let (_, components) = components(plan, cfg)?;
+    Ok(components
+        .values()
+        .filter(|c| c.id.starts_with("native:"))
+        .map(|c| c.name.clone())
+        .collect())
+}
+
+fn purl(component: &Component) -> Option<String> {
+    if component.id.starts_with("native:") {
+        None
+    } else {
+        Some(format!(
+            "pkg:cargo/{}@{}",
+            component.name, component.version
+        ))
+    }
+}
+
+fn cyclonedx(roots: &[String], components: &BTreeMap<String, Component>, tools: &[Tool]) -> Value {
+    let component = |c: &Component| {
+        let mut value = json!({
+            "type": "library",
+            "bom-ref": c.id,
+            "name": c.name,
+            "version": c.version,
+        });
+        if let Some(purl) = purl(c) {
+            value["purl"] = json!(purl);
+        }
+        if c.license != "NOASSERTION" {
+            value["licenses"] = json!([{ "expression": c.license }]);
+        }
+        value
+    };
+    let mut metadata = json!({
+        "timestamp": timestamp(),
+        "tools": {
+            "components": tools
+                .iter()
+                .map(|t| json!({ "type": "application", "name": t.name, "version": t.version }))
+                .collect::<Vec<_>>(),
+        },
+    });
+    if let Some(root) = roots.first().and_then(|r| components.get(r)) {
+        let mut root = component(root);
+        root["type"] = json!("application");
+        metadata["component"] = root;
+    }
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "version": 1,
+        "metadata": metadata,
+        "components": components
+            .values()
+            .filter(|c| roots.first() != Some(&c.id))
+            .map(component)
+            .collect::<Vec<_>>(),
+        "dependencies": components
+            .values()
+            .map(|c| json!({ "ref": c.id, "dependsOn": c.deps }))
+            .collect::<Vec<_>>(),
+    })
+}
+
+fn spdx(roots: &[String], components: &BTreeMap<String, Component>, tools: &[Tool]) -> Value {
+    // SPDX identifiers only allow letters, digits, dots and dashes
+    let spdx_id = |id: &str| {
+        let id: String = id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                    c
+                } else {
+                    '-'
+                }
+            })
+            .collect();
+        format!("SPDXRef-{}", id)
+    };
+    let name = roots
+        .first()
+        .and_then(|r| components.get(r))
+        .map_or("unknown", |c| &c.name);
+    let mut relationships: Vec<Value> = roots
+        .iter()
+        .map(|r| {
+            json!({
+                "spdxElementId": "SPDXRef-DOCUMENT",
+                "relationshipType": "DESCRIBES",
+                "relatedSpdxElement": spdx_id(r),
+            })
+        })
+        .collect();
+    for component in components.values() {
+        relationships.extend(component.deps.iter().map(|d| {
+            json!({
+                "spdxElementId": spdx_id(&component.id),
+                "relationshipType": "DEPENDS_ON",
+                "relatedSpdxElement": spdx_id(d),
+            })
+        }));
+    }
+    let mut creators = vec!["Tool: cargo-autobuild".to_string()];
+    creators.extend(
+        tools
+            .iter()
+            .skip(1)
+            .map(|t| format!("Tool: {}-{}", t.name, t.version)),
+    );
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": name,
+        "documentNamespace": format!(
+            "https://spdx.org/spdxdocs/{}-{}",
+            name,
+            timestamp().replace(':', "")
+        ),
+        "creationInfo": {
+            "created": timestamp(),
+            "creators": creators,
+        },
+        "packages": components
+            .values()
+            .map(|c| {
+                let mut value = json!({
+                    "SPDXID": spdx_id(&c.id),
+                    "name": c.name,
+                    "versionInfo": c.version,
+                    "downloadLocation": "NOASSERTION",
+                    "licenseDeclared": c.license,
+                    "licenseConcluded": "NOASSERTION",
+                    "copyrightText": "NOASSERTION",
+                });
+                if let Some(purl) = purl(c) {
+                    value["externalRefs"] = json!([{
+                        "referenceCategory": "PACKAGE-MANAGER",
+                        "referenceType": "purl",
+                        "referenceLocator": purl,
+                    }]);
+                }
+                value
+            })
+            .collect::<Vec<_>>(),
+        "relationships": relationships,
+    })
+}
+
+pub fn write(plan: &Plan, cfg: &Configuration, format: &str) -> std::io::Result<()> {
+    let (roots, components) = components(plan, cfg)?;
+    let tools = tools(cfg);
+    // --format defaults to dot for `graph`, which means the default document format here
+    let (document, file) = match format {
+        "cyclonedx" | "dot" => (cyclonedx(&roots, &components, &tools), "sbom.cdx.json"),
+        "spdx" => (spdx(&roots, &components, &tools), "sbom.spdx.json"),
+        format => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown SBOM format {}; use cyclonedx or spdx", format),
+            ))
+        }
+    };
+    let path = cfg.build_dir.join(file);
+    let json = serde_json::to_vec_pretty(&document).map_err(std::io::Error::other)?;
+    std::fs::write(&path, json)?;
+    println!("Wrote {}", path.display());
+    Ok(())
+}
@@ -0,0 +1,656 @@
+use serde_json::{json, Map, Value};
+
+fn strings() -> Value {
+    json!({ "type": "array", "items": { "type": "string" } })
+}
+
+fn object(required: &[&str], properties: Value) -> Value {
+    json!({
+        "type": "object",
+        "required": required,
+        "properties": properties,
+        "additionalProperties": false,
+    })
+}
+
+fn step(kind: &str, required: &[&str], properties: Value) -> Value {
+    let mut properties = match properties {
+        Value::Object(map) => map,
+        _ => Map::new(),
+    };
+    properties.insert("type".to_string(), json!({ "const": kind }));
+    let mut required = required.to_vec();
+    required.push("type");
+    object(&required, Value::Object(properties))
+}
+
+fn steps() -> Vec<Value> {
+    let target = json!({ "$ref": "#/definitions/build-target" });
+    vec![
+        step(
+            "build-crate",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "features": strings(),
+                "target": target,
+                "rustflags": strings(),
+                "rustdocflags": strings(),
+                "env": { "type": "object", "additionalProperties": { "type": "string" } },
+                "install-dirs": strings(),
+                "no-default-features": { "type": "boolean" },
+                "crate-types": {
+                    "type": "array",
+                    "items": { "enum": ["lib", "rlib", "dylib", "cdylib", "staticlib"] },
+                },
+                "bins": strings(),
+                "examples": strings(),
+                "benches": strings(),
+                "universal": { "type": "boolean" },
+                "resources": { "type": "string" },
+                "lib-version": { "type": "string" },
+                "soversion": { "type": "string" },
+            }),
+        ),
+        step(
+            "build-workspace",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "members": strings(),
+                "exclude": strings(),
+                "features": strings(),
+                "target": target,
+                "rustflags": strings(),
+            }),
+        ),
+        step(
+            "subdirectory",
+            &["path"],
+            json!({
+                "path": { "type": "string" },
+                "cmd": { "type": "string" },
+                "args": strings(),
+            }),
+        ),
+        step(
+            "install",
+            &["target"],
+            json!({
+                "file": { "type": "string" },
+                "from-step": { "type": "string" },
+                "kinds": strings(),
+                "target": { "type": "string" },
+                "mode": { "type": ["integer", "string"] },
+                "exclude": strings(),
+                "flatten": { "type": "boolean" },
+                "rename": { "type": "string" },
+                "caps": { "type": "string" },
+                "setuid": { "type": "boolean" },
+                "setgid": { "type": "boolean" },
+                "backup": { "enum": ["none", "simple", "numbered"] },
+            }),
+        ),
+        step(
+            "install-symlink",
+            &["link", "to"],
+            json!({
+                "link": { "type": "string" },
+                "to": { "type": "string" },
+                "hard": { "type": "boolean" },
+                "absolute": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "command",
+            &["cmd"],
+            json!({
+                "cmd": { "type": "string" },
+                "args": strings(),
+                "inputs": strings(),
+                "sandbox": { "type": "boolean" },
+                "network": { "type": "boolean" },
+            }),
+        ),
+        step("generate-docs", &[], json!({})),
+        step(
+            "configure-file",
+            &["base"],
+            json!({
+                "base": { "type": "string" },
+                "input": { "type": "string" },
+            }),
+        ),
+        step(
+            "codesign",
+            &["files"],
+            json!({
+                "files": strings(),
+                "identity": { "type": "string" },
+                "entitlements": { "type": "string" },
+                "hardened-runtime": { "type": "boolean" },
+                "notarize": { "type": "boolean" },
+                "keychain-profile": { "type": "string" },
+            }),
+        ),
+        step(
+            "objcopy",
+            &["input", "output"],
+            json!({
+                "input": { "type": "string" },
+                "output": { "type": "string" },
+                "format": { "enum": ["binary", "ihex"] },
+            }),
+        ),
+        step(
+            "compile-translations",
+            &[],
+            json!({
+                "dir": { "type": "string" },
+                "domain": { "type": "string" },
+                "languages": strings(),
+            }),
+        ),
+        step(
+            "install-desktop",
+            &[],
+            json!({
+                "desktop": strings(),
+                "icons": strings(),
+                "icon-name": { "type": "string" },
+                "metainfo": strings(),
+                "validate": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "install-systemd",
+            &[],
+            json!({
+                "units": strings(),
+                "user-units": strings(),
+                "tmpfiles": strings(),
+                "sysusers": strings(),
+                "dbus-services": strings(),
+                "dbus-system-services": strings(),
+            }),
+        ),
+        step(
+            "test",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "features": strings(),
+                "target": target,
+                "rustflags": strings(),
+                "junit": { "type": "string" },
+                "tap": { "type": "string" },
+                "nextest": { "type": "boolean" },
+                "partition": { "type": "string" },
+                "retries": { "type": "integer", "minimum": 0 },
+            }),
+        ),
+        step(
+            "bench",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "packages": strings(),
+                "features": strings(),
+                "target": target,
+                "rustflags": strings(),
+                "baseline": { "type": "string" },
+                "save-baseline": { "type": "string" },
+                "threshold": { "type": "number", "minimum": 0 },
+            }),
+        ),
+        step(
+            "fuzz",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "fuzz-dir": { "type": "string" },
+                "targets": strings(),
+                "features": strings(),
+                "rustflags": strings(),
+                "cargo-fuzz": { "type": "boolean" },
+                "smoke-seconds": { "type": "integer", "minimum": 0 },
+                "corpus": { "type": "string" },
+            }),
+        ),
+        step(
+            "lint",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "features": strings(),
+                "target": target,
+                "all-targets": { "type": "boolean" },
+                "deny": strings(),
+                "warn": strings(),
+                "allow": strings(),
+                "required": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "format-check",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "required": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "audit",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "tool": { "enum": ["cargo-audit", "cargo-deny"] },
+                "ignore": strings(),
+                "licenses": strings(),
+                "deny-warnings": { "type": "boolean" },
+                "required": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "fetch",
+            &["url", "sha256"],
+            json!({
+                "url": { "type": "string" },
+                "mirrors": strings(),
+                "sha256": { "type": "string", "pattern": "^[0-9a-fA-F]{64}$" },
+                "dest": { "type": "string" },
+            }),
+        ),
+        step(
+            "apply-patches",
+            &[],
+            json!({
+                "dir": { "type": "string" },
+                "patches": strings(),
+                "series": { "type": "string" },
+                "strip": { "type": "integer", "minimum": 0 },
+                "builtin": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "bindgen",
+            &["headers", "output"],
+            json!({
+                "headers": strings(),
+                "output": { "type": "string" },
+                "env": { "type": "string" },
+                "pkg-config": strings(),
+                "include-dirs": strings(),
+                "args": strings(),
+                "clang-args": strings(),
+                "target": target,
+            }),
+        ),
+        step(
+            "protoc",
+            &["schemas", "output"],
+            json!({
+                "schemas": strings(),
+                "output": { "type": "string" },
+                "env": { "type": "string" },
+                "include-dirs": strings(),
+                "plugins": {
+                    "type": "array",
+                    "items": object(&["name"], json!({
+                        "name": { "type": "string" },
+                        "options": strings(),
+                    })),
+                },
+                "args": strings(),
+            }),
+        ),
+        step(
+            "flatc",
+            &["schemas", "output"],
+            json!({
+                "schemas": strings(),
+                "output": { "type": "string" },
+                "env": { "type": "string" },
+                "include-dirs": strings(),
+                "args": strings(),
+            }),
+        ),
+        step(
+            "generate-parser",
+            &["generator", "input", "output"],
+            json!({
+                "generator": { "enum": ["yacc", "lex"] },
+                "input": { "type": "string" },
+                "output": { "type": "string" },
+                "header": { "type": "string" },
+                "program": { "type": "string" },
+                "env": { "type": "string" },
+                "args": strings(),
+            }),
+        ),
+        step(
+            "assemble",
+            &["sources"],
+            json!({
+                "sources": strings(),
+                "flags": strings(),
+                "archive": { "type": "string" },
+                "link": { "type": "boolean" },
+                "env": { "type": "string" },
+            }),
+        ),
+        step(
+            "archive",
+            &["name"],
+            json!({
+                "name": { "type": "string" },
+                "objects": strings(),
+                "from-steps": strings(),
+                "thin": { "type": "boolean" },
+                "deterministic": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "sysroot",
+            &[],
+            json!({
+                "dest": { "type": "string" },
+                "target": target,
+                "from-steps": strings(),
+                "files": strings(),
+                "link": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "cbindgen",
+            &["output"],
+            json!({
+                "path": { "type": "string" },
+                "output": { "type": "string" },
+                "config": { "type": "string" },
+                "lang": { "type": "string" },
+            }),
+        ),
+        step(
+            "pkg-config",
+            &["name"],
+            json!({
+                "name": { "type": "string" },
+                "path": { "type": "string" },
+                "description": { "type": "string" },
+                "version": { "type": "string" },
+                "requires": strings(),
+                "requires-private": strings(),
+                "libs": strings(),
+                "libs-private": strings(),
+                "cflags": strings(),
+            }),
+        ),
+        step(
+            "extract",
+            &["archive", "dest"],
+            json!({
+                "archive": { "type": "string" },
+                "dest": { "type": "string", "minLength": 1 },
+                "sha256": { "type": "string", "pattern": "^[0-9a-fA-F]{64}$" },
+                "strip-components": { "type": "integer", "minimum": 0 },
+            }),
+        ),
+        step(
+            "third-party-licenses",
+            &[],
+            json!({
+                "path": { "type": "string" },
+                "target": target,
+                "output": { "type": "string" },
+                "install": { "type": "boolean" },
+            }),
+        ),
+        step(
+            "plugin",
+            &["program"],
+            json!({
+                "program": { "type": "string" },
+                "args": strings(),
+                "inputs": strings(),
+                "options": { "type": "object" },
+            }),
+        ),
+        json!({
+            "type": "object",
+            "required": ["type"],
+            "properties": {
+                "type": { "not": { "enum": crate::config::BUILTIN_STEPS } },
+            },
+        }),
+    ]
+}
+
+fn products() -> Vec<Value> {
+    vec![step(
+        "c-library",
+        &["name"],
+        json!({
+            "name": { "type": "string" },
+            "path": { "type": "string" },
+            "features": strings(),
+            "target": { "$ref": "#/definitions/build-target" },
+            "lib-version": { "type": "string" },
+            "soversion": { "type": "string" },
+            "header": { "type": "string" },
+            "cbindgen-config": { "type": "string" },
+            "lang": { "enum": ["c", "c++", "cython"] },
+            "description": { "type": "string" },
+            "requires": strings(),
+            "requires-private": strings(),
+            "libs-private": strings(),
+        }),
+    )]
+}
+
+// The top-level tables come from several json! objects, since one object with all of them
+// goes past the macro's recursion limit
+fn properties() -> Value {
+    let step_list = json!({ "type": "array", "items": { "$ref": "#/definitions/step" } });
+    let scalar = json!({ "type": ["boolean", "integer", "string"] });
+    let injected = object(&[], json!({ "prepend": strings(), "append": strings() }));
+    let settings = json!({
+        "format-version": { "const": crate::config::FORMAT_VERSION },
+        "include": { "type": ["string", "array"], "items": { "type": "string" } },
+        "options": object(&[], json!({
+            "enable-sccache": { "type": "boolean" },
+            "enable-ccache": { "type": "boolean" },
+            "enable-pgo": { "type": "boolean" },
+            "sanitizers": strings(),
+            "static-link": { "type": "boolean" },
+            "hardened": { "type": "boolean" },
+            "hosts": strings(),
+            "download-cache": { "type": "string" },
+            "unstable-flags": strings(),
+            "rustc-wrapper": { "type": "string" },
+            "rustflags": object(&[], json!({
+                "target": injected,
+                "host": injected,
+                "build-script": injected,
+            })),
+            "rustdocflags": strings(),
+            "target-dir": { "type": "string" },
+            "isolate": { "type": "array", "items": { "enum": ["triple", "profile"] } },
+            "scratch-dir": { "type": "string" },
+        })),
+        "profiles": {
+            "type": "object",
+            "additionalProperties": object(&[], json!({
+                "inherits": { "type": "string" },
+                "opt-level": scalar,
+                "debug": { "type": "boolean" },
+                "lto": scalar,
+                "codegen-units": { "type": "integer", "minimum": 0 },
+                "linker-plugin-lto": { "type": "boolean" },
+                "panic": { "type": "string" },
+                "rustflags": strings(),
+            })),
+        },
+        "target": {
+            "type": "object",
+            "additionalProperties": object(&[], json!({
+                "cpu": { "type": "string" },
+                "features": strings(),
+                "api-level": { "type": "integer", "minimum": 0 },
+                "min-os-version": { "type": "string" },
+                "spec": { "type": "string" },
+                "linker-script": { "type": "string" },
+                "rustflags": strings(),
+                "env": { "type": "object", "additionalProperties": { "type": "string" } },
+                "dirs": { "type": "object", "additionalProperties": { "type": "string" } },
+                "step": { "type": "object", "additionalProperties": { "type": "object" } },
+            })),
+        },
+        "dirs": { "type": "object", "additionalProperties": { "type": "string" } },
+        "compilers": {
+            "type": "object",
+            "additionalProperties": { "type": "string" },
+        },
+        "programs": {
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "required": ["type"],
+                "properties": {
+                    "type": { "type": "string" },
+                    "names": strings(),
+                    "test-steps": step_list,
+                    "compiler-target": { "$ref": "#/definitions/build-target" },
+                },
+                "additionalProperties": { "type": "string" },
+                "if": { "properties": { "type": { "enum": crate::config::PROGRAM_TYPES } } },
+                "then": {
+                    "propertyNames": { "enum": ["type", "names", "test-steps", "compiler-target"] },
+                },
+            },
+        },
+    });
+    let phases = json!({
+        "build": object(&[], json!({ "step": step_list })),
+        "install": object(&[], json!({ "step": step_list })),
+        "test": object(&[], json!({ "step": step_list })),
+        "bench": object(&[], json!({ "step": step_list })),
+        "pgo": object(&[], json!({ "train": step_list })),
+        "bootstrap": object(&["program", "artifact"], json!({
+            "program": { "type": "string" },
+            "artifact": { "type": "string" },
+            "stages": { "type": "integer", "minimum": 1 },
+            "compare": strings(),
+        })),
+        "probe": {
+            "type": "array",
+            "items": object(&["program"], json!({
+                "description": { "type": "string" },
+                "program": { "type": "string" },
+                "args": strings(),
+                "options": { "type": "object" },
+            })),
+        },
+        "hooks": object(&[], json!({
+            "pre-configure": strings(),
+            "post-configure": strings(),
+            "pre-build": strings(),
+            "post-build": strings(),
+            "pre-install": strings(),
+            "post-install": strings(),
+        })),
+        "link-audit": object(&[], json!({
+            "enable": { "type": "boolean" },
+            "warn-only": { "type": "boolean" },
+            "allow": strings(),
+        })),
+        "remote": object(&[], json!({
+            "workers": strings(),
+            "dir": { "type": "string" },
+            "program": { "type": "string" },
+        })),
+        "container": object(&[], json!({
+            "image": { "type": "string" },
+            "engine": { "type": "string" },
+            "program": { "type": "string" },
+            "volumes": strings(),
+            "env": strings(),
+        })),
+        "product": { "type": "array", "items": { "oneOf": products() } },
+    });
+    let mut properties = Map::new();
+    for fragment in [settings, phases] {
+        if let Value::Object(map) = fragment {
+            properties.extend(map);
+        }
+    }
+    Value::Object(properties)
+}
+
+pub fn json_schema() -> Value {
+    // Any step in a phase may have an id, a condition and a policy
+    let steps: Vec<Value> = steps()
+        .into_iter()
+        .map(|mut step| {
+            step["properties"]["id"] = json!({ "type": "string", "pattern": "^[A-Za-z0-9_-]+$" });
+            step["properties"]["if"] = json!({ "type": "string" });
+            step["properties"]["retries"] = json!({ "type": "integer", "minimum": 0 });
+            step["properties"]["timeout"] = json!({ "type": "integer", "minimum": 1 });
+            step["properties"]["on-failure"] =
+                json!({ "enum": ["abort", "continue", "skip-dependents"] });
+            step["properties"]["output-mode"] = json!({ "enum": ["stream", "log", "on-failure"] });
+            step["properties"]["distribute"] = json!({ "type": "boolean" });
+            step
+        })
+        .collect();
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "title": "autobuild configuration",
+        "definitions": {
+            "build-target": { "type": "string" },
+            "step": { "oneOf": steps },
+        },
+        "type": "object",
+        "required": ["format-version"],
+        "additionalProperties": false,
+        "properties": properties(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_lists_every_table() {
+        let schema = json_schema();
+        let properties = schema["properties"].as_object().unwrap();
+        for table in &[
+            "format-version",
+            "options",
+            "programs",
+            "build",
+            "hooks",
+            "product",
+        ] {
+            assert!(properties.contains_key(*table), "missing {}", table);
+        }
+        let product = &properties["product"]["items"]["oneOf"][0];
+        assert_eq!(product["properties"]["type"]["const"], "c-library");
+        assert_eq!(product["required"], json!(["name", "type"]));
+    }
+
+    #[test]
+    fn schema_has_every_builtin_step() {
+        let schema = json_schema();
+        let kinds: Vec<_> = schema["definitions"]["step"]["oneOf"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|step| step["properties"]["type"]["const"].as_str())
+            .collect();
+        for kind in crate::config::BUILTIN_STEPS {
+            assert!(kinds.contains(kind), "missing {}", kind);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    hash::{Hash, Hasher},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{InstallDirectory, ParserGenerator, Step},
+    configure::Configuration,
+    steps,
+};
+
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone)]
+pub struct StepRecord {
+    pub fingerprint: u64,
+    pub inputs: u64,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct BuildState {
+    steps: HashMap<String, StepRecord>,
+    #[serde(default)]
+    outputs: HashMap<String, Vec<PathBuf>>,
+}
+
+fn state_file(build_dir: &Path) -> PathBuf {
+    build_dir.join("state.json")
+}
+
+impl BuildState {
+    pub fn load(build_dir: &Path) -> Self {
+        std::fs::read(state_file(build_dir))
+            .ok()
+            .and_then(|buf| serde_json::from_slice(&buf).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, build_dir: &Path) -> std::io::Result<()> {
+        let buf = serde_json::to_vec_pretty(self)
+            .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+        std::fs::write(state_file(build_dir), buf)
+    }
+
+    // A step is only up to date while everything it made is still there
+    pub fn is_fresh(&self, name: &str, record: &StepRecord) -> bool {
+        self.steps.get(name) == Some(record)
+            && self
+                .outputs
+                .get(name)
+                .into_iter()
+                .flatten()
+                .all(|output| output.exists())
+    }
+
+    pub fn record(&mut self, name: &str, record: StepRecord) {
+        self.steps.insert(name.to_string(), record);
+    }
+
+    pub fn invalidate(&mut self, name: &str) {
+        self.steps.remove(name);
+    }
+
+    pub fn record_outputs(&mut self, name: &str, outputs: Vec<PathBuf>) {
+        self.outputs.insert(name.to_string(), outputs);
+    }
+
+    pub fn outputs(&self) -> impl Iterator<Item = &PathBuf> {
+        self.outputs.values().flatten()
+    }
+}
+
+// Directories in `skip`, where builds write their output, are left out
+fn hash_path(hasher: &mut Fnv, path: &Path, skip: &[PathBuf]) -> std::io::Result<()> {
+    if skip.iter().any(|dir| dir == path) {
+        return Ok(());
+    }
+    path.hash(hasher);
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<_>>>()?;
+        entries.sort();
+        for entry in entries {
+            // A link to a directory is hashed by where it points, as following it could loop
+            if entry.symlink_metadata()?.file_type().is_symlink() && entry.is_dir() {
+                entry.hash(hasher);
+                std::fs::read_link(&entry)?.hash(hasher);
+            } else {
+                hash_path(hasher, &entry, skip)?;
+            }
+        }
+    } else if path.is_file() {
+        hasher.write(&std::fs::read(path)?);
+    }
+    Ok(())
+}
+
+// Where builds write, which is never an input even when it is inside one
+pub fn output_dirs(cfg: &Configuration) -> Vec<PathBuf> {
+    let mut dirs = vec![cfg.build_dir.clone(), cfg.src_dir.join("target")];
+    dirs.extend(cfg.target_dir.clone());
+    dirs
+}
+
+fn hash_vars(hasher: &mut Fnv, cfg: &Configuration) {
+    cfg.vars.iter().collect::<BTreeMap<_, _>>().hash(hasher);
+}
+
+fn fingerprint(step: &Step, cfg: &Configuration) -> Option<u64> {
+    let mut hasher = Fnv::default();
+    match step {
+        Step::BuildCrate(step) => {
+            "build-crate".hash(&mut hasher);
+            step.universal.hash(&mut hasher);
+            step.resources.hash(&mut hasher);
+            step.lib_version.hash(&mut hasher);
+            step.soversion.hash(&mut hasher);
+            cfg.cargo.hash(&mut hasher);
+            cfg.rustc_for(&step.target).rustc.hash(&mut hasher);
+            cfg.profile.hash(&mut hasher);
+            step.path.hash(&mut hasher);
+            crate::steps::resolve_features(&step.features, cfg).hash(&mut hasher);
+            step.rustflags.hash(&mut hasher);
+            step.rustdocflags.hash(&mut hasher);
+            step.no_default_features.hash(&mut hasher);
+            step.crate_types.hash(&mut hasher);
+            step.bins.hash(&mut hasher);
+            step.examples.hash(&mut hasher);
+            step.benches.hash(&mut hasher);
+            crate::steps::install_dir_env(step, cfg)
+                .ok()
+                .map(|env| env.into_iter().collect::<BTreeMap<_, _>>())
+                .hash(&mut hasher);
+            step.env
+                .iter()
+                .collect::<BTreeMap<_, _>>()
+                .hash(&mut hasher);
+            cfg.injected_rustflags.hash(&mut hasher);
+            cfg.target_dir.hash(&mut hasher);
+            cfg.resolve_target(&step.target)
+                .get_name()
+                .hash(&mut hasher);
+            cfg.spec_for(&step.target).hash(&mut hasher);
+            cfg.build_env
+                .iter()
+                .collect::<BTreeMap<_, _>>()
+                .hash(&mut hasher);
+        }
+        Step::BuildWorkspace(step) => {
+            "build-workspace".hash(&mut hasher);
+            cfg.cargo.hash(&mut hasher);
+            cfg.rustc_for(&step.target).rustc.hash(&mut hasher);
+            cfg.profile.hash(&mut hasher);
+            step.path.hash(&mut hasher);
+            step.members.hash(&mut hasher);
+            step.exclude.hash(&mut hasher);
+            crate::steps::resolve_features(&step.features, cfg).hash(&mut hasher);
+            step.rustflags.hash(&mut hasher);
+            cfg.injected_rustflags.hash(&mut hasher);
+            cfg.target_dir.hash(&mut hasher);
+            cfg.resolve_target(&step.target)
+                .get_name()
+                .hash(&mut hasher);
+            cfg.spec_for(&step.target).hash(&mut hasher);
+            cfg.build_env
+                .iter()
+                .collect::<BTreeMap<_, _>>()
+                .hash(&mut hasher);
+        }
+        Step::Command(step) if !step.inputs.is_empty() => {
+            "command".hash(&mut hasher);
+            step.cmd.hash(&mut hasher);
+            step.args.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::ConfigureFile(step) => {
+            "configure-file".hash(&mut hasher);
+            step.base.hash(&mut hasher);
+            step.input.hash(&mut hasher);
+            cfg.src_dir.join(&step.base).exists().hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Bindgen(step) => {
+            "bindgen".hash(&mut hasher);
+            cfg.bindgen.hash(&mut hasher);
+            step.headers.hash(&mut hasher);
+            step.output.hash(&mut hasher);
+            step.pkg_config.hash(&mut hasher);
+            step.include_dirs.hash(&mut hasher);
+            step.args.hash(&mut hasher);
+            step.clang_args.hash(&mut hasher);
+            cfg.resolve_target(&step.target)
+                .get_name()
+                .hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Protoc(step) => {
+            "protoc".hash(&mut hasher);
+            cfg.protoc.hash(&mut hasher);
+            step.schemas.hash(&mut hasher);
+            step.output.hash(&mut hasher);
+            step.include_dirs.hash(&mut hasher);
+            for plugin in &step.plugins {
+                plugin.name.hash(&mut hasher);
+                plugin.options.hash(&mut hasher);
+                cfg.protoc_plugins.get(&plugin.name).hash(&mut hasher);
+            }
+            step.args.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Flatc(step) => {
+            "flatc".hash(&mut hasher);
+            cfg.flatc.hash(&mut hasher);
+            step.schemas.hash(&mut hasher);
+            step.output.hash(&mut hasher);
+            step.include_dirs.hash(&mut hasher);
+            step.args.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::GenerateParser(step) => {
+            "generate-parser".hash(&mut hasher);
+            step.generator.hash(&mut hasher);
+            match &step.program {
+                Some(key) => cfg.programs.get(key).hash(&mut hasher),
+                None if step.generator == ParserGenerator::Yacc => cfg.yacc.hash(&mut hasher),
+                None => cfg.lex.hash(&mut hasher),
+            }
+            step.input.hash(&mut hasher);
+            step.output.hash(&mut hasher);
+            step.header.hash(&mut hasher);
+            step.args.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Assemble(step) => {
+            "assemble".hash(&mut hasher);
+            cfg.assembler.hash(&mut hasher);
+            cfg.cc.hash(&mut hasher);
+            cfg.ar.hash(&mut hasher);
+            step.sources.hash(&mut hasher);
+            step.flags.hash(&mut hasher);
+            step.archive.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Archive(step) => {
+            "archive".hash(&mut hasher);
+            cfg.ar.hash(&mut hasher);
+            step.name.hash(&mut hasher);
+            step.objects.hash(&mut hasher);
+            step.from_steps.hash(&mut hasher);
+            step.thin.hash(&mut hasher);
+            step.deterministic.hash(&mut hasher);
+        }
+        Step::Sysroot(step) => {
+            "sysroot".hash(&mut hasher);
+            step.dest.hash(&mut hasher);
+            cfg.resolve_target(&step.target)
+                .get_name()
+                .hash(&mut hasher);
+            step.from_steps.hash(&mut hasher);
+            step.files.hash(&mut hasher);
+            step.link.hash(&mut hasher);
+        }
+        Step::Cbindgen(step) => {
+            "cbindgen".hash(&mut hasher);
+            cfg.cbindgen.hash(&mut hasher);
+            step.path.hash(&mut hasher);
+            step.output.hash(&mut hasher);
+            step.config.hash(&mut hasher);
+            step.lang.hash(&mut hasher);
+        }
+        Step::PkgConfig(step) => {
+            "pkg-config".hash(&mut hasher);
+            step.name.hash(&mut hasher);
+            step.path.hash(&mut hasher);
+            step.description.hash(&mut hasher);
+            step.version.hash(&mut hasher);
+            step.requires.hash(&mut hasher);
+            step.requires_private.hash(&mut hasher);
+            step.libs.hash(&mut hasher);
+            step.libs_private.hash(&mut hasher);
+            step.cflags.hash(&mut hasher);
+            // The file records where the library is installed
+            for dir in &[
+                InstallDirectory::Prefix,
+                InstallDirectory::LibDir,
+                InstallDirectory::IncludeDir,
+            ] {
+                cfg.dirs.get(dir).hash(&mut hasher);
+            }
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Extract(step) => {
+            "extract".hash(&mut hasher);
+            step.archive.hash(&mut hasher);
+            step.dest.hash(&mut hasher);
+            step.sha256.hash(&mut hasher);
+            step.strip_components.hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        Step::Plugin(step) => {
+            "plugin".hash(&mut hasher);
+            step.program.hash(&mut hasher);
+            step.args.hash(&mut hasher);
+            toml::Value::Table(step.options.clone())
+                .to_string()
+                .hash(&mut hasher);
+            hash_vars(&mut hasher, cfg);
+        }
+        _ => return None,
+    }
+    Some(hasher.finish())
+}
+
+// An optimized PGO build is out of date whenever the merged profile changes
+fn profile_use(rustflags: &[String]) -> Option<PathBuf> {
+    rustflags
+        .iter()
+        .find_map(|flag| flag.strip_prefix("-Cprofile-use="))
+        .map(PathBuf::from)
+}
+
+pub fn step_record(
+    name: &str,
+    step: &Step,
+    cfg: &Configuration,
+) -> std::io::Result<Option<StepRecord>> {
+    // The instrumented builds and training runs write the profiles pgo.merge reads, into a
+    // directory the first instrumented build empties, so skipping them would leave nothing to
+    // merge
+    if name.starts_with("pgo.") {
+        return Ok(None);
+    }
+    let fingerprint = match fingerprint(step, cfg) {
+        Some(fingerprint) => fingerprint,
+        None => return Ok(None),
+    };
+
+    let mut hasher = Fnv::default();
+    let mut inputs = steps::step_inputs(step, cfg)?;
+    match step {
+        Step::BuildCrate(step) => {
+            inputs.push(cfg.src_dir.join(&step.path).join("Cargo.lock"));
+            inputs.extend(step.resources.iter().map(|r| cfg.src_dir.join(r)));
+            inputs.extend(profile_use(&step.rustflags));
+        }
+        Step::BuildWorkspace(step) => {
+            inputs.push(cfg.src_dir.join(&step.path).join("Cargo.lock"));
+            inputs.extend(profile_use(&step.rustflags));
+        }
+        _ => {}
+    }
+    let skip = output_dirs(cfg);
+    for input in &inputs {
+        hash_path(&mut hasher, input, &skip)?;
+    }
+
+    Ok(Some(StepRecord {
+        fingerprint,
+        inputs: hasher.finish(),
+    }))
+}
@@ -0,0 +1,2905 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fs::File,
+    io::{BufRead, BufReader, ErrorKind, Write},
+    path::{Component, Path, PathBuf, Prefix},
+    process::{Child, Command, ExitStatus, Stdio},
+    str::FromStr,
+    sync::mpsc::RecvTimeoutError,
+    time::{Duration, Instant},
+};
+
+use target_tuples::Target;
+
+use crate::{
+    cargo::{CrateTarget, Metadata, Package},
+    compat::Flavor,
+    config::{
+        AuditStep, AuditTool, BenchStep, BuildCrateStep, BuildTarget, BuildWorkspaceStep,
+        CodesignStep, CommandStep, CompileTranslationsStep, ConfigureFileStep, CustomStep,
+        FlatcStep, FormatCheckStep, FuzzStep, ImageFormat, InstallDesktopStep, InstallDirectory,
+        InstallStep, InstallSymlinkStep, InstallSystemdStep, InstallTarget, LintStep, Mode,
+        ObjcopyStep, ProtocStep, Step, SubdirectoryStep, TestStep, ThirdPartyLicensesStep,
+    },
+    configure::Configuration,
+    filesystem::{probe, Capabilities},
+    handler::StepHandler,
+    journal::Journal,
+    tests::rustc::RustcTargetInfo,
+};
+
+fn unhandled(step: &CustomStep) -> std::io::Error {
+    std::io::Error::new(
+        ErrorKind::Unsupported,
+        format!("No handler is registered for steps of type {}", step.kind),
+    )
+}
+
+// Applies to every command the step being run starts
+#[derive(Default)]
+pub struct StepContext {
+    // From the step's `timeout`
+    pub deadline: Option<Instant>,
+    // Where the output goes instead of the terminal
+    pub log: Option<File>,
+    // Where cargo builds instead of the configured target directory, and the artifact variables
+    // that go with it, as the instrumented builds of PGO and their training runs have their own
+    pub target_dir: Option<PathBuf>,
+    pub vars: HashMap<String, String>,
+}
+
+thread_local! {
+    static CONTEXT: RefCell<StepContext> = RefCell::default();
+}
+
+pub fn with_context<T>(context: StepContext, f: impl FnOnce() -> T) -> T {
+    let outer = CONTEXT.with(|c| c.replace(context));
+    let result = f();
+    CONTEXT.with(|c| c.replace(outer));
+    result
+}
+
+pub fn log_path(step: &str, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join("logs").join(format!("{}.log", step))
+}
+
+fn timed_out(cmd: &Command) -> std::io::Error {
+    std::io::Error::new(ErrorKind::TimedOut, format!("{:?} timed out", cmd))
+}
+
+fn wait_until(child: &mut Child, cmd: &Command, deadline: Instant) -> std::io::Result<ExitStatus> {
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out(cmd));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+// Points `cmd` at the step's target directory, variables and log, and returns its deadline. The
+// log takes standard output only when `stdout` is set, as some callers read it themselves
+fn apply_context(cmd: &mut Command, stdout: bool) -> std::io::Result<Option<Instant>> {
+    let (deadline, log, target_dir, vars) = CONTEXT.with(|c| {
+        let c = c.borrow();
+        (
+            c.deadline,
+            c.log.as_ref().map(File::try_clone),
+            c.target_dir.clone(),
+            c.vars.clone(),
+        )
+    });
+    if let Some(dir) = target_dir {
+        cmd.env("CARGO_TARGET_DIR", dir);
+    }
+    cmd.envs(vars);
+    if let Some(log) = log {
+        let log = log?;
+        if stdout {
+            cmd.stdout(log.try_clone()?);
+        }
+        cmd.stderr(log);
+    }
+    Ok(deadline)
+}
+
+// Writes a line of the step's own output to where the output of its commands goes
+pub fn note(line: &str) {
+    CONTEXT.with(|c| match &c.borrow().log {
+        Some(log) => {
+            let _ = writeln!(&mut &*log, "{}", line);
+        }
+        None => println!("{}", line),
+    })
+}
+
+pub fn run(cmd: &mut Command) -> std::io::Result<()> {
+    let deadline = apply_context(cmd, true)?;
+    let status = match deadline {
+        Some(deadline) => wait_until(&mut cmd.spawn()?, cmd, deadline)?,
+        None => cmd.status()?,
+    };
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, status
+        )))
+    }
+}
+
+// Runs `cmd` as `run` does, but hands each line of its standard output to `line` and leaves the
+// exit status to the caller
+fn run_reading(
+    cmd: &mut Command,
+    mut line: impl FnMut(&str) -> std::io::Result<()>,
+) -> std::io::Result<ExitStatus> {
+    let deadline = apply_context(cmd, false)?;
+    let mut child = cmd.stdout(Stdio::piped()).spawn()?;
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    // Read on a thread of its own, so that the deadline holds while the command prints nothing
+    let (send, lines) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for text in stdout.lines() {
+            if send.send(text).is_err() {
+                break;
+            }
+        }
+    });
+    loop {
+        let text = match deadline {
+            Some(deadline) => {
+                match lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(text) => text,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                    Err(RecvTimeoutError::Timeout) => {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                        return Err(timed_out(cmd));
+                    }
+                }
+            }
+            None => match lines.recv() {
+                Ok(text) => text,
+                Err(_) => break,
+            },
+        };
+        if let Err(e) = text.and_then(|text| line(&text)) {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(e);
+        }
+    }
+    match deadline {
+        Some(deadline) => wait_until(&mut child, cmd, deadline),
+        None => child.wait(),
+    }
+}
+
+pub fn manifest_at(path: &OsStr, cfg: &Configuration) -> PathBuf {
+    let mut manifest = cfg.src_dir.join(path);
+    manifest.push("Cargo.toml");
+    manifest
+}
+
+fn workspace_members<'a>(
+    step: &BuildWorkspaceStep,
+    metadata: &'a Metadata,
+) -> std::io::Result<Vec<&'a Package>> {
+    let members: Vec<&Package> = metadata
+        .packages
+        .iter()
+        .filter(|p| {
+            step.members.is_empty()
+                || step
+                    .members
+                    .iter()
+                    .any(|m| crate::glob::matches(m, &p.name))
+        })
+        .filter(|p| {
+            !step
+                .exclude
+                .iter()
+                .any(|m| crate::glob::matches(m, &p.name))
+        })
+        .collect();
+    if members.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!(
+                "No workspace members of {} match {:?}",
+                metadata.workspace_root.display(),
+                step.members
+            ),
+        ));
+    }
+    Ok(members)
+}
+
+fn target_dir(metadata: &Metadata, cfg: &Configuration) -> PathBuf {
+    if let Some(dir) = CONTEXT.with(|c| c.borrow().target_dir.clone()) {
+        return dir;
+    }
+    match &cfg.target_dir {
+        Some(dir) => dir.clone(),
+        None => metadata.target_directory.clone(),
+    }
+}
+
+fn artifact_dir(metadata: &Metadata, target: &BuildTarget, cfg: &Configuration) -> PathBuf {
+    let rustc = cfg.rustc_for(target);
+    if !Flavor::of(&rustc.version).uses_cargo() {
+        return crate::compat::output_dir(rustc, cfg);
+    }
+    let mut dir = target_dir(metadata, cfg);
+    if let Some(spec) = cfg.spec_for(target) {
+        dir.push(spec.file_stem().unwrap_or_default());
+    } else {
+        dir.push(cfg.resolve_target(target).get_name());
+    }
+    dir.push(cfg.profile.target_subdir());
+    dir
+}
+
+const UNIVERSAL_TARGETS: &[&str] = &["aarch64-apple-darwin", "x86_64-apple-darwin"];
+
+// The crate types a library can be built as; proc macros only ever build as themselves
+pub const LIB_CRATE_TYPES: &[&str] = &["lib", "rlib", "dylib", "cdylib", "staticlib"];
+
+fn universal_dir(metadata: &Metadata, cfg: &Configuration) -> PathBuf {
+    let mut dir = target_dir(metadata, cfg).join("universal-apple-darwin");
+    dir.push(cfg.profile.target_subdir());
+    dir
+}
+
+fn universal_target(name: &str) -> std::io::Result<BuildTarget> {
+    Target::from_str(name).map(BuildTarget::Input).map_err(|_| {
+        std::io::Error::new(ErrorKind::InvalidInput, format!("Unknown target {}", name))
+    })
+}
+
+#[derive(Clone)]
+pub struct Artifact {
+    pub key: String,
+    pub kind: String,
+    pub path: PathBuf,
+}
+
+pub fn target_artifacts(
+    package: &Package,
+    target: &CrateTarget,
+    dir: &Path,
+    info: &RustcTargetInfo,
+) -> Vec<Artifact> {
+    let mut artifacts = Vec::new();
+    let lib_name = target.name.replace('-', "_");
+    for kind in &target.crate_types {
+        let (prefix, name, suffix) = match &**kind {
+            "bin" => (OsStr::new(""), &target.name, &info.exe_suffix),
+            "lib" | "rlib" => (&*info.rlib_prefix, &lib_name, &info.rlib_suffix),
+            "dylib" => (&*info.dylib_prefix, &lib_name, &info.dylib_suffix),
+            "cdylib" => (&*info.cdylib_prefix, &lib_name, &info.cdylib_suffix),
+            "staticlib" => (&*info.staticlib_prefix, &lib_name, &info.staticlib_suffix),
+            "proc-macro" => (&*info.procmacro_prefix, &lib_name, &info.procmacro_suffix),
+            _ => continue,
+        };
+        let mut file = prefix.to_owned();
+        file.push(name);
+        file.push(suffix);
+        artifacts.push(Artifact {
+            key: format!("{}/{}", package.name, file.to_string_lossy()),
+            kind: kind.clone(),
+            path: dir.join(file),
+        });
+    }
+    artifacts
+}
+
+// With crate-types, the library builds those types in place of the ones in its manifest
+fn select_crate_types(metadata: &mut Metadata, step: &BuildCrateStep) {
+    if step.crate_types.is_empty() {
+        return;
+    }
+    for target in metadata.packages.iter_mut().flat_map(|p| &mut p.targets) {
+        if target.kind.iter().any(|k| LIB_CRATE_TYPES.contains(&&**k)) {
+            target.crate_types = step.crate_types.clone();
+        }
+    }
+}
+
+// The libraries and binaries of a package, without its examples and benches
+fn package_artifacts(
+    package: &Package,
+    step: Option<&BuildCrateStep>,
+    dir: &Path,
+    info: &RustcTargetInfo,
+) -> Vec<Artifact> {
+    package
+        .targets
+        .iter()
+        .filter(|t| selected(step, t) && !t.kind.iter().any(|k| k == "example" || k == "bench"))
+        .flat_map(|target| target_artifacts(package, target, dir, info))
+        .collect()
+}
+
+// Proc macros run inside the compiler, so cargo builds them for the build machine and keeps them
+// with the build scripts, outside the directory of the target it was given
+fn crate_artifacts(
+    package: &Package,
+    metadata: &Metadata,
+    step: Option<&BuildCrateStep>,
+    target: &BuildTarget,
+    cfg: &Configuration,
+) -> Vec<Artifact> {
+    let dir = artifact_dir(metadata, target, cfg);
+    let info = cfg.target_info(target);
+    let macro_dir = if Flavor::of(&cfg.rustc_for(target).version).uses_cargo() {
+        target_dir(metadata, cfg).join(cfg.profile.target_subdir())
+    } else {
+        dir.clone()
+    };
+    let macro_info = cfg.target_info(&BuildTarget::Build);
+    let mut artifacts = Vec::new();
+    for t in package.targets.iter().filter(|t| selected(step, t)) {
+        let kind = t.kind.iter().find(|k| matches!(&***k, "example" | "bench"));
+        if t.crate_types.iter().any(|k| k == "proc-macro") {
+            artifacts.extend(target_artifacts(package, t, &macro_dir, macro_info));
+        } else if let Some(kind) = kind {
+            // Named after their directory, so an example may share the name of a binary
+            let sub = format!("{}s", kind);
+            for mut artifact in target_artifacts(package, t, &dir.join(&sub), info) {
+                let file = artifact.path.file_name().unwrap().to_string_lossy();
+                artifact.key = format!("{}/{}/{}", package.name, sub, file);
+                if artifact.kind == "bin" {
+                    artifact.kind = kind.clone();
+                }
+                artifacts.push(artifact);
+            }
+        } else {
+            artifacts.extend(target_artifacts(package, t, &dir, info));
+        }
+    }
+    artifacts
+}
+
+// Examples and benches are built only when a build-crate step names them, and naming binaries
+// leaves out the others
+fn selected(step: Option<&BuildCrateStep>, target: &CrateTarget) -> bool {
+    let is = |kind: &str| target.kind.iter().any(|k| k == kind);
+    if is("example") {
+        step.is_some_and(|s| s.examples.contains(&target.name))
+    } else if is("bench") {
+        step.is_some_and(|s| s.benches.contains(&target.name))
+    } else if is("bin") {
+        step.is_none_or(|s| s.bins.is_empty() || s.bins.contains(&target.name))
+    } else {
+        !is("test") && !is("custom-build")
+    }
+}
+
+// cargo leaves bench executables in deps under a hashed name, so `build` is asked where each is
+// and it is copied to where its artifact says it is
+fn copy_benches(build: &mut Command, dir: &Path, info: &RustcTargetInfo) -> std::io::Result<()> {
+    let mut benches = Vec::new();
+    build.arg("--message-format").arg("json");
+    read_artifacts(build, |message| {
+        if let (Some(target), Some(executable)) = (message.target, message.executable) {
+            if target.kind.iter().any(|k| k == "bench") {
+                benches.push((target.name, executable));
+            }
+        }
+    })?;
+    std::fs::create_dir_all(dir.join("benches"))?;
+    for (name, executable) in benches {
+        let dest =
+            dir.join("benches")
+                .join(format!("{}{}", name, info.exe_suffix.to_string_lossy()));
+        std::fs::copy(executable, dest)?;
+    }
+    Ok(())
+}
+
+pub fn step_artifacts(step: &Step, cfg: &Configuration) -> std::io::Result<Vec<Artifact>> {
+    cfg.handlers.get(step.kind())?.outputs(step, cfg)
+}
+
+pub fn step_inputs(step: &Step, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    cfg.handlers.get(step.kind())?.inputs(step, cfg)
+}
+
+pub fn run_step(step: &Step, cfg: &Configuration) -> std::io::Result<()> {
+    cfg.handlers.get(step.kind())?.execute(step, cfg)
+}
+
+pub fn describe_step(step: &Step, cfg: &Configuration) -> String {
+    match cfg.handlers.get(step.kind()) {
+        Ok(handler) => handler.describe(step, cfg),
+        Err(_) => step.kind().to_string(),
+    }
+}
+
+pub struct Builtin;
+
+impl StepHandler for Builtin {
+    fn describe(&self, step: &Step, cfg: &Configuration) -> String {
+        match step {
+            Step::BuildCrate(step) => format!("build the crate in {}", display_path(&step.path)),
+            Step::BuildWorkspace(step) => {
+                format!("build the workspace in {}", display_path(&step.path))
+            }
+            Step::Subdirectory(step) => format!("run make in {}", step.path.display()),
+            Step::Install(step) => {
+                let file = match &step.from_step {
+                    Some(name) => format!("the artifacts of {}", name),
+                    None => substitute(&step.file.to_string_lossy(), cfg),
+                };
+                match cfg.install_path(&step.target) {
+                    Ok(target) => format!("install {} to {}", file, target.display()),
+                    Err(_) => format!("install {}", file),
+                }
+            }
+            Step::InstallSymlink(step) => match cfg.install_path(&step.link) {
+                Ok(link) => format!("link {} to {}", link.display(), link_to(step, cfg)),
+                Err(_) => "install a link".to_string(),
+            },
+            Step::Command(step) => format!("run {}", step.cmd.display()),
+            Step::GenerateDocs(_) => "generate documentation".to_string(),
+            Step::ConfigureFile(step) => format!("configure {}", step.base.display()),
+            Step::Codesign(step) => format!("sign {} file(s)", step.files.len()),
+            Step::Objcopy(step) => format!("convert {}", step.input.display()),
+            Step::CompileTranslations(step) => {
+                format!(
+                    "compile and install the translations in {}",
+                    step.dir.display()
+                )
+            }
+            Step::InstallDesktop(_) => "install desktop integration files".to_string(),
+            Step::InstallSystemd(_) => "install systemd and D-Bus files".to_string(),
+            Step::Test(step) => format!("test the crate in {}", display_path(&step.path)),
+            Step::Bench(step) => format!("benchmark the crate in {}", display_path(&step.path)),
+            Step::Fuzz(step) => format!("build the fuzz targets in {}", step.fuzz_dir.display()),
+            Step::Lint(step) => format!("run clippy on {}", display_path(&step.path)),
+            Step::FormatCheck(step) => {
+                format!("check the formatting of {}", display_path(&step.path))
+            }
+            Step::Audit(step) => format!("audit the dependencies of {}", display_path(&step.path)),
+            Step::ThirdPartyLicenses(step) => {
+                format!("collect the licenses into {}", step.output.display())
+            }
+            Step::Fetch(step) => format!("download {}", step.url),
+            Step::ApplyPatches(step) => format!("patch {}", display_path(step.dir.as_os_str())),
+            Step::Extract(step) => format!("extract {}", step.archive.display()),
+            Step::Bindgen(step) => format!("generate bindings in {}", step.output.display()),
+            Step::Protoc(step) => format!("generate protobuf code in {}", step.output.display()),
+            Step::Flatc(step) => format!("generate flatbuffers code in {}", step.output.display()),
+            Step::Archive(step) => format!("archive {}", crate::archive::lib_file(&step.name, cfg)),
+            Step::Sysroot(step) => format!("assemble the sysroot in {}", step.dest.display()),
+            Step::Cbindgen(step) => format!("generate the header {}", step.output.display()),
+            Step::PkgConfig(step) => format!("write {}.pc", step.name),
+            Step::Assemble(step) => match &step.archive {
+                Some(archive) => format!("assemble {}", archive),
+                None => "assemble".to_string(),
+            },
+            Step::GenerateParser(step) => format!(
+                "generate {} from {}",
+                step.output.display(),
+                step.input.display()
+            ),
+            Step::Plugin(step) => crate::plugin::describe(step),
+            Step::Custom(step) => step.kind.clone(),
+        }
+    }
+
+    fn inputs(&self, step: &Step, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+        builtin_inputs(step, cfg)
+    }
+
+    fn outputs(&self, step: &Step, cfg: &Configuration) -> std::io::Result<Vec<Artifact>> {
+        builtin_artifacts(step, cfg)
+    }
+
+    fn variables(
+        &self,
+        step: &Step,
+        cfg: &Configuration,
+    ) -> std::io::Result<HashMap<String, String>> {
+        match step {
+            Step::Plugin(step) => crate::plugin::variables(step, cfg),
+            _ => Ok(HashMap::new()),
+        }
+    }
+
+    fn execute(&self, step: &Step, cfg: &Configuration) -> std::io::Result<()> {
+        run_builtin(step, cfg)
+    }
+}
+
+fn display_path(path: &OsStr) -> std::path::Display<'_> {
+    Path::new(if path.is_empty() {
+        OsStr::new(".")
+    } else {
+        path
+    })
+    .display()
+}
+
+fn builtin_artifacts(step: &Step, cfg: &Configuration) -> std::io::Result<Vec<Artifact>> {
+    let (manifest, target) = match step {
+        Step::Plugin(step) => return crate::plugin::outputs(step, cfg),
+        Step::ConfigureFile(step) => {
+            return Ok(vec![Artifact {
+                key: step.base.display().to_string(),
+                kind: "file".to_string(),
+                path: cfg.src_dir.join(&step.base),
+            }])
+        }
+        Step::Objcopy(step) => {
+            let output = substitute(&step.output.to_string_lossy(), cfg);
+            return Ok(vec![Artifact {
+                key: output.clone(),
+                kind: "file".to_string(),
+                path: cfg.src_dir.join(output),
+            }]);
+        }
+        Step::Fetch(step) => {
+            return Ok(vec![Artifact {
+                key: crate::fetch::file_name(step).display().to_string(),
+                kind: "file".to_string(),
+                path: crate::fetch::dest(step, cfg),
+            }])
+        }
+        Step::Cbindgen(step) => {
+            return Ok(vec![Artifact {
+                key: step.output.display().to_string(),
+                kind: "file".to_string(),
+                path: crate::clib::header(step, cfg),
+            }])
+        }
+        Step::PkgConfig(step) => {
+            return Ok(vec![Artifact {
+                key: format!("{}.pc", step.name),
+                kind: "file".to_string(),
+                path: crate::clib::pc_file(step, cfg),
+            }])
+        }
+        Step::Archive(step) => {
+            return Ok(vec![Artifact {
+                key: crate::archive::lib_file(&step.name, cfg),
+                kind: "staticlib".to_string(),
+                path: crate::archive::output(step, cfg),
+            }])
+        }
+        Step::Sysroot(step) => {
+            return Ok(vec![Artifact {
+                key: step.dest.display().to_string(),
+                kind: "dir".to_string(),
+                path: crate::sysroot::dest(step, cfg),
+            }])
+        }
+        Step::Assemble(step) => {
+            let mut artifacts = Vec::new();
+            for src in crate::assemble::sources(step, cfg)? {
+                let path = crate::assemble::object(&src, cfg);
+                artifacts.push(Artifact {
+                    key: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    kind: "object".to_string(),
+                    path,
+                });
+            }
+            if let Some(path) = crate::assemble::archive(step, cfg) {
+                artifacts.push(Artifact {
+                    key: path.file_name().unwrap().to_string_lossy().into_owned(),
+                    kind: "staticlib".to_string(),
+                    path,
+                });
+            }
+            return Ok(artifacts);
+        }
+        Step::GenerateParser(step) => {
+            return Ok(std::iter::once(&step.output)
+                .chain(&step.header)
+                .map(|output| Artifact {
+                    key: output.display().to_string(),
+                    kind: "file".to_string(),
+                    path: crate::codegen::output(output, cfg),
+                })
+                .collect())
+        }
+        Step::Protoc(ProtocStep { output, .. }) | Step::Flatc(FlatcStep { output, .. }) => {
+            return Ok(vec![Artifact {
+                key: output.display().to_string(),
+                kind: "dir".to_string(),
+                path: crate::codegen::output(output, cfg),
+            }])
+        }
+        Step::Bindgen(step) => {
+            return Ok(vec![Artifact {
+                key: step.output.display().to_string(),
+                kind: "file".to_string(),
+                path: crate::bindgen::output(step, cfg),
+            }])
+        }
+        Step::Extract(step) => {
+            return Ok(vec![Artifact {
+                key: step.dest.display().to_string(),
+                kind: "dir".to_string(),
+                path: crate::extract::dest(step, cfg),
+            }])
+        }
+        Step::ThirdPartyLicenses(step) => {
+            return Ok(vec![Artifact {
+                key: step.output.display().to_string(),
+                kind: "file".to_string(),
+                path: cfg.build_dir.join(&step.output),
+            }])
+        }
+        Step::CompileTranslations(step) => {
+            return Ok(translations(step, cfg)?
+                .into_iter()
+                .map(|(_, _, artifact)| artifact)
+                .collect())
+        }
+        Step::BuildCrate(step) => (manifest_at(&step.path, cfg), &step.target),
+        Step::BuildWorkspace(step) => (manifest_at(&step.path, cfg), &step.target),
+        _ => return Ok(Vec::new()),
+    };
+    let crate_step = match step {
+        Step::BuildCrate(step) => Some(step),
+        _ => None,
+    };
+    let mut metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    if let Some(step) = crate_step {
+        select_crate_types(&mut metadata, step);
+    }
+    // A build-crate step builds only the package at its path, not the rest of its workspace
+    let packages = match step {
+        Step::BuildWorkspace(step) => workspace_members(step, &metadata)?,
+        _ => metadata
+            .packages
+            .iter()
+            .filter(|p| p.manifest_path == manifest)
+            .collect(),
+    };
+    let manifest = manifest.as_path();
+    if let Step::BuildCrate(BuildCrateStep {
+        universal: true, ..
+    }) = step
+    {
+        let dir = universal_dir(&metadata, cfg);
+        let info = &cfg.rustc.target_info;
+        return Ok(packages
+            .into_iter()
+            .flat_map(|p| {
+                package_artifacts(p, crate_step, &dir, info)
+                    .into_iter()
+                    .filter(|a| matches!(&*a.kind, "bin" | "cdylib" | "dylib" | "staticlib"))
+                    .flat_map(move |a| versioned_artifacts(step, p, manifest, a, info))
+            })
+            .collect());
+    }
+    let info = cfg.target_info(target);
+    Ok(packages
+        .into_iter()
+        .flat_map(|p| {
+            crate_artifacts(p, &metadata, crate_step, target, cfg)
+                .into_iter()
+                .flat_map(move |a| versioned_artifacts(step, p, manifest, a, info))
+        })
+        .collect())
+}
+
+struct LibNames {
+    file: String,
+    // Each link points at the name after it, ending in `file`
+    links: Vec<String>,
+    link_args: Vec<String>,
+}
+
+// `libfoo.so` becomes `libfoo.so.X.Y.Z` reached through `libfoo.so.X` and `libfoo.so`, and
+// `libfoo.dylib` becomes `libfoo.X.Y.Z.dylib` reached through `libfoo.X.dylib`
+fn lib_names(
+    step: &BuildCrateStep,
+    artifact: &Artifact,
+    info: &RustcTargetInfo,
+) -> Option<LibNames> {
+    let version = step
+        .lib_version
+        .as_deref()
+        .filter(|_| artifact.kind == "cdylib")?;
+    let soversion = step
+        .soversion
+        .as_deref()
+        .unwrap_or_else(|| version.split('.').next().unwrap_or_default());
+    let name = artifact.path.file_name()?.to_str()?;
+    let (file, soname, link_args) = match info.cdylib_suffix.to_str()? {
+        ".so" => {
+            let soname = format!("{}.{}", name, soversion);
+            (
+                format!("{}.{}", name, version),
+                soname.clone(),
+                vec![format!("-Wl,-soname,{}", soname)],
+            )
+        }
+        ".dylib" => {
+            let stem = name.strip_suffix(".dylib")?;
+            let soname = format!("{}.{}.dylib", stem, soversion);
+            (
+                format!("{}.{}.dylib", stem, version),
+                soname.clone(),
+                vec![
+                    format!("-Wl,-install_name,@rpath/{}", soname),
+                    format!("-Wl,-current_version,{}", version),
+                    format!("-Wl,-compatibility_version,{}", soversion),
+                ],
+            )
+        }
+        _ => return None,
+    };
+    let mut links = vec![name.to_string()];
+    if soname != file {
+        links.push(soname);
+    }
+    Some(LibNames {
+        file,
+        links,
+        link_args,
+    })
+}
+
+// As in versioned_libraries, only the package at the step's path is linked with a soname
+fn versioned_artifacts(
+    step: &Step,
+    package: &Package,
+    manifest: &Path,
+    artifact: Artifact,
+    info: &RustcTargetInfo,
+) -> Vec<Artifact> {
+    let names = match step {
+        Step::BuildCrate(step) if package.manifest_path == manifest => {
+            lib_names(step, &artifact, info)
+        }
+        _ => None,
+    };
+    let names = match names {
+        Some(names) => names,
+        None => return vec![artifact],
+    };
+    let package = artifact.key.split('/').next().unwrap_or_default();
+    names
+        .links
+        .iter()
+        .chain(std::iter::once(&names.file))
+        .map(|name| Artifact {
+            key: format!("{}/{}", package, name),
+            kind: artifact.kind.clone(),
+            path: artifact.path.with_file_name(name),
+        })
+        .collect()
+}
+
+fn versioned_libraries(
+    step: &BuildCrateStep,
+    cfg: &Configuration,
+) -> std::io::Result<Vec<(Artifact, LibNames)>> {
+    if step.lib_version.is_none() {
+        return Ok(Vec::new());
+    }
+    let info = if step.universal {
+        &cfg.rustc.target_info
+    } else {
+        cfg.target_info(&step.target)
+    };
+    let manifest = manifest_at(&step.path, cfg);
+    let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    let dir = if step.universal {
+        universal_dir(&metadata, cfg)
+    } else {
+        artifact_dir(&metadata, &step.target, cfg)
+    };
+    // Only the package at the step's path is built with the link arguments, not the other
+    // members of its workspace
+    Ok(metadata
+        .packages
+        .iter()
+        .filter(|p| p.manifest_path == manifest)
+        .flat_map(|p| package_artifacts(p, Some(step), &dir, info))
+        .filter_map(|a| lib_names(step, &a, info).map(|names| (a, names)))
+        .collect())
+}
+
+// Moves the cdylibs cargo produced to their versioned names and links the rest of the chain
+fn version_libraries(step: &BuildCrateStep, cfg: &Configuration) -> std::io::Result<()> {
+    for (artifact, names) in versioned_libraries(step, cfg)? {
+        // Cargo leaves an earlier link in place when the library was fresh
+        match artifact.path.symlink_metadata() {
+            Ok(meta) if meta.file_type().is_symlink() => continue,
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        }
+        let file = artifact.path.with_file_name(&names.file);
+        if file.symlink_metadata().is_ok() {
+            std::fs::remove_file(&file)?;
+        }
+        std::fs::copy(&artifact.path, &file)?;
+        let targets = names
+            .links
+            .iter()
+            .skip(1)
+            .chain(std::iter::once(&names.file));
+        for (link, to) in names.links.iter().zip(targets) {
+            let link = artifact.path.with_file_name(link);
+            if link.symlink_metadata().is_ok() {
+                std::fs::remove_file(&link)?;
+            }
+            if cfg.build_fs.symlinks() {
+                symlink(Path::new(to), &link)?;
+            } else {
+                std::fs::copy(&file, &link)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Passed to `cargo rustc --lib`, as RUSTFLAGS would give every dependency the same soname
+fn soname_flags(step: &BuildCrateStep, cfg: &Configuration) -> std::io::Result<Vec<String>> {
+    Ok(versioned_libraries(step, cfg)?
+        .into_iter()
+        .flat_map(|(_, names)| names.link_args)
+        .map(|arg| format!("-Clink-arg={}", arg))
+        .collect())
+}
+
+fn verify_static(step: &Step, cfg: &Configuration) -> std::io::Result<()> {
+    let mut offenders = Vec::new();
+    for artifact in step_artifacts(step, cfg)? {
+        if artifact.kind != "bin" {
+            continue;
+        }
+        if let Some(info) = crate::elf::dynamic_info(&artifact.path)? {
+            if !info.is_static() {
+                offenders.push(format!(
+                    "{} ({})",
+                    artifact.key,
+                    if info.needed.is_empty() {
+                        "dynamic loader".to_string()
+                    } else {
+                        info.needed.join(", ")
+                    }
+                ));
+            }
+        }
+    }
+    if offenders.is_empty() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "Static linking was requested but these binaries link dynamically: {}",
+            offenders.join("; ")
+        )))
+    }
+}
+
+fn configure_file_input(step: &ConfigureFileStep, cfg: &Configuration) -> PathBuf {
+    match &step.input {
+        Some(input) => cfg.src_dir.join(input),
+        None => {
+            let mut input = OsString::from(cfg.src_dir.join(&step.base));
+            input.push(".in");
+            PathBuf::from(input)
+        }
+    }
+}
+
+fn builtin_inputs(step: &Step, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    match step {
+        Step::BuildCrate(step) => {
+            let manifest = manifest_at(&step.path, cfg);
+            let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+            let mut inputs = vec![manifest];
+            for package in &metadata.packages {
+                inputs.extend(package.sources());
+            }
+            inputs.extend(cfg.spec_for(&step.target).map(Path::to_owned));
+            inputs.extend(cfg.generated.iter().cloned());
+            Ok(inputs)
+        }
+        Step::Fuzz(step) => {
+            let manifest = fuzz_manifest(step, cfg);
+            let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+            let mut inputs = vec![manifest, manifest_at(&step.path, cfg)];
+            for package in &metadata.packages {
+                inputs.extend(package.sources());
+            }
+            Ok(inputs)
+        }
+        Step::Test(TestStep { path, .. })
+        | Step::Bench(BenchStep { path, .. })
+        | Step::Lint(LintStep { path, .. })
+        | Step::FormatCheck(FormatCheckStep { path, .. })
+        | Step::Audit(AuditStep { path, .. })
+        | Step::ThirdPartyLicenses(ThirdPartyLicensesStep { path, .. }) => {
+            let manifest = manifest_at(path, cfg);
+            let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+            let mut inputs = vec![manifest];
+            for package in &metadata.packages {
+                inputs.extend(package.sources());
+            }
+            Ok(inputs)
+        }
+        Step::BuildWorkspace(step) => {
+            let manifest = manifest_at(&step.path, cfg);
+            let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+            let mut inputs = vec![manifest];
+            for package in workspace_members(step, &metadata)? {
+                inputs.push(package.manifest_path.clone());
+                inputs.extend(package.sources());
+            }
+            inputs.extend(cfg.spec_for(&step.target).map(Path::to_owned));
+            inputs.extend(cfg.generated.iter().cloned());
+            Ok(inputs)
+        }
+        Step::Command(step) => Ok(step
+            .inputs
+            .iter()
+            .map(|p| cfg.src_dir.join(substitute(&p.to_string_lossy(), cfg)))
+            .collect()),
+        Step::ConfigureFile(step) => Ok(vec![configure_file_input(step, cfg)]),
+        Step::Install(step) if step.from_step.is_some() => step_outputs(step, cfg),
+        Step::Install(step) => {
+            let file = substitute(&step.file.to_string_lossy(), cfg);
+            Ok(vec![cfg.src_dir.join(crate::glob::base(&file))])
+        }
+        Step::Subdirectory(step) => Ok(vec![cfg.src_dir.join(&step.path)]),
+        Step::GenerateDocs(_) | Step::InstallSymlink(_) | Step::Fetch(_) => Ok(Vec::new()),
+        Step::ApplyPatches(step) => Ok(step
+            .patches
+            .iter()
+            .chain(&step.series)
+            .map(|p| cfg.src_dir.join(p))
+            .chain(std::iter::once(crate::patch::dir(step, cfg)))
+            .collect()),
+        Step::Extract(step) => Ok(vec![crate::extract::archive(step, cfg)]),
+        Step::Bindgen(step) => Ok(crate::bindgen::inputs(step, cfg)),
+        Step::Protoc(step) => Ok(crate::codegen::protoc_inputs(step, cfg)),
+        Step::Flatc(step) => Ok(crate::codegen::flatc_inputs(step, cfg)),
+        Step::GenerateParser(step) => Ok(vec![crate::codegen::parser_input(step, cfg)]),
+        Step::Assemble(step) => crate::assemble::sources(step, cfg),
+        Step::Archive(step) => crate::archive::inputs(step, cfg),
+        Step::Sysroot(step) => crate::sysroot::sources(step, cfg),
+        Step::Cbindgen(step) => crate::clib::cbindgen_inputs(step, cfg),
+        Step::PkgConfig(step) => Ok(vec![manifest_at(&step.path, cfg)]),
+        Step::Codesign(step) => Ok(step
+            .files
+            .iter()
+            .map(|f| cfg.src_dir.join(substitute(&f.to_string_lossy(), cfg)))
+            .collect()),
+        Step::Objcopy(step) => Ok(vec![cfg
+            .src_dir
+            .join(substitute(&step.input.to_string_lossy(), cfg))]),
+        Step::CompileTranslations(step) => Ok(vec![cfg.src_dir.join(&step.dir)]),
+        Step::InstallDesktop(step) => listed_files(
+            step.desktop.iter().chain(&step.icons).chain(&step.metainfo),
+            cfg,
+        ),
+        Step::InstallSystemd(step) => listed_files(
+            systemd_files(step).into_iter().flat_map(|(_, files)| files),
+            cfg,
+        ),
+        Step::Plugin(step) => crate::plugin::inputs(step, cfg),
+        Step::Custom(step) => Err(unhandled(step)),
+    }
+}
+
+fn run_builtin(step: &Step, cfg: &Configuration) -> std::io::Result<()> {
+    match step {
+        Step::BuildCrate(s) => {
+            build_crate(s, cfg)?;
+            version_libraries(s, cfg)?;
+            if cfg.static_link {
+                verify_static(step, cfg)?;
+            }
+            Ok(())
+        }
+        Step::BuildWorkspace(s) => {
+            build_workspace(s, cfg)?;
+            if cfg.static_link {
+                verify_static(step, cfg)?;
+            }
+            Ok(())
+        }
+        Step::Command(step) => command(step, cfg),
+        Step::ConfigureFile(step) => configure_file(step, cfg),
+        Step::Install(step) => install(step, cfg),
+        Step::InstallSymlink(step) => install_symlink(step, cfg),
+        Step::GenerateDocs(_) => generate_docs(cfg),
+        Step::Subdirectory(step) => subdirectory(step, cfg),
+        Step::Codesign(step) => codesign(step, cfg),
+        Step::Objcopy(step) => objcopy(step, cfg),
+        Step::CompileTranslations(step) => compile_translations(step, cfg),
+        Step::InstallDesktop(step) => install_desktop(step, cfg),
+        Step::InstallSystemd(step) => install_systemd(step, cfg),
+        Step::Test(step) => test(step, cfg),
+        Step::Bench(step) => bench(step, cfg),
+        Step::Fuzz(step) => fuzz(step, cfg),
+        Step::Lint(step) => lint(step, cfg),
+        Step::FormatCheck(step) => format_check(step, cfg),
+        Step::Audit(step) => audit(step, cfg),
+        Step::Fetch(step) => crate::fetch::fetch(step, cfg),
+        Step::ApplyPatches(step) => crate::patch::apply(step, cfg),
+        Step::Extract(step) => crate::extract::extract(step, cfg),
+        Step::Bindgen(step) => crate::bindgen::generate(step, cfg),
+        Step::Protoc(step) => crate::codegen::protoc(step, cfg),
+        Step::Flatc(step) => crate::codegen::flatc(step, cfg),
+        Step::GenerateParser(step) => crate::codegen::generate_parser(step, cfg),
+        Step::Assemble(step) => crate::assemble::assemble(step, cfg),
+        Step::Archive(step) => crate::archive::archive(step, cfg),
+        Step::Sysroot(step) => crate::sysroot::assemble(step, cfg),
+        Step::Cbindgen(step) => crate::clib::cbindgen(step, cfg),
+        Step::PkgConfig(step) => crate::clib::pkg_config(step, cfg),
+        Step::ThirdPartyLicenses(step) => third_party_licenses(step, cfg),
+        Step::Plugin(step) => crate::plugin::execute(step, cfg),
+        Step::Custom(step) => Err(unhandled(step)),
+    }
+}
+
+fn cargo_build(
+    cfg: &Configuration,
+    manifest: &Path,
+    target: &BuildTarget,
+    features: &[String],
+    rustflags: &[String],
+) -> Command {
+    let mut cmd = Command::new(&cfg.cargo);
+    cmd.arg("build").arg("--profile");
+    cargo_options(cmd, cfg, manifest, target, features, rustflags)
+}
+
+// Adds everything after the profile flag, which cargo-nextest spells differently from cargo
+fn cargo_options(
+    mut cmd: Command,
+    cfg: &Configuration,
+    manifest: &Path,
+    target: &BuildTarget,
+    features: &[String],
+    rustflags: &[String],
+) -> Command {
+    cmd.arg(&cfg.profile.cargo_profile);
+    cargo_flags(cmd, cfg, manifest, target, features, rustflags)
+}
+
+// What `cargo_options` adds after the profile, for commands given no profile
+fn cargo_flags(
+    mut cmd: Command,
+    cfg: &Configuration,
+    manifest: &Path,
+    target: &BuildTarget,
+    features: &[String],
+    rustflags: &[String],
+) -> Command {
+    cmd.arg("--manifest-path")
+        .arg(manifest)
+        .env("RUSTC", &cfg.rustc_for(target).rustc)
+        .envs(&cfg.toolchain_env)
+        .envs(&cfg.build_env)
+        .envs(cfg.profile.cargo_env());
+    if let Some(dir) = &cfg.target_dir {
+        cmd.env("CARGO_TARGET_DIR", dir);
+    }
+    let injected = &cfg.injected_rustflags.target;
+    if !cfg.profile.rustflags.is_empty() || !rustflags.is_empty() || !injected.is_empty() {
+        let mut flags = injected.prepend.join(" ");
+        let env = std::env::var("RUSTFLAGS").unwrap_or_default();
+        for flag in std::iter::once(&env)
+            .filter(|f| !f.is_empty())
+            .chain(&cfg.profile.rustflags)
+            .chain(rustflags)
+            .chain(&injected.append)
+        {
+            if !flags.is_empty() {
+                flags.push(' ');
+            }
+            flags.push_str(flag);
+        }
+        cmd.env("RUSTFLAGS", flags);
+    }
+    cmd.envs(rustdoc_env(cfg, &[]));
+    if let Some(spec) = cfg.spec_for(target) {
+        cmd.arg("-Zjson-target-spec").arg("--target").arg(spec);
+    } else {
+        cmd.arg("--target")
+            .arg(cfg.resolve_target(target).get_name());
+    }
+    if cfg
+        .sanitizers
+        .iter()
+        .any(|s| s == "memory" || s == "thread")
+    {
+        cmd.arg("-Zbuild-std");
+    } else if matches!(target, BuildTarget::Spec(_))
+        || (cfg.spec_for(target).is_some() && cfg.rustc_for(target).no_std)
+    {
+        cmd.arg("-Zbuild-std=core,alloc");
+    }
+    crate::wrappers::install(&mut cmd, cfg, true);
+    let features = resolve_features(features, cfg);
+    if !features.is_empty() {
+        cmd.arg("--features").arg(features.join(","));
+    }
+    if cfg.offline {
+        cmd.arg("--offline");
+    }
+    cmd
+}
+
+fn option_values(text: &str, cfg: &Configuration) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("${option:") {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start + "${option:".len()..];
+        match tail.find('}') {
+            Some(end) => {
+                out.push_str(cfg.options.get(&tail[..end]).map_or("", |v| &**v));
+                rest = &tail[end + 1..];
+            }
+            None => {
+                out.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+// Features may name configure options as ${option:with-foo}, which are empty when not given.
+// `feature?=value` enables the feature unless the value is empty, "no" or "false"
+pub fn resolve_features(features: &[String], cfg: &Configuration) -> Vec<String> {
+    features
+        .iter()
+        .filter_map(|feature| match feature.split_once("?=") {
+            Some((name, value)) => {
+                let value = option_values(value, cfg);
+                Some(option_values(name, cfg))
+                    .filter(|_| !matches!(value.trim(), "" | "no" | "false"))
+            }
+            None => Some(option_values(feature, cfg)),
+        })
+        .filter(|feature| !feature.is_empty())
+        .collect()
+}
+
+fn rustdoc_env(cfg: &Configuration, extra: &[String]) -> Option<(&'static str, String)> {
+    if cfg.rustdocflags.is_empty() && extra.is_empty() {
+        return None;
+    }
+    let mut flags = std::env::var("RUSTDOCFLAGS").unwrap_or_default();
+    for flag in cfg.rustdocflags.iter().chain(extra) {
+        if !flags.is_empty() {
+            flags.push(' ');
+        }
+        flags.push_str(flag);
+    }
+    Some(("RUSTDOCFLAGS", flags))
+}
+
+fn generate_docs(cfg: &Configuration) -> std::io::Result<()> {
+    let mut cmd = Command::new(&cfg.cargo);
+    cmd.arg("doc")
+        .arg("--no-deps")
+        .arg("--manifest-path")
+        .arg(cfg.src_dir.join("Cargo.toml"))
+        .env("RUSTC", &cfg.rustc.rustc)
+        .envs(rustdoc_env(cfg, &[]));
+    if let Some(dir) = &cfg.target_dir {
+        cmd.env("CARGO_TARGET_DIR", dir);
+    }
+    crate::wrappers::install(&mut cmd, cfg, false);
+    run(&mut cmd)
+}
+
+fn compile_resources(rc_file: &Path, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    let rc = cfg.rc.as_ref().ok_or_else(|| {
+        std::io::Error::new(ErrorKind::NotFound, "No resource compiler was configured")
+    })?;
+    let windres = rc
+        .file_name()
+        .is_some_and(|n| n.to_string_lossy().contains("windres"));
+    let out_dir = cfg.build_dir.join("res");
+    std::fs::create_dir_all(&out_dir)?;
+    let mut out = out_dir.join(rc_file.file_stem().unwrap_or_default());
+    out.set_extension(if windres { "o" } else { "res" });
+
+    let mut cmd = Command::new(rc);
+    if windres {
+        cmd.arg("-O")
+            .arg("coff")
+            .arg("-i")
+            .arg(rc_file)
+            .arg("-o")
+            .arg(&out);
+    } else {
+        let mut fo = OsString::from("/fo");
+        fo.push(&out);
+        cmd.arg("/nologo").arg(fo).arg(rc_file);
+    }
+    if let Some(dir) = rc_file.parent() {
+        cmd.current_dir(dir);
+    }
+    run(&mut cmd)?;
+    Ok(out)
+}
+
+// Each directory in install-dirs reaches the crate as an upper case variable, so that
+// `env!("SYSCONFDIR")` finds the configured path rather than a hardcoded one
+pub fn install_dir_env(
+    step: &BuildCrateStep,
+    cfg: &Configuration,
+) -> std::io::Result<HashMap<String, String>> {
+    step.install_dirs
+        .iter()
+        .map(|name| {
+            let path = cfg
+                .dirs
+                .get(&InstallDirectory::from_name(name))
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::NotFound,
+                        format!("Unknown install directory {}", name),
+                    )
+                })?;
+            let var = name.to_uppercase().replace('-', "_");
+            Ok((var, path.display().to_string()))
+        })
+        .collect()
+}
+
+fn build_crate(step: &BuildCrateStep, cfg: &Configuration) -> std::io::Result<()> {
+    let manifest = manifest_at(&step.path, cfg);
+    let mut rustflags = step.rustflags.clone();
+    let mut bin_args = Vec::new();
+    if let Some(resources) = &step.resources {
+        let res = compile_resources(&cfg.src_dir.join(resources), cfg)?;
+        bin_args.push(format!("-Clink-arg={}", res.display()));
+    }
+    let lib_args = soname_flags(step, cfg)?;
+    let mut env = install_dir_env(step, cfg)?;
+    env.extend(step.env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    if !Flavor::of(&cfg.rustc_for(&step.target).version).uses_cargo() {
+        if step.universal {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "Universal builds require a compiler cargo can drive",
+            ));
+        }
+        if !step.bins.is_empty() || !step.examples.is_empty() || !step.benches.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                "Picking binaries, examples or benches requires a compiler cargo can drive",
+            ));
+        }
+        let mut metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+        select_crate_types(&mut metadata, step);
+        let packages: Vec<&Package> = metadata.packages.iter().collect();
+        rustflags.extend(bin_args);
+        rustflags.extend(lib_args);
+        return crate::compat::build(
+            &packages,
+            &step.target,
+            &step.features,
+            !step.no_default_features,
+            &rustflags,
+            &env,
+            cfg,
+        );
+    }
+    let cargo = |subcommand: &str, target: &BuildTarget| {
+        let mut cmd = Command::new(&cfg.cargo);
+        cmd.arg(subcommand).arg("--profile");
+        let mut cmd = cargo_options(cmd, cfg, &manifest, target, &step.features, &rustflags);
+        cmd.envs(rustdoc_env(cfg, &step.rustdocflags)).envs(&env);
+        if step.no_default_features {
+            cmd.arg("--no-default-features");
+        }
+        cmd
+    };
+    let mut metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    select_crate_types(&mut metadata, step);
+    let has = |kinds: &[&str]| {
+        metadata
+            .packages
+            .iter()
+            .flat_map(|p| &p.targets)
+            .any(|t| t.kind.iter().any(|k| kinds.contains(&&**k)))
+    };
+    let picked = !step.bins.is_empty() || !step.examples.is_empty() || !step.benches.is_empty();
+    // The library goes through `cargo rustc` when it needs crate types or link arguments, and so
+    // does each binary of the package that links the resources
+    let rustc_lib = !step.crate_types.is_empty() || !lib_args.is_empty();
+    let rustc_bins: Vec<&str> = if bin_args.is_empty() {
+        Vec::new()
+    } else if !step.bins.is_empty() {
+        step.bins.iter().map(|name| &**name).collect()
+    } else {
+        metadata
+            .packages
+            .iter()
+            .filter(|p| p.manifest_path == manifest)
+            .flat_map(|p| &p.targets)
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| &*t.name)
+            .collect()
+    };
+    let explicit = picked || rustc_lib || !rustc_bins.is_empty();
+    let mut targets: Vec<&str> = Vec::new();
+    if explicit {
+        if !rustc_lib && (has(LIB_CRATE_TYPES) || has(&["proc-macro"])) {
+            targets.push("--lib");
+        }
+        if rustc_bins.is_empty() && step.bins.is_empty() && has(&["bin"]) {
+            targets.push("--bins");
+        }
+        for (flag, names) in &[
+            ("--bin", &step.bins),
+            ("--example", &step.examples),
+            ("--bench", &step.benches),
+        ] {
+            if *flag == "--bin" && !rustc_bins.is_empty() {
+                continue;
+            }
+            for name in names.iter() {
+                targets.push(*flag);
+                targets.push(name);
+            }
+        }
+    }
+    // cargo takes --crate-type and arguments for one target only from `cargo rustc`, which
+    // builds one target at a time, so the others are built by `cargo build`. The library comes
+    // last, as building the binaries builds it again without its arguments
+    let crate_build = |target: &BuildTarget| {
+        if !explicit || !targets.is_empty() {
+            let mut build = cargo("build", target);
+            build.args(&targets);
+            if step.benches.is_empty() {
+                run(&mut build)?;
+            } else {
+                let dir = artifact_dir(&metadata, target, cfg);
+                copy_benches(&mut build, &dir, cfg.target_info(target))?;
+            }
+        }
+        for name in &rustc_bins {
+            run(cargo("rustc", target)
+                .arg("--bin")
+                .arg(name)
+                .arg("--")
+                .args(&bin_args))?;
+        }
+        if rustc_lib {
+            let mut cmd = cargo("rustc", target);
+            cmd.arg("--lib");
+            if !step.crate_types.is_empty() {
+                cmd.arg("--crate-type").arg(step.crate_types.join(","));
+            }
+            if !lib_args.is_empty() {
+                cmd.arg("--").args(&lib_args);
+            }
+            run(&mut cmd)?;
+        }
+        Ok(())
+    };
+    if !step.universal {
+        return crate_build(&step.target);
+    }
+
+    let lipo = cfg
+        .lipo
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "Universal builds require lipo"))?;
+    let mut slices = Vec::new();
+    for name in UNIVERSAL_TARGETS {
+        let target = universal_target(name)?;
+        crate_build(&target)?;
+        slices.push(artifact_dir(&metadata, &target, cfg));
+    }
+
+    let out_dir = universal_dir(&metadata, cfg);
+    std::fs::create_dir_all(&out_dir)?;
+    for package in &metadata.packages {
+        for artifact in package_artifacts(package, Some(step), &out_dir, &cfg.rustc.target_info) {
+            if !matches!(&*artifact.kind, "bin" | "cdylib" | "dylib" | "staticlib") {
+                continue;
+            }
+            let file = artifact.path.file_name().unwrap();
+            let mut cmd = Command::new(lipo);
+            cmd.arg("-create").arg("-output").arg(&artifact.path);
+            for slice in &slices {
+                cmd.arg(slice.join(file));
+            }
+            run(&mut cmd)?;
+        }
+    }
+    Ok(())
+}
+
+fn build_workspace(step: &BuildWorkspaceStep, cfg: &Configuration) -> std::io::Result<()> {
+    let manifest = manifest_at(&step.path, cfg);
+    let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    if !Flavor::of(&cfg.rustc_for(&step.target).version).uses_cargo() {
+        return crate::compat::build(
+            &workspace_members(step, &metadata)?,
+            &step.target,
+            &step.features,
+            true,
+            &step.rustflags,
+            &HashMap::new(),
+            cfg,
+        );
+    }
+    let mut cmd = cargo_build(
+        cfg,
+        &manifest,
+        &step.target,
+        &step.features,
+        &step.rustflags,
+    );
+    for package in workspace_members(step, &metadata)? {
+        cmd.arg("--package").arg(&package.name);
+    }
+    run(&mut cmd)
+}
+
+fn nextest<'a>(step: &TestStep, cfg: &'a Configuration) -> std::io::Result<Option<&'a PathBuf>> {
+    match (&cfg.nextest, step.nextest) {
+        (_, Some(false)) => Ok(None),
+        (None, Some(true)) => Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            "This test step requires cargo-nextest, which was not found",
+        )),
+        (nextest, _) => Ok(nextest.as_ref()),
+    }
+}
+
+fn cargo_test(step: &TestStep, cfg: &Configuration) -> Command {
+    let mut cmd = Command::new(&cfg.cargo);
+    cmd.arg("test").arg("--profile");
+    cargo_options(
+        cmd,
+        cfg,
+        &manifest_at(&step.path, cfg),
+        &step.target,
+        &step.features,
+        &step.rustflags,
+    )
+}
+
+fn test_command(step: &TestStep, cfg: &Configuration) -> std::io::Result<Command> {
+    let nextest = nextest(step, cfg)?;
+    let mut cmd = match nextest {
+        Some(nextest) => {
+            let mut cmd = Command::new(nextest);
+            cmd.arg("nextest")
+                .arg("run")
+                .env("CARGO", &cfg.cargo)
+                .arg("--cargo-profile");
+            let mut cmd = cargo_options(
+                cmd,
+                cfg,
+                &manifest_at(&step.path, cfg),
+                &step.target,
+                &step.features,
+                &step.rustflags,
+            );
+            if let Some(partition) = &step.partition {
+                cmd.arg("--partition").arg(partition);
+            }
+            if let Some(retries) = step.retries {
+                cmd.arg("--retries").arg(retries.to_string());
+            }
+            cmd
+        }
+        None => {
+            if step.partition.is_some() || step.retries.is_some() {
+                eprintln!(
+                    "cargo-autobuild: warning: cargo-nextest was not found; running cargo test without partition or retries"
+                );
+            }
+            cargo_test(step, cfg)
+        }
+    };
+    let reports = step.junit.is_some() || step.tap.is_some();
+    if reports && nextest.is_some() {
+        cmd.arg("--message-format")
+            .arg("libtest-json")
+            .env("NEXTEST_EXPERIMENTAL_LIBTEST_JSON", "1");
+    } else if reports {
+        // The test binaries are run by `test_binaries`
+        cmd.arg("--no-run").arg("--message-format").arg("json");
+    }
+    Ok(cmd)
+}
+
+// Records the tests `cmd` reports as libtest JSON, passing its other output through
+fn report_tests(
+    cmd: &mut Command,
+    cases: &mut Vec<crate::test_report::TestCase>,
+) -> std::io::Result<ExitStatus> {
+    run_reading(cmd, |line| {
+        if !crate::test_report::parse_line(line, cases) {
+            note(line);
+        }
+        Ok(())
+    })
+}
+
+// Runs a cargo command given --message-format json, passing its diagnostics and other output
+// through and handing each artifact it built to `artifact`
+fn read_artifacts(
+    cmd: &mut Command,
+    mut artifact: impl FnMut(crate::cargo::BuildMessage),
+) -> std::io::Result<()> {
+    let status = run_reading(cmd, |line| {
+        let message: crate::cargo::BuildMessage = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(_) => {
+                note(line);
+                return Ok(());
+            }
+        };
+        if message.reason == "compiler-artifact" {
+            artifact(message);
+        } else if let Some(rendered) = message.message.and_then(|m| m.rendered) {
+            note(rendered.trim_end());
+        }
+        Ok(())
+    })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, status
+        )))
+    }
+}
+
+// cargo runs test binaries under the target's runner, such as a WebAssembly runtime, so
+// `test_binaries` does too
+fn target_runner(target: &BuildTarget, cfg: &Configuration) -> Vec<String> {
+    let triple = match cfg.spec_for(target) {
+        Some(spec) => spec
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        None => cfg.resolve_target(target).get_name().to_string(),
+    };
+    let var = format!(
+        "CARGO_TARGET_{}_RUNNER",
+        triple.to_uppercase().replace(['-', '.'], "_")
+    );
+    cfg.toolchain_env
+        .get(&var)
+        .cloned()
+        .or_else(|| std::env::var(&var).ok())
+        .map(|runner| runner.split_whitespace().map(str::to_string).collect())
+        .unwrap_or_default()
+}
+
+// Builds the tests with `build`, a `cargo test --no-run`, and runs each test binary it made.
+// libtest prints JSON only behind -Zunstable-options, which RUSTC_BOOTSTRAP unlocks on stable;
+// set for cargo, it would also let the crates being tested use unstable features
+fn test_binaries(
+    build: &mut Command,
+    runner: &[String],
+    cases: &mut Vec<crate::test_report::TestCase>,
+) -> std::io::Result<Vec<(Command, ExitStatus)>> {
+    let mut binaries = Vec::new();
+    read_artifacts(build, |message| {
+        if !message.profile.is_some_and(|p| p.test) {
+            return;
+        }
+        if let (Some(executable), Some(manifest)) = (message.executable, message.manifest_path) {
+            binaries.push((executable, manifest));
+        }
+    })?;
+
+    let mut results = Vec::new();
+    for (executable, manifest) in binaries {
+        let dir = manifest.parent().unwrap_or_else(|| Path::new("."));
+        let mut cmd = match runner.split_first() {
+            Some((program, args)) => {
+                let mut cmd = Command::new(program);
+                cmd.args(args).arg(executable);
+                cmd
+            }
+            None => Command::new(executable),
+        };
+        cmd.arg("-Zunstable-options")
+            .arg("--format")
+            .arg("json")
+            .arg("--report-time")
+            .env("RUSTC_BOOTSTRAP", "1")
+            .env("CARGO_MANIFEST_DIR", dir)
+            .current_dir(dir);
+        let status = report_tests(&mut cmd, cases)?;
+        results.push((cmd, status));
+    }
+    Ok(results)
+}
+
+// Whether `cargo test` would run doctests, which only libraries have
+fn has_doctests(step: &TestStep, cfg: &Configuration) -> std::io::Result<bool> {
+    let manifest = manifest_at(&step.path, cfg);
+    let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+    let at_path = metadata
+        .packages
+        .iter()
+        .any(|p| p.manifest_path == manifest);
+    Ok(metadata
+        .packages
+        .iter()
+        .filter(|p| !at_path || p.manifest_path == manifest)
+        .flat_map(|p| &p.targets)
+        .any(|t| {
+            t.kind
+                .iter()
+                .any(|k| k == "proc-macro" || LIB_CRATE_TYPES.contains(&&**k))
+        }))
+}
+
+fn test(step: &TestStep, cfg: &Configuration) -> std::io::Result<()> {
+    let mut cmd = test_command(step, cfg)?;
+    if step.junit.is_none() && step.tap.is_none() {
+        return run(&mut cmd);
+    }
+
+    let mut cases = Vec::new();
+    let mut results = if nextest(step, cfg)?.is_some() {
+        let status = report_tests(&mut cmd, &mut cases)?;
+        vec![(cmd, status)]
+    } else {
+        test_binaries(&mut cmd, &target_runner(&step.target, cfg), &mut cases)?
+    };
+    // Neither cargo-nextest nor the test binaries run doctests, which cannot report as libtest
+    // JSON without RUSTC_BOOTSTRAP on cargo itself. They run apart and are left out of the report
+    if has_doctests(step, cfg)? {
+        let mut doc = cargo_test(step, cfg);
+        doc.arg("--doc");
+        let status = run_reading(&mut doc, |line| {
+            note(line);
+            Ok(())
+        })?;
+        results.push((doc, status));
+    }
+
+    let suite = display_path(&step.path).to_string();
+    if let Some(junit) = &step.junit {
+        let path = cfg.src_dir.join(substitute(&junit.to_string_lossy(), cfg));
+        crate::test_report::write(&path, &crate::test_report::junit(&suite, &cases))?;
+    }
+    if let Some(tap) = &step.tap {
+        let path = cfg.src_dir.join(substitute(&tap.to_string_lossy(), cfg));
+        crate::test_report::write(&path, &crate::test_report::tap(&cases))?;
+    }
+    match results.into_iter().find(|(_, status)| !status.success()) {
+        None => Ok(()),
+        Some((cmd, status)) => Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, status
+        ))),
+    }
+}
+
+fn bench(step: &BenchStep, cfg: &Configuration) -> std::io::Result<()> {
+    let mut cmd = Command::new(&cfg.cargo);
+    cmd.arg("bench");
+    // cargo bench builds with the bench profile unless a profile was asked for
+    let options = if cfg.profile.explicit {
+        cmd.arg("--profile");
+        cargo_options
+    } else {
+        cargo_flags
+    };
+    cmd = options(
+        cmd,
+        cfg,
+        &manifest_at(&step.path, cfg),
+        &step.target,
+        &step.features,
+        &step.rustflags,
+    );
+    for package in &step.packages {
+        cmd.arg("--package").arg(package);
+    }
+    let mut parser = crate::bench::Parser::new();
+    let mut results = std::collections::BTreeMap::new();
+    let status = run_reading(&mut cmd, |line| {
+        note(line);
+        parser.line(line, &mut results);
+        Ok(())
+    })?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!(
+            "{:?} failed ({})",
+            cmd, status
+        )));
+    }
+
+    let suite = display_path(&step.path).to_string();
+    let results = crate::bench::archive(&cfg.build_dir, &suite, results)?;
+    let compared = match &step.baseline {
+        Some(baseline) => crate::bench::compare(&cfg.build_dir, baseline, step.threshold, &results),
+        None => Ok(()),
+    };
+    if let Some(name) = &step.save_baseline {
+        crate::bench::save_baseline(&cfg.build_dir, name, &results)?;
+    }
+    compared
+}
+
+fn lint(step: &LintStep, cfg: &Configuration) -> std::io::Result<()> {
+    if !cfg.clippy {
+        eprintln!("cargo-autobuild: warning: clippy is not installed; skipping the lint step");
+        return Ok(());
+    }
+    let mut cmd = Command::new(&cfg.cargo);
+    cmd.arg("clippy").arg("--profile");
+    cmd = cargo_options(
+        cmd,
+        cfg,
+        &manifest_at(&step.path, cfg),
+        &step.target,
+        &step.features,
+        &[],
+    );
+    if step.all_targets {
+        cmd.arg("--all-targets");
+    }
+    cmd.arg("--");
+    for (flag, lints) in &[("-D", &step.deny), ("-W", &step.warn), ("-A", &step.allow)] {
+        for lint in lints.iter() {
+            cmd.arg(flag).arg(lint);
+        }
+    }
+    run(&mut cmd)
+}
+
+fn format_check(step: &FormatCheckStep, cfg: &Configuration) -> std::io::Result<()> {
+    if !cfg.rustfmt {
+        eprintln!("cargo-autobuild: warning: rustfmt is not installed; skipping the format check");
+        return Ok(());
+    }
+    run(Command::new(&cfg.cargo)
+        .arg("fmt")
+        .arg("--all")
+        .arg("--manifest-path")
+        .arg(manifest_at(&step.path, cfg))
+        .arg("--")
+        .arg("--check"))
+}
+
+// cargo-deny takes its allow lists from a deny.toml, so one is written for each run
+fn deny_config(step: &AuditStep, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    let strings = |list: &[String]| {
+        toml::Value::Array(list.iter().cloned().map(toml::Value::String).collect())
+    };
+    let mut advisories = toml::value::Table::new();
+    advisories.insert("ignore".to_string(), strings(&step.ignore));
+    let mut config = toml::value::Table::new();
+    config.insert("advisories".to_string(), toml::Value::Table(advisories));
+    if let Some(licenses) = &step.licenses {
+        let mut table = toml::value::Table::new();
+        table.insert("allow".to_string(), strings(licenses));
+        config.insert("licenses".to_string(), toml::Value::Table(table));
+    }
+    let text = toml::to_string(&config)
+        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e.to_string()))?;
+    let path = cfg.build_dir.join("deny.toml");
+    std::fs::write(&path, text)?;
+    Ok(path)
+}
+
+fn audit(step: &AuditStep, cfg: &Configuration) -> std::io::Result<()> {
+    let tool = match step.tool {
+        Some(AuditTool::CargoAudit) => cfg.cargo_audit.as_ref().map(|p| (AuditTool::CargoAudit, p)),
+        Some(AuditTool::CargoDeny) => cfg.cargo_deny.as_ref().map(|p| (AuditTool::CargoDeny, p)),
+        None => cfg
+            .cargo_deny
+            .as_ref()
+            .map(|p| (AuditTool::CargoDeny, p))
+            .or_else(|| cfg.cargo_audit.as_ref().map(|p| (AuditTool::CargoAudit, p))),
+    };
+    let manifest = manifest_at(&step.path, cfg);
+    match tool {
+        None => {
+            eprintln!("cargo-autobuild: warning: neither cargo-audit nor cargo-deny was found; skipping the audit");
+            Ok(())
+        }
+        Some((AuditTool::CargoDeny, cargo_deny)) => {
+            let mut cmd = Command::new(cargo_deny);
+            cmd.arg("deny")
+                .arg("--manifest-path")
+                .arg(&manifest)
+                .arg("check")
+                .arg("--config")
+                .arg(deny_config(step, cfg)?)
+                .env("CARGO", &cfg.cargo);
+            if step.deny_warnings {
+                cmd.arg("--deny").arg("warnings");
+            }
+            cmd.arg("advisories");
+            if step.licenses.is_some() {
+                cmd.arg("licenses");
+            }
+            run(&mut cmd)
+        }
+        Some((AuditTool::CargoAudit, cargo_audit)) => {
+            if step.licenses.is_some() {
+                eprintln!("cargo-autobuild: warning: cargo-audit does not check licenses; use cargo-deny for the license allow list");
+            }
+            let metadata = crate::cargo::metadata(&cfg.cargo, &manifest)?;
+            let mut cmd = Command::new(cargo_audit);
+            cmd.arg("audit")
+                .arg("--file")
+                .arg(metadata.workspace_root.join("Cargo.lock"));
+            for id in &step.ignore {
+                cmd.arg("--ignore").arg(id);
+            }
+            if step.deny_warnings {
+                cmd.arg("--deny").arg("warnings");
+            }
+            run(&mut cmd)
+        }
+    }
+}
+
+fn third_party_licenses(step: &ThirdPartyLicensesStep, cfg: &Configuration) -> std::io::Result<()> {
+    let target = match cfg.spec_for(&step.target) {
+        Some(_) => &cfg.host,
+        None => cfg.resolve_target(&step.target),
+    };
+    let metadata =
+        crate::cargo::dependencies(&cfg.cargo, &manifest_at(&step.path, cfg), target.get_name())?;
+    let path = cfg.build_dir.join(&step.output);
+    std::fs::write(&path, crate::licenses::notice(&metadata)?)?;
+    note(&format!("Wrote {}", path.display()));
+    if step.install {
+        let docdir = destdir(&cfg.dirs[&InstallDirectory::DocDir]);
+        install_copy(&path, &docdir.join(&step.output), cfg)?;
+    }
+    Ok(())
+}
+
+// What cargo-fuzz passes to rustc so that libfuzzer-sys gets coverage feedback
+const SANCOV_FLAGS: &[&str] = &[
+    "-Cpasses=sancov-module",
+    "-Cllvm-args=-sanitizer-coverage-level=4",
+    "-Cllvm-args=-sanitizer-coverage-inline-8bit-counters",
+    "-Cllvm-args=-sanitizer-coverage-pc-table",
+    "-Cllvm-args=-sanitizer-coverage-trace-compares",
+    "--cfg",
+    "fuzzing",
+];
+
+fn fuzz_manifest(step: &FuzzStep, cfg: &Configuration) -> PathBuf {
+    let mut manifest = cfg.src_dir.join(&step.path).join(&step.fuzz_dir);
+    manifest.push("Cargo.toml");
+    manifest
+}
+
+// Builds the fuzz targets for the build machine, since the smoke test runs them, and returns
+// where the binaries ended up
+fn build_fuzz_targets(
+    step: &FuzzStep,
+    cfg: &Configuration,
+    metadata: &Metadata,
+    targets: &[String],
+) -> std::io::Result<PathBuf> {
+    let cargo_fuzz = match (&cfg.cargo_fuzz, step.cargo_fuzz) {
+        (_, Some(false)) => None,
+        (None, Some(true)) => {
+            return Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                "This fuzz step requires cargo-fuzz, which was not found",
+            ))
+        }
+        (cargo_fuzz, _) => cargo_fuzz.as_ref(),
+    };
+    let cargo_fuzz = match cargo_fuzz {
+        Some(cargo_fuzz) => cargo_fuzz,
+        None => {
+            let mut rustflags: Vec<String> = SANCOV_FLAGS.iter().map(|s| s.to_string()).collect();
+            rustflags.extend(step.rustflags.iter().cloned());
+            let mut cmd = cargo_build(
+                cfg,
+                &fuzz_manifest(step, cfg),
+                &BuildTarget::Build,
+                &step.features,
+                &rustflags,
+            );
+            for target in targets {
+                cmd.arg("--bin").arg(target);
+            }
+            run(&mut cmd)?;
+            return Ok(artifact_dir(metadata, &BuildTarget::Build, cfg));
+        }
+    };
+
+    // cargo-fuzz builds one target, or all of them when none is named
+    let names: Vec<Option<&String>> = if step.targets.is_empty() {
+        vec![None]
+    } else {
+        targets.iter().map(Some).collect()
+    };
+    for name in names {
+        let mut cmd = Command::new(cargo_fuzz);
+        cmd.arg("fuzz")
+            .arg("build")
+            .arg("--fuzz-dir")
+            .arg(cfg.src_dir.join(&step.path).join(&step.fuzz_dir))
+            .arg("--target")
+            .arg(cfg.build.get_name())
+            .current_dir(cfg.src_dir.join(&step.path))
+            .env("CARGO", &cfg.cargo)
+            .env("RUSTC", &cfg.rustc.rustc)
+            .envs(&cfg.toolchain_env);
+        let features = resolve_features(&step.features, cfg);
+        if !features.is_empty() {
+            cmd.arg("--features").arg(features.join(","));
+        }
+        if !step.rustflags.is_empty() {
+            let mut flags = std::env::var("RUSTFLAGS").unwrap_or_default();
+            for flag in &step.rustflags {
+                if !flags.is_empty() {
+                    flags.push(' ');
+                }
+                flags.push_str(flag);
+            }
+            cmd.env("RUSTFLAGS", flags);
+        }
+        cmd.args(name);
+        run(&mut cmd)?;
+    }
+    Ok(metadata
+        .target_directory
+        .join(cfg.build.get_name())
+        .join("release"))
+}
+
+fn fuzz(step: &FuzzStep, cfg: &Configuration) -> std::io::Result<()> {
+    let metadata = crate::cargo::metadata(&cfg.cargo, &fuzz_manifest(step, cfg))?;
+    let targets = if step.targets.is_empty() {
+        metadata
+            .packages
+            .iter()
+            .flat_map(|p| &p.targets)
+            .filter(|t| t.kind.iter().any(|k| k == "bin"))
+            .map(|t| t.name.clone())
+            .collect()
+    } else {
+        step.targets.clone()
+    };
+    let dir = build_fuzz_targets(step, cfg, &metadata, &targets)?;
+
+    let seconds = match step.smoke_seconds {
+        Some(seconds) if cfg.smoke_tests => seconds,
+        _ => return Ok(()),
+    };
+    let corpus = cfg.src_dir.join(&step.path).join(
+        step.corpus
+            .clone()
+            .unwrap_or_else(|| step.fuzz_dir.join("corpus")),
+    );
+    for target in &targets {
+        note(&format!("Fuzzing {} for {}s", target, seconds));
+        // libFuzzer adds new inputs to the first corpus directory, so keep the checked-in one
+        // untouched by putting a scratch directory first
+        let scratch = cfg.scratch_dir.join("fuzz").join(target);
+        std::fs::create_dir_all(&scratch)?;
+        let mut cmd = Command::new(dir.join(format!("{}{}", target, std::env::consts::EXE_SUFFIX)));
+        cmd.arg(format!("-max_total_time={}", seconds))
+            .arg(&scratch)
+            .current_dir(&cfg.build_dir);
+        if corpus.join(target).is_dir() {
+            cmd.arg(corpus.join(target));
+        }
+        run(&mut cmd)?;
+    }
+    Ok(())
+}
+
+fn command(step: &CommandStep, cfg: &Configuration) -> std::io::Result<()> {
+    if step.network && cfg.offline {
+        return Err(std::io::Error::other(format!(
+            "{} needs the network, but --offline was given",
+            step.cmd.display()
+        )));
+    }
+    let program = OsString::from(substitute(&step.cmd.to_string_lossy(), cfg));
+    let mut cmd = if step.sandbox {
+        crate::sandbox::command(program, step.network, cfg)
+    } else {
+        Command::new(program)
+    };
+    run(cmd
+        .args(
+            step.args
+                .iter()
+                .map(|arg| substitute(&arg.to_string_lossy(), cfg)),
+        )
+        .current_dir(&cfg.src_dir)
+        .envs(&cfg.vars)
+        .envs(&cfg.build_env))
+}
+
+fn subdirectory(step: &SubdirectoryStep, cfg: &Configuration) -> std::io::Result<()> {
+    let cmd = step.cmd.as_deref().unwrap_or_else(|| Path::new("make"));
+    run(Command::new(cmd)
+        .args(&step.args)
+        .current_dir(cfg.src_dir.join(&step.path))
+        .envs(&cfg.vars)
+        .envs(&cfg.tool_env))
+}
+
+fn codesign(step: &CodesignStep, cfg: &Configuration) -> std::io::Result<()> {
+    let codesign = cfg
+        .codesign
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "codesign was not configured"))?;
+    let files: Vec<PathBuf> = step
+        .files
+        .iter()
+        .map(|f| cfg.src_dir.join(substitute(&f.to_string_lossy(), cfg)))
+        .collect();
+
+    let identity = substitute(&step.identity, cfg);
+    let mut cmd = Command::new(codesign);
+    cmd.arg("--force").arg("--sign").arg(&identity);
+    if identity != "-" {
+        cmd.arg("--timestamp");
+    }
+    if step.hardened_runtime {
+        cmd.arg("--options").arg("runtime");
+    }
+    if let Some(entitlements) = &step.entitlements {
+        cmd.arg("--entitlements")
+            .arg(cfg.src_dir.join(entitlements));
+    }
+    run(cmd.args(&files))?;
+
+    if !step.notarize {
+        return Ok(());
+    }
+    let notarytool = cfg
+        .notarytool
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "notarytool was not configured"))?;
+    let profile = step.keychain_profile.as_ref().ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "Notarization requires keychain-profile",
+        )
+    })?;
+    for file in &files {
+        let mut archive = file.clone().into_os_string();
+        archive.push(".zip");
+        run(Command::new("ditto")
+            .arg("-c")
+            .arg("-k")
+            .arg("--keepParent")
+            .arg(file)
+            .arg(&archive))?;
+        let result = run(Command::new(notarytool)
+            .arg("submit")
+            .arg(&archive)
+            .arg("--keychain-profile")
+            .arg(profile)
+            .arg("--wait"));
+        std::fs::remove_file(&archive)?;
+        result?;
+    }
+    Ok(())
+}
+
+fn objcopy(step: &ObjcopyStep, cfg: &Configuration) -> std::io::Result<()> {
+    let objcopy = cfg
+        .objcopy
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "objcopy was not configured"))?;
+    let output = cfg
+        .src_dir
+        .join(substitute(&step.output.to_string_lossy(), cfg));
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let format = match step.format {
+        ImageFormat::Binary => "binary",
+        ImageFormat::Ihex => "ihex",
+    };
+    run(Command::new(objcopy)
+        .arg("-O")
+        .arg(format)
+        .arg(
+            cfg.src_dir
+                .join(substitute(&step.input.to_string_lossy(), cfg)),
+        )
+        .arg(output))
+}
+
+// The language, catalog and compiled .mo file of each translation in `dir`
+fn translations(
+    step: &CompileTranslationsStep,
+    cfg: &Configuration,
+) -> std::io::Result<Vec<(String, PathBuf, Artifact)>> {
+    let dir = cfg.src_dir.join(&step.dir);
+    let mut languages = step.languages.clone();
+    if languages.is_empty() && dir.is_dir() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "po") {
+                languages.extend(path.file_stem().map(|s| s.to_string_lossy().into_owned()));
+            }
+        }
+        languages.sort();
+    }
+    // gettext domains are usually named after the package
+    let domain = match &step.domain {
+        Some(domain) => substitute(domain, cfg),
+        None => {
+            let manifest = std::fs::canonicalize(cfg.src_dir.join("Cargo.toml"))?;
+            crate::cargo::metadata(&cfg.cargo, &manifest)?
+                .packages
+                .into_iter()
+                .find(|p| p.manifest_path == manifest)
+                .map(|p| p.name)
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::NotFound,
+                        "No package at the root of the source tree names the translations; give the step a domain",
+                    )
+                })?
+        }
+    };
+    let file = format!("{}.mo", domain);
+    Ok(languages
+        .into_iter()
+        .map(|lang| {
+            let mut path = cfg.build_dir.join("locale");
+            path.push(&lang);
+            path.push("LC_MESSAGES");
+            path.push(&file);
+            let artifact = Artifact {
+                key: format!("{}/{}", lang, file),
+                kind: "mo".to_string(),
+                path,
+            };
+            let po = dir.join(format!("{}.po", lang));
+            (lang, po, artifact)
+        })
+        .collect())
+}
+
+fn compile_translations(
+    step: &CompileTranslationsStep,
+    cfg: &Configuration,
+) -> std::io::Result<()> {
+    let msgfmt = cfg
+        .msgfmt
+        .as_ref()
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "msgfmt was not configured"))?;
+    let translations = translations(step, cfg)?;
+    if translations.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} contains no .po files", step.dir.display()),
+        ));
+    }
+    let localedir = destdir(&cfg.dirs[&InstallDirectory::LocaleDir]);
+    for (lang, po, artifact) in translations {
+        if let Some(parent) = artifact.path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        run(Command::new(msgfmt)
+            .arg("--check")
+            .arg("-o")
+            .arg(&artifact.path)
+            .arg(&po))?;
+
+        let mut dest = localedir.join(lang);
+        dest.push("LC_MESSAGES");
+        dest.push(artifact.path.file_name().unwrap());
+        install_copy(&artifact.path, &dest, cfg)?;
+    }
+    Ok(())
+}
+
+fn install_copy(src: &Path, dest: &Path, cfg: &Configuration) -> std::io::Result<()> {
+    if let Some(parent) = dest.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    note(&format!("install {} -> {}", src.display(), dest.display()));
+    std::fs::copy(src, dest)?;
+    crate::uninstall::record(&cfg.build_dir, dest)
+}
+
+pub fn listed_files<'a>(
+    files: impl Iterator<Item = &'a PathBuf>,
+    cfg: &Configuration,
+) -> std::io::Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for file in files {
+        let file = substitute(&file.to_string_lossy(), cfg);
+        if crate::glob::is_glob(&file) {
+            paths.extend(crate::glob::expand(&cfg.src_dir, &file)?);
+        } else {
+            paths.push(cfg.src_dir.join(file));
+        }
+    }
+    Ok(paths)
+}
+
+// hicolor sorts bitmaps by their size; PNGs record it in the IHDR chunk
+fn icon_size(icon: &Path) -> std::io::Result<String> {
+    if icon
+        .extension()
+        .is_some_and(|ext| ext == "svg" || ext == "svgz")
+    {
+        return Ok("scalable".to_string());
+    }
+    let data = std::fs::read(icon)?;
+    match (data.get(..8), data.get(12..24)) {
+        (Some(b"\x89PNG\r\n\x1a\n"), Some(ihdr)) if ihdr.starts_with(b"IHDR") => {
+            let width = u32::from_be_bytes([ihdr[4], ihdr[5], ihdr[6], ihdr[7]]);
+            let height = u32::from_be_bytes([ihdr[8], ihdr[9], ihdr[10], ihdr[11]]);
+            Ok(format!("{}x{}", width, height))
+        }
+        _ => Err(std::io::Error::new(
+            ErrorKind::InvalidData,
+            format!("{} is neither a PNG nor an SVG icon", icon.display()),
+        )),
+    }
+}
+
+fn install_desktop(step: &InstallDesktopStep, cfg: &Configuration) -> std::io::Result<()> {
+    let desktop = listed_files(step.desktop.iter(), cfg)?;
+    let icons = listed_files(step.icons.iter(), cfg)?;
+    let metainfo = listed_files(step.metainfo.iter(), cfg)?;
+    if step.validate {
+        if let Some(validate) = &cfg.desktop_file_validate {
+            for file in &desktop {
+                run(Command::new(validate).arg(file))?;
+            }
+        }
+        if let Some(appstreamcli) = &cfg.appstreamcli {
+            for file in &metainfo {
+                run(Command::new(appstreamcli)
+                    .arg("validate")
+                    .arg("--no-net")
+                    .arg(file))?;
+            }
+        }
+    }
+
+    let datadir = destdir(&cfg.dirs[&InstallDirectory::DataDir]);
+    for file in &desktop {
+        let dest = datadir.join("applications").join(file.file_name().unwrap());
+        install_copy(file, &dest, cfg)?;
+    }
+    for icon in &icons {
+        let mut dest = datadir.join("icons/hicolor");
+        dest.push(icon_size(icon)?);
+        dest.push("apps");
+        match &step.icon_name {
+            // Icon names are usually reverse-DNS, so the extension cannot be set on the path
+            Some(name) => {
+                let ext = icon.extension().unwrap_or_default().to_string_lossy();
+                dest.push(format!("{}.{}", name, ext));
+            }
+            None => dest.push(icon.file_name().unwrap()),
+        }
+        install_copy(icon, &dest, cfg)?;
+    }
+    for file in &metainfo {
+        let dest = datadir.join("metainfo").join(file.file_name().unwrap());
+        install_copy(file, &dest, cfg)?;
+    }
+    Ok(())
+}
+
+fn systemd_files(step: &InstallSystemdStep) -> Vec<(&str, &[PathBuf])> {
+    vec![
+        ("systemdsystemunitdir", &step.units),
+        ("systemduserunitdir", &step.user_units),
+        ("tmpfilesdir", &step.tmpfiles),
+        ("sysusersdir", &step.sysusers),
+        ("dbussessionservicedir", &step.dbus_services),
+        ("dbussystemservicedir", &step.dbus_system_services),
+    ]
+}
+
+// Unit files spell directories in upper case, as in ExecStart=@BINDIR@/foo
+fn substitute_dirs(text: &str, cfg: &Configuration) -> String {
+    let mut text = text.to_string();
+    for (dir, path) in &cfg.dirs {
+        let var = format!("@{}@", dir.name().to_uppercase().replace('-', "_"));
+        text = text.replace(&var, &path.display().to_string());
+    }
+    substitute(&text, cfg)
+}
+
+fn install_systemd(step: &InstallSystemdStep, cfg: &Configuration) -> std::io::Result<()> {
+    for (dir, files) in systemd_files(step) {
+        if files.is_empty() {
+            continue;
+        }
+        let dir = destdir(&cfg.dirs[&InstallDirectory::from_name(dir)]);
+        std::fs::create_dir_all(&dir)?;
+        for src in listed_files(files.iter(), cfg)? {
+            let name = src.file_name().unwrap().to_string_lossy();
+            let dest = dir.join(name.strip_suffix(".in").unwrap_or(&name));
+            note(&format!("install {} -> {}", src.display(), dest.display()));
+            let text = std::fs::read_to_string(&src)?;
+            std::fs::write(&dest, substitute_dirs(&text, cfg))?;
+            crate::uninstall::record(&cfg.build_dir, &dest)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn substitute(text: &str, cfg: &Configuration) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('@') {
+        out.push_str(&rest[..start]);
+        let tail = &rest[start + 1..];
+        let var = tail.find('@').map(|end| {
+            let name = &tail[..end];
+            let value = CONTEXT
+                .with(|c| c.borrow().vars.get(name).cloned())
+                .or_else(|| cfg.vars.get(name).cloned());
+            (end, value)
+        });
+        match var {
+            Some((end, Some(value))) => {
+                out.push_str(&value);
+                rest = &tail[end + 1..];
+            }
+            _ => {
+                out.push('@');
+                rest = tail;
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+fn configure_file(step: &ConfigureFileStep, cfg: &Configuration) -> std::io::Result<()> {
+    let input = configure_file_input(step, cfg);
+    let text = std::fs::read_to_string(&input)?;
+    std::fs::write(cfg.src_dir.join(&step.base), substitute(&text, cfg))
+}
+
+pub fn destdir(path: &Path) -> PathBuf {
+    match std::env::var_os("DESTDIR") {
+        Some(destdir) if !destdir.is_empty() => {
+            let mut dest = PathBuf::from(destdir);
+            for component in path.components() {
+                match component {
+                    // The server and share of a UNC path stay apart from other installs
+                    Component::Prefix(prefix) => {
+                        if let Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) =
+                            prefix.kind()
+                        {
+                            dest.push(server);
+                            dest.push(share);
+                        }
+                    }
+                    Component::RootDir => {}
+                    component => dest.push(component),
+                }
+            }
+            crate::paths::long(&dest)
+        }
+        _ => crate::paths::long(path),
+    }
+}
+
+fn install_file(
+    step: &InstallStep,
+    src: &Path,
+    dest: &Path,
+    cfg: &Configuration,
+    journal: &mut Journal,
+    install_fs: &mut Option<Capabilities>,
+) -> std::io::Result<()> {
+    // Keep link chains such as versioned shared libraries intact. Without symbolic links on the
+    // install filesystem, every name gets a copy of the file
+    if src.symlink_metadata()?.file_type().is_symlink() {
+        // Probed where the link goes, under DESTDIR, once the directory is there
+        let dir = dest.parent().unwrap_or_else(|| Path::new("."));
+        journal.create_dirs(dir)?;
+        if install_fs.get_or_insert_with(|| probe(dir)).symlinks() {
+            let to = std::fs::read_link(src)?;
+            note(&format!("ln -s {} {}", to.display(), dest.display()));
+            return journal.place(dest, |tmp| symlink(&to, tmp));
+        }
+    }
+    note(&format!("install {} -> {}", src.display(), dest.display()));
+    journal.place(dest, |tmp| {
+        std::fs::copy(src, tmp)?;
+        match &step.mode {
+            Some(Mode::Octal(mode)) => set_mode(tmp, *mode as u32),
+            Some(Mode::Chmod(mode)) => run(Command::new("chmod").arg(mode).arg(tmp)),
+            None => Ok(()),
+        }
+    })?;
+
+    // Emscripten's loader fetches the module by its original name
+    let wasm = src.with_extension("wasm");
+    if src.extension().is_some_and(|ext| ext == "js") && wasm.is_file() {
+        let wasm_dest = dest.with_file_name(wasm.file_name().unwrap());
+        note(&format!(
+            "install {} -> {}",
+            wasm.display(),
+            wasm_dest.display()
+        ));
+        journal.place(&wasm_dest, |tmp| std::fs::copy(&wasm, tmp).map(|_| ()))?;
+    }
+    install_attributes(step, dest, cfg)
+}
+
+// Privileged attributes only take effect for root, and DESTDIR staging usually runs without it
+fn install_attributes(step: &InstallStep, dest: &Path, cfg: &Configuration) -> std::io::Result<()> {
+    if step.caps.is_none() && !step.setuid && !step.setgid {
+        return Ok(());
+    }
+    if !is_root() {
+        eprintln!(
+            "cargo-autobuild: warning: not running as root; skipping {} on {}",
+            attribute_names(step).join(" and "),
+            dest.display()
+        );
+        return Ok(());
+    }
+    let mut bits = Vec::new();
+    if step.setuid {
+        bits.push("u+s");
+    }
+    if step.setgid {
+        bits.push("g+s");
+    }
+    if !bits.is_empty() {
+        run(Command::new("chmod").arg(bits.join(",")).arg(dest))?;
+    }
+    if let Some(caps) = &step.caps {
+        match &cfg.setcap {
+            Some(setcap) => run(Command::new(setcap).arg(caps).arg(dest))?,
+            None => eprintln!(
+                "cargo-autobuild: warning: setcap was not found; skipping {} on {}",
+                caps,
+                dest.display()
+            ),
+        }
+    }
+    Ok(())
+}
+
+fn attribute_names(step: &InstallStep) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some(caps) = &step.caps {
+        names.push(format!("capabilities {}", caps));
+    }
+    if step.setuid {
+        names.push("setuid".to_string());
+    }
+    if step.setgid {
+        names.push("setgid".to_string());
+    }
+    names
+}
+
+#[cfg(unix)]
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .is_ok_and(|out| String::from_utf8_lossy(&out.stdout).trim() == "0")
+}
+
+#[cfg(not(unix))]
+fn is_root() -> bool {
+    false
+}
+
+fn excluded(step: &InstallStep, rel: &str) -> bool {
+    let name = rel.rsplit('/').next().unwrap_or(rel);
+    step.exclude
+        .iter()
+        .any(|pattern| crate::glob::matches(pattern, rel) || crate::glob::matches(pattern, name))
+}
+
+fn step_outputs(step: &InstallStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let name = step.from_step.as_deref().unwrap_or_default();
+    let artifacts = cfg.step_artifacts.get(name).ok_or_else(|| {
+        std::io::Error::new(
+            ErrorKind::NotFound,
+            format!("No planned step is named {}", name),
+        )
+    })?;
+    Ok(artifacts
+        .iter()
+        .filter(|a| match &step.kinds {
+            Some(kinds) => kinds.contains(&a.kind),
+            None => matches!(&*a.kind, "bin" | "cdylib" | "dylib" | "staticlib"),
+        })
+        .map(|a| a.path.clone())
+        .collect())
+}
+
+fn install_sources(step: &InstallStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let file = substitute(&step.file.to_string_lossy(), cfg);
+    let sources = match &step.from_step {
+        Some(name) if !file.is_empty() => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "Install steps take either file or from-step, not both ({})",
+                    name
+                ),
+            ))
+        }
+        Some(_) => step_outputs(step, cfg)?,
+        None if file.is_empty() => {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "Install steps require file or from-step",
+            ))
+        }
+        None if crate::glob::is_glob(&file) => crate::glob::expand(&cfg.src_dir, &file)?,
+        None => return Ok(vec![cfg.src_dir.join(&file)]),
+    };
+    let sources: Vec<PathBuf> = sources
+        .into_iter()
+        .filter(|src| {
+            let rel = src.strip_prefix(&cfg.src_dir).unwrap_or(src);
+            !excluded(step, &rel.to_string_lossy().replace('\\', "/"))
+        })
+        .collect();
+    if sources.is_empty() {
+        return Err(std::io::Error::new(
+            ErrorKind::NotFound,
+            match &step.from_step {
+                Some(name) => format!("{} produces nothing to install", name),
+                None => format!("{} matched no files", file),
+            },
+        ));
+    }
+    Ok(sources)
+}
+
+fn install(step: &InstallStep, cfg: &Configuration) -> std::io::Result<()> {
+    let sources = install_sources(step, cfg)?;
+    if step.rename.is_some() && (sources.len() != 1 || sources[0].is_dir()) {
+        return Err(std::io::Error::new(
+            ErrorKind::InvalidInput,
+            "rename requires the install step to name a single file",
+        ));
+    }
+    let target = destdir(&cfg.install_path(&step.target)?);
+    let files = install_files(step, &sources, target)?;
+
+    // Either every file of the step is installed or none is
+    let mut journal = Journal::begin(&cfg.build_dir, step.backup)?;
+    let mut install_fs = None;
+    let result = files.iter().try_for_each(|(src, dest)| {
+        install_file(step, src, dest, cfg, &mut journal, &mut install_fs)
+    });
+    match result {
+        Ok(()) => journal.commit(),
+        Err(e) => {
+            eprintln!("cargo-autobuild: install failed: {}; rolling back", e);
+            journal.rollback()?;
+            Err(e)
+        }
+    }
+}
+
+// Where each file goes, with directories expanded. Two files bound for the same place, as
+// flattening a tree can make, are refused before anything is installed
+fn install_files(
+    step: &InstallStep,
+    sources: &[PathBuf],
+    target: PathBuf,
+) -> std::io::Result<Vec<(PathBuf, PathBuf)>> {
+    let into_dir = sources.len() > 1
+        || match &step.target {
+            InstallTarget::Base { path, .. } => {
+                path.as_os_str().is_empty() || path.to_string_lossy().ends_with('/')
+            }
+            InstallTarget::Absolute(path) => path.to_string_lossy().ends_with('/'),
+        };
+    let mut files = Vec::new();
+    for src in sources {
+        let mut dest = target.clone();
+        if into_dir || dest.is_dir() {
+            dest.push(src.file_name().ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Cannot install {}", src.display()),
+                )
+            })?);
+        }
+        if src.is_dir() {
+            for rel in crate::glob::tree(src)? {
+                let path = src.join(&rel);
+                // A link to a directory is installed as the link
+                if path.symlink_metadata()?.is_dir() || excluded(step, &rel) {
+                    continue;
+                }
+                let file_dest = if step.flatten {
+                    dest.join(path.file_name().unwrap())
+                } else {
+                    dest.join(&rel)
+                };
+                files.push((path, file_dest));
+            }
+            continue;
+        }
+        if let Some(rename) = &step.rename {
+            dest.set_file_name(rename);
+        }
+        files.push((src.clone(), dest));
+    }
+
+    let mut seen: HashMap<&Path, &Path> = HashMap::new();
+    for (src, dest) in &files {
+        if let Some(first) = seen.insert(dest, src) {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} and {} would both be installed as {}",
+                    first.display(),
+                    src.display(),
+                    dest.display()
+                ),
+            ));
+        }
+    }
+    Ok(files)
+}
+
+// The path from `dir` to `to`, both absolute
+fn relative_path(dir: &Path, to: &Path) -> PathBuf {
+    let dir: Vec<_> = dir.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = dir.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut path = PathBuf::new();
+    for _ in common..dir.len() {
+        path.push("..");
+    }
+    path.extend(&to[common..]);
+    path
+}
+
+// A relative `to` is taken verbatim as the link contents; on Windows, \lib and C:lib are not
+// relative but anchored to a drive, so they are resolved like any other absolute target
+fn link_target(step: &InstallSymlinkStep, cfg: &Configuration) -> std::io::Result<PathBuf> {
+    if let InstallTarget::Absolute(to) = &step.to {
+        if matches!(
+            to.components().next(),
+            Some(Component::Normal(_) | Component::CurDir | Component::ParentDir)
+        ) {
+            return Ok(to.clone());
+        }
+    }
+    let to = cfg.install_path(&step.to)?;
+    let link = cfg.install_path(&step.link)?;
+    match link.parent() {
+        Some(dir) if !step.absolute && !step.hard => Ok(relative_path(dir, &to)),
+        _ => Ok(to),
+    }
+}
+
+fn link_to(step: &InstallSymlinkStep, cfg: &Configuration) -> String {
+    match link_target(step, cfg) {
+        Ok(to) => to.display().to_string(),
+        Err(_) => "its target".to_string(),
+    }
+}
+
+fn install_symlink(step: &InstallSymlinkStep, cfg: &Configuration) -> std::io::Result<()> {
+    let link = destdir(&cfg.install_path(&step.link)?);
+    let to = link_target(step, cfg)?;
+    if let Some(parent) = link.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if link.symlink_metadata().is_ok() {
+        std::fs::remove_file(&link)?;
+    }
+    // What the link points at, as seen from here
+    let resolved = match link.parent() {
+        Some(dir) if to.is_relative() => dir.join(&to),
+        _ => destdir(&to),
+    };
+    if step.hard {
+        note(&format!("ln {} {}", resolved.display(), link.display()));
+        std::fs::hard_link(&resolved, &link)?;
+    } else if link.parent().is_some_and(|dir| !probe(dir).symlinks()) {
+        // Filesystems such as FAT hold no symbolic links
+        note(&format!(
+            "install {} -> {}",
+            resolved.display(),
+            link.display()
+        ));
+        std::fs::copy(&resolved, &link)?;
+    } else {
+        note(&format!("ln -s {} {}", to.display(), link.display()));
+        symlink(&to, &link)?;
+    }
+    crate::uninstall::record(&cfg.build_dir, &link)
+}
+
+#[cfg(unix)]
+pub fn symlink(to: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(to, link)
+}
+
+#[cfg(windows)]
+pub fn symlink(to: &Path, link: &Path) -> std::io::Result<()> {
+    let resolved = match link.parent() {
+        Some(dir) => dir.join(to),
+        None => to.to_owned(),
+    };
+    if resolved.is_dir() {
+        std::os::windows::fs::symlink_dir(to, link)
+    } else {
+        std::os::windows::fs::symlink_file(to, link)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn symlink(_: &Path, link: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        ErrorKind::Other,
+        format!(
+            "Cannot create symbolic link {} on this platform",
+            link.display()
+        ),
+    ))
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: u32) -> std::io::Result<()> {
+    Ok(())
+}
+
+pub fn report_cache_stats(cfg: &Configuration) {
+    if let Some(wrapper) = &cfg.rustc_wrapper {
+        if crate::configure::is_sccache(wrapper) {
+            let _ = Command::new(wrapper).arg("--show-stats").status();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own under the system temporary directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("autobuild-install-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn install_step(target: &Path, extra: &str) -> InstallStep {
+        let target = target.to_string_lossy().replace('\\', "/");
+        toml::from_str(&format!("target = {:?}\n{}", target, extra)).unwrap()
+    }
+
+    fn touch(path: &Path) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "").unwrap();
+    }
+
+    #[test]
+    fn installs_several_files_into_the_directory() {
+        let dir = scratch("into-dir");
+        let sources = [dir.join("a"), dir.join("b")];
+        let step = install_step(&dir.join("out"), "");
+        let files = install_files(&step, &sources, dir.join("out")).unwrap();
+        assert_eq!(
+            files,
+            vec![
+                (dir.join("a"), dir.join("out").join("a")),
+                (dir.join("b"), dir.join("out").join("b")),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn renames_a_single_file() {
+        let dir = scratch("rename");
+        touch(&dir.join("src/tool"));
+        let sources = [dir.join("src/tool")];
+        let step = install_step(&dir.join("out/"), "rename = \"tool-1\"");
+        let files = install_files(&step, &sources, dir.join("out")).unwrap();
+        assert_eq!(files, vec![(dir.join("src/tool"), dir.join("out/tool-1"))]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn installs_trees_with_exclusions() {
+        let dir = scratch("tree");
+        touch(&dir.join("doc/a/b.html"));
+        touch(&dir.join("doc/c.html"));
+        touch(&dir.join("doc/a/d.tmp"));
+        let sources = [dir.join("doc")];
+        let out = dir.join("out");
+
+        let step = install_step(&out, "exclude = [\"*.tmp\"]");
+        let mut files = install_files(&step, &sources, out.clone()).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                (dir.join("doc/a/b.html"), out.join("a/b.html")),
+                (dir.join("doc/c.html"), out.join("c.html")),
+            ]
+        );
+
+        let step = install_step(&out, "exclude = [\"*.tmp\"]\nflatten = true");
+        let mut files = install_files(&step, &sources, out.clone()).unwrap();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                (dir.join("doc/a/b.html"), out.join("b.html")),
+                (dir.join("doc/c.html"), out.join("c.html")),
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_files_bound_for_the_same_place() {
+        let dir = scratch("collision");
+        touch(&dir.join("doc/a/index.html"));
+        touch(&dir.join("doc/b/index.html"));
+        let step = install_step(&dir.join("out"), "flatten = true");
+        let err = install_files(&step, &[dir.join("doc")], dir.join("out")).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidInput);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn finds_relative_paths() {
+        let path = |dir: &str, to: &str| relative_path(Path::new(dir), Path::new(to));
+        assert_eq!(
+            path("/usr/lib", "/usr/lib/libz.so.1"),
+            Path::new("libz.so.1")
+        );
+        assert_eq!(path("/usr/bin", "/usr/lib/tool"), Path::new("../lib/tool"));
+        assert_eq!(path("/opt/a/b", "/opt"), Path::new("../.."));
+        assert_eq!(path("/usr/bin", "/usr/bin"), Path::new(""));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeSet,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use crate::{config::SysrootStep, configure::Configuration};
+
+const LIB_KINDS: &[&str] = &["lib", "rlib", "dylib", "staticlib", "object"];
+
+pub fn dest(step: &SysrootStep, cfg: &Configuration) -> PathBuf {
+    cfg.build_dir.join(&step.dest)
+}
+
+// Where rustc looks for the libraries of the target when given --sysroot
+fn lib_dir(step: &SysrootStep, cfg: &Configuration) -> PathBuf {
+    let mut dir = dest(step, cfg).join("lib/rustlib");
+    dir.push(cfg.resolve_target(&step.target).get_name());
+    dir.push("lib");
+    dir
+}
+
+// Lists what was installed, so that the next run removes what is no longer built
+fn manifest(step: &SysrootStep, cfg: &Configuration) -> PathBuf {
+    let mut name = "manifest-".to_string();
+    name.push_str(cfg.resolve_target(&step.target).get_name());
+    dest(step, cfg).join("lib/rustlib").join(name)
+}
+
+// Rust libraries come with the crates they were built against, which cargo leaves in `deps` with
+// hashed names; rustc needs those in the sysroot too
+pub fn sources(step: &SysrootStep, cfg: &Configuration) -> std::io::Result<Vec<PathBuf>> {
+    let mut sources = crate::steps::listed_files(step.files.iter(), cfg)?;
+    for name in &step.from_steps {
+        let artifacts = cfg.step_artifacts.get(name).ok_or_else(|| {
+            std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("No planned step is named {}", name),
+            )
+        })?;
+        for artifact in artifacts.iter().filter(|a| LIB_KINDS.contains(&&*a.kind)) {
+            sources.push(artifact.path.clone());
+            if !matches!(&*artifact.kind, "lib" | "rlib") {
+                continue;
+            }
+            let deps = match artifact.path.parent() {
+                Some(dir) if dir.join("deps").is_dir() => dir.join("deps"),
+                _ => continue,
+            };
+            for entry in std::fs::read_dir(deps)? {
+                let path = entry?.path();
+                if path.extension().is_some_and(|e| e == "rlib") {
+                    sources.push(path);
+                }
+            }
+        }
+    }
+    sources.sort();
+    sources.dedup();
+    Ok(sources)
+}
+
+fn up_to_date(src: &Path, dest: &Path) -> std::io::Result<bool> {
+    let dest = match dest.symlink_metadata() {
+        Ok(dest) => dest,
+        Err(_) => return Ok(false),
+    };
+    let src = src.metadata()?;
+    Ok(!dest.file_type().is_symlink()
+        && dest.len() == src.len()
+        && dest.modified()? >= src.modified()?)
+}
+
+fn place(src: &Path, dest: &Path, link: bool) -> std::io::Result<()> {
+    if dest.symlink_metadata().is_ok() {
+        std::fs::remove_file(dest)?;
+    }
+    if link {
+        crate::steps::symlink(src, dest)
+    } else {
+        std::fs::copy(src, dest).map(drop)
+    }
+}
+
+// Files are copied, or linked with `link`, only when they changed since the last run
+pub fn assemble(step: &SysrootStep, cfg: &Configuration) -> std::io::Result<()> {
+    let dir = lib_dir(step, cfg);
+    std::fs::create_dir_all(&dir)?;
+    let manifest = manifest(step, cfg);
+    let previous: BTreeSet<String> = match std::fs::read_to_string(&manifest) {
+        Ok(text) => text.lines().map(str::to_string).collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => BTreeSet::new(),
+        Err(e) => return Err(e),
+    };
+
+    let mut installed = BTreeSet::new();
+    for src in sources(step, cfg)? {
+        let name = src.file_name().unwrap().to_string_lossy().into_owned();
+        let dest = dir.join(&name);
+        let fresh = if step.link {
+            std::fs::read_link(&dest).is_ok_and(|to| to == src)
+        } else {
+            up_to_date(&src, &dest)?
+        };
+        if !fresh {
+            crate::steps::note(&format!("sysroot {} -> {}", src.display(), dest.display()));
+            place(&src, &dest, step.link)?;
+        }
+        installed.insert(name);
+    }
+    for stale in previous.difference(&installed) {
+        let path = dir.join(stale);
+        if path.symlink_metadata().is_ok() {
+            crate::steps::note(&format!("sysroot: removing {}", path.display()));
+            std::fs::remove_file(path)?;
+        }
+    }
+
+    let mut text = String::new();
+    for name in &installed {
+        text.push_str(name);
+        text.push('\n');
+    }
+    std::fs::write(manifest, text)
+}
@@ -0,0 +1,50 @@
+use std::{path::PathBuf, process::Command};
+
+// The install directory, the pkg-config package and variable that locate it, and where it lives
+// under $prefix when the package has no .pc file
+pub const DIRS: &[(&str, &str, &str, &str)] = &[
+    (
+        "systemdsystemunitdir",
+        "systemd",
+        "systemdsystemunitdir",
+        "lib/systemd/system",
+    ),
+    (
+        "systemduserunitdir",
+        "systemd",
+        "systemduserunitdir",
+        "lib/systemd/user",
+    ),
+    ("tmpfilesdir", "systemd", "tmpfilesdir", "lib/tmpfiles.d"),
+    ("sysusersdir", "systemd", "sysusersdir", "lib/sysusers.d"),
+    (
+        "dbussessionservicedir",
+        "dbus-1",
+        "session_bus_services_dir",
+        "share/dbus-1/services",
+    ),
+    (
+        "dbussystemservicedir",
+        "dbus-1",
+        "system_bus_services_dir",
+        "share/dbus-1/system-services",
+    ),
+];
+
+pub fn pkg_config_variable(package: &str, variable: &str) -> Option<PathBuf> {
+    let pkg_config = match std::env::var_os("PKG_CONFIG") {
+        Some(pkg_config) => PathBuf::from(pkg_config),
+        None => crate::tests::which_any(&["pkg-config", "pkgconf"]).ok()?,
+    };
+    let output = Command::new(pkg_config)
+        .arg(format!("--variable={}", variable))
+        .arg(package)
+        .output()
+        .ok()?;
+    let value = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if output.status.success() && !value.is_empty() {
+        Some(PathBuf::from(value))
+    } else {
+        None
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Ignored,
+}
+
+pub struct TestCase {
+    pub name: String,
+    pub outcome: Outcome,
+    pub time: Option<f64>,
+    pub output: Option<String>,
+}
+
+// One line of libtest's `--format json` output; suite and bench events are not needed here
+#[derive(Deserialize)]
+struct Event {
+    #[serde(rename = "type")]
+    kind: String,
+    event: String,
+    #[serde(default)]
+    name: Option<String>,
+    #[serde(default)]
+    exec_time: Option<f64>,
+    #[serde(default)]
+    stdout: Option<String>,
+}
+
+// Records the test a JSON line finished, if any; returns false for lines that are not JSON,
+// which the caller passes through
+pub fn parse_line(line: &str, cases: &mut Vec<TestCase>) -> bool {
+    let event: Event = match serde_json::from_str(line) {
+        Ok(event) => event,
+        Err(_) => return false,
+    };
+    if event.kind != "test" {
+        return true;
+    }
+    let outcome = match &*event.event {
+        "ok" => Outcome::Passed,
+        "failed" | "timeout" => Outcome::Failed,
+        "ignored" => Outcome::Ignored,
+        _ => return true,
+    };
+    let name = event.name.unwrap_or_default();
+    crate::steps::note(&format!(
+        "test {} ... {}",
+        name,
+        match outcome {
+            Outcome::Passed => "ok",
+            Outcome::Failed => "FAILED",
+            Outcome::Ignored => "ignored",
+        }
+    ));
+    cases.push(TestCase {
+        name,
+        outcome,
+        time: event.exec_time,
+        output: event.stdout,
+    });
+    true
+}
+
+fn count(cases: &[TestCase], outcome: Outcome) -> usize {
+    cases.iter().filter(|c| c.outcome == outcome).count()
+}
+
+fn escape(st: &str) -> String {
+    st.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn junit(suite: &str, cases: &[TestCase]) -> String {
+    let time: f64 = cases.iter().filter_map(|c| c.time).sum();
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    out += &format!(
+        "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        escape(suite),
+        cases.len(),
+        count(cases, Outcome::Failed),
+        count(cases, Outcome::Ignored),
+        time
+    );
+    for case in cases {
+        // libtest names are paths; JUnit readers group by everything before the test itself
+        let (class, name) = match case.name.rsplit_once("::") {
+            Some((class, name)) => (class, name),
+            None => (suite, &*case.name),
+        };
+        out += &format!(
+            "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+            escape(class),
+            escape(name),
+            case.time.unwrap_or_default()
+        );
+        match case.outcome {
+            Outcome::Passed => out += "/>\n",
+            Outcome::Ignored => out += ">\n      <skipped/>\n    </testcase>\n",
+            Outcome::Failed => {
+                out += ">\n      <failure message=\"test failed\">";
+                out += &escape(case.output.as_deref().unwrap_or_default());
+                out += "</failure>\n    </testcase>\n";
+            }
+        }
+    }
+    out += "  </testsuite>\n</testsuites>\n";
+    out
+}
+
+pub fn tap(cases: &[TestCase]) -> String {
+    let mut out = format!("TAP version 13\n1..{}\n", cases.len());
+    for (i, case) in cases.iter().enumerate() {
+        let (status, directive) = match case.outcome {
+            Outcome::Passed => ("ok", ""),
+            Outcome::Ignored => ("ok", " # SKIP ignored"),
+            Outcome::Failed => ("not ok", ""),
+        };
+        out += &format!("{} {} - {}{}\n", status, i + 1, case.name, directive);
+        if let (Outcome::Failed, Some(output)) = (case.outcome, &case.output) {
+            out += "  ---\n  output: |\n";
+            for line in output.lines() {
+                out += &format!("    {}\n", line);
+            }
+            out += "  ...\n";
+        }
+    }
+    out
+}
+
+pub fn write(path: &Path, contents: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)?;
+    crate::steps::note(&format!("Test report saved to {}", path.display()));
+    Ok(())
+}
@@ -1,36 +1,90 @@
 use std::{
+    cell::Cell,
     ffi::OsStr,
     io::ErrorKind,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
+static WORK_DIRS: AtomicUsize = AtomicUsize::new(0);
+
+// A directory of its own for one probe. It is removed when dropped unless the probe failed, so
+// that what the probe compiled can be looked at
+pub struct WorkDir {
+    path: PathBuf,
+    keep: Cell<bool>,
+}
+
+impl WorkDir {
+    // Numbered within the run, so each configure replaces the directories of the last
+    pub fn new(parent: &Path, name: &str) -> std::io::Result<WorkDir> {
+        let n = WORK_DIRS.fetch_add(1, Ordering::Relaxed);
+        let path = parent.join(format!("{}-{}", name, n));
+        if path.exists() {
+            std::fs::remove_dir_all(&path)?;
+        }
+        std::fs::create_dir_all(&path)?;
+        Ok(WorkDir {
+            path,
+            keep: Cell::new(false),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn check<T>(&self, result: std::io::Result<T>) -> std::io::Result<T> {
+        result.map_err(|e| {
+            self.keep.set(true);
+            std::io::Error::new(
+                e.kind(),
+                format!("{} (probe files kept in {})", e, self.path.display()),
+            )
+        })
+    }
+}
+
+impl Drop for WorkDir {
+    fn drop(&mut self) {
+        if !self.keep.get() {
+            let _ = std::fs::remove_dir_all(&self.path);
+        }
+    }
+}
+
+// Runs one probe in a fresh work directory under `parent`
+pub fn in_work_dir<T>(
+    parent: &Path,
+    name: &str,
+    probe: impl FnOnce(&Path) -> std::io::Result<T>,
+) -> std::io::Result<T> {
+    let dir = WorkDir::new(parent, name)?;
+    dir.check(probe(dir.path()))
+}
+
 pub fn which_any<S: AsRef<OsStr>>(names: &[S]) -> std::io::Result<PathBuf> {
     let paths = std::env::var("PATH").map_err(|e| std::io::Error::new(ErrorKind::NotFound, e))?;
     for i in std::env::split_paths(&paths) {
         for stem in names {
             let mut path = i.clone();
             path.push(Path::new(stem));
-            let mut meta = std::fs::symlink_metadata(&path)?;
-            while meta.file_type().is_symlink() {
-                path = std::fs::read_link(path)?;
-                meta = std::fs::symlink_metadata(&path)?;
-            }
-            if path.exists() {
+            let path = crate::paths::long(&path);
+            if path.is_file() {
                 return Ok(path);
             }
         }
     }
 
-    return Err(std::io::Error::new(
+    Err(std::io::Error::new(
         ErrorKind::NotFound,
         "Cannot find files",
-    ));
+    ))
 }
 
 pub mod rustc {
     use std::{
         ffi::{OsStr, OsString},
-        fs::File,
         io::{BufRead, ErrorKind},
         path::{Path, PathBuf},
         process::{Command, Stdio},
@@ -38,9 +92,7 @@ pub mod rustc {
 
     use target_tuples::{Target, Vendor};
 
-    use crate::config::Step;
-
-    #[derive(Default)]
+    #[derive(Default, Clone)]
     pub struct RustcTargetInfo {
         pub target: String,
         pub exe_suffix: OsString,
@@ -56,6 +108,7 @@ pub mod rustc {
         pub procmacro_suffix: OsString,
     }
 
+    #[derive(Clone)]
     pub struct RustcTestsResult {
         pub rustc: PathBuf,
         pub rustflags: Vec<OsString>,
@@ -64,6 +117,7 @@ pub mod rustc {
         pub target_info: RustcTargetInfo,
     }
 
+    #[derive(Clone)]
     pub struct RustcVersion {
         pub prgname: String,
         pub major: i32,
@@ -72,6 +126,7 @@ pub mod rustc {
         pub channel: RustcChannel,
     }
 
+    #[derive(Clone, Copy)]
     pub enum RustcChannel {
         Stable,
         Beta,
@@ -80,13 +135,178 @@ pub mod rustc {
         Unstable,
     }
 
+    const CRATE_TYPES: &[&str] = &["bin", "rlib", "dylib", "staticlib", "cdylib", "proc-macro"];
+
+    fn split_file_name(name: &str) -> (OsString, OsString) {
+        let prefix = name
+            .find("comptest")
+            .map(|u| name[..u].to_string())
+            .unwrap_or_default();
+        let suffix = name
+            .find(".")
+            .map(|u| name[u..].to_string())
+            .unwrap_or_default();
+        (prefix.into(), suffix.into())
+    }
+
+    // Targets without dynamic linking (e.g. bare-metal ones) drop some crate types with a warning,
+    // so the printed names are matched back up against the types that were kept.
+    fn probe_file_names(
+        rustc: &Path,
+        flags: &str,
+        target_arg: Option<&OsStr>,
+        file: &Path,
+    ) -> std::io::Result<Option<RustcTargetInfo>> {
+        let mut cmd = Command::new(rustc);
+        cmd.args(flags.split(' ').filter(|f| !f.is_empty()))
+            .arg("--crate-name")
+            .arg("comptest")
+            .arg("--crate-type")
+            .arg(CRATE_TYPES.join(","));
+        if let Some(target) = target_arg {
+            cmd.arg("--target").arg(target);
+        }
+        let output = cmd
+            .arg("--print")
+            .arg("file-names")
+            .arg(file)
+            .stdin(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let mut lines = output.stdout.lines();
+        let mut ret = RustcTargetInfo::default();
+        for ty in CRATE_TYPES {
+            if stderr.contains(&format!("unsupported crate type `{}`", ty)) {
+                continue;
+            }
+            let name = lines.next().ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Could not determine file names from invoking {}",
+                        rustc.display()
+                    ),
+                )
+            })??;
+            let (prefix, suffix) = split_file_name(&name);
+            match *ty {
+                "bin" => ret.exe_suffix = suffix,
+                "rlib" => {
+                    ret.rlib_prefix = prefix;
+                    ret.rlib_suffix = suffix;
+                }
+                "dylib" => {
+                    ret.dylib_prefix = prefix;
+                    ret.dylib_suffix = suffix;
+                }
+                "staticlib" => {
+                    ret.staticlib_prefix = prefix;
+                    ret.staticlib_suffix = suffix;
+                }
+                "cdylib" => {
+                    ret.cdylib_prefix = prefix;
+                    ret.cdylib_suffix = suffix;
+                }
+                _ => {
+                    ret.procmacro_prefix = prefix;
+                    ret.procmacro_suffix = suffix;
+                }
+            }
+        }
+        Ok(Some(ret))
+    }
+
+    // A single query answers for every built-in target, where the compile-based probe tries each
+    // candidate name in turn. It is unstable, so only compilers that take unstable options are
+    // asked; the others, and those that still refuse, are probed the slow way
+    fn target_specs(
+        rustc: &Path,
+        version: &RustcVersion,
+    ) -> Option<serde_json::Map<String, serde_json::Value>> {
+        if !matches!(version.channel, RustcChannel::Nightly | RustcChannel::Dev) {
+            return None;
+        }
+        let output = Command::new(rustc)
+            .arg("-Zunstable-options")
+            .arg("--print")
+            .arg("all-target-specs-json")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        match serde_json::from_slice(&output.stdout).ok()? {
+            serde_json::Value::Object(specs) => Some(specs),
+            _ => None,
+        }
+    }
+
+    // Fields left at rustc's defaults are omitted from the specs. Crate types the target cannot
+    // build get no names, as with `--print file-names`
+    fn spec_info(name: &str, spec: &serde_json::Value) -> RustcTargetInfo {
+        let field = |key: &str, default: &str| -> OsString {
+            spec.get(key)
+                .and_then(|v| v.as_str())
+                .unwrap_or(default)
+                .into()
+        };
+        let flag = |key: &str| spec.get(key).and_then(|v| v.as_bool()).unwrap_or(false);
+        let (dll_prefix, dll_suffix) = if flag("dynamic-linking") {
+            (field("dll-prefix", "lib"), field("dll-suffix", ".so"))
+        } else {
+            (OsString::new(), OsString::new())
+        };
+        let (dylib_prefix, dylib_suffix) = if flag("only-cdylib") {
+            (OsString::new(), OsString::new())
+        } else {
+            (dll_prefix.clone(), dll_suffix.clone())
+        };
+        RustcTargetInfo {
+            target: name.to_string(),
+            exe_suffix: field("exe-suffix", ""),
+            rlib_prefix: "lib".into(),
+            rlib_suffix: ".rlib".into(),
+            dylib_prefix: dylib_prefix.clone(),
+            dylib_suffix: dylib_suffix.clone(),
+            staticlib_prefix: field("staticlib-prefix", "lib"),
+            staticlib_suffix: field("staticlib-suffix", ".a"),
+            cdylib_prefix: dll_prefix,
+            cdylib_suffix: dll_suffix,
+            procmacro_prefix: dylib_prefix,
+            procmacro_suffix: dylib_suffix,
+        }
+    }
+
     fn find_rustc_target(
         rustc: &Path,
         flags: &mut String,
         file: &Path,
         target: &Target,
+        spec: Option<&Path>,
+        version: &RustcVersion,
     ) -> std::io::Result<RustcTargetInfo> {
-        let mut ret = RustcTargetInfo::default();
+        if let Some(spec) = spec {
+            if let Some(mut ret) = probe_file_names(rustc, flags, Some(spec.as_os_str()), file)? {
+                ret.target = spec.display().to_string();
+                *flags += " --target ";
+                *flags += &ret.target;
+                return Ok(ret);
+            }
+            return Err(std::io::Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "{} does not accept the target specification {}",
+                    rustc.display(),
+                    spec.display()
+                ),
+            ));
+        }
 
         if rustc
             .file_name()
@@ -94,843 +314,629 @@ pub mod rustc {
             .to_string_lossy()
             .starts_with(target.get_name())
         {
-            match Command::new(rustc)
-                .args(flags.split(' '))
-                .arg("--crate-name")
-                .arg("comptest")
-                .arg("--crate-type")
-                .arg("bin,rlib,dylib,staticlib,cdylib,proc-macro")
-                .arg("--print")
-                .arg("file-names")
-                .arg(file)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()
-            {
-                Ok(output) if output.status.success() => {
+            return match probe_file_names(rustc, flags, None, file)? {
+                Some(mut ret) => {
                     ret.target = target.get_name().to_string();
-                    let mut lines = output.stdout.lines();
-
-                    let exename = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-
-                    ret.exe_suffix = exename
-                        .find(".")
-                        .map(|u| exename[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let rlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.rlib_prefix = rlibname
-                        .find("comptest")
-                        .map(|u| rlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.rlib_suffix = rlibname
-                        .find(".")
-                        .map(|u| rlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let dylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.dylib_prefix = dylibname
-                        .find("comptest")
-                        .map(|u| dylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.dylib_suffix = dylibname
-                        .find(".")
-                        .map(|u| dylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let staticlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.staticlib_prefix = staticlibname
-                        .find("comptest")
-                        .map(|u| staticlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.staticlib_suffix = staticlibname
-                        .find(".")
-                        .map(|u| staticlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let cdylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.cdylib_prefix = cdylibname
-                        .find("comptest")
-                        .map(|u| cdylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.cdylib_suffix = cdylibname
-                        .find(".")
-                        .map(|u| cdylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let procmacroname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.procmacro_prefix = procmacroname
-                        .find("comptest")
-                        .map(|u| procmacroname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.procmacro_suffix = procmacroname
-                        .find(".")
-                        .map(|u| procmacroname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
                     Ok(ret)
                 }
-                Ok(_) => Err(std::io::Error::new(
+                None => Err(std::io::Error::new(
                     ErrorKind::Unsupported,
                     format!("Cannot execute {}", rustc.display()),
                 )),
-                Err(e) => Err(e),
-            }
-        } else {
-            match Command::new(rustc)
-                .args(flags.split(' '))
-                .arg("--crate-name")
-                .arg("comptest")
-                .arg("--crate-type")
-                .arg("bin,rlib,dylib,staticlib,cdylib,proc-macro")
-                .arg("--target")
-                .arg(target.get_name())
-                .arg("--print")
-                .arg("file-names")
-                .arg(file)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()
-            {
-                Ok(output) if output.status.success() => {
-                    ret.target = target.get_name().to_string();
-                    *flags += " --target ";
-                    *flags += &ret.target;
-                    let mut lines = output.stdout.lines();
-
-                    let exename = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-
-                    ret.exe_suffix = exename
-                        .find(".")
-                        .map(|u| exename[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let rlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.rlib_prefix = rlibname
-                        .find("comptest")
-                        .map(|u| rlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.rlib_suffix = rlibname
-                        .find(".")
-                        .map(|u| rlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let dylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.dylib_prefix = dylibname
-                        .find("comptest")
-                        .map(|u| dylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.dylib_suffix = dylibname
-                        .find(".")
-                        .map(|u| dylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let staticlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.staticlib_prefix = staticlibname
-                        .find("comptest")
-                        .map(|u| staticlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.staticlib_suffix = staticlibname
-                        .find(".")
-                        .map(|u| staticlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let cdylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.cdylib_prefix = cdylibname
-                        .find("comptest")
-                        .map(|u| cdylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.cdylib_suffix = cdylibname
-                        .find(".")
-                        .map(|u| cdylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let procmacroname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.procmacro_prefix = procmacroname
-                        .find("comptest")
-                        .map(|u| procmacroname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.procmacro_suffix = procmacroname
-                        .find(".")
-                        .map(|u| procmacroname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    return Ok(ret);
-                }
-                Ok(_) => (),
-                Err(e) => return Err(e),
             };
+        }
 
-            match Command::new(rustc)
-                .args(flags.split(' '))
-                .arg("--crate-name")
-                .arg("comptest")
-                .arg("--crate-type")
-                .arg("bin,rlib,dylib,staticlib,cdylib,proc-macro")
-                .arg("--target")
-                .arg(target.to_string())
-                .arg("--print")
-                .arg("file-names")
-                .arg(file)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()
+        let ntarget = Target::from_components(
+            target.arch(),
+            Vendor::Unknown,
+            target.operating_system(),
+            target.environment(),
+            target.object_format(),
+        );
+        let candidates = [
+            target.get_name().to_string(),
+            target.to_string(),
+            ntarget.to_string(),
+        ];
+        if let Some(specs) = target_specs(rustc, version) {
+            if let Some((name, spec)) = candidates
+                .iter()
+                .find_map(|name| specs.get(name).map(|spec| (name, spec)))
             {
-                Ok(output) if output.status.success() => {
-                    ret.target = target.to_string();
-                    *flags += " --target ";
-                    *flags += &ret.target;
-                    let mut lines = output.stdout.lines();
-
-                    let exename = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-
-                    ret.exe_suffix = exename
-                        .find(".")
-                        .map(|u| exename[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let rlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.rlib_prefix = rlibname
-                        .find("comptest")
-                        .map(|u| rlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.rlib_suffix = rlibname
-                        .find(".")
-                        .map(|u| rlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let dylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.dylib_prefix = dylibname
-                        .find("comptest")
-                        .map(|u| dylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.dylib_suffix = dylibname
-                        .find(".")
-                        .map(|u| dylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let staticlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.staticlib_prefix = staticlibname
-                        .find("comptest")
-                        .map(|u| staticlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.staticlib_suffix = staticlibname
-                        .find(".")
-                        .map(|u| staticlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let cdylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.cdylib_prefix = cdylibname
-                        .find("comptest")
-                        .map(|u| cdylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.cdylib_suffix = cdylibname
-                        .find(".")
-                        .map(|u| cdylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let procmacroname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.procmacro_prefix = procmacroname
-                        .find("comptest")
-                        .map(|u| procmacroname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.procmacro_suffix = procmacroname
-                        .find(".")
-                        .map(|u| procmacroname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    return Ok(ret);
-                }
-                Ok(_) => (),
-                Err(e) => return Err(e),
-            };
+                *flags += " --target ";
+                *flags += name;
+                return Ok(spec_info(name, spec));
+            }
+        }
+        for name in &candidates {
+            if let Some(mut ret) = probe_file_names(rustc, flags, Some(OsStr::new(name)), file)? {
+                ret.target = name.clone();
+                *flags += " --target ";
+                *flags += &ret.target;
+                return Ok(ret);
+            }
+        }
 
-            let ntarget = Target::from_components(
-                target.arch(),
-                Vendor::Unknown,
-                target.operating_system(),
-                target.environment(),
-                target.object_format(),
-            );
-
-            match Command::new(rustc)
-                .args(flags.split(' '))
-                .arg("--crate-name")
-                .arg("comptest")
-                .arg("--crate-type")
-                .arg("bin,rlib,dylib,staticlib,cdylib,proc-macro")
-                .arg("--target")
-                .arg(target.to_string())
-                .arg("--print")
-                .arg("file-names")
-                .arg(file)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::null())
-                .output()
-            {
-                Ok(output) if output.status.success() => {
-                    ret.target = ntarget.to_string();
-                    *flags += " --target ";
-                    *flags += &ret.target;
-                    let mut lines = output.stdout.lines();
-
-                    let exename = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-
-                    ret.exe_suffix = exename
-                        .find(".")
-                        .map(|u| exename[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let rlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.rlib_prefix = rlibname
-                        .find("comptest")
-                        .map(|u| rlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.rlib_suffix = rlibname
-                        .find(".")
-                        .map(|u| rlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let dylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.dylib_prefix = dylibname
-                        .find("comptest")
-                        .map(|u| dylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.dylib_suffix = dylibname
-                        .find(".")
-                        .map(|u| dylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let staticlibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.staticlib_prefix = staticlibname
-                        .find("comptest")
-                        .map(|u| staticlibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.staticlib_suffix = staticlibname
-                        .find(".")
-                        .map(|u| staticlibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let cdylibname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.cdylib_prefix = cdylibname
-                        .find("comptest")
-                        .map(|u| cdylibname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.cdylib_suffix = cdylibname
-                        .find(".")
-                        .map(|u| cdylibname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    let procmacroname = lines.next().ok_or_else(|| {
-                        std::io::Error::new(
-                            ErrorKind::Unsupported,
-                            format!(
-                                "Could not determine file names from invoking {}",
-                                rustc.display()
-                            ),
-                        )
-                    })??;
-                    ret.procmacro_prefix = procmacroname
-                        .find("comptest")
-                        .map(|u| procmacroname[..u].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    ret.procmacro_suffix = procmacroname
-                        .find(".")
-                        .map(|u| procmacroname[u..].to_string())
-                        .unwrap_or_default()
-                        .into();
-
-                    return Ok(ret);
-                }
-                Ok(_) => (),
-                Err(e) => return Err(e),
-            };
+        Err(std::io::Error::new(
+            ErrorKind::Unsupported,
+            format!(
+                "Could not determine how to compile for {} using {}",
+                target.get_name(),
+                rustc.display()
+            ),
+        ))
+    }
 
-            Err(std::io::Error::new(
-                ErrorKind::Unsupported,
-                format!(
-                    "Could not determine how to compile for {} using {}",
-                    target.get_name(),
-                    rustc.display()
-                ),
-            ))
+    // For tools and proc macros, which run on the build machine; compilers that cannot name their
+    // outputs are assumed to follow its usual naming
+    pub fn build_target_info(
+        rustc: &RustcTestsResult,
+        build: &Target,
+        tmpdir: &Path,
+    ) -> std::io::Result<RustcTargetInfo> {
+        if !crate::compat::Flavor::of(&rustc.version).accepts_rustc_flags() {
+            return Ok(crate::compat::target_info(build));
         }
+        super::in_work_dir(tmpdir, "buildtest", |tmpdir| {
+            let comptest_path = tmpdir.join("buildtest.rs");
+            std::fs::write(&comptest_path, "fn main(){}\n")?;
+            let mut flags = String::new();
+            Ok(find_rustc_target(
+                &rustc.rustc,
+                &mut flags,
+                &comptest_path,
+                build,
+                None,
+                &rustc.version,
+            )
+            .unwrap_or_else(|_| crate::compat::target_info(build)))
+        })
+    }
+
+    pub fn spec_target_info(
+        rustc: &RustcTestsResult,
+        spec: &Path,
+        tmpdir: &Path,
+    ) -> std::io::Result<RustcTargetInfo> {
+        super::in_work_dir(tmpdir, "spectest", |tmpdir| {
+            let mut flags = Vec::new();
+            let mut args = rustc.rustflags.iter();
+            while let Some(arg) = args.next() {
+                if arg == "--target" {
+                    args.next();
+                } else {
+                    flags.push(arg.to_string_lossy());
+                }
+            }
+            let comptest_path = tmpdir.join("spectest.rs");
+            std::fs::write(&comptest_path, "fn main(){}\n")?;
+            probe_file_names(
+                &rustc.rustc,
+                &flags.join(" "),
+                Some(spec.as_os_str()),
+                &comptest_path,
+            )?
+            .map(|mut info| {
+                info.target = spec.display().to_string();
+                info
+            })
+            .ok_or_else(|| {
+                std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "{} does not accept the target specification {}",
+                        rustc.rustc.display(),
+                        spec.display()
+                    ),
+                )
+            })
+        })
     }
 
     pub fn find_compiler(
         var: &OsStr,
-        flags_var: &OsStr,
+        default_flags: &str,
         target: &Target,
+        spec: Option<&Path>,
         cross_compiling: bool,
+        configured: Option<&Path>,
         tmpdir: &Path,
     ) -> std::io::Result<RustcTestsResult> {
-        let mut flags = match std::env::var(flags_var) {
-            Ok(flags) => flags,
-            Err(std::env::VarError::NotPresent) => "-O -g".to_string(),
-            Err(e) => return Err(std::io::Error::new(ErrorKind::InvalidData, e)),
-        };
+        super::in_work_dir(tmpdir, "comptest", |tmpdir| {
+            let mut flags = match std::env::var("RUSTFLAGS") {
+                Ok(flags) => flags,
+                Err(std::env::VarError::NotPresent) => default_flags.to_string(),
+                Err(e) => return Err(std::io::Error::new(ErrorKind::InvalidData, e)),
+            };
 
-        let rustc = if let Some(path) = std::env::var_os(var) {
-            PathBuf::from(path)
-        } else {
-            super::which_any(&[
-                "rustc",
-                "lcrustc",
-                &format!("{}-gccrs", target.get_name()),
-                "gccrs",
-            ])?
-        };
+            let rustc = if let Some(path) = std::env::var_os(var) {
+                PathBuf::from(path)
+            } else if let Some(path) = configured {
+                path.to_owned()
+            } else {
+                super::which_any(&[
+                    "rustc",
+                    "lcrustc",
+                    &format!("{}-gccrs", target.get_name()),
+                    "gccrs",
+                ])?
+            };
 
-        let comptest_path = {
-            let mut path = tmpdir.to_owned();
-            path.push("comptest.rs");
-            path
-        };
+            let comptest_path = {
+                let mut path = tmpdir.to_owned();
+                path.push("comptest.rs");
+                path
+            };
 
-        std::fs::write(
-            &comptest_path,
-            r#"
+            std::fs::write(
+                &comptest_path,
+                r#"
 fn main(){}
 
 "#,
-        )?;
+            )?;
 
-        let targ = find_rustc_target(&rustc, &mut flags, &comptest_path, &target)?;
-        let out = Command::new(&rustc).arg("--version").output()?;
+            let out = Command::new(&rustc).arg("--version").output()?;
 
-        let version = out.stdout.lines().next().ok_or_else(|| {
-            std::io::Error::new(
-                ErrorKind::Unsupported,
-                format!("Cannot determine the version of {}", rustc.display()),
-            )
-        })??;
-
-        let mut components = version.split(' ');
-        let name = components.next().ok_or_else(|| {
-            std::io::Error::new(
-                ErrorKind::Unsupported,
-                format!("Cannot determine the version of {}", rustc.display()),
-            )
-        })?;
-        let mut prgname = None;
-        if name != "rustc" {
-            prgname = Some(name);
-        }
-
-        let ver = components.next().ok_or_else(|| {
-            std::io::Error::new(
-                ErrorKind::Unsupported,
-                format!("Cannot determine the version of {}", rustc.display()),
-            )
-        })?;
-
-        let mut parts = ver.split(".");
-        let major = parts
-            .next()
-            .ok_or_else(|| {
+            let version = out.stdout.lines().next().ok_or_else(|| {
                 std::io::Error::new(
                     ErrorKind::Unsupported,
                     format!("Cannot determine the version of {}", rustc.display()),
                 )
-            })?
-            .parse()
-            .map_err(|_| {
+            })??;
+
+            let mut components = version.split(' ');
+            let name = components.next().ok_or_else(|| {
                 std::io::Error::new(
                     ErrorKind::Unsupported,
                     format!("Cannot determine the version of {}", rustc.display()),
                 )
             })?;
-        let minor = parts
-            .next()
-            .ok_or_else(|| {
-                std::io::Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Cannot determine the version of {}", rustc.display()),
-                )
-            })?
-            .parse()
-            .map_err(|_| {
+            let mut prgname = None;
+            if name != "rustc" {
+                prgname = Some(name);
+            }
+
+            let mut ver = components.next().ok_or_else(|| {
                 std::io::Error::new(
                     ErrorKind::Unsupported,
                     format!("Cannot determine the version of {}", rustc.display()),
                 )
             })?;
-        let tail = parts.next().ok_or_else(|| {
-            std::io::Error::new(
-                ErrorKind::Unsupported,
-                format!("Cannot determine the version of {}", rustc.display()),
-            )
-        })?;
+            // gccrs prints `gccrs (GCC) 14.1.0`
+            if ver.starts_with('(') {
+                ver = components.next().unwrap_or_default();
+            }
 
-        let mut patch_and_maybe_channel = tail.split('-');
-        let patch = patch_and_maybe_channel
-            .next()
-            .ok_or_else(|| {
-                std::io::Error::new(
-                    ErrorKind::Unsupported,
-                    format!("Cannot determine the version of {}", rustc.display()),
-                )
-            })?
-            .parse()
-            .map_err(|_| {
+            let mut parts = ver.split(".");
+            let major = parts
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?
+                .parse()
+                .map_err(|_| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?;
+            let minor = parts
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?
+                .parse()
+                .map_err(|_| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?;
+            let tail = parts.next().ok_or_else(|| {
                 std::io::Error::new(
                     ErrorKind::Unsupported,
                     format!("Cannot determine the version of {}", rustc.display()),
                 )
             })?;
 
-        let mut channel = if let Some(channel) = patch_and_maybe_channel.next() {
-            match channel {
-                "beta" => RustcChannel::Beta,
-                "nightly" => RustcChannel::Nightly,
-                _ => RustcChannel::Dev,
-            }
-        } else {
-            RustcChannel::Stable
-        };
-
-        if let Some(paren) = components.next() {
-            let paren = paren.get(1..);
-            match paren {
-                Some("mrustc") => {
-                    prgname = Some("mrust");
+            let mut patch_and_maybe_channel = tail.split('-');
+            let patch = patch_and_maybe_channel
+                .next()
+                .ok_or_else(|| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?
+                .parse()
+                .map_err(|_| {
+                    std::io::Error::new(
+                        ErrorKind::Unsupported,
+                        format!("Cannot determine the version of {}", rustc.display()),
+                    )
+                })?;
+
+            let mut channel = if let Some(channel) = patch_and_maybe_channel.next() {
+                match channel {
+                    "beta" => RustcChannel::Beta,
+                    "nightly" => RustcChannel::Nightly,
+                    _ => RustcChannel::Dev,
                 }
-                Some("lccc") => {
-                    prgname = Some("lcrustc");
+            } else {
+                RustcChannel::Stable
+            };
+
+            if let Some(paren) = components.next() {
+                let paren = paren.get(1..);
+                match paren {
+                    Some("mrustc") => {
+                        prgname = Some("mrust");
+                    }
+                    Some("lccc") => {
+                        prgname = Some("lcrustc");
+                    }
+                    _ => {}
                 }
-                _ => {}
             }
-        }
 
-        let prgname = match prgname {
-            Some(s) => s,
-            None => "rustc",
-        }
-        .to_string();
+            let prgname = prgname.unwrap_or("rustc").to_string();
 
-        if prgname.starts_with("lc") {
-            channel = RustcChannel::Unstable
-        }
+            if prgname.starts_with("lc") {
+                channel = RustcChannel::Unstable
+            }
 
-        let version = RustcVersion {
-            major,
-            minor,
-            patch,
-            prgname,
-            channel,
-        };
+            let version = RustcVersion {
+                major,
+                minor,
+                patch,
+                prgname,
+                channel,
+            };
 
-        let output_file = {
-            let mut path = tmpdir.to_owned();
-            let mut name = OsString::from("comptest");
-            name.push(&targ.exe_suffix);
-            path.push(name);
-            path
-        };
+            let flavor = crate::compat::Flavor::of(&version);
+            let targ = if flavor.accepts_rustc_flags() {
+                find_rustc_target(&rustc, &mut flags, &comptest_path, target, spec, &version)?
+            } else if spec.is_some() {
+                return Err(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!("{} does not accept target specifications", rustc.display()),
+                ));
+            } else {
+                flags = flavor.translate_all(flags.split(' ')).join(" ");
+                crate::compat::target_info(target)
+            };
 
-        if Command::new(&rustc)
-            .args(flags.split(' '))
-            .arg("--crate-type")
-            .arg("bin")
-            .arg("--emit")
-            .arg(format!("link={}", output_file.display()))
-            .arg("--crate-name")
-            .arg("comptest")
-            .arg(&comptest_path)
-            .stdout(Stdio::null())
-            .stderr(Stdio::null())
-            .stdin(Stdio::null())
-            .status()?
-            .success()
-        {
-            if !cross_compiling {
-                if !Command::new(&output_file).status()?.success() {
+            let output_file = {
+                let mut path = tmpdir.to_owned();
+                let mut name = OsString::from("comptest");
+                name.push(&targ.exe_suffix);
+                path.push(name);
+                path
+            };
+
+            if Command::new(&rustc)
+                .args(flags.split(' ').filter(|f| !f.is_empty()))
+                .args(flavor.compile_args("comptest", "bin", &output_file))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success()
+            {
+                if !cross_compiling && !Command::new(&output_file).status()?.success() {
                     return Err(std::io::Error::new(
                         ErrorKind::Unsupported,
                         format!("Cannot execute binaries produced by {}", rustc.display()),
                     ));
                 }
-            }
-            return Ok(RustcTestsResult {
-                rustc,
-                rustflags: flags.split(" ").map(|s| OsString::from(s)).collect(),
-                no_std: false,
-                version,
-                target_info: targ,
-            });
-        };
+                return Ok(RustcTestsResult {
+                    rustc,
+                    rustflags: flags.split(" ").map(OsString::from).collect(),
+                    no_std: false,
+                    version,
+                    target_info: targ,
+                });
+            };
 
-        std::fs::write(
-            &comptest_path,
-            r#"
+            std::fs::write(
+                &comptest_path,
+                r#"
 #![no_std]
+
+pub fn comptest(a: u32, b: u32) -> u32 {
+    core::cmp::max(a, b)
+}
+"#,
+            )?;
+
+            let output_file = {
+                let mut path = tmpdir.to_owned();
+                let mut name = OsString::from(&targ.rlib_prefix);
+                name.push("comptest");
+                name.push(&targ.rlib_suffix);
+                path.push(name);
+                path
+            };
+
+            if Command::new(&rustc)
+                .args(flags.split(' ').filter(|f| !f.is_empty()))
+                .args(flavor.compile_args("comptest", "rlib", &output_file))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success()
+            {
+                Ok(RustcTestsResult {
+                    rustc,
+                    rustflags: flags.split(" ").map(OsString::from).collect(),
+                    no_std: true,
+                    version,
+                    target_info: targ,
+                })
+            } else if spec.is_some() {
+                // Custom targets ship without a prebuilt `core`; it is built with `-Zbuild-std` instead
+                Ok(RustcTestsResult {
+                    rustc,
+                    rustflags: flags.split(" ").map(OsString::from).collect(),
+                    no_std: true,
+                    version,
+                    target_info: targ,
+                })
+            } else {
+                Err(std::io::Error::new(
+                    ErrorKind::Unsupported,
+                    format!(
+                        "Cannot compile simple test program with {}",
+                        rustc.display()
+                    ),
+                ))
+            }
+        })
+    }
+}
+
+pub mod lto {
+    use std::{
+        ffi::OsString,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+
+    pub fn is_clang(cc: &Path) -> std::io::Result<bool> {
+        let output = Command::new(cc)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        Ok(String::from_utf8_lossy(&output.stdout).contains("clang"))
+    }
+
+    pub fn linker_plugin_lto(
+        rustc: &RustcTestsResult,
+        clang: &Path,
+        tmpdir: &Path,
+    ) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "ltotest", |tmpdir| {
+            let c_path = tmpdir.join("ltotest.c");
+            let obj_path = tmpdir.join("ltotest.o");
+            let rs_path = tmpdir.join("ltotest.rs");
+            let output_file = {
+                let mut name = OsString::from("ltotest");
+                name.push(&rustc.target_info.exe_suffix);
+                tmpdir.join(name)
+            };
+
+            std::fs::write(&c_path, "int autobuild_lto_probe(void){return 0;}\n")?;
+            std::fs::write(
+                &rs_path,
+                r#"
+extern "C" {
+    fn autobuild_lto_probe() -> i32;
+}
+
+fn main() {
+    unsafe {
+        autobuild_lto_probe();
+    }
+}
 "#,
-        )?;
-
-        let output_file = {
-            let mut path = tmpdir.to_owned();
-            let mut name = OsString::from(&targ.rlib_prefix);
-            name.push("comptest");
-            name.push(&targ.rlib_suffix);
-            path.push(name);
-            path
+            )?;
+
+            if !Command::new(clang)
+                .arg("-flto=thin")
+                .arg("-c")
+                .arg(&c_path)
+                .arg("-o")
+                .arg(&obj_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success()
+            {
+                return Ok(false);
+            }
+
+            let mut linker = OsString::from("linker=");
+            linker.push(clang);
+            let mut link_arg = OsString::from("link-arg=");
+            link_arg.push(&obj_path);
+            Ok(Command::new(&rustc.rustc)
+                .args(&rustc.rustflags)
+                .arg("-Clinker-plugin-lto")
+                .arg("-C")
+                .arg(linker)
+                .arg("-Clink-arg=-fuse-ld=lld")
+                .arg("-C")
+                .arg(link_arg)
+                .arg("-o")
+                .arg(&output_file)
+                .arg(&rs_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+}
+
+pub mod hardening {
+    use std::{
+        ffi::{OsStr, OsString},
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    // Compiled with optimization, without which glibc warns about _FORTIFY_SOURCE
+    pub fn cc_accepts(
+        cc: &Path,
+        exe_suffix: &OsStr,
+        flags: &[&str],
+        ldflags: &[&str],
+        tmpdir: &Path,
+    ) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "hardentest", |tmpdir| {
+            let c_path = tmpdir.join("hardentest.c");
+            let output_file = {
+                let mut name = OsString::from("hardentest");
+                name.push(exe_suffix);
+                tmpdir.join(name)
+            };
+            std::fs::write(&c_path, "int main(void){return 0;}\n")?;
+            Ok(Command::new(cc)
+                .arg("-O2")
+                .args(flags)
+                .args(ldflags)
+                .arg(&c_path)
+                .arg("-o")
+                .arg(&output_file)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+}
+
+pub mod sanitizer {
+    use std::{
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+
+    pub fn supported(
+        rustc: &RustcTestsResult,
+        sanitizer: &str,
+        tmpdir: &Path,
+    ) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "santest", |tmpdir| {
+            let comptest_path = tmpdir.join("santest.rs");
+            std::fs::write(&comptest_path, "fn main(){}\n")?;
+            let mut cmd = Command::new(&rustc.rustc);
+            cmd.args(&rustc.rustflags)
+                .arg(format!("-Zsanitizer={}", sanitizer));
+            if !rustc.rustflags.iter().any(|f| f == "--target") {
+                cmd.arg("--target").arg(&rustc.target_info.target);
+            }
+            Ok(cmd
+                .arg("-o")
+                .arg(tmpdir.join("santest"))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+}
+
+pub mod linker {
+    use std::{
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+
+    pub fn rustc_args(path: &Path, msvc: bool) -> Option<Vec<String>> {
+        if msvc {
+            return Some(vec![format!("-Clinker={}", path.display())]);
+        }
+        let name = path.file_name()?.to_string_lossy();
+        let name = name.strip_suffix(".exe").unwrap_or(&name);
+        let flavor = if name.ends_with("mold") {
+            "mold"
+        } else if name.ends_with("ld.lld") || name == "lld" {
+            "lld"
+        } else if name.ends_with("ld.gold") {
+            "gold"
+        } else if name.ends_with("ld.bfd") || name.ends_with("ld") {
+            "bfd"
+        } else {
+            return None;
         };
+        Some(vec![format!("-Clink-arg=-fuse-ld={}", flavor)])
+    }
 
-        if Command::new(&rustc)
-            .args(flags.split(' '))
-            .arg("--crate-type")
-            .arg("rlib")
-            .arg("--emit")
-            .arg(format!("link={}", output_file.display()))
-            .arg("--crate-name")
-            .arg("comptest")
+    pub fn links_with(
+        rustc: &RustcTestsResult,
+        args: &[String],
+        tmpdir: &Path,
+    ) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "ldtest", |tmpdir| {
+            let comptest_path = tmpdir.join("ldtest.rs");
+            std::fs::write(&comptest_path, "fn main(){}\n")?;
+            Ok(Command::new(&rustc.rustc)
+                .args(&rustc.rustflags)
+                .args(args)
+                .arg("-o")
+                .arg(tmpdir.join("ldtest"))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+}
+
+pub mod static_link {
+    use std::{
+        io::ErrorKind,
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+    use crate::compat::Flavor;
+
+    pub fn build_test_binary(rustc: &RustcTestsResult, tmpdir: &Path) -> std::io::Result<PathBuf> {
+        let comptest_path = tmpdir.join("statictest.rs");
+        let output_file = tmpdir.join("statictest");
+        std::fs::write(&comptest_path, "fn main(){}\n")?;
+        let flavor = Flavor::of(&rustc.version);
+        if Command::new(&rustc.rustc)
+            .args(&rustc.rustflags)
+            .args(flavor.translate_all(Some("-Ctarget-feature=+crt-static")))
+            .args(flavor.compile_args("statictest", "bin", &output_file))
             .arg(&comptest_path)
             .stdout(Stdio::null())
             .stderr(Stdio::null())
@@ -938,21 +944,232 @@ fn main(){}
             .status()?
             .success()
         {
-            Ok(RustcTestsResult {
-                rustc,
-                rustflags: flags.split(" ").map(|s| OsString::from(s)).collect(),
-                no_std: false,
-                version,
-                target_info: targ,
-            })
+            Ok(output_file)
         } else {
             Err(std::io::Error::new(
                 ErrorKind::Unsupported,
                 format!(
-                    "Cannot compile simple test program with {}",
-                    rustc.display()
+                    "Cannot link a static program with {}",
+                    rustc.rustc.display()
                 ),
             ))
         }
     }
 }
+
+pub mod codegen {
+    use std::{
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+
+    pub fn rejected_flags(
+        rustc: &RustcTestsResult,
+        args: &[String],
+        tmpdir: &Path,
+    ) -> std::io::Result<Option<String>> {
+        super::in_work_dir(tmpdir, "cputest", |tmpdir| {
+            let comptest_path = tmpdir.join("cputest.rs");
+            let (source, crate_type) = if rustc.no_std {
+                ("#![no_std]\n", "rlib")
+            } else {
+                ("fn main(){}\n", "bin")
+            };
+            std::fs::write(&comptest_path, source)?;
+            let output = Command::new(&rustc.rustc)
+                .args(&rustc.rustflags)
+                .args(args)
+                .arg("--crate-type")
+                .arg(crate_type)
+                .arg("-o")
+                .arg(tmpdir.join("cputest"))
+                .arg(&comptest_path)
+                .stdin(Stdio::null())
+                .output()?;
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let complaint = stderr
+                .lines()
+                .find(|l| l.contains("is not a recognized") || l.contains("unknown and unstable"));
+            Ok(match complaint {
+                Some(line) => Some(line.trim().to_string()),
+                None if !output.status.success() => Some(format!(
+                    "{} failed to compile a test program",
+                    rustc.rustc.display()
+                )),
+                None => None,
+            })
+        })
+    }
+
+    pub fn cfg(rustc: &RustcTestsResult) -> std::io::Result<Vec<String>> {
+        let output = Command::new(&rustc.rustc)
+            .args(&rustc.rustflags)
+            .arg("--print")
+            .arg("cfg")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        if !output.status.success() {
+            return Err(std::io::Error::other(format!(
+                "{} --print cfg failed",
+                rustc.rustc.display()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::to_string)
+            .collect())
+    }
+
+    pub fn native_cpu(rustc: &RustcTestsResult) -> std::io::Result<Option<String>> {
+        let output = Command::new(&rustc.rustc)
+            .args(&rustc.rustflags)
+            .arg("--print")
+            .arg("target-cpus")
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()?;
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|l| l.trim_start().starts_with("native"))
+            .find_map(|l| {
+                let start = l.find("(currently ")? + "(currently ".len();
+                let end = l[start..].find(')')? + start;
+                Some(l[start..end].trim_end_matches('.').to_string())
+            }))
+    }
+}
+
+pub mod runner {
+    use std::{
+        ffi::OsString,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+    use crate::compat::Flavor;
+
+    pub fn runs(rustc: &RustcTestsResult, runner: &Path, tmpdir: &Path) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "runtest", |tmpdir| {
+            let comptest_path = tmpdir.join("runtest.rs");
+            let output_file = {
+                let mut name = OsString::from("runtest");
+                name.push(&rustc.target_info.exe_suffix);
+                tmpdir.join(name)
+            };
+            std::fs::write(&comptest_path, "fn main(){}\n")?;
+            if !Command::new(&rustc.rustc)
+                .args(&rustc.rustflags)
+                .args(Flavor::of(&rustc.version).compile_args("runtest", "bin", &output_file))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success()
+            {
+                return Ok(false);
+            }
+            Ok(Command::new(runner)
+                .arg(&output_file)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+}
+
+pub mod language {
+    use std::{
+        path::{Path, PathBuf},
+        process::{Command, Stdio},
+    };
+
+    use super::rustc::RustcTestsResult;
+    use crate::compat::Flavor;
+
+    // Newest first
+    const EDITIONS: &[&str] = &["2024", "2021", "2018"];
+
+    // Only checks the crate, so that targets without a prebuilt `std` still answer
+    fn accepts(rustc: &RustcTestsResult, args: &[String], tmpdir: &Path) -> std::io::Result<bool> {
+        super::in_work_dir(tmpdir, "langtest", |tmpdir| {
+            let comptest_path = tmpdir.join("langtest.rs");
+            std::fs::write(&comptest_path, "#![no_std]\n")?;
+            Ok(Command::new(&rustc.rustc)
+                .args(&rustc.rustflags)
+                .args(args)
+                .arg("--crate-type")
+                .arg("lib")
+                .arg("--emit")
+                .arg(format!(
+                    "metadata={}",
+                    tmpdir.join("langtest.rmeta").display()
+                ))
+                .arg(&comptest_path)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .stdin(Stdio::null())
+                .status()?
+                .success())
+        })
+    }
+
+    pub fn max_edition(rustc: &RustcTestsResult, tmpdir: &Path) -> std::io::Result<&'static str> {
+        let flavor = Flavor::of(&rustc.version);
+        if !flavor.accepts_rustc_flags() {
+            return Ok(flavor.max_edition());
+        }
+        for edition in EDITIONS {
+            if accepts(rustc, &[format!("--edition={}", edition)], tmpdir)? {
+                return Ok(edition);
+            }
+        }
+        Ok("2015")
+    }
+
+    pub fn check_cfg(rustc: &RustcTestsResult, tmpdir: &Path) -> std::io::Result<bool> {
+        if !Flavor::of(&rustc.version).accepts_rustc_flags() {
+            return Ok(false);
+        }
+        accepts(
+            rustc,
+            &["--check-cfg".to_string(), "cfg(autobuild)".to_string()],
+            tmpdir,
+        )
+    }
+
+    pub fn unstable_flag(
+        rustc: &RustcTestsResult,
+        flag: &str,
+        tmpdir: &Path,
+    ) -> std::io::Result<bool> {
+        if !Flavor::of(&rustc.version).accepts_rustc_flags() {
+            return Ok(false);
+        }
+        accepts(rustc, &[format!("-Z{}", flag)], tmpdir)
+    }
+
+    // rustdoc belongs to one rustc, so only the one installed beside it counts; gccrs has none
+    pub fn rustdoc(rustc: &RustcTestsResult) -> Option<PathBuf> {
+        if let Some(rustdoc) = std::env::var_os("RUSTDOC") {
+            return Some(PathBuf::from(rustdoc));
+        }
+        let name = rustc.rustc.file_name()?.to_str()?;
+        if !name.contains("rustc") {
+            return None;
+        }
+        let rustdoc = rustc
+            .rustc
+            .with_file_name(name.replacen("rustc", "rustdoc", 1));
+        match rustdoc.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => Some(rustdoc).filter(|p| p.is_file()),
+            _ => super::which_any(&[rustdoc]).ok(),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    io::ErrorKind,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+
+#[derive(Serialize, Clone)]
+pub struct Timing {
+    pub name: String,
+    pub start: f64,
+    pub duration: f64,
+    pub status: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub deps: Vec<usize>,
+}
+
+impl Timing {
+    pub fn new(name: &str, epoch: Instant, start: Instant, duration: Duration) -> Self {
+        Timing {
+            name: name.to_string(),
+            start: start.duration_since(epoch).as_secs_f64(),
+            duration: duration.as_secs_f64(),
+            status: String::new(),
+            deps: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Report {
+    pub total: f64,
+    pub utilization: f64,
+    pub critical_path: Vec<String>,
+    pub probes: Vec<Timing>,
+    pub steps: Vec<Timing>,
+}
+
+fn critical_path(steps: &[Timing]) -> Vec<usize> {
+    let mut finish = vec![0.0f64; steps.len()];
+    let mut prev = vec![None; steps.len()];
+    for (i, step) in steps.iter().enumerate() {
+        let mut start = 0.0;
+        for &dep in &step.deps {
+            if dep < i && finish[dep] >= start {
+                start = finish[dep];
+                prev[i] = Some(dep);
+            }
+        }
+        finish[i] = start + step.duration;
+    }
+
+    let mut path = Vec::new();
+    let mut cur = (0..steps.len()).max_by(|&a, &b| finish[a].total_cmp(&finish[b]));
+    while let Some(i) = cur {
+        path.push(i);
+        cur = prev[i];
+    }
+    path.reverse();
+    path
+}
+
+impl Report {
+    pub fn new(probes: Vec<Timing>, steps: Vec<Timing>, total: Duration) -> Self {
+        let critical_path = critical_path(&steps)
+            .into_iter()
+            .map(|i| steps[i].name.clone())
+            .collect();
+
+        let busy: f64 = steps.iter().map(|s| s.duration).sum();
+        let span = steps
+            .iter()
+            .map(|s| s.start + s.duration)
+            .fold(0.0, f64::max)
+            - steps.iter().map(|s| s.start).fold(f64::INFINITY, f64::min);
+        let utilization = if span > 0.0 { busy / span } else { 0.0 };
+
+        Report {
+            total: total.as_secs_f64(),
+            utilization,
+            critical_path,
+            probes,
+            steps,
+        }
+    }
+
+    fn html(&self) -> String {
+        let mut out = String::new();
+        out += "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\n";
+        out += "<title>cargo-autobuild timings</title>\n<style>\n";
+        out += "body{font-family:sans-serif}table{border-collapse:collapse;width:100%}\n";
+        out += "td{padding:2px 6px}.bar{background:#8ab;height:1em}\n";
+        out += ".crit .bar{background:#d64}.name{white-space:nowrap;width:1%}\n";
+        out += "</style></head><body>\n<h1>cargo-autobuild timings</h1>\n";
+        out += &format!(
+            "<p>Total time: {:.2}s. Step utilization: {:.2}. Critical path: {}.</p>\n",
+            self.total,
+            self.utilization,
+            self.critical_path
+                .iter()
+                .map(|s| escape(s))
+                .collect::<Vec<_>>()
+                .join(" &rarr; ")
+        );
+
+        for (title, timings) in [("Probes", &self.probes), ("Steps", &self.steps)] {
+            out += &format!("<h2>{}</h2>\n<table>\n", title);
+            for timing in timings.iter() {
+                let crit = title == "Steps" && self.critical_path.contains(&timing.name);
+                let scale = if self.total > 0.0 {
+                    100.0 / self.total
+                } else {
+                    0.0
+                };
+                out += &format!(
+                    "<tr{}><td class=\"name\">{}</td><td class=\"name\">{:.2}s {}</td><td><div class=\"bar\" style=\"margin-left:{:.2}%;width:{:.2}%\"></div></td></tr>\n",
+                    if crit { " class=\"crit\"" } else { "" },
+                    escape(&timing.name),
+                    timing.duration,
+                    escape(&timing.status),
+                    timing.start * scale,
+                    (timing.duration * scale).max(0.1),
+                );
+            }
+            out += "</table>\n";
+        }
+        out += "</body></html>\n";
+        out
+    }
+
+    pub fn write(&self, dir: &Path, formats: &str) -> std::io::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        for format in formats.split(',') {
+            let path = match format {
+                "html" => {
+                    let path = dir.join("autobuild-timing.html");
+                    std::fs::write(&path, self.html())?;
+                    path
+                }
+                "json" => {
+                    let path = dir.join("autobuild-timing.json");
+                    let buf = serde_json::to_vec_pretty(self)
+                        .map_err(|e| std::io::Error::new(ErrorKind::InvalidData, e))?;
+                    std::fs::write(&path, buf)?;
+                    path
+                }
+                format => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("Unknown timings format {}", format),
+                    ))
+                }
+            };
+            println!("Timing report saved to {}", path.display());
+        }
+        Ok(())
+    }
+}
+
+fn escape(st: &str) -> String {
+    st.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
@@ -0,0 +1,151 @@
+use std::{
+    io::{IsTerminal, Write},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use crate::timings::Timing;
+
+const BOLD: &str = "1";
+const DIM: &str = "2";
+const RED: &str = "31";
+const GREEN: &str = "32";
+const YELLOW: &str = "33";
+
+const FRAMES: &[char] = &['|', '/', '-', '\\'];
+
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+// For --no-color
+pub fn disable() {
+    PLAIN.store(true, Ordering::Relaxed);
+}
+
+// Colors, spinners and progress are only drawn on terminals, and never when NO_COLOR is set
+pub fn fancy() -> bool {
+    !PLAIN.load(Ordering::Relaxed)
+        && std::env::var_os("NO_COLOR").is_none_or(|v| v.is_empty())
+        && std::env::var_os("TERM").is_none_or(|t| t != "dumb")
+        && std::io::stdout().is_terminal()
+}
+
+fn paint(text: &str, style: &str) -> String {
+    if fancy() {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    } else {
+        text.to_string()
+    }
+}
+
+pub struct Spinner {
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Spinner {
+    // Drawn at the cursor and erased when dropped, so whatever is printed next takes its place
+    pub fn start() -> Option<Spinner> {
+        if !fancy() {
+            return None;
+        }
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = std::thread::spawn(move || {
+            for frame in FRAMES.iter().cycle() {
+                if stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let mut out = std::io::stdout().lock();
+                let _ = write!(out, "{}\x08", frame);
+                let _ = out.flush();
+                drop(out);
+                std::thread::park_timeout(Duration::from_millis(100));
+            }
+            let mut out = std::io::stdout().lock();
+            let _ = write!(out, " \x08");
+            let _ = out.flush();
+        });
+        Some(Spinner {
+            stop,
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for Spinner {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+pub fn step_started(index: usize, total: usize, name: &str, description: &str) {
+    if fancy() {
+        println!(
+            "{} Running {} ({})",
+            paint(&format!("[{}/{}]", index + 1, total), DIM),
+            paint(name, BOLD),
+            description
+        );
+    } else {
+        println!("Running {} ({})", name, description);
+    }
+}
+
+pub fn step_skipped(name: &str) {
+    println!("{}", paint(&format!("Skipping {} (up to date)", name), DIM));
+}
+
+// Plain output leaves failures to the error messages that follow
+pub fn step_finished(name: &str, ok: bool, elapsed: Duration) {
+    if !fancy() {
+        return;
+    }
+    if ok {
+        println!(
+            "{} {} ({:.1}s)",
+            paint("✓", GREEN),
+            name,
+            elapsed.as_secs_f64()
+        );
+    } else {
+        println!(
+            "{} {} failed ({:.1}s)",
+            paint("✗", RED),
+            name,
+            elapsed.as_secs_f64()
+        );
+    }
+}
+
+pub fn summary(timings: &[Timing], elapsed: Duration) {
+    if timings.is_empty() {
+        return;
+    }
+    let count = |status: &str| timings.iter().filter(|t| t.status == status).count();
+    let mut parts = Vec::new();
+    for (status, label, style) in &[
+        ("ok", "ok", GREEN),
+        ("skipped", "up to date", DIM),
+        ("failed", "failed", RED),
+        ("blocked", "not run", YELLOW),
+    ] {
+        let n = count(status);
+        if n != 0 {
+            parts.push(paint(&format!("{} {}", n, label), style));
+        }
+    }
+    println!(
+        "{} {} in {:.1}s",
+        paint("Finished:", BOLD),
+        parts.join(", "),
+        elapsed.as_secs_f64()
+    );
+}
@@ -0,0 +1,66 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{cli::Options, config::Config};
+
+pub const MANIFEST: &str = "install-manifest.txt";
+
+// Appends an installed path to the manifest read back by `uninstall`
+pub fn record(build_dir: &Path, path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(build_dir)?;
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(build_dir.join(MANIFEST))?;
+    writeln!(file, "{}", path.display())
+}
+
+pub fn installed(build_dir: &Path) -> std::io::Result<Vec<PathBuf>> {
+    let manifest = build_dir.join(MANIFEST);
+    if !manifest.is_file() {
+        return Ok(Vec::new());
+    }
+    Ok(std::fs::read_to_string(manifest)?
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect())
+}
+
+pub fn uninstall(opts: &Options, config: &Config) -> std::io::Result<()> {
+    let src_dir = std::fs::canonicalize(opts.src_dir())?;
+    let build_dir = crate::configure::build_dir(opts, &src_dir);
+    let hosts = if opts.hosts.is_empty() {
+        &config.options.hosts
+    } else {
+        &opts.hosts
+    };
+    let mut build_dirs = vec![build_dir.clone()];
+    build_dirs.extend(hosts.iter().map(|host| build_dir.join(host)));
+
+    for dir in &build_dirs {
+        let mut paths = installed(dir)?;
+        // Later installs win, so remove in reverse and only once
+        paths.reverse();
+        let mut seen = std::collections::HashSet::new();
+        paths.retain(|path| seen.insert(path.clone()));
+        for path in &paths {
+            // symlink_metadata so that dangling links are still removed
+            if path.symlink_metadata().is_err() {
+                continue;
+            }
+            if opts.dry_run {
+                println!("would remove {}", path.display());
+            } else {
+                println!("removing {}", path.display());
+                std::fs::remove_file(path)?;
+            }
+        }
+        if !opts.dry_run && !paths.is_empty() {
+            std::fs::remove_file(dir.join(MANIFEST))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,67 @@
+use std::path::PathBuf;
+
+use target_tuples::Target;
+
+use crate::tests::which_any;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Emscripten,
+    Wasi,
+}
+
+pub struct CTools {
+    pub cc: PathBuf,
+    pub cxx: PathBuf,
+    pub ar: PathBuf,
+    pub cflags: Option<String>,
+}
+
+pub fn flavor(target: &Target) -> Option<Flavor> {
+    let name = target.get_name();
+    if !name.starts_with("wasm") {
+        None
+    } else if name.ends_with("emscripten") {
+        Some(Flavor::Emscripten)
+    } else if name.contains("wasi") {
+        Some(Flavor::Wasi)
+    } else {
+        None
+    }
+}
+
+pub fn c_tools(flavor: Flavor, target: &Target) -> Option<CTools> {
+    match flavor {
+        Flavor::Emscripten => Some(CTools {
+            cc: which_any(&["emcc"]).ok()?,
+            cxx: which_any(&["em++"]).ok()?,
+            ar: which_any(&["emar"]).ok()?,
+            cflags: None,
+        }),
+        Flavor::Wasi => {
+            let sdk = PathBuf::from(std::env::var_os("WASI_SDK_PATH")?);
+            let bin = sdk.join("bin");
+            let cc = bin.join(format!("clang{}", std::env::consts::EXE_SUFFIX));
+            if !cc.is_file() {
+                return None;
+            }
+            Some(CTools {
+                cxx: bin.join(format!("clang++{}", std::env::consts::EXE_SUFFIX)),
+                ar: bin.join(format!("llvm-ar{}", std::env::consts::EXE_SUFFIX)),
+                cflags: Some(format!(
+                    "--target={} --sysroot={}",
+                    target.get_name(),
+                    sdk.join("share/wasi-sysroot").display()
+                )),
+                cc,
+            })
+        }
+    }
+}
+
+pub fn runner(flavor: Flavor) -> Option<PathBuf> {
+    match flavor {
+        Flavor::Emscripten => which_any(&["node", "nodejs"]).ok(),
+        Flavor::Wasi => which_any(&["wasmtime", "wasmer"]).ok(),
+    }
+}
@@ -0,0 +1,121 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+use notify::{DebouncedEvent, RecursiveMode, Watcher};
+
+use crate::{configure::Configuration, plan::PlannedStep, steps};
+
+const DEBOUNCE: Duration = Duration::from_millis(300);
+
+fn changed_path(event: DebouncedEvent) -> Option<PathBuf> {
+    match event {
+        DebouncedEvent::Create(path)
+        | DebouncedEvent::Write(path)
+        | DebouncedEvent::Remove(path)
+        | DebouncedEvent::Rename(_, path) => Some(path),
+        _ => None,
+    }
+}
+
+fn run_steps(steps: &[&PlannedStep], cfg: &Configuration) -> bool {
+    for step in steps {
+        println!("[watch] running {}", step.name);
+        if let Err(e) = steps::run_step(&step.step, cfg) {
+            println!("[watch] {} failed: {}", step.name, e);
+            return false;
+        }
+    }
+    true
+}
+
+pub fn watch(steps: &[PlannedStep], cfg: &Configuration) -> std::io::Result<()> {
+    let mut inputs = Vec::with_capacity(steps.len());
+    for step in steps {
+        inputs.push(steps::step_inputs(&step.step, cfg)?);
+    }
+
+    let (tx, rx) = channel();
+    let mut watcher = notify::watcher(tx, DEBOUNCE).map_err(std::io::Error::other)?;
+    let mut watched: Vec<&Path> = Vec::new();
+    for path in inputs.iter().flatten() {
+        if watched.iter().any(|w| path.starts_with(w)) || !path.exists() {
+            continue;
+        }
+        watcher
+            .watch(path, RecursiveMode::Recursive)
+            .map_err(std::io::Error::other)?;
+        watched.push(path);
+    }
+
+    // Builds write there, and each write would set off another build
+    let ignored = crate::state::output_dirs(cfg);
+
+    println!("[watch] watching {} path(s)", watched.len());
+    let all: Vec<&PlannedStep> = steps.iter().collect();
+    let ok = run_steps(&all, cfg);
+    println!(
+        "[watch] initial build {}",
+        if ok { "succeeded" } else { "failed" }
+    );
+
+    let mut iteration = 0;
+    loop {
+        let first = rx
+            .recv()
+            .map_err(|e| std::io::Error::new(ErrorKind::BrokenPipe, e))?;
+        let mut changed: Vec<PathBuf> = changed_path(first).into_iter().collect();
+
+        let deadline = Instant::now() + DEBOUNCE;
+        loop {
+            match rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(event) => changed.extend(changed_path(event)),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(std::io::Error::new(
+                        ErrorKind::BrokenPipe,
+                        "File watcher disconnected",
+                    ))
+                }
+            }
+        }
+
+        changed.retain(|c| !ignored.iter().any(|dir| c.starts_with(dir)));
+        // A step depends only on steps before it, so one pass also reruns everything downstream
+        // of a step whose inputs changed
+        let mut rerun = vec![false; steps.len()];
+        for (i, step) in steps.iter().enumerate() {
+            rerun[i] = step.deps.iter().any(|&d| rerun[d])
+                || changed
+                    .iter()
+                    .any(|c| inputs[i].iter().any(|p| c.starts_with(p)));
+        }
+        let affected: Vec<&PlannedStep> = steps
+            .iter()
+            .zip(&rerun)
+            .filter(|(_, &rerun)| rerun)
+            .map(|(step, _)| step)
+            .collect();
+
+        if affected.is_empty() {
+            continue;
+        }
+
+        iteration += 1;
+        println!(
+            "[watch] #{}: {} file(s) changed, rerunning {} step(s)",
+            iteration,
+            changed.len(),
+            affected.len()
+        );
+        let ok = run_steps(&affected, cfg);
+        println!(
+            "[watch] #{} {}",
+            iteration,
+            if ok { "succeeded" } else { "failed" }
+        );
+    }
+}
@@ -1 +1,120 @@
-pub fn rustc(args: impl Iterator<Item: AsRef<OsStr>>) -> std::io::Result<i32> {}
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    config::{InjectedFlags, InjectedRustflags},
+    configure::Configuration,
+};
+
+// Set while cargo runs cargo-autobuild in place of rustc; empty unless another wrapper is
+// configured to run inside it
+const WRAPPER_VAR: &str = "CARGO_AUTOBUILD_RUSTC_WRAPPER";
+const CROSS_VAR: &str = "CARGO_AUTOBUILD_CROSS";
+
+// Flags are separated by the unit separator, as in CARGO_ENCODED_RUSTFLAGS
+fn flags_var(kind: &str, position: &str) -> String {
+    format!("CARGO_AUTOBUILD_{}_RUSTFLAGS_{}", kind, position)
+}
+
+fn export(cmd: &mut Command, kind: &str, flags: &InjectedFlags) {
+    cmd.env(flags_var(kind, "PREPEND"), flags.prepend.join("\x1f"))
+        .env(flags_var(kind, "APPEND"), flags.append.join("\x1f"));
+}
+
+// RUSTFLAGS cannot tell host crates and build scripts apart from the rest, so when either has
+// flags of its own cargo runs every rustc through cargo-autobuild, which sorts them out
+pub fn install(cmd: &mut Command, cfg: &Configuration, cross: bool) {
+    let InjectedRustflags {
+        host, build_script, ..
+    } = &cfg.injected_rustflags;
+    if host.is_empty() && build_script.is_empty() {
+        match &cfg.rustc_wrapper {
+            Some(wrapper) => cmd.env("RUSTC_WRAPPER", wrapper),
+            None => cmd.env_remove("RUSTC_WRAPPER"),
+        };
+        return;
+    }
+    let exe = std::env::current_exe().unwrap_or_else(|_| PathBuf::from("cargo-autobuild"));
+    cmd.env("RUSTC_WRAPPER", exe).env(
+        WRAPPER_VAR,
+        cfg.rustc_wrapper
+            .as_deref()
+            .unwrap_or_else(|| Path::new("")),
+    );
+    if cross {
+        cmd.env(CROSS_VAR, "1");
+    } else {
+        cmd.env_remove(CROSS_VAR);
+    }
+    export(cmd, "HOST", host);
+    export(cmd, "BUILD_SCRIPT", build_script);
+}
+
+pub fn is_wrapping() -> bool {
+    std::env::var_os(WRAPPER_VAR).is_some()
+}
+
+fn value<'a>(args: &'a [OsString], flag: &str) -> Option<&'a OsStr> {
+    args.iter()
+        .position(|a| a == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(|a| &**a)
+}
+
+fn injected(kind: &str, position: &str) -> Vec<OsString> {
+    std::env::var_os(flags_var(kind, position))
+        .map(|flags| {
+            flags
+                .to_string_lossy()
+                .split('\x1f')
+                .filter(|f| !f.is_empty())
+                .map(OsString::from)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+// Host flags go to proc macros and build scripts, and to everything built without --target when
+// cross compiling; build scripts get their own after them. Invocations that compile nothing,
+// such as `rustc -vV`, are passed through untouched
+pub fn rustc(mut args: impl Iterator<Item = OsString>) -> std::io::Result<i32> {
+    let rustc = args
+        .next()
+        .or_else(|| std::env::var_os("RUSTC"))
+        .unwrap_or_else(|| "rustc".into());
+    let args: Vec<OsString> = args.collect();
+    let mut kinds = Vec::new();
+    if let Some(name) = value(&args, "--crate-name") {
+        let build_script = name.to_string_lossy().starts_with("build_script_");
+        let host = build_script
+            || value(&args, "--crate-type") == Some(OsStr::new("proc-macro"))
+            || (std::env::var_os(CROSS_VAR).is_some() && value(&args, "--target").is_none());
+        if host {
+            kinds.push("HOST");
+        }
+        if build_script {
+            kinds.push("BUILD_SCRIPT");
+        }
+    }
+
+    let mut cmd = match std::env::var_os(WRAPPER_VAR).filter(|w| !w.is_empty()) {
+        Some(wrapper) => {
+            let mut cmd = Command::new(wrapper);
+            cmd.arg(rustc);
+            cmd
+        }
+        None => Command::new(rustc),
+    };
+    for kind in &kinds {
+        cmd.args(injected(kind, "PREPEND"));
+    }
+    cmd.args(&args);
+    for kind in &kinds {
+        cmd.args(injected(kind, "APPEND"));
+    }
+    let status = cmd.env_remove(WRAPPER_VAR).status()?;
+    Ok(status.code().unwrap_or(1))
+}